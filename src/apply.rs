@@ -0,0 +1,209 @@
+//! --apply and --apply-dry-run: make the winning provider the system's resolver with the
+//! platform's own tools.
+
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use dns_speed_test::TestResult;
+use crate::ConfigFormat;
+
+/// A winner that answered less often than this is not applied: switching to it would make name
+/// resolution worse, not better.
+pub const MIN_SUCCESS_RATE: f64 = 95.0;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// One change to the system's resolver settings.
+enum Step {
+    /// A program and its arguments
+    Run(Vec<String>),
+    /// Replace the contents of a file
+    Write { path: &'static str, contents: String },
+}
+
+impl Step {
+    fn run(program: &str, args: &[&str]) -> Step {
+        Step::Run(std::iter::once(program).chain(args.iter().copied()).map(String::from).collect())
+    }
+
+    /// The shell command that makes this change, as shown in dry runs and to users who need to
+    /// run it themselves.
+    fn command_line(&self, sudo: bool) -> String {
+        let sudo = if sudo { "sudo " } else { "" };
+        match self {
+            Step::Run(argv) => format!("{}{}", sudo, argv.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" ")),
+            Step::Write { path, contents } => format!("printf {} | {}tee {} >/dev/null", quote(contents), sudo, path),
+        }
+    }
+
+    fn execute(&self) -> Result<(), String> {
+        match self {
+            Step::Run(argv) => {
+                let status = Command::new(&argv[0]).args(&argv[1..]).status()
+                    .map_err(|e| format!("cannot run {}: {}", argv[0], e))?;
+                if !status.success() {
+                    return Err(format!("`{}` failed ({})", self.command_line(false), status));
+                }
+                Ok(())
+            },
+            Step::Write { path, contents } => std::fs::write(path, contents)
+                .map_err(|e| format!("cannot write {}: {}", path, e)),
+        }
+    }
+}
+
+/// Quotes `arg` for the platform's shell when it contains anything but plain characters.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_.,:/=@%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else if cfg!(windows) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        // printf interprets the newlines the arguments of Step::Write contain.
+        format!("'{}'", arg.replace('\'', r"'\''").replace('\n', r"\n"))
+    }
+}
+
+/// Standard output of a command, or `None` if it cannot run or fails.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).stderr(Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn is_privileged() -> bool {
+    if cfg!(windows) {
+        // `net session` needs an elevated prompt; it is the usual test for one.
+        output("net", &["session"]).is_some()
+    } else {
+        output("id", &["-u"]).is_some_and(|uid| uid.trim() == "0")
+    }
+}
+
+/// The steps that configure `addresses` (best first) as the system's nameservers.
+fn plan(result: &TestResult) -> Result<Vec<Step>, String> {
+    let addresses: Vec<&str> = result.addresses.iter().map(String::as_str).collect();
+    if cfg!(windows) {
+        let servers = addresses.iter().map(|ip| format!("'{}'", ip)).collect::<Vec<_>>().join(",");
+        // The interface of the default route is the one in use.
+        let script = format!(
+            "Set-DnsClientServerAddress -InterfaceIndex (Get-NetRoute -DestinationPrefix 0.0.0.0/0 | \
+             Sort-Object RouteMetric | Select-Object -First 1).ifIndex -ServerAddresses ({})",
+            servers
+        );
+        return Ok(vec![Step::run("powershell", &["-NoProfile", "-Command", &script])]);
+    }
+    if cfg!(target_os = "macos") {
+        // The first enabled service in the list; the first line explains the asterisks.
+        let services = output("networksetup", &["-listallnetworkservices"])
+            .ok_or("cannot list network services with networksetup")?;
+        let service = services.lines().skip(1).find(|line| !line.starts_with('*'))
+            .ok_or("no enabled network service found")?;
+        let mut args = vec!["-setdnsservers", service];
+        args.extend(&addresses);
+        return Ok(vec![Step::run("networksetup", &args)]);
+    }
+
+    if uses_systemd_resolved() {
+        let route = output("ip", &["route", "show", "default"]).unwrap_or_default();
+        let interface = route.split_whitespace().skip_while(|&word| word != "dev").nth(1)
+            .ok_or("cannot find the interface of the default route")?;
+        let mut dns = vec!["dns", interface];
+        dns.extend(&addresses);
+        // `~.` routes every domain to this interface's servers.
+        return Ok(vec![
+            Step::run("resolvectl", &dns),
+            Step::run("resolvectl", &["domain", interface, "~."]),
+        ]);
+    }
+    if let Some(connection) = active_connection() {
+        let (v4, v6): (Vec<&str>, Vec<&str>) = addresses.iter().partition(|ip| !ip.contains(':'));
+        let mut modify = vec!["connection".to_string(), "modify".to_string(), connection.clone()];
+        for (family, servers) in [("ipv4", v4), ("ipv6", v6)] {
+            if !servers.is_empty() {
+                modify.extend([format!("{}.dns", family), servers.join(" ")]);
+                modify.extend([format!("{}.ignore-auto-dns", family), "yes".to_string()]);
+            }
+        }
+        return Ok(vec![
+            Step::Run(std::iter::once("nmcli".to_string()).chain(modify).collect()),
+            Step::run("nmcli", &["connection", "up", &connection]),
+        ]);
+    }
+    Ok(vec![Step::Write { path: RESOLV_CONF, contents: ConfigFormat::ResolvConf.snippet(result) }])
+}
+
+/// Whether /etc/resolv.conf points at systemd-resolved, in which case writing it would be undone
+/// and resolvectl is the way to change the servers.
+fn uses_systemd_resolved() -> bool {
+    let linked = std::fs::read_link(RESOLV_CONF).is_ok_and(|target| target.to_string_lossy().contains("systemd/resolve"));
+    let stub = std::fs::read_to_string(RESOLV_CONF)
+        .is_ok_and(|text| text.lines().any(|line| line.split_whitespace().eq(["nameserver", dns_speed_test::SYSTEMD_RESOLVED_STUB])));
+    (linked || stub) && output("resolvectl", &["--version"]).is_some()
+}
+
+/// The NetworkManager connection in use, if NetworkManager manages the network.
+fn active_connection() -> Option<String> {
+    if !Path::new("/run/NetworkManager").exists() {
+        return None;
+    }
+    let connections = output("nmcli", &["-t", "-f", "NAME,DEVICE", "connection", "show", "--active"])?;
+    connections.lines()
+        .filter_map(|line| line.rsplit_once(':'))
+        .find(|&(_, device)| device != "lo")
+        .map(|(name, _)| name.replace("\\:", ":"))
+}
+
+/// Configures `best` as the system resolver, or with `dry_run` only shows how. Asks before
+/// changing anything unless `yes`, and refuses without the needed privileges, listing the
+/// commands to run instead.
+pub fn apply(out: &mut dyn Write, best: &TestResult, dry_run: bool, yes: bool) -> Result<(), String> {
+    if best.success_rate < MIN_SUCCESS_RATE {
+        return Err(format!(
+            "not applying {}: its success rate of {:.1}% is below the {}% needed",
+            best.provider, best.success_rate, MIN_SUCCESS_RATE
+        ));
+    }
+    if best.system_resolver {
+        writeln!(out, "\n{} is already a system resolver; nothing to change.", best.provider).ok();
+        return Ok(());
+    }
+
+    let steps = plan(best)?;
+    writeln!(out, "\nTo make {} ({}) the system resolver:", best.provider, best.addresses.join(", ")).ok();
+    for step in &steps {
+        writeln!(out, "    {}", step.command_line(false)).ok();
+    }
+    if dry_run {
+        writeln!(out, "Dry run: nothing was changed.").ok();
+        return Ok(());
+    }
+    if !is_privileged() {
+        let how = if cfg!(windows) { "from an elevated PowerShell" } else { "as root" };
+        let commands: Vec<String> = steps.iter()
+            .map(|step| format!("    {}", step.command_line(!cfg!(windows))))
+            .collect();
+        return Err(format!("changing the resolver needs administrator rights; run {}:\n{}", how, commands.join("\n")));
+    }
+    if !yes {
+        if !io::stdin().is_terminal() {
+            return Err("not applying without confirmation; pass --yes to apply non-interactively".to_string());
+        }
+        write!(out, "Apply these changes? [y/N] ").ok();
+        out.flush().ok();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).ok();
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            writeln!(out, "Nothing was changed.").ok();
+            return Ok(());
+        }
+    }
+    for step in &steps {
+        step.execute()?;
+    }
+    writeln!(out, "{} is now the system resolver.", best.provider).ok();
+    if steps.iter().any(|step| matches!(step, Step::Run(argv) if argv[0] == "resolvectl")) {
+        writeln!(out, "resolvectl settings last until the interface is reconfigured or the machine restarts.").ok();
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Earlier runs saved with --save-baseline and read back by --compare and `diff`. A baseline is
+//! a JSON report; only the fields needed for the comparison are read, so reports with more fields
+//! load too.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+use dns_speed_test::{SpeedTestConfig, TestResult};
+use crate::report::SCHEMA_VERSION;
+
+/// Read first, so a newer layout is reported as such rather than as a parse error.
+#[derive(Deserialize)]
+struct Version {
+    /// Reports written before the field existed have version 1's layout
+    #[serde(default = "first_version")]
+    schema_version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Baseline {
+    timestamp: u64,
+    config: BaselineConfig,
+    results: Vec<BaselineResult>,
+    #[serde(default)]
+    excluded: Vec<BaselineResult>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselineConfig {
+    rounds: u32,
+    protocol: String,
+    #[serde(default)]
+    domains: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BaselineResult {
+    pub provider: String,
+    /// Null in reports since providers without answers got no median; older ones have the timeout
+    pub median_ms: Option<f64>,
+    pub success_rate: f64,
+}
+
+impl BaselineResult {
+    /// The median in milliseconds, `None` if the run got no answers.
+    pub fn answered_median(&self) -> Option<f64> {
+        self.median_ms.filter(|&median| self.success_rate > 0.0 && median > 0.0)
+    }
+}
+
+impl BaselineConfig {
+    fn differences(&self, rounds: u32, protocol: &str, domains: &[String]) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.rounds != rounds {
+            differences.push(format!("{} rounds instead of {}", self.rounds, rounds));
+        }
+        if self.protocol != protocol {
+            differences.push(format!("{} instead of {}", self.protocol, protocol));
+        }
+        // Older reports did not list their domains.
+        if !self.domains.is_empty() && !domains.is_empty() && self.domains != domains {
+            differences.push(format!("{} different domains", self.domains.len()));
+        }
+        differences
+    }
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+pub fn load(path: &Path) -> Result<Baseline, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let invalid = |e: serde_json::Error| format!("{} is not a saved JSON report: {}", path.display(), e);
+    let version: Version = serde_json::from_str(&text).map_err(invalid)?;
+    if version.schema_version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} uses report format {}, but this version reads up to {}",
+            path.display(), version.schema_version, SCHEMA_VERSION
+        ));
+    }
+    let mut baseline: Baseline = serde_json::from_str(&text).map_err(invalid)?;
+    baseline.path = path.to_path_buf();
+    Ok(baseline)
+}
+
+impl Baseline {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.timestamp)
+    }
+
+    /// How the baseline run was set up differently from this one, e.g. "3 rounds instead of 5".
+    pub fn differences(&self, cfg: &SpeedTestConfig) -> Vec<String> {
+        self.config.differences(cfg.rounds, cfg.protocol_label(), &cfg.domains)
+    }
+
+    /// How this run was set up differently from the `newer` one, in the words of `differences`.
+    pub fn differences_from(&self, newer: &Baseline) -> Vec<String> {
+        self.config.differences(newer.config.rounds, &newer.config.protocol, &newer.config.domains)
+    }
+
+    /// Every provider of the run, excluded ones included.
+    pub fn results(&self) -> impl Iterator<Item = &BaselineResult> {
+        self.results.iter().chain(&self.excluded)
+    }
+
+    pub fn find(&self, provider: &str) -> Option<&BaselineResult> {
+        self.results().find(|result| result.provider == provider)
+    }
+
+    /// Change of the provider's median since the baseline as a fraction of the baseline median,
+    /// with the baseline median. `None` if the provider is new or either run got no answers.
+    pub fn change(&self, result: &TestResult) -> Option<(Duration, f64)> {
+        let before = self.find(&result.provider)?.answered_median()?;
+        let now = result.median_duration.filter(|_| result.success_rate > 0.0)?.as_secs_f64() * 1000.0;
+        Some((Duration::from_secs_f64(before / 1000.0), (now - before) / before))
+    }
+
+    /// Providers of this run that the baseline does not have.
+    pub fn new_providers<'a>(&self, providers: &[&'a str]) -> Vec<&'a str> {
+        providers.iter().copied().filter(|provider| self.find(provider).is_none()).collect()
+    }
+
+    /// Providers of the baseline that this run does not have.
+    pub fn missing_providers(&self, providers: &[&str]) -> Vec<&str> {
+        self.results()
+            .map(|before| before.provider.as_str())
+            .filter(|provider| !providers.contains(provider))
+            .collect()
+    }
+}
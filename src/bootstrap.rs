@@ -0,0 +1,44 @@
+//! Hostnames to addresses for the setup phase, such as --server entries given by hostname: the
+//! only lookups the tool makes through the system resolver, and never while it measures. Tests
+//! swap the system resolver for a stub to hold the measurement to that.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+
+/// What a system lookup of a hostname gives: its addresses, or why there are none.
+pub type SystemResolver = fn(&str) -> Result<Vec<IpAddr>, String>;
+
+static SYSTEM_RESOLVER: OnceLock<SystemResolver> = OnceLock::new();
+
+/// Sends every later system lookup of this process to `resolver` instead; only the first call
+/// has an effect.
+pub(crate) fn set_system_resolver(resolver: SystemResolver) {
+    let _ = SYSTEM_RESOLVER.set(resolver);
+}
+
+/// All addresses of `host`, without duplicates, in the order they were returned: from the
+/// system resolver, or from the DNS server at `bootstrap` if given.
+pub(crate) async fn lookup_host(host: &str, bootstrap: Option<IpAddr>) -> Result<Vec<IpAddr>, String> {
+    let mut addresses: Vec<IpAddr> = match (bootstrap, SYSTEM_RESOLVER.get()) {
+        (None, Some(resolver)) => resolver(host)?,
+        (None, None) => tokio::net::lookup_host((host, 0)).await
+            .map_err(|e| e.to_string())?
+            .map(|address| address.ip())
+            .collect(),
+        (Some(server), _) => {
+            let servers = NameServerConfigGroup::from_ips_clear(&[server], 53, true);
+            let mut opts = ResolverOpts::default();
+            opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+            TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], servers), opts)
+                .lookup_ip(host).await
+                .map_err(|e| e.to_string())?
+                .iter()
+                .collect()
+        },
+    };
+    let mut seen = Vec::new();
+    addresses.retain(|ip| if seen.contains(ip) { false } else { seen.push(*ip); true });
+    Ok(addresses)
+}
@@ -0,0 +1,64 @@
+//! --check: whether the providers and the network are fit for a run, before a long one or when
+//! everything fails. Each address gets the pre-flight check's probes once, one query and TCP
+//! connects, without the rounds.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use futures::future::join_all;
+use hickory_resolver::proto::rr::{DNSClass, RecordType};
+use hickory_resolver::Name;
+use crate::measure::measure_latency;
+use crate::providers::{DnsProvider, Transport};
+use crate::raw;
+
+/// Asked for a route when no tested provider has an IPv6 address: Google Public DNS.
+const IPV6_ROUTE_PROBE: Ipv6Addr = Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888);
+
+/// One address of a provider as --check found it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressCheck {
+    pub address: IpAddr,
+    /// How long one UDP query for the first test domain took to get a response, whatever its
+    /// response code; `None` without one within the timeout
+    pub latency: Option<Duration>,
+    /// Whether port 853 accepted a TCP connection; `None` without a DoT endpoint
+    pub dot: Option<bool>,
+    /// Whether port 443 did; `None` without a DoH endpoint
+    pub doh: Option<bool>,
+}
+
+/// Checks every address of `provider` at once, each probe within `timeout`.
+pub(crate) async fn check_provider(provider: &DnsProvider, domain: &str, bind: Option<IpAddr>, timeout: Duration) -> Vec<AddressCheck> {
+    let name = Name::from_ascii(format!("{}.", domain.trim_end_matches('.'))).unwrap_or_else(|_| Name::root());
+    let message = raw::message(name, RecordType::A, DNSClass::IN);
+    let connect = |ip: IpAddr, transport: Transport| async move {
+        if !provider.supports(transport) {
+            return None;
+        }
+        Some(measure_latency(SocketAddr::new(ip, transport.port()), bind, timeout).await.is_some())
+    };
+    let checks = provider.addresses.iter().map(|&ip| {
+        let message = &message;
+        async move {
+            let query = async {
+                let start = Instant::now();
+                raw::query(ip, provider.port_for(Transport::Udp), bind, message, timeout).await.map(|_| start.elapsed())
+            };
+            let (latency, dot, doh) = tokio::join!(query, connect(ip, Transport::Tls), connect(ip, Transport::Https));
+            AddressCheck { address: ip, latency, dot, doh }
+        }
+    });
+    join_all(checks).await
+}
+
+/// Whether this machine has a route to the IPv6 internet, from `bind` if given. Connecting a
+/// UDP socket only looks the route up; nothing is sent.
+pub(crate) fn ipv6_route(bind: Option<IpAddr>) -> bool {
+    if bind.is_some_and(|bind| bind.is_ipv4()) {
+        return false;
+    }
+    let local = bind.filter(IpAddr::is_ipv6).unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+    std::net::UdpSocket::bind(SocketAddr::new(local, 0))
+        .and_then(|socket| socket.connect(SocketAddr::new(IpAddr::V6(IPV6_ROUTE_PROBE), 53)))
+        .is_ok()
+}
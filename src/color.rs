@@ -0,0 +1,85 @@
+//! --color: ANSI colors for the table report. Only the table uses them; JSON, CSV and progress
+//! lines are always plain.
+
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+use clap::ValueEnum;
+
+/// Medians below this are green.
+const FAST_MEDIAN: Duration = Duration::from_millis(20);
+/// Medians from `FAST_MEDIAN` up to this are yellow, slower ones red.
+const SLOW_MEDIAN: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// When the report goes to a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Green,
+    Yellow,
+    Red,
+    /// Bold green, for the recommended provider
+    Best,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Red => "31",
+            Color::Best => "1;32",
+        }
+    }
+
+    /// Green, yellow or red for a median, red for a provider that never answered and has none.
+    pub fn for_median(median: Option<Duration>) -> Color {
+        let Some(median) = median else { return Color::Red };
+        if median >= SLOW_MEDIAN {
+            Color::Red
+        } else if median >= FAST_MEDIAN {
+            Color::Yellow
+        } else {
+            Color::Green
+        }
+    }
+}
+
+/// Whether colors are written, decided once per report.
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub const PLAIN: Palette = Palette { enabled: false };
+
+    /// `Auto` colors only a report on stdout that is a terminal, and respects `NO_COLOR`
+    /// (<https://no-color.org>); `Always` overrides both.
+    pub fn new(choice: ColorChoice, to_stdout: bool) -> Palette {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                to_stdout
+                    && io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            },
+        };
+        Palette { enabled }
+    }
+
+    /// Wraps `text` in the color's escape codes. Pad the text to its column width first: the
+    /// codes take no room on screen, but `format!` widths would count them.
+    pub fn paint(self, text: String, color: Option<Color>) -> String {
+        match color {
+            Some(color) if self.enabled => format!("\x1b[{}m{}\x1b[0m", color.code(), text),
+            _ => text,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! The optional TOML config file and the DNSSPEEDTEST_* environment variables. The file's values
+//! sit between the built-in defaults and the environment, which sits below the command line: a
+//! flag given explicitly wins over its variable, and either wins over the file.
+//!
+//! Every variable is named after its flag, e.g. DNSSPEEDTEST_ROUNDS for --rounds, and takes what
+//! the flag does; clap reads all but DNSSPEEDTEST_DOMAINS, which stands in for the file's
+//! `domains` key. Lists are comma-separated.
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use dns_speed_test::{describe_toml_error, DnsProvider};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use crate::{parse_address, parse_timeout, with_port, Args, OutputFormat, Protocol, MAX_COOLDOWN_MS};
+
+/// Replaces the domain list like the file's `domains` key, e.g. "google.com,github.com".
+const DOMAINS_VAR: &str = "DNSSPEEDTEST_DOMAINS";
+
+/// Printed by --print-default-config.
+pub const DEFAULT_CONFIG: &str = r#"# dnsspeedtest configuration.
+# Save as ~/.config/dnsspeedtest/config.toml or pass --config PATH. Every setting is optional,
+# and flags given on the command line or as DNSSPEEDTEST_* variables override the values here.
+
+# Number of times every domain is queried per provider
+# rounds = 5
+
+# Per-query timeout: "3s", "500ms" or a number of seconds (max 60s)
+# timeout = "3s"
+
+# Pause between queries in milliseconds (max 60000)
+# cooldown = 100
+
+# Protocol: "udp", "dot" or "doh"; providers without an endpoint for it are skipped
+# protocol = "udp"
+
+# Final report: "table" or "json"
+# format = "table"
+
+# Domains queried in every round, replacing the built-in list
+# domains = ["google.com", "github.com", "wikipedia.org"]
+
+# Extra servers to test, like --server (which replaces this list when given)
+# [[providers]]
+# name = "Router"
+# addresses = ["192.168.1.1"]  # a port other than 53 as "192.168.1.1:5353" or "[::1]:5353"
+# dot_host = "dns.example.net"  # needed for --protocol dot
+# doh_host = "dns.example.net"  # needed for --protocol doh
+# doq_host = "dns.example.net"  # needed for --protocol doq
+"#;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default, deserialize_with = "rounds")]
+    rounds: Option<u32>,
+    #[serde(default, deserialize_with = "timeout")]
+    timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "cooldown")]
+    cooldown: Option<u64>,
+    #[serde(default, deserialize_with = "value_enum")]
+    protocol: Option<Protocol>,
+    #[serde(default, deserialize_with = "value_enum")]
+    format: Option<OutputFormat>,
+    #[serde(default, deserialize_with = "domains")]
+    domains: Option<Vec<String>>,
+    #[serde(default)]
+    providers: Vec<ProviderConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProviderConfig {
+    name: String,
+    #[serde(deserialize_with = "addresses")]
+    addresses: Addresses,
+    doh_host: Option<String>,
+    dot_host: Option<String>,
+    doq_host: Option<String>,
+}
+
+fn rounds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match u32::deserialize(deserializer)? {
+        0 => Err(D::Error::custom("rounds must be at least 1")),
+        rounds => Ok(Some(rounds)),
+    }
+}
+
+fn timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let text = match toml::Value::deserialize(deserializer)? {
+        toml::Value::String(text) => text,
+        toml::Value::Integer(seconds) => seconds.to_string(),
+        toml::Value::Float(seconds) => seconds.to_string(),
+        _ => return Err(D::Error::custom("expected a duration such as \"3s\" or a number of seconds")),
+    };
+    parse_timeout(&text).map(Some).map_err(D::Error::custom)
+}
+
+fn cooldown<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    match u64::deserialize(deserializer)? {
+        ms if ms > MAX_COOLDOWN_MS => Err(D::Error::custom(format!("cooldown must be at most {} ms", MAX_COOLDOWN_MS))),
+        ms => Ok(Some(ms)),
+    }
+}
+
+/// A clap value name such as "dot", so the file accepts exactly what the flags do.
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(deserializer: D) -> Result<Option<T>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    T::from_str(&name, true).map(Some).map_err(|_| {
+        let valid: Vec<String> = T::value_variants().iter()
+            .filter_map(|variant| variant.to_possible_value())
+            .map(|value| format!("\"{}\"", value.get_name()))
+            .collect();
+        D::Error::custom(format!("\"{}\" is not one of {}", name, valid.join(", ")))
+    })
+}
+
+fn domains<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    let domains = Vec::<String>::deserialize(deserializer)?;
+    if domains.is_empty() {
+        return Err(D::Error::custom("at least one domain is needed"));
+    }
+    Ok(Some(domains))
+}
+
+/// A provider's addresses and their shared port, `None` for 53.
+#[derive(Debug)]
+struct Addresses {
+    ips: Vec<IpAddr>,
+    port: Option<u16>,
+}
+
+fn addresses<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Addresses, D::Error> {
+    let addresses = Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|address| parse_address(address.trim()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(D::Error::custom)?;
+    let Some(&(_, port)) = addresses.first() else {
+        return Err(D::Error::custom("at least one address is needed"));
+    };
+    if addresses.iter().any(|&(_, other)| other != port) {
+        return Err(D::Error::custom("all addresses of a provider need the same port"));
+    }
+    Ok(Addresses { ips: addresses.into_iter().map(|(ip, _)| ip).collect(), port })
+}
+
+/// Whether the flag `id` was given on the command line or through its environment variable,
+/// rather than left at its default.
+pub fn explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), Some(ValueSource::CommandLine | ValueSource::EnvVariable))
+}
+
+/// Applies DNSSPEEDTEST_DOMAINS, the one variable without a flag for clap to read it for; it
+/// wins over the file's `domains`.
+pub fn apply_env(args: &mut Args) -> Result<(), String> {
+    let Some(value) = std::env::var_os(DOMAINS_VAR) else { return Ok(()) };
+    let value = value.into_string().map_err(|_| format!("{} is not valid UTF-8", DOMAINS_VAR))?;
+    let domains: Vec<String> = value.split(',')
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .map(str::to_string)
+        .collect();
+    if domains.is_empty() {
+        return Err(format!("{} needs at least one domain", DOMAINS_VAR));
+    }
+    args.domains = domains;
+    Ok(())
+}
+
+/// `~/.config/dnsspeedtest/config.toml` (honouring `XDG_CONFIG_HOME`), or
+/// `%APPDATA%\dnsspeedtest\config.toml` on Windows.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("dnsspeedtest").join("config.toml"))
+}
+
+/// Reads and parses the config file. A missing file is only an error when it was asked for
+/// explicitly with --config.
+pub fn load(path: &Path, explicit: bool) -> Result<Option<ConfigFile>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => return Ok(None),
+        Err(e) => return Err(format!("cannot read config file {}: {}", path.display(), e)),
+    };
+    toml::from_str(&text).map(Some).map_err(|error| describe_toml_error(path, &text, &error))
+}
+
+impl ConfigFile {
+    /// Applies the file's settings to `args` wherever neither the command line nor the
+    /// environment set them.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| !explicit(matches, id);
+        if let Some(rounds) = self.rounds.filter(|_| unset("rounds")) {
+            args.rounds = rounds;
+        }
+        if let Some(timeout) = self.timeout.filter(|_| unset("timeout")) {
+            args.timeout = timeout;
+        }
+        if let Some(cooldown) = self.cooldown.filter(|_| unset("cooldown")) {
+            args.cooldown = cooldown;
+        }
+        if let Some(protocol) = self.protocol.filter(|_| unset("protocol")) {
+            args.protocol = protocol;
+        }
+        // --json counts as setting the format.
+        if let Some(format) = self.format.filter(|_| unset("format") && unset("json")) {
+            args.format = format;
+        }
+        if let Some(domains) = self.domains {
+            args.domains = domains;
+        }
+        if !self.providers.is_empty() && unset("servers") {
+            args.servers = self.providers.into_iter()
+                .map(|provider| DnsProvider {
+                    name: with_port(provider.name, provider.addresses.port),
+                    organization: None,
+                    addresses: provider.addresses.ips,
+                    doh_host: provider.doh_host,
+                    doh_path: None,
+                    dot_host: provider.dot_host,
+                    doq_host: provider.doq_host,
+                    doq_port: None,
+                    system: false,
+                    port: provider.addresses.port,
+                    timeout: None,
+                    attempts: None,
+                    cooldown: None,
+                })
+                .collect();
+        }
+    }
+}
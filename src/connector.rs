@@ -0,0 +1,270 @@
+//! The connections behind every resolver. Two kinds are made here rather than by hickory:
+//! DoH to an endpoint with a path of its own, such as a NextDNS profile URL, as hickory only
+//! ever queries `/dns-query` (these go over HTTP/2 like hickory's); and, when asked for, UDP
+//! whose truncated responses are retried over TCP and recorded, which hickory would otherwise
+//! do out of sight or not at all, and that can advertise another EDNS buffer size. DoQ
+//! connections are hickory's but get the query timeout for their handshake, which hickory
+//! would otherwise wait on until QUIC gives up. Everything else is left to hickory.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::name_server::{ConnectionProvider, GenericConnection, TokioConnectionProvider, TokioRuntimeProvider};
+use hickory_resolver::proto::op::Message;
+use hickory_resolver::proto::xfer::{DnsHandle, DnsRequest, DnsResponse, FirstAnswer};
+use hickory_resolver::AsyncResolver;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+const MIME: &str = "application/dns-message";
+
+/// The resolver every measurement uses.
+pub(crate) type Resolver = AsyncResolver<Connector>;
+
+/// A resolver for `config` whose connections `connector` makes.
+pub(crate) fn resolver(config: ResolverConfig, opts: ResolverOpts, connector: Connector) -> Resolver {
+    AsyncResolver::new(config, opts, connector)
+}
+
+/// The time of both legs of a query whose UDP response was truncated.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TruncatedQuery {
+    /// Until the truncated response arrived
+    pub udp: Duration,
+    /// From then until the TCP answer, connection setup included
+    pub tcp: Duration,
+}
+
+/// The queries a resolver retried over TCP, shared by its connections.
+#[derive(Debug, Default)]
+pub(crate) struct Truncations(Mutex<Vec<TruncatedQuery>>);
+
+impl Truncations {
+    /// The queries recorded so far, leaving none.
+    pub(crate) fn take(&self) -> Vec<TruncatedQuery> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Opens DoH connections with a custom path and, with `truncations`, UDP connections that retry
+/// over TCP itself, and hands every other one to hickory.
+#[derive(Clone, Default)]
+pub(crate) struct Connector {
+    path: Option<Arc<str>>,
+    truncations: Option<Arc<Truncations>>,
+    /// UDP payload size for queries with EDNS, `None` for hickory's 1232 bytes
+    edns_buffer: Option<u16>,
+    hickory: TokioConnectionProvider,
+}
+
+impl Connector {
+    /// DoH queries go to `path` instead of `/dns-query`.
+    pub(crate) fn new(path: Option<&str>) -> Connector {
+        Connector { path: path.map(Arc::from), ..Connector::default() }
+    }
+
+    /// Truncated UDP responses are retried over TCP to the same server, like a stub resolver
+    /// does, and both legs recorded in `truncations`. The query's time is that of both.
+    pub(crate) fn retrying_truncated(self, truncations: Arc<Truncations>) -> Connector {
+        Connector { truncations: Some(truncations), ..self }
+    }
+
+    /// Queries with EDNS over those UDP connections advertise `size` bytes instead of 1232.
+    pub(crate) fn advertising(self, size: u16) -> Connector {
+        Connector { edns_buffer: Some(size), ..self }
+    }
+}
+
+impl ConnectionProvider for Connector {
+    type Conn = Connection;
+    type FutureConn = BoxFuture<'static, Result<Connection, ResolveError>>;
+    type RuntimeProvider = TokioRuntimeProvider;
+
+    fn new_connection(&self, config: &NameServerConfig, options: &ResolverOpts) -> Self::FutureConn {
+        match (&self.path, &self.truncations) {
+            (Some(path), _) if config.protocol == Protocol::Https => {
+                let (config, path, timeout) = (config.clone(), path.clone(), options.timeout);
+                async move {
+                    tokio::time::timeout(timeout, connect(config, path, timeout)).await
+                        .unwrap_or_else(|_| Err(ResolveErrorKind::Timeout.into()))
+                }.boxed()
+            },
+            (_, Some(truncations)) if config.protocol == Protocol::Udp => {
+                let mut tcp = config.clone();
+                tcp.protocol = Protocol::Tcp;
+                let fallback = TcpFallback {
+                    config: tcp,
+                    options: options.clone(),
+                    hickory: self.hickory.clone(),
+                    truncations: truncations.clone(),
+                    edns_buffer: self.edns_buffer,
+                };
+                self.hickory.new_connection(config, options)
+                    .map(|conn| conn.map(|udp| Connection::Udp(udp, fallback)))
+                    .boxed()
+            },
+            _ if config.protocol == Protocol::Quic => {
+                let connect = self.hickory.new_connection(config, options);
+                tokio::time::timeout(options.timeout, connect)
+                    .map(|conn| conn.unwrap_or_else(|_| Err(ResolveErrorKind::Timeout.into())).map(Connection::Hickory))
+                    .boxed()
+            },
+            _ => self.hickory.new_connection(config, options).map(|conn| conn.map(Connection::Hickory)).boxed(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum Connection {
+    Hickory(GenericConnection),
+    Path(PathConnection),
+    Udp(GenericConnection, TcpFallback),
+}
+
+impl DnsHandle for Connection {
+    type Response = BoxStream<'static, Result<DnsResponse, ResolveError>>;
+    type Error = ResolveError;
+
+    fn send<R: Into<DnsRequest> + Unpin + Send + 'static>(&self, request: R) -> Self::Response {
+        match self {
+            Connection::Hickory(conn) => conn.send(request).boxed(),
+            Connection::Path(conn) => conn.clone().query(request.into()).into_stream().boxed(),
+            Connection::Udp(conn, fallback) => fallback.clone().query(conn.clone(), request.into()).into_stream().boxed(),
+        }
+    }
+}
+
+/// Where a UDP connection retries a truncated response.
+#[derive(Clone)]
+pub(crate) struct TcpFallback {
+    config: NameServerConfig,
+    options: ResolverOpts,
+    hickory: TokioConnectionProvider,
+    truncations: Arc<Truncations>,
+    edns_buffer: Option<u16>,
+}
+
+impl TcpFallback {
+    /// Sends `request` over `udp`, and once more over a new TCP connection when the answer is
+    /// truncated. A failed retry is recorded too: the client waited for it all the same.
+    async fn query(self, udp: GenericConnection, mut request: DnsRequest) -> Result<DnsResponse, ResolveError> {
+        if let (Some(size), Some(edns)) = (self.edns_buffer, request.extensions_mut()) {
+            edns.set_max_payload(size);
+        }
+        let start = Instant::now();
+        let response = udp.send(request.clone()).first_answer().await?;
+        if !response.truncated() {
+            return Ok(response);
+        }
+        let truncated_at = start.elapsed();
+        let retried = async {
+            let tcp = self.hickory.new_connection(&self.config, &self.options).await?;
+            tcp.send(request).first_answer().await
+        };
+        let retried = tokio::time::timeout(self.options.timeout, retried).await
+            .unwrap_or_else(|_| Err(ResolveErrorKind::Timeout.into()));
+        self.truncations.0.lock().unwrap().push(TruncatedQuery { udp: truncated_at, tcp: start.elapsed() - truncated_at });
+        tracing::debug!(server = %self.config.socket_addr, "truncated UDP response, retried over TCP");
+        retried
+    }
+}
+
+/// An HTTP/2 connection to one endpoint, shared by the queries in flight on it.
+#[derive(Clone)]
+pub(crate) struct PathConnection {
+    sender: h2::client::SendRequest<Bytes>,
+    uri: http::Uri,
+    timeout: Duration,
+}
+
+/// Trusts the web's root certificates and offers `alpn`, e.g. `h2` for DoH.
+pub(crate) fn tls_config(alpn: Option<&[u8]>) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+    }));
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.into_iter().map(<[u8]>::to_vec).collect();
+    config
+}
+
+fn error(what: &str, error: impl std::fmt::Display) -> ResolveError {
+    format!("DoH {}: {}", what, error).into()
+}
+
+/// TCP from the configured bind address, TLS for the configured name, then the HTTP/2
+/// handshake; the connection is driven in the background until it is dropped.
+async fn connect(config: NameServerConfig, path: Arc<str>, timeout: Duration) -> Result<Connection, ResolveError> {
+    let host = config.tls_dns_name.clone().ok_or("DoH needs the endpoint's hostname")?;
+    let server: SocketAddr = config.socket_addr;
+    let tcp = match config.bind_addr {
+        Some(bind) => {
+            let socket = if server.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+            socket.bind(bind)?;
+            socket.connect(server).await?
+        },
+        None => TcpStream::connect(server).await?,
+    };
+    let uri = format!("https://{}{}", host, path).parse::<http::Uri>().map_err(|e| error("URL", e))?;
+    let name = ServerName::try_from(host.as_str()).map_err(|e| error("hostname", e))?;
+    let tls = TlsConnector::from(Arc::new(tls_config(Some(b"h2")))).connect(name, tcp).await?;
+    let (sender, connection) = h2::client::handshake(tls).await.map_err(|e| error("handshake", e))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!(%host, error = %e, "DoH connection closed");
+        }
+    });
+    Ok(Connection::Path(PathConnection { sender, uri, timeout }))
+}
+
+impl PathConnection {
+    /// Queries `uri` over an HTTP/2 connection whose handshake is done.
+    pub(crate) fn new(sender: h2::client::SendRequest<Bytes>, uri: http::Uri, timeout: Duration) -> PathConnection {
+        PathConnection { sender, uri, timeout }
+    }
+
+    /// POSTs the query (RFC 8484) and reads the answer, all within the timeout.
+    pub(crate) async fn query(self, request: DnsRequest) -> Result<DnsResponse, ResolveError> {
+        let (mut message, _) = request.into_parts();
+        let id = message.id();
+        // The ID is 0 so that identical queries are identical HTTP requests, as RFC 8484 asks.
+        message.set_id(0);
+        let body = Bytes::from(message.to_vec()?);
+        let exchange = async {
+            let mut sender = self.sender.clone().ready().await.map_err(|e| error("connection", e))?;
+            let request = http::Request::post(self.uri.clone())
+                .header(http::header::CONTENT_TYPE, MIME)
+                .header(http::header::ACCEPT, MIME)
+                .body(())
+                .map_err(|e| error("request", e))?;
+            let (response, mut stream) = sender.send_request(request, false).map_err(|e| error("request", e))?;
+            stream.send_data(body, true).map_err(|e| error("request", e))?;
+            let response = response.await.map_err(|e| error("response", e))?;
+            if !response.status().is_success() {
+                return Err(format!("DoH endpoint answered HTTP {}", response.status()).into());
+            }
+            let mut body = response.into_body();
+            let mut bytes = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.map_err(|e| error("response", e))?;
+                let _ = body.flow_control().release_capacity(chunk.len());
+                bytes.extend_from_slice(&chunk);
+            }
+            Ok::<_, ResolveError>(bytes)
+        };
+        let bytes = tokio::time::timeout(self.timeout, exchange).await
+            .unwrap_or_else(|_| Err(ResolveErrorKind::Timeout.into()))?;
+        let mut response = Message::from_vec(&bytes)?;
+        response.set_id(id);
+        Ok(DnsResponse::new(response, bytes))
+    }
+}
@@ -0,0 +1,170 @@
+//! --daemon and --once: the test as a long-running service, e.g. on a Raspberry Pi for a month.
+//! Every run appends one compact JSON line per provider to the day's file in --log-dir,
+//! `dnsspeedtest-YYYY-MM-DD.jsonl` (UTC), so the files rotate by themselves and nothing of past
+//! runs stays in memory.
+//!
+//! ```json
+//! {"timestamp":"2024-05-01T12:00:00.123Z","provider":"Cloudflare","transport":"UDP","median_ms":11.8,"avg_ms":12.4,"min_ms":9.9,"max_ms":31.0,"p95_ms":20.2,"success_rate":100.0}
+//! ```
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use dns_speed_test::{run_all, SpeedTestConfig, TestResult};
+use serde::Serialize;
+use tokio::time::{sleep, Instant};
+use crate::notify::Notifier;
+
+const FILE_PREFIX: &str = "dnsspeedtest-";
+const FILE_SUFFIX: &str = ".jsonl";
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where and how long the runs are kept.
+pub struct LogDir<'a> {
+    pub dir: &'a Path,
+    /// Files of days further back than this are deleted after every run; `None` keeps them all
+    pub retention_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(serialize_with = "crate::samples_file::rfc3339")]
+    timestamp: SystemTime,
+    provider: &'a str,
+    transport: &'a str,
+    /// The latencies are left out for a provider that answered no query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p95_ms: Option<f64>,
+    success_rate: f64,
+    /// Only present, as `true`, for a run cut short by SIGTERM or Ctrl+C
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+}
+
+impl<'a> Line<'a> {
+    fn new(result: &'a TestResult, timestamp: SystemTime, partial: bool) -> Line<'a> {
+        let ms = |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.0);
+        Line {
+            timestamp,
+            provider: &result.provider,
+            transport: &result.transport,
+            median_ms: ms(result.median_duration),
+            avg_ms: ms(result.avg_duration),
+            min_ms: ms(result.min_latency),
+            max_ms: ms(result.max_latency),
+            p95_ms: ms(result.p95),
+            success_rate: result.success_rate,
+            partial,
+        }
+    }
+}
+
+/// "2024-05-01" for any time of that day in UTC.
+fn date(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+fn log_path(dir: &Path, time: SystemTime) -> PathBuf {
+    dir.join(format!("{}{}{}", FILE_PREFIX, date(time), FILE_SUFFIX))
+}
+
+/// Appends the lines of one run to the file of the day it started on and syncs it to disk.
+fn append(dir: &Path, results: &[TestResult], started: SystemTime, partial: bool) -> io::Result<PathBuf> {
+    let path = log_path(dir, started);
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut out = BufWriter::new(&file);
+    for result in results {
+        serde_json::to_writer(&mut out, &Line::new(result, started, partial))?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    drop(out);
+    file.sync_all()?;
+    Ok(path)
+}
+
+/// Deletes the log files of days more than `days` before `now`; other files are left alone.
+fn prune(dir: &Path, days: u32, now: SystemTime) -> io::Result<usize> {
+    let cutoff = date(now - DAY * days);
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else { continue };
+        let Some(day) = name.strip_prefix(FILE_PREFIX).and_then(|rest| rest.strip_suffix(FILE_SUFFIX)) else { continue };
+        // The dates sort as text.
+        if day.len() == 10 && day < cutoff.as_str() {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Resolves on SIGTERM (where there is one) or Ctrl+C.
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = terminate.recv() => {},
+                _ = tokio::signal::ctrl_c() => {},
+            }
+            return;
+        }
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// Runs the test every `interval`, or once with `once`, logging every run to `log` and passing
+/// it to `notifier`. A stop signal aborts the run in progress, whose finished providers are
+/// still logged, and exits with status 0; a file that cannot be written is reported and retried
+/// with the next run.
+pub async fn run(cfg: &SpeedTestConfig, log: LogDir<'_>, interval: Duration, once: bool, mut notifier: Option<Notifier>) -> ! {
+    if let Err(e) = fs::create_dir_all(log.dir) {
+        eprintln!("Failed to create {}: {}", log.dir.display(), e);
+        std::process::exit(crate::EXIT_ERROR);
+    }
+    let cancel = cfg.cancel.clone();
+    tokio::spawn(async move {
+        stop_signal().await;
+        cancel.cancel();
+    });
+
+    loop {
+        let start = Instant::now();
+        let started = SystemTime::now();
+        let results = run_all(cfg).await;
+        let stopped = cfg.cancel.is_cancelled();
+        match append(log.dir, &results, started, stopped) {
+            Ok(path) => eprintln!("[{}] Logged {} providers to {} in {:.1}s",
+                humantime::format_rfc3339_seconds(SystemTime::now()), results.len(), path.display(), start.elapsed().as_secs_f64()),
+            Err(e) => eprintln!("Failed to write the run to {}: {}", log_path(log.dir, started).display(), e),
+        }
+        if let Some(notifier) = notifier.as_mut().filter(|_| !stopped) {
+            notifier.cycle(&results).await;
+        }
+        drop(results);
+        if let Some(days) = log.retention_days {
+            if let Err(e) = prune(log.dir, days, SystemTime::now()) {
+                eprintln!("Failed to delete old logs in {}: {}", log.dir.display(), e);
+            }
+        }
+        if once || stopped {
+            std::process::exit(0);
+        }
+        tokio::select! {
+            _ = sleep(interval.saturating_sub(start.elapsed())) => {},
+            _ = cfg.cancel.cancelled() => std::process::exit(0),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Discovery of Designated Resolvers (RFC 9462): a resolver's plain-DNS address answers SVCB
+//! queries for `_dns.resolver.arpa` with the encrypted endpoints it offers, e.g.
+//!
+//! ```text
+//! _dns.resolver.arpa. 300 IN SVCB 1 dns.google. alpn=dot port=853 ipv4hint=8.8.8.8,8.8.4.4
+//! _dns.resolver.arpa. 300 IN SVCB 2 dns.google. alpn=h2,h3 port=443 key7="/dns-query{?dns}"
+//! ```
+//!
+//! Each ALPN of a record becomes one endpoint; the lowest priority is the resolver's preference.
+
+use std::net::IpAddr;
+use std::time::Duration;
+use hickory_resolver::proto::op::{Message, ResponseCode};
+use hickory_resolver::proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue, SVCB};
+use hickory_resolver::proto::rr::{DNSClass, RData, RecordType};
+use hickory_resolver::Name;
+use serde::Serialize;
+use crate::providers::Transport;
+use crate::raw;
+
+/// The special-use name a resolver answers about itself.
+const DDR_NAME: &str = "_dns.resolver.arpa.";
+/// The `dohpath` SvcParamKey (RFC 9461), which hickory does not know by name.
+const DOHPATH_KEY: u16 = 7;
+pub(crate) const DDR_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An encrypted protocol a designation names, by its ALPN.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DdrProtocol {
+    /// DNS over TLS, ALPN `dot`
+    Dot,
+    /// DNS over HTTPS/2, ALPN `h2`
+    Doh,
+    /// DNS over HTTP/3, ALPN `h3`
+    Doh3,
+    /// DNS over QUIC, ALPN `doq`
+    Doq,
+}
+
+impl DdrProtocol {
+    fn from_alpn(alpn: &str) -> Option<DdrProtocol> {
+        match alpn {
+            "dot" => Some(DdrProtocol::Dot),
+            "h2" => Some(DdrProtocol::Doh),
+            "h3" => Some(DdrProtocol::Doh3),
+            "doq" => Some(DdrProtocol::Doq),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DdrProtocol::Dot => "DoT",
+            DdrProtocol::Doh => "DoH",
+            DdrProtocol::Doh3 => "DoH3",
+            DdrProtocol::Doq => "DoQ",
+        }
+    }
+
+    /// The transport this tool can test it over; `None` for DoH3.
+    pub fn transport(self) -> Option<Transport> {
+        match self {
+            DdrProtocol::Dot => Some(Transport::Tls),
+            DdrProtocol::Doh => Some(Transport::Https),
+            DdrProtocol::Doq => Some(Transport::Quic),
+            DdrProtocol::Doh3 => None,
+        }
+    }
+
+    /// The port used without a `port` parameter.
+    fn default_port(self) -> u16 {
+        match self {
+            DdrProtocol::Dot | DdrProtocol::Doq => 853,
+            DdrProtocol::Doh | DdrProtocol::Doh3 => 443,
+        }
+    }
+}
+
+/// One encrypted endpoint a resolver designates for itself.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DdrEndpoint {
+    pub protocol: DdrProtocol,
+    /// The SVCB priority; lower is preferred
+    pub priority: u16,
+    /// The TLS name to connect to and verify
+    pub host: String,
+    pub port: u16,
+    /// The DoH path from the `dohpath` template, without its `{?dns}` variable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doh_path: Option<String>,
+    /// The `ipv4hint` and `ipv6hint` addresses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<IpAddr>,
+}
+
+impl DdrEndpoint {
+    /// "DoH dns.google:443/dns-query", as in the detailed output.
+    pub fn describe(&self) -> String {
+        format!("{} {}:{}{}", self.protocol.label(), self.host, self.port, self.doh_path.as_deref().unwrap_or_default())
+    }
+}
+
+/// The endpoints of `records`, by priority and then protocol. Alias-mode records (priority 0)
+/// and records without a usable target name or a known ALPN are left out; a DoH endpoint needs
+/// a `dohpath`.
+pub(crate) fn endpoints<'a>(records: impl IntoIterator<Item = &'a SVCB>) -> Vec<DdrEndpoint> {
+    let mut endpoints = Vec::new();
+    for record in records {
+        let target = record.target_name().to_ascii();
+        let host = target.trim_end_matches('.');
+        if record.svc_priority() == 0 || host.is_empty() {
+            continue;
+        }
+        let mut alpns: &[String] = &[];
+        let mut port = None;
+        let mut doh_path = None;
+        let mut addresses = Vec::new();
+        for (key, value) in record.svc_params() {
+            match (key, value) {
+                (_, SvcParamValue::Alpn(alpn)) => alpns = &alpn.0,
+                (_, SvcParamValue::Port(value)) => port = Some(*value),
+                (_, SvcParamValue::Ipv4Hint(hint)) => addresses.extend(hint.0.iter().map(|a| IpAddr::V4(a.0))),
+                (_, SvcParamValue::Ipv6Hint(hint)) => addresses.extend(hint.0.iter().map(|aaaa| IpAddr::V6(aaaa.0))),
+                (SvcParamKey::Unknown(DOHPATH_KEY), SvcParamValue::Unknown(unknown)) => doh_path = dohpath(&unknown.0),
+                _ => {},
+            }
+        }
+        for protocol in alpns.iter().filter_map(|alpn| DdrProtocol::from_alpn(alpn)) {
+            let is_doh = matches!(protocol, DdrProtocol::Doh | DdrProtocol::Doh3);
+            if is_doh && doh_path.is_none() {
+                continue;
+            }
+            endpoints.push(DdrEndpoint {
+                protocol,
+                priority: record.svc_priority(),
+                host: host.to_string(),
+                port: port.unwrap_or(protocol.default_port()),
+                doh_path: doh_path.clone().filter(|_| is_doh),
+                addresses: addresses.clone(),
+            });
+        }
+    }
+    endpoints.sort_by_key(|endpoint| (endpoint.priority, endpoint.protocol));
+    endpoints.dedup();
+    endpoints
+}
+
+/// The path of a `dohpath` URI template such as `/dns-query{?dns}`, which must be relative
+/// and carry the `dns` variable.
+fn dohpath(value: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(value).ok().filter(|text| text.starts_with('/'))?;
+    let (path, variables) = text.split_once('{')?;
+    variables.trim_end_matches('}').trim_start_matches('?').split(',').any(|name| name == "dns").then(|| path.to_string())
+}
+
+/// Asks the resolver at `ip`:`port` for its designations over UDP. `None` if it did not answer
+/// within `timeout`; no endpoints if it answered without any, e.g. NXDOMAIN or NODATA.
+pub(crate) async fn discover(ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> Option<Vec<DdrEndpoint>> {
+    let name = Name::from_ascii(DDR_NAME).ok()?;
+    let response = raw::query(ip, port, bind, &raw::message(name, RecordType::SVCB, DNSClass::IN), timeout).await?;
+    Some(designations(&response))
+}
+
+/// The endpoints of a wire-format `response` to a DDR query, `None` if it does not parse.
+pub(crate) fn parse(response: &[u8]) -> Option<Vec<DdrEndpoint>> {
+    Message::from_vec(response).ok().map(|response| designations(&response))
+}
+
+fn designations(response: &Message) -> Vec<DdrEndpoint> {
+    if response.response_code() != ResponseCode::NoError {
+        return Vec::new();
+    }
+    endpoints(response.answers().iter().filter_map(|record| match record.data() {
+        Some(RData::SVCB(svcb)) => Some(svcb),
+        _ => None,
+    }))
+}
@@ -0,0 +1,122 @@
+//! Providers that answered none of a run's queries, remembered in `state.json` in the cache
+//! directory so later runs skip them instead of waiting out their timeouts again, until
+//! --dead-expiry has passed or --retest-dead asks for them. Entries are per address and
+//! protocol, so a provider dead over UDP is still tested over DoH.
+//!
+//! ```json
+//! {"dead":[{"address":"94.140.14.14","protocol":"UDP","provider":"AdGuard DNS","failed_at":1714521600}]}
+//! ```
+//!
+//! A run reads the file again just before writing it and only changes the entries of the
+//! addresses it tested, so runs side by side keep each other's findings; the file is replaced in
+//! one rename, so a reader never sees half of it.
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use dns_speed_test::TestResult;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    #[serde(default)]
+    dead: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Entry {
+    address: IpAddr,
+    /// The transport label, e.g. "UDP" or "DoH"
+    protocol: String,
+    /// The row's name when it failed, for whoever reads the file
+    provider: String,
+    /// Unix seconds of the last run it failed completely in
+    failed_at: u64,
+}
+
+pub struct DeadProviders {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl DeadProviders {
+    /// Reads the state file. A missing one is empty; an unreadable or corrupt one is too, with a
+    /// warning, and is replaced once a run has something to record.
+    pub fn load() -> DeadProviders {
+        let path = crate::domains::cache_dir().map(|dir| dir.join("state.json"));
+        let entries = match &path {
+            Some(path) => read(path).unwrap_or_else(|message| {
+                eprintln!("Warning: ignoring the dead-provider state: {}", message);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        DeadProviders { path, entries }
+    }
+
+    /// When `address` last failed completely over `protocol`, unless that is more than `expiry` ago.
+    pub fn failed_at(&self, address: IpAddr, protocol: &str, expiry: Duration) -> Option<SystemTime> {
+        let now = SystemTime::now();
+        self.entries.iter()
+            .find(|entry| entry.address == address && entry.protocol == protocol)
+            .map(|entry| UNIX_EPOCH + Duration::from_secs(entry.failed_at))
+            .filter(|&failed| now.duration_since(failed).unwrap_or_default() <= expiry)
+    }
+
+    /// Remembers the addresses of the rows that answered nothing and forgets those of the rows
+    /// that answered something. Rows a time budget cut short say nothing either way. Failing to
+    /// write the file is a warning.
+    pub fn record(&mut self, results: &[&TestResult]) {
+        let Some(path) = &self.path else { return };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        // Whatever other runs wrote in the meantime.
+        let mut entries = read(path).unwrap_or_default();
+        let mut changed = false;
+        for result in results.iter().filter(|result| !result.budget_exceeded) {
+            let dead = result.success_rate == 0.0;
+            // Only the first address was tested when the provider has several; the rest are its
+            // fallbacks, which a dead row drags down with it.
+            let addresses = if dead { &result.addresses[..] } else { &result.addresses[..result.addresses.len().min(1)] };
+            for address in addresses.iter().filter_map(|address| address.parse::<IpAddr>().ok()) {
+                let same = |entry: &Entry| entry.address == address && entry.protocol == result.transport;
+                let count = entries.len();
+                entries.retain(|entry| !same(entry));
+                changed |= entries.len() != count;
+                if dead {
+                    entries.push(Entry { address, protocol: result.transport.clone(), provider: result.provider.clone(), failed_at: now });
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            if let Err(e) = write(path, &entries) {
+                eprintln!("Warning: cannot save the dead-provider state to {}: {}", path.display(), e);
+            }
+        }
+        self.entries = entries;
+    }
+}
+
+/// The entries of the state file at `path`; none if there is no file.
+fn read(path: &Path) -> Result<Vec<Entry>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    let state: State = serde_json::from_str(&text).map_err(|e| format!("{} is not a state file: {}", path.display(), e))?;
+    Ok(state.dead)
+}
+
+/// Replaces the file at `path` in one rename, through a file of this process's own.
+fn write(path: &Path, entries: &[Entry]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension(format!("json.{}.partial", std::process::id()));
+    let state = State { dead: entries.to_vec() };
+    std::fs::write(&partial, serde_json::to_string(&state)?)?;
+    std::fs::rename(&partial, path).inspect_err(|_| {
+        std::fs::remove_file(&partial).ok();
+    })
+}
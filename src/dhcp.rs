@@ -0,0 +1,170 @@
+//! --include-dhcp: the DNS servers the network hands out over DHCP, which can differ from the
+//! ones the machine uses after a VPN or a manual setting replaced them. Each platform keeps them
+//! somewhere else: systemd-networkd's lease files or dhclient's on Linux, `ipconfig getoption`
+//! on macOS, `ipconfig /all` on Windows.
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Where systemd-networkd keeps one lease file per interface.
+const NETWORKD_LEASES: &str = "/run/systemd/netif/leases";
+/// Where the ISC dhclient keeps its lease files, depending on the distribution.
+const DHCLIENT_LEASES: &[&str] = &["/var/lib/dhcp", "/var/lib/dhclient"];
+/// The interfaces asked on macOS: the built-in Ethernet or Wi-Fi and the next one.
+const MACOS_INTERFACES: &[&str] = &["en0", "en1"];
+
+/// The DNS servers of the current DHCP leases, in the order found, without duplicates. An
+/// error says why none could be read; none were handed out if the list is empty.
+pub(crate) fn discover() -> Result<Vec<IpAddr>, String> {
+    discover_with(output, |dir| read_dir(Path::new(dir)))
+}
+
+/// `discover` with the command runner and directory reader passed in; `read` lists the files of
+/// a directory with their text.
+pub(crate) fn discover_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<Vec<String>>) -> Result<Vec<IpAddr>, String> {
+    if cfg!(target_os = "linux") {
+        discover_linux(read)
+    } else if cfg!(target_os = "macos") {
+        let mut servers = Vec::new();
+        for interface in MACOS_INTERFACES {
+            if let Some(output) = run(&["ipconfig", "getoption", interface, "domain_name_server"]) {
+                add(&mut servers, parse_getoption(&output));
+            }
+        }
+        Ok(servers)
+    } else if cfg!(windows) {
+        run(&["ipconfig", "/all"]).map(|output| parse_ipconfig_all(&output)).ok_or_else(|| "ipconfig /all did not run".to_string())
+    } else {
+        Err("DHCP leases cannot be read on this platform".to_string())
+    }
+}
+
+/// The servers of systemd-networkd's leases, or of dhclient's if there are none; `read` lists
+/// the files of a directory with their text.
+fn discover_linux(read: impl Fn(&str) -> Option<Vec<String>>) -> Result<Vec<IpAddr>, String> {
+    if let Some(leases) = read(NETWORKD_LEASES).filter(|leases| !leases.is_empty()) {
+        let mut servers = Vec::new();
+        for lease in &leases {
+            add(&mut servers, parse_networkd_lease(lease));
+        }
+        return Ok(servers);
+    }
+    let mut found = false;
+    let mut servers = Vec::new();
+    for dir in DHCLIENT_LEASES {
+        for lease in read(dir).unwrap_or_default() {
+            found = true;
+            add(&mut servers, parse_dhclient_leases(&lease));
+        }
+    }
+    if found {
+        Ok(servers)
+    } else {
+        Err(format!("no lease files in {} or {}", NETWORKD_LEASES, DHCLIENT_LEASES.join(" or ")))
+    }
+}
+
+/// The text of every file in `dir`, `None` if it cannot be listed.
+fn read_dir(dir: &Path) -> Option<Vec<String>> {
+    let mut texts = Vec::new();
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        if let Ok(text) = std::fs::read_to_string(entry.path()) {
+            texts.push(text);
+        }
+    }
+    Some(texts)
+}
+
+/// What `argv` wrote to stdout, if it ran and succeeded.
+fn output(argv: &[&str]) -> Option<String> {
+    let output = Command::new(argv[0]).args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn add(servers: &mut Vec<IpAddr>, found: Vec<IpAddr>) {
+    for server in found {
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+}
+
+/// The `DNS=` line of a systemd-networkd lease file:
+///
+/// ```text
+/// ADDRESS=192.168.1.23
+/// DNS=192.168.1.1 1.1.1.1
+/// ```
+pub fn parse_networkd_lease(lease: &str) -> Vec<IpAddr> {
+    lease.lines()
+        .filter_map(|line| line.strip_prefix("DNS="))
+        .flat_map(|list| list.split_whitespace().filter_map(|address| address.parse().ok()))
+        .collect()
+}
+
+/// The servers of the last lease in a dhclient lease file, the one renewed most recently:
+///
+/// ```text
+/// lease {
+///   interface "eth0";
+///   option domain-name-servers 10.0.0.2,10.0.0.3;
+/// }
+/// ```
+pub fn parse_dhclient_leases(leases: &str) -> Vec<IpAddr> {
+    leases.lines().rev()
+        .find_map(|line| line.trim().strip_prefix("option domain-name-servers "))
+        .map(|list| list.trim_end_matches(';').split(',').filter_map(|address| address.trim().parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// `ipconfig getoption en0 domain_name_server`, which prints the first server, or nothing for
+/// an interface without a lease.
+pub fn parse_getoption(output: &str) -> Vec<IpAddr> {
+    output.split_whitespace().filter_map(|address| address.parse().ok()).collect()
+}
+
+/// The DNS servers of the adapters with DHCP enabled in `ipconfig /all`, where the first server
+/// follows the label and the others come alone on the next lines:
+///
+/// ```text
+/// Ethernet adapter Ethernet:
+///    DHCP Enabled. . . . . . . . . . . : Yes
+///    DNS Servers . . . . . . . . . . . : 10.0.0.2
+///                                        10.0.0.3
+/// ```
+///
+/// The labels are those of an English Windows.
+pub fn parse_ipconfig_all(output: &str) -> Vec<IpAddr> {
+    let mut servers = Vec::new();
+    let mut dhcp = false;
+    let mut in_list = false;
+    for line in output.lines() {
+        // An adapter's header starts at the left margin.
+        if !line.starts_with(' ') && !line.trim().is_empty() {
+            dhcp = false;
+            in_list = false;
+            continue;
+        }
+        let line = line.trim();
+        match line.split_once(" : ") {
+            Some((label, value)) => {
+                let label = label.trim_end_matches([' ', '.']);
+                if label == "DHCP Enabled" {
+                    dhcp = value.trim() == "Yes";
+                }
+                in_list = label == "DNS Servers";
+                if in_list && dhcp {
+                    add(&mut servers, value.trim().parse().into_iter().collect());
+                }
+            },
+            None if in_list && dhcp => add(&mut servers, line.parse().into_iter().collect()),
+            None => in_list = false,
+        }
+    }
+    servers
+}
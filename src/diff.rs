@@ -0,0 +1,104 @@
+//! The `diff` subcommand: compares two saved JSON reports provider by provider.
+
+use std::io::{self, Write};
+use std::path::Path;
+use crate::baseline::{self, BaselineResult};
+
+/// One provider found in both reports.
+struct Change<'a> {
+    provider: &'a str,
+    old: &'a BaselineResult,
+    new: &'a BaselineResult,
+}
+
+impl Change<'_> {
+    /// Change of the median in milliseconds and in percent; `None` when either run got no answers.
+    fn change(&self) -> Option<(f64, f64)> {
+        let (old, new) = (self.old.answered_median()?, self.new.answered_median()?);
+        Some((new - old, (new - old) / old * 100.0))
+    }
+
+    fn percent(&self) -> Option<f64> {
+        self.change().map(|(_, percent)| percent)
+    }
+}
+
+fn median(result: &BaselineResult) -> String {
+    result.answered_median().map_or("-".to_string(), |median| format!("{:.2}", median))
+}
+
+/// "12.30 ms" for the added/removed list.
+fn summary(result: &BaselineResult) -> String {
+    result.answered_median().map_or("no answers".to_string(), |median| format!("{:.2} ms", median))
+}
+
+/// Prints the comparison of `old_path` and `new_path`, largest regression first. Returns whether
+/// a median got slower by more than `threshold` percent.
+pub fn run(out: &mut dyn Write, old_path: &Path, new_path: &Path, threshold: f64) -> Result<bool, String> {
+    let old = baseline::load(old_path)?;
+    let new = baseline::load(new_path)?;
+
+    let mut changes: Vec<Change> = new.results()
+        .filter_map(|after| Some(Change { provider: &after.provider, old: old.find(&after.provider)?, new: after }))
+        .collect();
+    changes.sort_by(|a, b| match (a.percent(), b.percent()) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.provider.cmp(b.provider),
+    });
+    let regressed = changes.iter().any(|change| change.percent().is_some_and(|percent| percent > threshold));
+
+    let write = |out: &mut dyn Write| -> io::Result<()> {
+        let differences = old.differences_from(&new);
+        if !differences.is_empty() {
+            writeln!(out, "{:!<90}", "")?;
+            writeln!(out, "! Warning: the runs were measured differently: {}", differences.join(", "))?;
+            writeln!(out, "! The changes below are only indicative.")?;
+            writeln!(out, "{:!<90}", "")?;
+        }
+        writeln!(out, "Old: {} ({})", old_path.display(), humantime::format_rfc3339_seconds(old.time()))?;
+        writeln!(out, "New: {} ({})", new_path.display(), humantime::format_rfc3339_seconds(new.time()))?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<30} {:>10} {:>10} {:>10} {:>9} {:>10}", "Provider", "Old (ms)", "New (ms)", "Change", "%", "Success")?;
+        writeln!(out, "{:-<90}", "")?;
+        for change in &changes {
+            let (absolute, percent) = match change.change() {
+                Some((difference, percent)) => (format!("{:+.2}", difference), format!("{:+.0}%", percent)),
+                None => ("n/a".to_string(), String::new()),
+            };
+            let marker = if change.percent().is_some_and(|percent| percent > threshold) { " !" } else { "" };
+            writeln!(out, "{:<30} {:>10} {:>10} {:>10} {:>9} {:>+9.1}%{}",
+                crate::fit(change.provider.to_string(), 30),
+                median(change.old),
+                median(change.new),
+                absolute,
+                percent,
+                change.new.success_rate - change.old.success_rate,
+                marker
+            )?;
+        }
+        if regressed {
+            writeln!(out, "\n! Median more than {}% slower than before", threshold)?;
+        }
+
+        let providers = |report: &baseline::Baseline| -> Vec<String> {
+            report.results().map(|result| result.provider.clone()).collect()
+        };
+        let (old_providers, new_providers) = (providers(&old), providers(&new));
+        let added: Vec<&String> = new_providers.iter().filter(|provider| !old_providers.contains(provider)).collect();
+        let removed: Vec<&String> = old_providers.iter().filter(|provider| !new_providers.contains(provider)).collect();
+        if !added.is_empty() || !removed.is_empty() {
+            writeln!(out, "\nAdded/removed providers:")?;
+            for provider in added {
+                writeln!(out, "    + {} ({})", provider, new.find(provider).map_or(String::new(), summary))?;
+            }
+            for provider in removed {
+                writeln!(out, "    - {} ({})", provider, old.find(provider).map_or(String::new(), summary))?;
+            }
+        }
+        Ok(())
+    };
+    write(out).map_err(|e| format!("cannot write the comparison: {}", e))?;
+    Ok(regressed)
+}
@@ -0,0 +1,230 @@
+//! --domains-source: the test domains taken from the top of the Tranco list of popular sites
+//! (<https://tranco-list.eu>), downloaded at most once a week and cached on disk.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use hickory_resolver::proto::rr::Name;
+
+/// Domains taken when --domains-source gives no count.
+pub const DEFAULT_COUNT: usize = 50;
+/// Entries downloaded and cached, and so the most --domains-source can ask for.
+pub const MAX_COUNT: usize = 1000;
+/// A cached list older than this is downloaded again.
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Answers with the ID of the latest daily list as plain text.
+const LATEST_ID_URL: &str = "https://tranco-list.eu/top-1m-id";
+/// The cache starts with this, followed by the list ID.
+const CACHE_HEADER: &str = "# Tranco list ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The top N of the Tranco list
+    Tranco(usize),
+}
+
+/// The domains a run uses and where they came from.
+pub struct DomainList {
+    pub domains: Vec<String>,
+    /// Shown in the run header and the JSON report, e.g. "top 50 of Tranco list 6Z2X"
+    pub origin: String,
+}
+
+/// Parses `tranco` or `tranco:N`.
+pub fn parse_source(s: &str) -> Result<Source, String> {
+    let (name, count) = match s.split_once(':') {
+        Some((name, count)) => (name, Some(count)),
+        None => (s, None),
+    };
+    if !name.eq_ignore_ascii_case("tranco") {
+        return Err(format!("unknown domain source \"{}\", expected tranco or tranco:N", name));
+    }
+    let count = match count {
+        None => DEFAULT_COUNT,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if (1..=MAX_COUNT).contains(&count) => count,
+            _ => return Err(format!("the count must be a number from 1 to {}", MAX_COUNT)),
+        },
+    };
+    Ok(Source::Tranco(count))
+}
+
+/// Where downloaded lists and other state are kept: `$XDG_CACHE_HOME/dnsspeedtest`,
+/// `%LOCALAPPDATA%` on Windows, `~/.cache` elsewhere.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("LOCALAPPDATA")?),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("dnsspeedtest"))
+}
+
+/// The domains `normalize` kept and the entries it dropped, each with the reason.
+pub struct Normalized {
+    pub domains: Vec<String>,
+    pub dropped: Vec<(String, &'static str)>,
+}
+
+/// Makes a domain as typed or pasted usable as a query name: surrounding whitespace, a scheme
+/// and anything from the first `/` (as in "https://example.com/"), a port and trailing dots go,
+/// internationalized labels are punycode-encoded ("bücher.de" becomes "xn--bcher-kva.de") and
+/// the rest is lowercased. Names hickory would reject and bare labels such as TLDs are errors.
+pub fn normalize_one(raw: &str) -> Result<String, &'static str> {
+    let mut domain = raw.trim();
+    if let Some((_, rest)) = domain.split_once("://") {
+        domain = rest;
+    }
+    domain = domain.split(['/', '?', '#']).next().unwrap_or_default();
+    if let Some((host, port)) = domain.rsplit_once(':') {
+        if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) {
+            domain = host;
+        }
+    }
+    let domain = domain.trim_end_matches('.');
+    if domain.is_empty() {
+        return Err("empty");
+    }
+    // UTS 46 mapping, as browsers do: "MÜNCHEN.de" is "münchen.de", and labels it disallows fail.
+    let name = if domain.is_ascii() {
+        Name::from_ascii(domain).map_err(|_| "not a valid domain name")?
+    } else {
+        Name::from_utf8(domain).map_err(|_| "not a valid internationalized domain name")?
+    };
+    let domain = name.to_ascii().to_ascii_lowercase();
+    if !domain.contains('.') {
+        return Err("a bare label, not a domain");
+    }
+    Ok(domain)
+}
+
+/// How to show a query name as normalized by `normalize_one`, which is also how it is queried:
+/// punycode labels in Unicode again ("xn--bcher-kva.de" is "bücher.de"), a "/MX" suffix kept.
+pub fn display(query: &str) -> String {
+    let (domain, suffix) = match query.split_once('/') {
+        Some((domain, record_type)) => (domain, Some(record_type)),
+        None => (query, None),
+    };
+    if !domain.split('.').any(|label| label.starts_with("xn--")) {
+        return query.to_string();
+    }
+    let unicode = Name::from_ascii(domain).map_or_else(|_| domain.to_string(), |name| name.to_utf8());
+    match suffix {
+        Some(record_type) => format!("{}/{}", unicode, record_type),
+        None => unicode,
+    }
+}
+
+/// Normalizes each entry with `normalize_one`, dropping those it rejects and repeats of a
+/// domain already kept, in the original order.
+pub fn normalize<S: AsRef<str>>(raw: &[S]) -> Normalized {
+    let mut seen = HashSet::new();
+    let mut normalized = Normalized { domains: Vec::new(), dropped: Vec::new() };
+    for entry in raw {
+        let entry = entry.as_ref();
+        match normalize_one(entry) {
+            Ok(domain) if seen.insert(domain.clone()) => normalized.domains.push(domain),
+            Ok(_) => normalized.dropped.push((entry.to_string(), "duplicate")),
+            Err(reason) => normalized.dropped.push((entry.to_string(), reason)),
+        }
+    }
+    normalized
+}
+
+/// The first `count` usable domains of a list in Tranco's `rank,domain` format; lines of just a
+/// domain work too. Blank lines, `#` comments and duplicates are skipped along with bad entries.
+fn parse_list(text: &str, count: usize) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| normalize_one(line.rsplit(',').next().unwrap_or(line)).ok())
+        .filter(|domain| seen.insert(domain.clone()))
+        .take(count)
+        .collect()
+}
+
+/// The list ID recorded in the cache.
+fn cached_id(text: &str) -> Option<&str> {
+    text.lines().next()?.strip_prefix(CACHE_HEADER).map(str::trim)
+}
+
+/// Fetches the top `MAX_COUNT` of the latest list; returns its ID and the CSV.
+fn download() -> Result<(String, String), String> {
+    let agent = ureq::AgentBuilder::new().timeout(DOWNLOAD_TIMEOUT).build();
+    let get = |url: &str| agent.get(url).call()
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string());
+    let id = get(LATEST_ID_URL)?.trim().to_string();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("unexpected list ID \"{}\"", id));
+    }
+    let csv = get(&format!("https://tranco-list.eu/download/{}/{}", id, MAX_COUNT))?;
+    Ok((id, csv))
+}
+
+/// Writes the cache next to its final place first, so an interrupted write leaves the old one.
+fn store(path: &Path, id: &str, csv: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("csv.partial");
+    std::fs::write(&partial, format!("{}{}\n{}", CACHE_HEADER, id, csv))?;
+    std::fs::rename(&partial, path)
+}
+
+fn finish(domains: Vec<String>, count: usize, list: String) -> Result<DomainList, String> {
+    if domains.is_empty() {
+        return Err(format!("{} has no usable domains", list));
+    }
+    let origin = if domains.len() < count {
+        format!("all {} usable domains of {}", domains.len(), list)
+    } else {
+        format!("top {} of {}", domains.len(), list)
+    };
+    Ok(DomainList { domains, origin })
+}
+
+/// The domains for `source`, from `file` if given and otherwise from the cache, which is
+/// downloaded again when missing, older than a week or `refresh` is set. A failed download falls
+/// back to a stale cache with a warning. Without `online`, a cached list of any age is used
+/// and a missing one is an error.
+pub fn load(source: Source, file: Option<&Path>, refresh: bool, online: bool) -> Result<DomainList, String> {
+    let Source::Tranco(count) = source;
+    if let Some(file) = file {
+        let text = std::fs::read_to_string(file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+        return finish(parse_list(&text, count), count, format!("the Tranco list in {}", file.display()));
+    }
+
+    let path = cache_dir().map(|dir| dir.join("tranco.csv")).ok_or("cannot find a cache directory for the Tranco list; pass a copy with --domains-file")?;
+    let cached = std::fs::read_to_string(&path).ok().filter(|text| cached_id(text).is_some());
+    let age = std::fs::metadata(&path).and_then(|meta| meta.modified()).ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    let fresh = cached.is_some() && age.is_some_and(|age| age < MAX_AGE);
+    let text = match cached {
+        Some(text) if (fresh && !refresh) || !online => text,
+        None if !online => return Err("the Tranco list is not cached yet and a dry run does not download it; pass a copy with --domains-file".to_string()),
+        cached => match download() {
+            Ok((id, csv)) => {
+                if let Err(e) = store(&path, &id, &csv) {
+                    eprintln!("Warning: cannot cache the Tranco list in {}: {}", path.display(), e);
+                }
+                format!("{}{}\n{}", CACHE_HEADER, id, csv)
+            },
+            Err(e) => match cached {
+                Some(text) => {
+                    eprintln!("Warning: cannot download the Tranco list ({}); using the cached copy", e);
+                    text
+                },
+                None => return Err(format!(
+                    "cannot download the Tranco list: {}; get it from https://tranco-list.eu and pass it with --domains-file",
+                    e
+                )),
+            },
+        },
+    };
+    let list = format!("Tranco list {}", cached_id(&text).unwrap_or("?"));
+    finish(parse_list(&text, count), count, list)
+}
@@ -0,0 +1,139 @@
+//! --exporter: rerun the test forever and publish the latest results as Prometheus metrics,
+//! over HTTP and/or in a file for node_exporter's textfile collector.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use dns_speed_test::{run_all, SampleStatus, SpeedTestConfig, TestResult};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::sleep;
+
+/// Default address of the HTTP endpoint when no --textfile is given.
+pub const DEFAULT_LISTEN: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 9753);
+
+/// Failed and sent queries per provider since the exporter started, for the `_total` counters.
+#[derive(Default)]
+struct Totals {
+    failures: HashMap<String, u64>,
+    queries: HashMap<String, u64>,
+}
+
+/// Renders Prometheus text format. Providers without a single answer have no latency series,
+/// since their "latency" would only be the timeout.
+fn render(results: &[TestResult], totals: &Totals, finished: SystemTime, duration: Duration) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        writeln!(out, "# HELP {} {}", name, help).ok();
+        writeln!(out, "# TYPE {} {}", name, kind).ok();
+        for (labels, value) in samples {
+            writeln!(out, "{}{} {}", name, labels, value).ok();
+        }
+    };
+    let per_provider = |value: &dyn Fn(&TestResult) -> Option<f64>| -> Vec<(String, f64)> {
+        results.iter()
+            .filter_map(|result| Some((format!("{{provider=\"{}\"}}", escape(&result.provider)), value(result)?)))
+            .collect()
+    };
+    let ms = |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.0);
+
+    family("dnsspeedtest_median_ms", "gauge", "Median query latency in the last run, in milliseconds.",
+        per_provider(&|r| ms(r.median_duration)));
+    family("dnsspeedtest_avg_ms", "gauge", "Average query latency in the last run, in milliseconds.",
+        per_provider(&|r| ms(r.avg_duration)));
+    family("dnsspeedtest_min_ms", "gauge", "Fastest query in the last run, in milliseconds.",
+        per_provider(&|r| ms(r.min_latency)));
+    family("dnsspeedtest_max_ms", "gauge", "Slowest answered query in the last run, in milliseconds.",
+        per_provider(&|r| ms(r.max_latency)));
+    family("dnsspeedtest_success_ratio", "gauge", "Share of the last run's queries that were answered (0 to 1).",
+        per_provider(&|r| Some(r.success_rate / 100.0)));
+    family("dnsspeedtest_query_failures_total", "counter", "Queries that failed since the exporter started.",
+        per_provider(&|r| totals.failures.get(&r.provider).map(|&n| n as f64)));
+    family("dnsspeedtest_queries_total", "counter", "Queries sent since the exporter started.",
+        per_provider(&|r| totals.queries.get(&r.provider).map(|&n| n as f64)));
+    let finished = finished.duration_since(UNIX_EPOCH).map_or(0.0, |elapsed| elapsed.as_secs_f64());
+    family("dnsspeedtest_last_run_timestamp_seconds", "gauge", "Unix time at which the last run finished.",
+        vec![(String::new(), finished.floor())]);
+    family("dnsspeedtest_last_run_duration_seconds", "gauge", "How long the last run took.",
+        vec![(String::new(), duration.as_secs_f64())]);
+    out
+}
+
+/// Escapes a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+/// Writes the file next to its final name and renames it, so the collector never reads a
+/// partial file.
+fn write_textfile(path: &Path, metrics: &str) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, metrics)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Answers `GET /metrics` with the latest metrics; other paths get 404.
+async fn serve(mut stream: TcpStream, metrics: Arc<RwLock<String>>) {
+    let mut request = [0; 1024];
+    let Ok(read) = stream.read(&mut request).await else { return };
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        ("200 OK", metrics.read().unwrap().clone())
+    } else {
+        ("404 Not Found", "Metrics are at /metrics\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    );
+    stream.write_all(response.as_bytes()).await.ok();
+    stream.shutdown().await.ok();
+}
+
+/// Runs the test every `interval` until interrupted. The HTTP server runs in its own tasks, so
+/// scrapes are answered (with the previous run's metrics) while a run is in progress.
+pub async fn run(cfg: &SpeedTestConfig, interval: Duration, listen: Option<SocketAddr>, textfile: Option<&Path>) -> ! {
+    let metrics = Arc::new(RwLock::new(String::new()));
+    if let Some(address) = listen {
+        let listener = TcpListener::bind(address).await.unwrap_or_else(|e| {
+            eprintln!("Cannot listen on {}: {}", address, e);
+            std::process::exit(crate::EXIT_ERROR);
+        });
+        eprintln!("Serving metrics on http://{}/metrics", address);
+        let metrics = Arc::clone(&metrics);
+        tokio::spawn(async move {
+            loop {
+                if let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, Arc::clone(&metrics)));
+                }
+            }
+        });
+    }
+
+    let mut totals = Totals::default();
+    loop {
+        let run_start = Instant::now();
+        let results = run_all(cfg).await;
+        for result in &results {
+            let queries = result.samples.len() as u64;
+            let failures = result.samples.iter().filter(|sample| sample.status != SampleStatus::Ok).count() as u64;
+            *totals.queries.entry(result.provider.clone()).or_default() += queries;
+            *totals.failures.entry(result.provider.clone()).or_default() += failures;
+        }
+        let rendered = render(&results, &totals, SystemTime::now(), run_start.elapsed());
+        if let Some(path) = textfile {
+            if let Err(e) = write_textfile(path, &rendered) {
+                eprintln!("Failed to write metrics to {}: {}", path.display(), e);
+            }
+        }
+        *metrics.write().unwrap() = rendered;
+        eprintln!("[{}] Measured {} providers in {:.1}s",
+            humantime::format_rfc3339_seconds(SystemTime::now()), results.len(), run_start.elapsed().as_secs_f64());
+        sleep(interval.saturating_sub(run_start.elapsed())).await;
+    }
+}
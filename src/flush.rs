@@ -0,0 +1,52 @@
+//! --flush-cache: empty the operating system's DNS cache before the run, so the system
+//! resolver starts as cold as the providers queried directly.
+
+use std::process::{Command, Stdio};
+
+/// Runs a program with its arguments (`argv[0]` is the program); `Err` says why it could not
+/// run or failed. [`run_command`] runs it for real; anything else stands in for it.
+pub type CommandRunner<'a> = &'a dyn Fn(&[&str]) -> Result<(), String>;
+
+/// The commands that flush this platform's cache, all of which have to succeed; empty where
+/// there is no known way.
+fn commands() -> &'static [&'static [&'static str]] {
+    if cfg!(windows) {
+        &[&["ipconfig", "/flushdns"]]
+    } else if cfg!(target_os = "macos") {
+        // The directory services cache, then the resolver's own.
+        &[&["dscacheutil", "-flushcache"], &["killall", "-HUP", "mDNSResponder"]]
+    } else if cfg!(target_os = "linux") {
+        &[&["resolvectl", "flush-caches"]]
+    } else {
+        &[]
+    }
+}
+
+/// Runs `argv` with its output hidden, reporting the first line it wrote to stderr on failure.
+pub fn run_command(argv: &[&str]) -> Result<(), String> {
+    let output = Command::new(argv[0]).args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", argv[0], e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().map(str::trim).find(|line| !line.is_empty())
+        .map_or(output.status.to_string(), String::from);
+    Err(format!("`{}` failed: {}", argv.join(" "), reason))
+}
+
+/// Flushes the cache with `run`, returning the commands that did it. Stops at the first command
+/// that fails.
+pub(crate) fn flush(run: CommandRunner) -> Result<String, String> {
+    let commands = commands();
+    if commands.is_empty() {
+        return Err("no known way to flush the DNS cache on this platform".to_string());
+    }
+    for argv in commands {
+        run(argv)?;
+    }
+    Ok(commands.iter().map(|argv| argv.join(" ")).collect::<Vec<_>>().join(", "))
+}
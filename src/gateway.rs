@@ -0,0 +1,76 @@
+//! --include-gateway: find the default gateway, usually the router, whose DNS forwarder is many
+//! people's real resolver. Each platform has its own source: the kernel's routing table on
+//! Linux, `route` on macOS and Windows.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::{Command, Stdio};
+
+/// The kernel's IPv4 routing table on Linux.
+const PROC_ROUTE: &str = "/proc/net/route";
+
+/// The IPv4 default gateway, `None` if there is none or it cannot be read on this platform.
+pub(crate) fn discover() -> Option<IpAddr> {
+    discover_with(output, |path| std::fs::read_to_string(path).ok())
+}
+
+/// `discover` with the command runner and file reader passed in.
+pub(crate) fn discover_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<String>) -> Option<IpAddr> {
+    if cfg!(target_os = "linux") {
+        parse_proc_route(&read(PROC_ROUTE)?)
+    } else if cfg!(target_os = "macos") {
+        parse_route_get(&run(&["route", "-n", "get", "default"])?)
+    } else if cfg!(windows) {
+        parse_route_print(&run(&["route", "print", "-4", "0.0.0.0"])?)
+    } else {
+        None
+    }
+}
+
+/// What `argv` wrote to stdout, if it ran and succeeded.
+fn output(argv: &[&str]) -> Option<String> {
+    let output = Command::new(argv[0]).args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The gateway of the default route with the lowest metric in /proc/net/route, where addresses
+/// are little-endian hex, e.g. "0101A8C0" for 192.168.1.1.
+pub fn parse_proc_route(table: &str) -> Option<IpAddr> {
+    const RTF_UP: u32 = 0x1;
+    const RTF_GATEWAY: u32 = 0x2;
+    table.lines().skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [_, destination, gateway, flags, _, _, metric, ..] = fields[..] else { return None };
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if destination != "00000000" || flags & (RTF_UP | RTF_GATEWAY) != RTF_UP | RTF_GATEWAY {
+                return None;
+            }
+            let gateway = Ipv4Addr::from(u32::from_str_radix(gateway, 16).ok()?.swap_bytes());
+            Some((metric.parse::<u32>().unwrap_or(u32::MAX), gateway))
+        })
+        .min_by_key(|&(metric, _)| metric)
+        .map(|(_, gateway)| IpAddr::V4(gateway))
+}
+
+/// The "gateway:" line of `route -n get default`.
+pub fn parse_route_get(output: &str) -> Option<IpAddr> {
+    output.lines()
+        .find_map(|line| line.trim().strip_prefix("gateway:"))
+        .and_then(|gateway| gateway.trim().parse().ok())
+}
+
+/// The gateway column of the first 0.0.0.0/0 row of `route print -4 0.0.0.0`, which reads the
+/// same in every language: "0.0.0.0  0.0.0.0  192.168.1.1  192.168.1.10  25".
+pub fn parse_route_print(output: &str) -> Option<IpAddr> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+            _ => None,
+        }
+    })
+}
@@ -0,0 +1,125 @@
+//! The cost of a new DoT or DoH connection, which hickory's pooled connections hide after the
+//! first query: one connection made here, timed step by step — TCP, the TLS handshake, then the
+//! first query on it — and closed again. Clients that open a connection for every few queries,
+//! such as mobile apps, pay the whole of it each time.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use hickory_resolver::proto::op::Message;
+use hickory_resolver::proto::rr::{DNSClass, RecordType};
+use hickory_resolver::proto::xfer::{DnsRequest, DnsRequestOptions};
+use hickory_resolver::Name;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_rustls::rustls::ServerName;
+use tokio_rustls::TlsConnector;
+use crate::connector::{tls_config, PathConnection};
+use crate::providers::{DnsProvider, Transport};
+use crate::raw;
+use crate::results::ConnectionSplit;
+
+/// Times a new connection to `ip` over `transport`, DoT or DoH, and an A query for `domain` on
+/// it, each step within `timeout`. `steady_median` is left for the rounds to fill in.
+pub(crate) async fn probe_connection(
+    provider: &DnsProvider,
+    ip: IpAddr,
+    transport: Transport,
+    domain: &str,
+    bind: Option<IpAddr>,
+    timeout: Duration,
+) -> ConnectionSplit {
+    let mut split = ConnectionSplit::default();
+    let server = SocketAddr::new(ip, provider.port_for(transport));
+    let Some(host) = provider.tls_host(transport) else {
+        split.error = Some(format!("no {} endpoint", transport.label()));
+        return split;
+    };
+    let name = Name::from_ascii(format!("{}.", domain.trim_end_matches('.'))).unwrap_or_else(|_| Name::root());
+    let message = raw::message(name, RecordType::A, DNSClass::IN);
+
+    let start = Instant::now();
+    let tcp = match step(timeout, connect(server, bind)).await {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            split.error = Some(format!("TCP connect: {}", e));
+            return split;
+        },
+    };
+    split.tcp = Some(start.elapsed());
+
+    let start = Instant::now();
+    let alpn: Option<&[u8]> = (transport == Transport::Https).then_some(b"h2");
+    let handshake = async {
+        let name = ServerName::try_from(host).map_err(|e| e.to_string())?;
+        TlsConnector::from(Arc::new(tls_config(alpn))).connect(name, tcp).await.map_err(|e| e.to_string())
+    };
+    let tls = match step(timeout, handshake).await {
+        Ok(tls) => tls,
+        Err(e) => {
+            split.error = Some(format!("TLS handshake: {}", e));
+            return split;
+        },
+    };
+    split.tls = Some(start.elapsed());
+
+    let start = Instant::now();
+    let answered = match transport {
+        Transport::Https => step(timeout, doh_query(tls, host, provider.doh_query_path(), &message, timeout)).await,
+        _ => step(timeout, dot_query(tls, &message)).await,
+    };
+    match answered {
+        Ok(()) => split.first_query = Some(start.elapsed()),
+        Err(e) => split.error = Some(format!("query: {}", e)),
+    }
+    split
+}
+
+/// `future` within `timeout`, a timeout being an error like the others.
+async fn step<T>(timeout: Duration, future: impl std::future::Future<Output = Result<T, String>>) -> Result<T, String> {
+    tokio::time::timeout(timeout, future).await.unwrap_or_else(|_| Err(format!("timed out after {}", humantime::format_duration(timeout))))
+}
+
+async fn connect(server: SocketAddr, bind: Option<IpAddr>) -> Result<TcpStream, String> {
+    let socket = if server.is_ipv4() { TcpSocket::new_v4() } else { TcpSocket::new_v6() }.map_err(|e| e.to_string())?;
+    if let Some(bind) = bind {
+        socket.bind(SocketAddr::new(bind, 0)).map_err(|e| e.to_string())?;
+    }
+    socket.connect(server).await.map_err(|e| e.to_string())
+}
+
+/// One query over DoT (RFC 7858), framed like over TCP.
+async fn dot_query(mut stream: impl AsyncRead + AsyncWrite + Unpin, message: &Message) -> Result<(), String> {
+    let bytes = message.to_vec().map_err(|e| e.to_string())?;
+    let mut framed = u16::try_from(bytes.len()).map_err(|e| e.to_string())?.to_be_bytes().to_vec();
+    framed.extend_from_slice(&bytes);
+    stream.write_all(&framed).await.map_err(|e| e.to_string())?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await.map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut buf).await.map_err(|e| e.to_string())?;
+    let response = Message::from_vec(&buf).map_err(|e| e.to_string())?;
+    if response.id() != message.id() {
+        return Err("response with another ID".to_string());
+    }
+    let _ = stream.shutdown().await;
+    Ok(())
+}
+
+/// One query over DoH, the HTTP/2 handshake included, as a client on a new connection waits for
+/// both. The connection closes when the last handle to it is dropped.
+async fn doh_query(
+    tls: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    host: &str,
+    path: &str,
+    message: &Message,
+    timeout: Duration,
+) -> Result<(), String> {
+    let uri = format!("https://{}{}", host, path).parse::<http::Uri>().map_err(|e| e.to_string())?;
+    let (sender, connection) = h2::client::handshake(tls).await.map_err(|e| format!("HTTP/2 handshake: {}", e))?;
+    let driver = tokio::spawn(connection);
+    let request = DnsRequest::new(message.clone(), DnsRequestOptions::default());
+    let answered = PathConnection::new(sender, uri, timeout).query(request).await.map(|_| ()).map_err(|e| e.to_string());
+    driver.abort();
+    answered
+}
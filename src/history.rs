@@ -0,0 +1,165 @@
+//! --db, --history and --with-history: every run appended to an SQLite database, and the
+//! recent medians of one provider or the long-term success rates of all read back from it.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::{params, Connection};
+use crate::{SampleStatus, SpeedTestConfig, TestResult};
+
+/// Schema changes, in order; the database's `user_version` is the number already applied. Add
+/// new ones at the end and never edit an applied one.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE runs (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        rounds INTEGER NOT NULL,
+        domains TEXT NOT NULL,
+        tool_version TEXT NOT NULL,
+        protocol TEXT NOT NULL
+    );
+    CREATE TABLE results (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        provider TEXT NOT NULL,
+        median_ms REAL,
+        avg_ms REAL,
+        min_ms REAL,
+        max_ms REAL,
+        p95_ms REAL,
+        success_rate REAL NOT NULL,
+        failures INTEGER NOT NULL
+    );
+    CREATE INDEX results_provider ON results (provider, run_id);",
+];
+
+/// One run of a provider, as listed by --history.
+pub struct HistoryEntry {
+    pub time: SystemTime,
+    /// `None` if no query was answered
+    pub median: Option<Duration>,
+    pub success_rate: f64,
+}
+
+/// Runs a provider needs within a window for its average success rate to mean anything.
+const MIN_RELIABILITY_RUNS: u32 = 3;
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MONTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A provider's average success rate over its runs of the last 7 and 30 days, as --with-history
+/// shows it; `None` with fewer than [`MIN_RELIABILITY_RUNS`] runs in the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reliability {
+    pub week: Option<f64>,
+    pub month: Option<f64>,
+}
+
+/// Opens the database, creating it or bringing its schema up to date as needed.
+pub fn open(path: &Path) -> Result<Connection, String> {
+    let failed = |e: rusqlite::Error| format!("cannot use history database {}: {}", path.display(), e);
+    let mut connection = Connection::open(path).map_err(failed)?;
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(failed)?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "history database {} has schema version {}, but this version knows up to {}",
+            path.display(), version, MIGRATIONS.len()
+        ));
+    }
+    if version < MIGRATIONS.len() {
+        let transaction = connection.transaction().map_err(failed)?;
+        transaction.execute_batch(&MIGRATIONS[version..].concat()).map_err(failed)?;
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len()).map_err(failed)?;
+        transaction.commit().map_err(failed)?;
+    }
+    Ok(connection)
+}
+
+/// Appends one run with all its providers. It is written in a single transaction, so an
+/// interrupted write leaves no partial run behind.
+pub fn record(connection: &mut Connection, cfg: &SpeedTestConfig, results: &[&TestResult]) -> rusqlite::Result<()> {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO runs (timestamp, rounds, domains, tool_version, protocol) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            timestamp as i64,
+            cfg.rounds,
+            serde_json::to_string(&cfg.domains).unwrap_or_default(),
+            env!("CARGO_PKG_VERSION"),
+            cfg.protocol_label(),
+        ],
+    )?;
+    let run_id = transaction.last_insert_rowid();
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO results (run_id, provider, median_ms, avg_ms, min_ms, max_ms, p95_ms, success_rate, failures)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for result in results {
+            // A provider that never answered has no latencies; they stay NULL.
+            let latency = |duration: Option<Duration>| duration.map(ms);
+            let failures = result.samples.iter().filter(|sample| sample.status != SampleStatus::Ok).count();
+            insert.execute(params![
+                run_id,
+                result.provider,
+                latency(result.median_duration),
+                latency(result.avg_duration),
+                latency(result.min_latency),
+                latency(result.max_latency),
+                latency(result.p95),
+                result.success_rate,
+                failures as i64,
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// The [`Reliability`] of every provider with runs in the 30 days before `now`, by name. Only
+/// the columns of the first schema are read, so a database of any version will do.
+pub fn reliability(connection: &Connection, now: SystemTime) -> rusqlite::Result<HashMap<String, Reliability>> {
+    let since = |window: Duration| now.checked_sub(window).unwrap_or(UNIX_EPOCH)
+        .duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()) as i64;
+    let mut query = connection.prepare(
+        "SELECT results.provider,
+                COUNT(CASE WHEN runs.timestamp >= ?1 THEN 1 END), AVG(CASE WHEN runs.timestamp >= ?1 THEN results.success_rate END),
+                COUNT(*), AVG(results.success_rate)
+         FROM results JOIN runs ON runs.id = results.run_id
+         WHERE runs.timestamp >= ?2
+         GROUP BY results.provider",
+    )?;
+    let enough = |runs: u32, average: Option<f64>| average.filter(|_| runs >= MIN_RELIABILITY_RUNS);
+    let providers = query
+        .query_map(params![since(WEEK), since(MONTH)], |row| {
+            let reliability = Reliability {
+                week: enough(row.get(1)?, row.get(2)?),
+                month: enough(row.get(3)?, row.get(4)?),
+            };
+            Ok((row.get(0)?, reliability))
+        })?
+        .collect();
+    providers
+}
+
+/// The provider's last `limit` runs, oldest first.
+pub fn provider_history(connection: &Connection, provider: &str, limit: usize) -> rusqlite::Result<Vec<HistoryEntry>> {
+    let mut query = connection.prepare(
+        "SELECT runs.timestamp, results.median_ms, results.success_rate
+         FROM results JOIN runs ON runs.id = results.run_id
+         WHERE results.provider = ?1 COLLATE NOCASE
+         ORDER BY runs.id DESC LIMIT ?2",
+    )?;
+    let mut entries = query
+        .query_map(params![provider, limit as i64], |row| {
+            let timestamp: i64 = row.get(0)?;
+            let median: Option<f64> = row.get(1)?;
+            Ok(HistoryEntry {
+                time: UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64),
+                median: median.map(|ms| Duration::from_secs_f64(ms / 1000.0)),
+                success_rate: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    entries.reverse();
+    Ok(entries)
+}
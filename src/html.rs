@@ -0,0 +1,207 @@
+//! --html: the report as a single HTML file with its CSS and script inline, so it opens offline
+//! and can be passed around as is.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+use dns_speed_test::TestResult;
+use crate::report::{JsonProvider, JsonReport};
+use crate::Unit;
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 72rem; padding: 0 1rem; color: #222; }
+h1 { margin-bottom: 0.2rem; }
+.meta { color: #666; margin-top: 0; }
+dl { display: grid; grid-template-columns: max-content 1fr; gap: 0.2rem 1rem; }
+dt { font-weight: 600; }
+dd { margin: 0; }
+table { border-collapse: collapse; width: 100%; }
+th, td { padding: 0.3rem 0.6rem; border-bottom: 1px solid #ddd; text-align: right; white-space: nowrap; }
+th:first-child, td:first-child { text-align: left; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+th.asc::after { content: ' \\25B2'; }
+th.desc::after { content: ' \\25BC'; }
+.bars { display: grid; grid-template-columns: max-content 1fr max-content; gap: 0.3rem 0.8rem; align-items: center; }
+.bar { background: #3b82f6; height: 1rem; border-radius: 2px; }
+.none { color: #999; }
+.warning { color: #b45309; }
+";
+
+/// Sorts the table by the clicked column, numerically by `data-value` where cells have one.
+const SCRIPT: &str = "
+document.querySelectorAll('table.sortable th').forEach(function (th, column) {
+  th.addEventListener('click', function () {
+    var table = th.closest('table');
+    var body = table.tBodies[0];
+    var ascending = !th.classList.contains('asc');
+    table.querySelectorAll('th').forEach(function (other) { other.classList.remove('asc', 'desc'); });
+    th.classList.add(ascending ? 'asc' : 'desc');
+    var key = function (row) {
+      var cell = row.cells[column];
+      var value = cell.getAttribute('data-value');
+      return value === null ? cell.textContent.toLowerCase() : parseFloat(value);
+    };
+    Array.from(body.rows)
+      .sort(function (a, b) {
+        var x = key(a), y = key(b);
+        var order = x < y ? -1 : x > y ? 1 : 0;
+        return ascending ? order : -order;
+      })
+      .forEach(function (row) { body.appendChild(row); });
+  });
+});
+";
+
+/// `text` with the characters that are special in HTML text and attribute values replaced.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// A numeric cell that sorts by its value; `None`, or a latency of a provider without answers,
+/// shows as n/a and sorts last.
+fn number_cell(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("<td data-value=\"{}\">{:.2}</td>", value, value),
+        None => "<td data-value=\"Infinity\" class=\"none\">n/a</td>".to_string(),
+    }
+}
+
+/// A latency cell shown in `unit` that sorts by milliseconds, like `number_cell`.
+fn latency_cell(duration: Option<Duration>, unit: Unit) -> String {
+    match duration {
+        Some(duration) => format!("<td data-value=\"{}\">{}</td>", ms(duration), unit.format(duration)),
+        None => number_cell(None),
+    }
+}
+
+fn table(html: &mut String, providers: &[JsonProvider], unit: Unit) {
+    html.push_str("<table class=\"sortable\">\n<thead><tr>");
+    let headers = ["Median", "Avg", "Min", "Max", "P95", "Jitter"].map(|header| unit.heading(header));
+    for header in ["Provider", "Score"].into_iter().chain(headers.iter().map(String::as_str)).chain(["Success"]) {
+        let _ = write!(html, "<th>{}</th>", header);
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for provider in providers {
+        let result = provider.result;
+        let latency = |duration: Option<Duration>| latency_cell(duration, unit);
+        // The success rate sorts highest first, like the best values of the other columns.
+        let _ = writeln!(html, "<tr><td>{}</td>{}{}{}{}{}{}{}<td data-value=\"{}\">{:.1}%</td></tr>",
+            escape(&result.provider),
+            number_cell(result.score),
+            latency(result.median_duration),
+            latency(result.avg_duration),
+            latency(result.min_latency),
+            latency(result.max_latency),
+            latency(result.p95),
+            latency(result.jitter),
+            100.0 - result.success_rate,
+            result.success_rate,
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+fn bar_chart(html: &mut String, providers: &[JsonProvider], unit: Unit) {
+    let slowest = providers.iter().filter_map(|provider| provider.result.median_duration)
+        .map(ms)
+        .fold(0.0, f64::max);
+    html.push_str("<div class=\"bars\">\n");
+    for result in providers.iter().map(|provider| provider.result) {
+        let _ = write!(html, "<div>{}</div>", escape(&result.provider));
+        if let Some(median) = result.median_duration {
+            let width = if slowest > 0.0 { ms(median) / slowest * 100.0 } else { 0.0 };
+            let _ = writeln!(html, "<div><div class=\"bar\" style=\"width: {:.1}%\"></div></div><div>{}</div>", width, unit.format_with_unit(median));
+        } else {
+            html.push_str("<div></div><div class=\"none\">no answers</div>\n");
+        }
+    }
+    html.push_str("</div>\n");
+}
+
+fn failures(html: &mut String, providers: &[JsonProvider]) {
+    let failing: Vec<&TestResult> = providers.iter().map(|provider| provider.result)
+        .filter(|result| !result.failures.is_empty() || !result.slow_domains.is_empty())
+        .collect();
+    if failing.is_empty() {
+        html.push_str("<p>Every query was answered.</p>\n");
+        return;
+    }
+    html.push_str("<dl>\n");
+    for result in failing {
+        let _ = write!(html, "<dt>{}</dt><dd>", escape(&result.provider));
+        if !result.failures.is_empty() {
+            let _ = write!(html, "{}", escape(&crate::failure_summary(result)));
+        }
+        if !result.recovered_domains.is_empty() {
+            let _ = write!(html, "<br>Recovered on retry: {}", escape(&crate::display_domains(&result.recovered_domains)));
+        }
+        if !result.slow_domains.is_empty() {
+            let _ = write!(html, "<br>Slow but answered after timeout: {}", escape(&crate::display_domains(&result.slow_domains)));
+        }
+        html.push_str("</dd>\n");
+    }
+    html.push_str("</dl>\n");
+}
+
+/// Renders `report`, the same model the JSON output serializes, with latencies in `unit`.
+pub fn write(out: &mut dyn Write, report: &JsonReport, unit: Unit) -> io::Result<()> {
+    let config = &report.config;
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(report.timestamp));
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    let _ = writeln!(html, "<title>DNS Speed Test report, {}</title>", time);
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    html.push_str("<h1>DNS Speed Test</h1>\n");
+    let _ = writeln!(html, "<p class=\"meta\">{} &middot; dns_speed_test {}</p>", time, report.version);
+    if report.partial {
+        html.push_str("<p class=\"warning\">Partial run: interrupted before every provider was tested.</p>\n");
+    }
+
+    html.push_str("<h2>Run</h2>\n<dl>\n");
+    let mut meta = |name: &str, value: String| {
+        let _ = writeln!(html, "<dt>{}</dt><dd>{}</dd>", name, escape(&value));
+    };
+    meta("Protocol", config.protocol.to_string());
+    meta("Rounds", format!("{} (after {} warm-up queries)", config.rounds, config.warmup));
+    meta("Timeout", format!("{} ms", config.timeout_ms));
+    for own in &config.provider_settings {
+        meta(&format!("Settings of {}", own.provider),
+            format!("{} ms timeout, {} attempt{}, {} ms cooldown", own.timeout_ms, own.attempts, if own.attempts == 1 { "" } else { "s" }, own.cooldown_ms));
+    }
+    meta("Record types", config.record_types.join(", "));
+    if let Some(source) = config.domains_source {
+        meta("Domain list", source.to_string());
+    }
+    meta("Domains", crate::display_domains(config.domains));
+    html.push_str("</dl>\n");
+
+    html.push_str("<h2>Results</h2>\n");
+    table(&mut html, &report.results, unit);
+    html.push_str("<h2>Median latency</h2>\n");
+    bar_chart(&mut html, &report.results, unit);
+    html.push_str("<h2>Failures</h2>\n");
+    failures(&mut html, &report.results);
+    if !report.excluded.is_empty() {
+        html.push_str("<h2>Excluded (below the success threshold)</h2>\n");
+        table(&mut html, &report.excluded, unit);
+    }
+
+    let _ = writeln!(html, "<script>{}</script>\n</body>\n</html>", SCRIPT);
+    out.write_all(html.as_bytes())
+}
@@ -0,0 +1,850 @@
+//! Benchmark DNS resolvers: query each provider for a list of domains over several rounds and
+//! collect latency, reliability and behaviour figures per provider.
+//!
+//! The library never prints. [`run_all`] and [`test_provider`] return the results; their
+//! `_with_progress` variants additionally report every step to a callback.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use dns_speed_test::{run_all, DnsProvider, SpeedTestConfig};
+//!
+//! # async fn example() {
+//! let cfg = SpeedTestConfig {
+//!     providers: vec![DnsProvider::new("Router", "192.168.1.1".parse().unwrap())],
+//!     rounds: 3,
+//!     timeout: Duration::from_secs(1),
+//!     ..Default::default()
+//! };
+//! for result in run_all(&cfg).await {
+//!     println!("{}: {:?} median, {:.1}% success", result.provider, result.median_duration, result.success_rate);
+//! }
+//! # }
+//! ```
+
+mod bootstrap;
+mod check;
+mod connector;
+mod ddr;
+mod dhcp;
+mod flush;
+mod gateway;
+mod handshake;
+pub mod history;
+mod measure;
+mod pacing;
+mod ping;
+mod probes;
+mod providers;
+mod providers_file;
+mod quic;
+mod raw;
+mod reachability;
+mod results;
+pub mod stats;
+mod submission;
+mod throughput;
+mod upstreams;
+mod vpn;
+
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use hickory_resolver::proto::rr::{DNSClass, RecordType};
+use hickory_resolver::Name;
+use tokio::time::Instant;
+
+pub use bootstrap::SystemResolver;
+pub use check::AddressCheck;
+pub use ddr::{DdrEndpoint, DdrProtocol};
+pub use dhcp::{parse_dhclient_leases, parse_getoption, parse_ipconfig_all, parse_networkd_lease};
+pub use flush::{run_command, CommandRunner};
+pub use gateway::{parse_proc_route, parse_route_get, parse_route_print};
+pub use measure::{compare_by, provider_order};
+pub use pacing::Pacer;
+pub use providers_file::{describe_toml_error, ProvidersFile, ProvidersMode};
+pub use submission::{redact, submission, Client, ClientField, ClientNetwork, Submission, SubmittedResult, SUBMISSION_VERSION};
+pub use throughput::{run_throughput, LoadProfile, ThroughputResult, ThroughputStep, MAX_TARGET_QPS, THROUGHPUT_STEPS};
+pub use tokio_util::sync::CancellationToken;
+pub use upstreams::{parse_resolv_conf, parse_resolvectl_dns};
+pub use vpn::{linux_tunnel, parse_ifconfig, parse_ipconfig, Egress, Tunnel, VpnCheck};
+pub use providers::{
+    add_system_resolvers, builtin_catalog, builtin_providers, is_local_cache, is_local_forwarder, mark_system_resolvers, preset, preset_providers, system_nameservers, valid_doh_path,
+    DnsProvider, IpVersion, Preset, ProviderSet, Transport, DEFAULT_PRESET, MAX_ATTEMPTS, PRESETS, SYSTEMD_RESOLVED_STUB,
+};
+pub use results::{
+    classify_address, group_by_organization, usable_answer, AddressRange, AnswerReachability, AnswerStability, AnswerUsability, AnyHandling, BlockOutcome, ConnectionSplit, BlockingResult, BlockingSample, BogonAnswer, BufferComparison, BufferRun, CacheComparison, Dns64, Dns64Status, DnssecStatus, DomainStats, EdnsComparison, FailoverResult, FailureKind, InterceptionCheck, NearTimeout,
+    PacketLoss, ProviderGroup, QueryFailure, ResponseSizes, RttMethod, RunSpread, Sample, SampleStatus, SocketComparison, Trimming, TruncationLegs, MIN_PERCENTILE_SAMPLES,
+    SaturationLevel, SaturationResult, TestResult, Timing, TransportComparison, TtlRange, UnreachableAnswer, UnstableAnswer,
+};
+
+pub const TEST_DOMAINS: &[&str] = &[
+    "google.com",
+    "gitlab.com",
+    "cloudflare.com",
+    "microsoft.com",
+    "github.com",
+    "netflix.com",
+    "amazon.com",
+    "facebook.com",
+    "wikipedia.org",
+    "reddit.com"
+];
+
+/// Addresses reverse-resolved in PTR mode unless others are given: public resolvers, whose PTR
+/// records are well maintained, and CDN edges, which often have none.
+pub const PTR_TARGETS: &[&str] = &[
+    "8.8.8.8",
+    "1.1.1.1",
+    "9.9.9.9",
+    "208.67.222.222",
+    "4.2.2.2",
+    "2001:4860:4860::8888",
+    "2606:4700:4700::1111",
+    "151.101.1.69",
+    "104.16.132.229",
+    "13.107.42.14",
+];
+
+/// Ad/tracker domains queried by the blocking test unless others are given.
+pub const BLOCKING_TEST_DOMAINS: [&str; 8] = [
+    "doubleclick.net",
+    "ads.facebook.com",
+    "googlesyndication.com",
+    "googleadservices.com",
+    "adnxs.com",
+    "scorecardresearch.com",
+    "taboola.com",
+    "adsrvr.org",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+#[value(rename_all = "UPPER")]
+pub enum QueryType {
+    A,
+    Aaaa,
+    Mx,
+    Txt,
+    Ns,
+    Https,
+    /// Every record of the name, which many servers decline (RFC 8482)
+    Any,
+    /// Reverse lookup of an IP address given in place of the domain
+    #[value(skip)]
+    Ptr,
+}
+
+impl QueryType {
+    pub fn record_type(self) -> RecordType {
+        match self {
+            QueryType::A => RecordType::A,
+            QueryType::Aaaa => RecordType::AAAA,
+            QueryType::Mx => RecordType::MX,
+            QueryType::Txt => RecordType::TXT,
+            QueryType::Ns => RecordType::NS,
+            QueryType::Https => RecordType::HTTPS,
+            QueryType::Any => RecordType::ANY,
+            QueryType::Ptr => RecordType::PTR,
+        }
+    }
+}
+
+/// Ranking metric. Providers without a single answer rank last whatever the metric; ties fall
+/// back to the median, then the success rate, then the average.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// Lowest composite score first, see [`stats::score`]
+    Score,
+    Median,
+    Avg,
+    Min,
+    Max,
+    /// 95th percentile latency, or the maximum with fewer than [`MIN_PERCENTILE_SAMPLES`]
+    /// latencies
+    P95,
+    /// 99th percentile latency, with the same fallback as `P95`
+    P99,
+    /// Highest success rate first
+    Success,
+    Name,
+    Jitter,
+}
+
+/// Whether queries carry an EDNS OPT record, as stub resolvers' queries usually do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EdnsMode {
+    Off,
+    On,
+    /// Test every provider both ways, as separate rows
+    Compare,
+    /// Test every provider without EDNS and with each of `EDNS_BUFFER_SIZES`, as separate rows
+    #[value(skip)]
+    CompareBuffers,
+}
+
+/// How the queries of a provider use their sockets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SocketMode {
+    /// One resolver for all of a provider's queries, which keeps its connections open
+    Reuse,
+    /// A new resolver per query, so nothing carries over from the one before, as with clients
+    /// that open a new socket for every lookup
+    Fresh,
+    /// Test every provider both ways and record the fresh median next to the reused one
+    Compare,
+}
+
+/// UDP payload size that queries with EDNS advertise unless told otherwise, the DNS Flag Day
+/// 2020 recommendation, which avoids IP fragmentation.
+pub const DEFAULT_EDNS_BUFFER: u16 = 1232;
+/// The sizes `EdnsMode::CompareBuffers` tests: the safe default and the traditional 4096, whose
+/// larger answers arrive in fragments that some networks drop.
+pub const EDNS_BUFFER_SIZES: [u16; 2] = [DEFAULT_EDNS_BUFFER, 4096];
+
+/// What to test and how. `Default` gives the built-in standard providers over IPv4 with the
+/// command line's defaults: 5 rounds, a 3 second timeout and a 100 ms cooldown.
+#[derive(Clone, Debug)]
+pub struct SpeedTestConfig {
+    pub providers: Vec<DnsProvider>,
+    pub domains: Vec<String>,
+    /// Shuffle the order of the queries for every provider and round with this seed; `None`
+    /// keeps the configured order
+    pub shuffle_seed: Option<u64>,
+    /// Test the providers in an order fixed by this seed, so none is always measured first on
+    /// an idle network; `None` keeps the configured order. The table is ranked either way.
+    pub provider_order_seed: Option<u64>,
+    /// Record types queried for every domain; each (domain, type) pair is one query
+    pub record_types: Vec<QueryType>,
+    /// Number of times every query is repeated per provider
+    pub rounds: u32,
+    /// Unmeasured queries for the first test domain sent before the rounds, so connection setup
+    /// is paid for up front; 0 leaves it in the first measured query
+    pub warmup: u32,
+    pub timeout: Duration,
+    /// Time a few TCP connections to the provider's port before the rounds
+    pub tcp_check: bool,
+    /// Over DoT and DoH, time one new connection's TCP, TLS and first query apart before the
+    /// rounds, into `connection_split`
+    pub handshake_check: bool,
+    /// Measure the network round trip to each address before the rounds, by ICMP echo where the
+    /// system allows it and TCP connect otherwise
+    pub ping: bool,
+    /// Tries the resolver makes for each query before reporting a timeout
+    pub attempts: usize,
+    pub edns: EdnsMode,
+    /// UDP payload size advertised with EDNS, up to 4096 bytes
+    pub edns_buffer: u16,
+    /// Times a query that timed out or got SERVFAIL is retried, after a short backoff, before it
+    /// counts as failed
+    pub retries: u32,
+    /// Pause between a provider's queries; the pause between rounds is twice as long
+    pub cooldown: Duration,
+    /// Round r (0-based) counts factor^r times in the median and mean
+    pub recency_weight: f64,
+    /// Percentage (0–50) of the fastest and of the slowest answers left out of the average,
+    /// minimum and maximum; the median and percentiles always use every answer
+    pub trim: f64,
+    /// With `Some(factor)`, every failed query counts as an answer taking `factor` times the
+    /// timeout in the average, minimum, maximum, median and percentiles
+    pub failure_penalty: Option<f64>,
+    /// Queries in flight at once per provider within a round; 1 sends them one by one with
+    /// `cooldown` in between, more sends them without cooldowns
+    pub parallel_queries: usize,
+    /// Cap on the queries per second sent to each provider, shared by its queries in flight and
+    /// counting retries and warm-ups; replaces `cooldown` between queries when set
+    pub qps: Option<f64>,
+    /// Query every domain that failed (other than with NXDOMAIN) once more after the rounds;
+    /// those answered move from `failed_domains` to `recovered_domains`
+    pub final_retry: bool,
+    /// Weights of the composite score that `SortKey::Score` ranks by
+    pub score_weights: stats::ScoreWeights,
+    pub transport: Transport,
+    /// Test every provider over each transport it supports instead of only `transport`, as
+    /// separate rows
+    pub compare_transports: bool,
+    /// With `compare_transports`, only the encrypted transports, DoT and DoH
+    pub encrypted_only: bool,
+    /// Rank rows within their transport, the transports in the order of [`Transport::ALL`]
+    pub group_by_transport: bool,
+    /// Number of providers tested at the same time
+    pub concurrency: usize,
+    /// Probe every provider once before the tests, all at the same time, and skip those that
+    /// neither answer a UDP query nor accept a TCP connection within a second; they are
+    /// reported as `unreachable`
+    pub preflight: bool,
+    /// Report every address of a provider instead of only the best one
+    pub all_ips: bool,
+    /// Prefix every query with a random label so it misses the resolver's cache
+    pub cache_bust: bool,
+    /// Count PTR queries answered with NXDOMAIN, i.e. addresses without a PTR record, as
+    /// answered: the resolver gave a definitive answer
+    pub missing_ptr_ok: bool,
+    /// Also run every provider with `cache_bust` and record the uncached median
+    pub compare_cache: bool,
+    pub socket_mode: SocketMode,
+    /// Also run every provider with a DoH endpoint over DoH
+    pub best_transport: bool,
+    /// Benchmark the primary/secondary pair with the primary unreachable
+    pub failover: bool,
+    pub dnssec_check: bool,
+    pub nxdomain_check: bool,
+    /// Random nonexistent names timed per address for `nxdomain_median`; 0 skips them
+    pub nxdomain_probes: u32,
+    /// Query HTTPS (type 65) records for every test domain after the rounds, for
+    /// `https_success_rate` and `https_median`
+    pub https_probe: bool,
+    /// Domains for the ad/tracker blocking test; `None` skips it
+    pub blocking_domains: Option<Vec<String>>,
+    pub qname_min_check: bool,
+    /// Ask every provider which anycast site answers, after the rounds
+    pub pop_check: bool,
+    /// Ask the providers with an identity query to prove they answered, after the rounds
+    pub identity_check: bool,
+    /// Ask every provider for the encrypted endpoints it designates (DDR), after the rounds
+    pub ddr_check: bool,
+    pub cookie_check: bool,
+    /// Send every test query once more after the rounds, over plain UDP, to measure the
+    /// response sizes and count truncated responses
+    pub size_check: bool,
+    pub saturation_test: bool,
+    /// Connect to port 443 of a sample of every provider's answers once all are tested, to see
+    /// whether they lead anywhere
+    pub verify_answers: bool,
+    /// Retry timed-out queries once with this much longer timeout
+    pub escalation_timeout: Option<Duration>,
+    /// Local address that queries, probes and TCP checks are sent from; providers' addresses of
+    /// the other family then count as unreachable
+    pub bind: Option<IpAddr>,
+    pub sort: SortKey,
+    /// Break exact ties in the ranking with a permutation derived from this seed
+    pub tie_break_seed: Option<u64>,
+    /// Stop a provider's rounds once the median's confidence interval is this tight; `None`
+    /// runs every round
+    pub adaptive: Option<stats::Precision>,
+    /// Stop sending a provider's queries after this long; its figures then cover the queries it
+    /// got through
+    pub provider_budget: Option<Duration>,
+    /// Stop sending queries this long after the run starts; providers not started by then are
+    /// reported as not tested
+    pub total_budget: Option<Duration>,
+    /// When the queries stop, checked between them; set from the budgets by [`run_all`] and
+    /// [`test_provider`]
+    pub deadline: Option<Instant>,
+    /// Cancelling it stops the run at the next query; providers that had not finished are left
+    /// out of the results
+    pub cancel: CancellationToken,
+}
+
+impl SpeedTestConfig {
+    /// The transport as reports name it, e.g. "DoT", or "compare" with `compare_transports`.
+    pub fn protocol_label(&self) -> &'static str {
+        if self.compare_transports && self.encrypted_only {
+            "encrypted"
+        } else if self.compare_transports {
+            "compare"
+        } else {
+            self.transport.label()
+        }
+    }
+
+    /// The settings `provider` is tested with: its own timeout, attempts and cooldown where it
+    /// has them, the run's otherwise.
+    pub fn for_provider(&self, provider: &DnsProvider) -> Cow<'_, SpeedTestConfig> {
+        if !provider.has_overrides() {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(SpeedTestConfig {
+            timeout: provider.timeout.unwrap_or(self.timeout),
+            attempts: provider.attempts.unwrap_or(self.attempts),
+            cooldown: provider.cooldown.unwrap_or(self.cooldown),
+            ..self.clone()
+        })
+    }
+}
+
+impl Default for SpeedTestConfig {
+    fn default() -> SpeedTestConfig {
+        SpeedTestConfig {
+            providers: builtin_providers(ProviderSet::Standard, IpVersion::V4),
+            domains: TEST_DOMAINS.iter().map(|domain| domain.to_string()).collect(),
+            shuffle_seed: None,
+            provider_order_seed: None,
+            record_types: vec![QueryType::A],
+            rounds: 5,
+            warmup: 1,
+            timeout: Duration::from_secs(3),
+            tcp_check: true,
+            handshake_check: true,
+            ping: false,
+            attempts: 1,
+            edns: EdnsMode::Off,
+            edns_buffer: DEFAULT_EDNS_BUFFER,
+            retries: 0,
+            cooldown: Duration::from_millis(100),
+            recency_weight: 1.0,
+            trim: 0.0,
+            failure_penalty: None,
+            parallel_queries: 1,
+            qps: None,
+            final_retry: true,
+            score_weights: stats::ScoreWeights::default(),
+            transport: Transport::Udp,
+            compare_transports: false,
+            encrypted_only: false,
+            group_by_transport: false,
+            concurrency: 3,
+            preflight: true,
+            all_ips: false,
+            cache_bust: false,
+            missing_ptr_ok: false,
+            compare_cache: false,
+            socket_mode: SocketMode::Reuse,
+            best_transport: false,
+            failover: false,
+            dnssec_check: true,
+            nxdomain_check: true,
+            nxdomain_probes: 5,
+            https_probe: false,
+            blocking_domains: None,
+            qname_min_check: false,
+            pop_check: true,
+            identity_check: true,
+            ddr_check: false,
+            cookie_check: false,
+            size_check: true,
+            saturation_test: false,
+            verify_answers: false,
+            escalation_timeout: None,
+            bind: None,
+            sort: SortKey::Score,
+            tie_break_seed: None,
+            adaptive: None,
+            provider_budget: None,
+            total_budget: None,
+            deadline: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+}
+
+/// Reported just before each measured query.
+#[derive(Clone, Copy, Debug)]
+pub struct QueryProgress<'a> {
+    pub provider: &'a str,
+    /// 0-based
+    pub round: u32,
+    /// The query, e.g. "github.com", or "github.com/MX" when several record types are tested
+    pub domain: &'a str,
+    /// 1-based number of this query among the provider's `total`
+    pub query: u32,
+    pub total: u32,
+}
+
+/// Reported when a measured query has been answered or has failed.
+#[derive(Clone, Copy, Debug)]
+pub struct QueryOutcome<'a> {
+    pub provider: &'a str,
+    /// The provider's address that was queried
+    pub address: IpAddr,
+    pub transport: Transport,
+    /// Whether the query went out with an EDNS OPT record
+    pub edns: bool,
+    /// UDP payload size the query advertised, `None` without EDNS
+    pub edns_buffer: Option<u16>,
+    /// Whether the name had a random label in front, as with `cache_bust` or for the uncached
+    /// half of `compare_cache`
+    pub cache_bust: bool,
+    /// The query, labelled as in [`QueryProgress`]
+    pub domain: &'a str,
+    /// Its duration and status, as recorded in the provider's `samples`
+    pub sample: &'a Sample,
+    /// Addresses in the answer
+    pub answers: usize,
+}
+
+/// A step of a run, passed to the progress callback.
+#[derive(Clone, Copy, Debug)]
+pub enum Progress<'a> {
+    /// Testing of a provider begins
+    Started(&'a DnsProvider),
+    Query(QueryProgress<'a>),
+    QueryDone(QueryOutcome<'a>),
+    /// A provider is done, with the rows `test_provider` returns for it
+    Finished(&'a DnsProvider, &'a [TestResult]),
+}
+
+/// Tests each of the provider's addresses in turn. Returns one row per address with
+/// `all_ips`, otherwise only the row of the best address, and no rows if the run is cancelled
+/// before the provider is done. Cooldowns only apply between this provider's own queries.
+pub async fn test_provider(provider: &DnsProvider, cfg: &SpeedTestConfig) -> Vec<TestResult> {
+    test_provider_with_progress(provider, cfg, &|_| {}).await
+}
+
+/// [`test_provider`], reporting the start, every query and its outcome, and the end to `on_progress`.
+pub async fn test_provider_with_progress(
+    provider: &DnsProvider,
+    cfg: &SpeedTestConfig,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> Vec<TestResult> {
+    if cfg.cancel.is_cancelled() {
+        return Vec::new();
+    }
+    on_progress(Progress::Started(provider));
+    let mut cfg = cfg.for_provider(provider);
+    if let Some(budget) = cfg.provider_budget {
+        let deadline = Instant::now() + budget;
+        cfg.to_mut().deadline = Some(cfg.deadline.map_or(deadline, |run| run.min(deadline)));
+    }
+    let cfg = &*cfg;
+    let results = if cfg.compare_transports {
+        // Transports without an endpoint are left out rather than reported as failing.
+        let mut results = Vec::new();
+        let transports = Transport::ALL.into_iter()
+            .filter(|&transport| provider.supports(transport))
+            .filter(|&transport| !cfg.encrypted_only || matches!(transport, Transport::Tls | Transport::Https));
+        for transport in transports {
+            let cfg = SpeedTestConfig { transport, ..cfg.clone() };
+            for mut result in test_edns_modes(provider, &cfg, on_progress).await {
+                result.provider = tagged(&result.provider, transport.label());
+                results.push(result);
+            }
+        }
+        results
+    } else {
+        test_edns_modes(provider, cfg, on_progress).await
+    };
+    if cfg.cancel.is_cancelled() {
+        // Cut short, the rows would be ranked on a fraction of the queries.
+        return Vec::new();
+    }
+    on_progress(Progress::Finished(provider, &results));
+    results
+}
+
+/// The rows of `test_provider` for one transport: those of `test_addresses`, once per EDNS
+/// setting with `EdnsMode::Compare` and once per buffer size with `EdnsMode::CompareBuffers`.
+async fn test_edns_modes(
+    provider: &DnsProvider,
+    cfg: &SpeedTestConfig,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> Vec<TestResult> {
+    match cfg.edns {
+        EdnsMode::Compare => {},
+        EdnsMode::CompareBuffers => return test_edns_buffers(provider, cfg, on_progress).await,
+        EdnsMode::Off | EdnsMode::On => return test_addresses(provider, cfg, on_progress).await,
+    }
+    let without = SpeedTestConfig { edns: EdnsMode::Off, ..cfg.clone() };
+    let with = SpeedTestConfig { edns: EdnsMode::On, ..cfg.clone() };
+    let mut without = test_addresses(provider, &without, on_progress).await;
+    let mut with = test_addresses(provider, &with, on_progress).await;
+    for (off, on) in without.iter_mut().zip(&mut with) {
+        on.edns_comparison = Some(EdnsComparison {
+            without_edns_median: off.median_duration,
+            without_edns_success_rate: off.success_rate,
+        });
+        off.provider = tagged(&off.provider, "no EDNS");
+        on.provider = tagged(&on.provider, "EDNS");
+    }
+    without.into_iter().chain(with).collect()
+}
+
+/// The rows of `test_edns_modes` with `EdnsMode::CompareBuffers`: without EDNS, then with each
+/// buffer size. The row without EDNS holds the comparison of all of them.
+async fn test_edns_buffers(
+    provider: &DnsProvider,
+    cfg: &SpeedTestConfig,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> Vec<TestResult> {
+    let without = SpeedTestConfig { edns: EdnsMode::Off, ..cfg.clone() };
+    let mut without = test_addresses(provider, &without, on_progress).await;
+    let mut sized = Vec::new();
+    for size in EDNS_BUFFER_SIZES {
+        let with = SpeedTestConfig { edns: EdnsMode::On, edns_buffer: size, ..cfg.clone() };
+        sized.push(test_addresses(provider, &with, on_progress).await);
+    }
+    for (i, off) in without.iter_mut().enumerate() {
+        let rows = std::iter::once(&*off).chain(sized.iter().filter_map(|rows| rows.get(i)));
+        off.buffer_comparison = Some(BufferComparison {
+            provider: off.provider.clone(),
+            runs: rows.map(BufferRun::of).collect(),
+        });
+        off.provider = tagged(&off.provider, "no EDNS");
+    }
+    for (size, rows) in EDNS_BUFFER_SIZES.iter().zip(&mut sized) {
+        for row in rows {
+            row.provider = tagged(&row.provider, &format!("EDNS {}", size));
+        }
+    }
+    without.into_iter().chain(sized.into_iter().flatten()).collect()
+}
+
+/// "Cloudflare (EDNS)", or "Cloudflare (Family, EDNS)" for names that already have tags.
+fn tagged(name: &str, tag: &str) -> String {
+    match name.strip_suffix(')') {
+        Some(tagged) => format!("{}, {})", tagged, tag),
+        None => format!("{} ({})", name, tag),
+    }
+}
+
+/// The rows of `test_provider` for one transport and EDNS setting.
+async fn test_addresses(
+    provider: &DnsProvider,
+    cfg: &SpeedTestConfig,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> Vec<TestResult> {
+    let mut results = Vec::new();
+    for &ip in &provider.addresses {
+        let mut result = measure::test_address(provider, ip, cfg, on_progress).await;
+        if provider.addresses.len() > 1 {
+            // "Cloudflare (1.0.0.1)", or "Cloudflare (Family, 1.0.0.3)" for tagged names.
+            result.provider = tagged(&provider.name, &ip.to_string());
+        }
+        results.push(result);
+    }
+
+    if let [primary, secondary, ..] = provider.addresses[..] {
+        if cfg.failover && cfg.transport == Transport::Udp && !cfg.cancel.is_cancelled() && !measure::past_deadline(cfg) {
+            results[0].failover = Some(measure::test_failover(primary, secondary, provider.port_for(Transport::Udp), cfg).await);
+        }
+    }
+
+    if !cfg.all_ips && !results.is_empty() {
+        // The addresses of one provider only differ in name by the address itself.
+        let key = if cfg.sort == SortKey::Name { SortKey::Median } else { cfg.sort };
+        results.sort_by(|a, b| measure::compare_by(a, b, key, cfg.tie_break_seed));
+        let mut best = results.swap_remove(0);
+        // The provider took as long as all its addresses did.
+        best.timing = best.timing + results.iter().map(|r| r.timing).sum();
+        best.budget_exceeded |= results.iter().any(|r| r.budget_exceeded);
+        // Configure the best address first and keep the others as fallbacks.
+        best.addresses.extend(results.iter().flat_map(|r| r.addresses.clone()));
+        if let Some(failover) = results.into_iter().find_map(|r| r.failover) {
+            best.failover = Some(failover);
+        }
+        results = vec![best];
+    }
+    results
+}
+
+/// Checks whether the network answers plain DNS itself instead of passing it on, which would
+/// make every provider measure the same resolver. Sends a few queries outside the test.
+pub async fn check_interception(cfg: &SpeedTestConfig) -> InterceptionCheck {
+    probes::probe_interception(&cfg.providers, cfg.bind).await
+}
+
+/// The test domains that resolve through none of `controls`, such as the system's nameservers
+/// and then the first provider: dead domains, which would fail for every provider alike. Sends
+/// one query per domain outside the test.
+pub async fn unresolvable_domains(domains: &[String], controls: &[(IpAddr, u16)], bind: Option<IpAddr>, timeout: Duration) -> Vec<String> {
+    probes::probe_control_domains(domains, controls, bind, timeout).await
+}
+
+/// --check: one UDP query for `domain` to each address of `provider`, and TCP connects to its
+/// DoT and DoH ports where it has those endpoints, each within `timeout`. Sent outside any test.
+pub async fn check_provider(provider: &DnsProvider, domain: &str, bind: Option<IpAddr>, timeout: Duration) -> Vec<AddressCheck> {
+    check::check_provider(provider, domain, bind, timeout).await
+}
+
+/// --failover for `primary` and `secondary` on `port`: the median of one pass over the test
+/// queries with both healthy, and one with the primary replaced by an address that never answers.
+pub async fn test_failover(primary: IpAddr, secondary: IpAddr, port: u16, cfg: &SpeedTestConfig) -> FailoverResult {
+    measure::test_failover(primary, secondary, port, cfg).await
+}
+
+/// All addresses of `host`, without duplicates: from the system resolver, or from the DNS
+/// server at `bootstrap` if given. For setup only; the measurement never looks up a hostname.
+pub async fn lookup_host(host: &str, bootstrap: Option<IpAddr>) -> Result<Vec<IpAddr>, String> {
+    bootstrap::lookup_host(host, bootstrap).await
+}
+
+/// The providers of the --providers-file at `path`, TOML or, with a .json extension, JSON.
+/// Errors name the file and, for a bad entry, its number and name.
+pub fn load_providers_file(path: &Path) -> Result<ProvidersFile, String> {
+    providers_file::load(path)
+}
+
+/// Makes `resolver` answer every later system lookup of [`lookup_host`] in this process, e.g.
+/// a stub in tests; only the first call has an effect.
+pub fn set_system_resolver(resolver: SystemResolver) {
+    bootstrap::set_system_resolver(resolver)
+}
+
+/// Empties the operating system's DNS cache with the platform's commands, each run by `run`
+/// (e.g. [`run_command`]), and returns them; stops at the first one that fails.
+pub fn flush_dns_cache(run: CommandRunner) -> Result<String, String> {
+    flush::flush(run)
+}
+
+/// The IPv4 default gateway, usually the router: from /proc/net/route on Linux, `route` on
+/// macOS and Windows. `None` if there is none or it cannot be read on this platform.
+pub fn default_gateway() -> Option<IpAddr> {
+    gateway::discover()
+}
+
+/// [`default_gateway`] with `run` giving what a command writes to stdout and `read` the text of
+/// a file, e.g. captured output in tests.
+pub fn default_gateway_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<String>) -> Option<IpAddr> {
+    gateway::discover_with(run, read)
+}
+
+/// The servers systemd-resolved forwards to, in the order it lists them: from `resolvectl dns`,
+/// or its upstream resolv.conf without it. An error says why they cannot be found.
+pub fn resolved_upstreams() -> Result<Vec<SocketAddr>, String> {
+    upstreams::discover()
+}
+
+/// [`resolved_upstreams`] on any platform, with `run` giving what a command writes to stdout and
+/// `read` the text of a file, e.g. captured output in tests.
+pub fn resolved_upstreams_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<String>) -> Result<Vec<SocketAddr>, String> {
+    upstreams::discover_with(run, read)
+}
+
+/// The DNS servers the network hands out over DHCP, in the order found: from systemd-networkd's
+/// or dhclient's leases on Linux, `ipconfig` on macOS and Windows. An error says why none could
+/// be read.
+pub fn dhcp_servers() -> Result<Vec<IpAddr>, String> {
+    dhcp::discover()
+}
+
+/// [`dhcp_servers`] with `run` giving what a command writes to stdout and `read` the text of
+/// every file in a directory, e.g. captured leases in tests.
+pub fn dhcp_servers_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<Vec<String>>) -> Result<Vec<IpAddr>, String> {
+    dhcp::discover_with(run, read)
+}
+
+/// A QUIC handshake with `server` for the DoQ endpoint `host`, from `bind` if given: how long
+/// it took, or why it failed within `timeout`. The connection is closed again right after.
+pub async fn quic_handshake(server: SocketAddr, host: &str, bind: Option<IpAddr>, timeout: Duration) -> Result<Duration, String> {
+    quic::handshake(server, host, bind, timeout).await
+}
+
+/// Whether the machine has a route to the IPv6 internet, from `bind` if given; no packet is sent.
+pub fn ipv6_route(bind: Option<IpAddr>) -> bool {
+    check::ipv6_route(bind)
+}
+
+/// The tunnel interfaces that are up and the way out the kernel picks for each of `addresses`,
+/// from `bind` if given, to tell whether the queries to them go through a VPN. Best effort and
+/// without a packet sent; it may run `ifconfig` or `ipconfig`.
+pub fn check_vpn(addresses: impl IntoIterator<Item = IpAddr>, bind: Option<IpAddr>) -> VpnCheck {
+    vpn::check(addresses, bind)
+}
+
+/// The ASN and country of the machine's public IPv4 address, for --submit-include: two DNS
+/// queries to 1.1.1.1, each within `timeout`. The address itself is not returned.
+pub async fn client_network(bind: Option<IpAddr>, timeout: Duration) -> Option<ClientNetwork> {
+    submission::client_network(bind, timeout).await
+}
+
+/// Whether a DNS server at `ip`:`port` answers a query for the root's NS records, with any
+/// response code, within `timeout`; e.g. a router before it is tested as a provider.
+pub async fn answers_dns(ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> bool {
+    let message = raw::message(Name::root(), RecordType::NS, DNSClass::IN);
+    raw::query(ip, port, bind, &message, timeout).await.is_some()
+}
+
+/// The encrypted endpoints the resolver at `ip`:`port` designates for itself by DDR (RFC 9462),
+/// preferred first; `None` if it did not answer within `timeout`.
+pub async fn discover_ddr(ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> Option<Vec<DdrEndpoint>> {
+    ddr::discover(ip, port, bind, timeout).await
+}
+
+/// The endpoints designated in a wire-format response to a DDR query, as [`discover_ddr`]
+/// reads them; `None` if the response does not parse.
+pub fn parse_ddr(response: &[u8]) -> Option<Vec<DdrEndpoint>> {
+    ddr::parse(response)
+}
+
+/// Tests every provider, `concurrency` at a time, and returns the results ranked by `sort`.
+/// Providers that `total_budget` left no time for get a `budget_exceeded` row without queries.
+pub async fn run_all(cfg: &SpeedTestConfig) -> Vec<TestResult> {
+    run_all_with_progress(cfg, &|_| {}).await
+}
+
+/// [`run_all`], reporting every provider's start, queries and end to `on_progress`. Providers
+/// run side by side unless `concurrency` is 1, so their events can interleave.
+pub async fn run_all_with_progress(cfg: &SpeedTestConfig, on_progress: &(dyn Fn(Progress) + Sync)) -> Vec<TestResult> {
+    let mut results = Vec::new();
+    let budgeted;
+    let cfg = match cfg.total_budget {
+        Some(budget) => {
+            budgeted = SpeedTestConfig { deadline: Some(Instant::now() + budget), ..cfg.clone() };
+            &budgeted
+        },
+        None => cfg,
+    };
+    // Dead providers would otherwise cost a timeout per query.
+    let reachable: Vec<bool> = if cfg.preflight {
+        futures::future::join_all(cfg.providers.iter().map(|provider| measure::preflight(provider, cfg))).await
+    } else {
+        vec![true; cfg.providers.len()]
+    };
+    // Collected up front: a mapping closure inside the stream would keep the returned future
+    // from being `Send`.
+    // Shuffled before the queue, so the providers running side by side are a random set too.
+    let tests: Vec<_> = provider_order(cfg).into_iter()
+        .map(|index| (&cfg.providers[index], reachable[index]))
+        .map(|(provider, reachable)| async move {
+            if measure::past_deadline(cfg) {
+                skip_untested(provider, cfg, on_progress)
+            } else if reachable {
+                test_provider_with_progress(provider, cfg, on_progress).await
+            } else {
+                skip_unreachable(provider, cfg, on_progress)
+            }
+        })
+        .collect();
+    let mut pending = stream::iter(tests).buffer_unordered(cfg.concurrency.max(1));
+    while let Some(rows) = pending.next().await {
+        results.extend(rows);
+    }
+
+    measure::discount_dnssec_servfails(&mut results, cfg);
+    results::flag_suspicious_answers(&mut results);
+    if cfg.verify_answers {
+        reachability::verify_answers(&mut results, cfg).await;
+    }
+    results.sort_by(|a, b| measure::compare_results(a, b, cfg));
+    results
+}
+
+/// The row of a provider that failed the pre-flight check, reported like a tested one.
+fn skip_unreachable(provider: &DnsProvider, cfg: &SpeedTestConfig, on_progress: &(dyn Fn(Progress) + Sync)) -> Vec<TestResult> {
+    if cfg.cancel.is_cancelled() {
+        return Vec::new();
+    }
+    on_progress(Progress::Started(provider));
+    let rows = vec![measure::preflight_failed(provider, &cfg.for_provider(provider))];
+    on_progress(Progress::Finished(provider, &rows));
+    rows
+}
+
+/// The row of a provider the run's time budget left no time for.
+fn skip_untested(provider: &DnsProvider, cfg: &SpeedTestConfig, on_progress: &(dyn Fn(Progress) + Sync)) -> Vec<TestResult> {
+    if cfg.cancel.is_cancelled() {
+        return Vec::new();
+    }
+    on_progress(Progress::Started(provider));
+    let rows = vec![measure::not_tested(provider, &cfg.for_provider(provider))];
+    on_progress(Progress::Finished(provider, &rows));
+    rows
+}
+
+/// Pools several runs of `cfg`, each as [`run_all`] returns it: one result per provider row
+/// (name and transport) with figures over the queries of every run, ranked like one run. A row
+/// missing from some runs, e.g. after an interrupted one, pools the runs it is in.
+pub fn pool_runs(runs: &[Vec<TestResult>], cfg: &SpeedTestConfig) -> Vec<TestResult> {
+    let mut rows: Vec<Vec<&TestResult>> = Vec::new();
+    for result in runs.iter().flatten() {
+        match rows.iter_mut().find(|row| row[0].provider == result.provider && row[0].transport == result.transport) {
+            Some(row) => row.push(result),
+            None => rows.push(vec![result]),
+        }
+    }
+    let mut results: Vec<TestResult> = rows.iter().map(|row| measure::pool(row, cfg)).collect();
+    results::flag_suspicious_answers(&mut results);
+    results.sort_by(|a, b| measure::compare_results(a, b, cfg));
+    results
+}
@@ -0,0 +1,37 @@
+//! -v and --log-file: the library's per-query tracing events (and, with -vv, hickory's own) on
+//! stderr and optionally in a file. Without either flag no subscriber is installed, so nothing
+//! is logged.
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// `verbose` is the number of -v flags: 1 logs every query, 2 and more add retries and the
+/// resolver's own events. A log file alone counts as one.
+pub fn init(verbose: u8, log_file: Option<&Path>) -> Result<(), String> {
+    if verbose == 0 && log_file.is_none() {
+        return Ok(());
+    }
+    let filter = match verbose {
+        0 | 1 => Targets::new().with_target("dns_speed_test", LevelFilter::DEBUG),
+        _ => Targets::new()
+            .with_target("dns_speed_test", LevelFilter::TRACE)
+            .with_target("hickory_resolver", LevelFilter::DEBUG)
+            .with_target("hickory_proto", LevelFilter::DEBUG),
+    };
+    let file = match log_file {
+        Some(path) => Some(std::fs::File::create(path).map_err(|e| format!("cannot create log file {}: {}", path.display(), e))?),
+        None => None,
+    };
+    // Always stderr, never stdout, which may carry --json or --csv.
+    let stderr = (verbose > 0).then(|| tracing_subscriber::fmt::layer()
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr).with_filter(filter.clone()));
+    let file = file.map(|file| tracing_subscriber::fmt::layer().with_ansi(false).with_writer(Mutex::new(file)).with_filter(filter));
+    tracing_subscriber::registry().with(stderr).with(file).try_init().map_err(|e| e.to_string())
+}
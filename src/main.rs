@@ -1,209 +1,4649 @@
-use std::time::{Instant, Duration};
-use hickory_resolver::config::{ResolverConfig, ResolverOpts};
-use hickory_resolver::TokioAsyncResolver;
-use hickory_resolver::config::Protocol;
-use std::net::SocketAddr;
-use tokio;
-use tokio::time::sleep;
-use tokio::io::AsyncWriteExt;
+mod apply;
+mod baseline;
+mod color;
+mod config;
+mod daemon;
+mod dead;
+mod diff;
+mod domains;
+mod exporter;
+mod html;
+mod logging;
+mod notify;
+mod report;
+mod sample_export;
+mod samples_file;
+mod submit;
+mod tui;
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use clap::error::ErrorKind;
 use hickory_resolver::Name;
+use dns_speed_test::{
+    add_system_resolvers, answers_dns, builtin_catalog, default_gateway, dhcp_servers, discover_ddr, flush_dns_cache, is_local_cache, is_local_forwarder, load_providers_file, lookup_host, mark_system_resolvers, pool_runs, preset, preset_providers, system_nameservers, valid_doh_path, CancellationToken, compare_by, group_by_organization, provider_order, resolved_upstreams, run_all, run_command, run_all_with_progress, unresolvable_domains, check_provider, ipv6_route, AddressCheck, DnsProvider, Dns64Status, DnssecStatus,
+    check_interception, check_vpn, ConnectionSplit, DomainStats, EdnsMode, Egress, FailureKind, InterceptionCheck, IpVersion, Preset, Progress, ProviderSet, ProvidersMode, QueryType, RttMethod, SampleStatus, DdrEndpoint, SocketMode, SortKey, SpeedTestConfig, TestResult, Transport, VpnCheck, DEFAULT_PRESET, MAX_ATTEMPTS, PRESETS,
+    history, run_throughput, stats, stats::{Precision, ScoreWeights}, client_network, submission, ClientField, LoadProfile, ThroughputResult, ThroughputStep, BLOCKING_TEST_DOMAINS, DEFAULT_EDNS_BUFFER, EDNS_BUFFER_SIZES, MAX_TARGET_QPS, MIN_PERCENTILE_SAMPLES, THROUGHPUT_STEPS, PTR_TARGETS, SYSTEMD_RESOLVED_STUB,
+    TEST_DOMAINS,
+};
+use tokio::time::sleep;
+use color::{Color, ColorChoice, Palette};
+use samples_file::SamplesFile;
+use report::{JsonConfig, JsonEndpoint, JsonOutput, JsonPlan, JsonPlannedProvider, JsonProvider, JsonProviderSettings, JsonReport, JsonSample, JsonSummary, RelativeToFastest};
+
+const DEFAULT_ROUNDS: u32 = 5;
+/// Rounds with --domain, which has far fewer queries per round.
+const SINGLE_DOMAIN_ROUNDS: u32 = 20;
+/// Rounds with --edns-buffer compare, which tests every provider three times over.
+const BUFFER_COMPARE_ROUNDS: u32 = 3;
+/// Success rate (percent) a provider needs to be recommended
+const DEFAULT_MIN_SUCCESS: f64 = 90.0;
+const DEFAULT_TIMEOUT: &str = "3s";
+const DEFAULT_COOLDOWN_MS: u64 = 100;
+const MAX_COOLDOWN_MS: u64 = 60_000;
+const DEFAULT_CONCURRENCY: u64 = 3;
+const MAX_CONCURRENCY: u64 = 32;
+const MAX_PARALLEL_QUERIES: u64 = 256;
+/// A provider whose last round's median is this many percent above its first round's gets a
+/// warning.
+const ROUND_TREND_WARNING: f64 = 50.0;
+/// Queries listed by name under a row whose answers were replaced between rounds.
+const UNSTABLE_ANSWERS_SHOWN: usize = 5;
+const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long --include-gateway waits for the gateway's DNS answer.
+const GATEWAY_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the control check waits for each control's answer about a test domain.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long --protocol auto waits for each provider's DDR answer.
+const DDR_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_LOAD_DURATION: &str = "10s";
+const MAX_LOAD_DURATION: Duration = Duration::from_secs(300);
+const DEFAULT_TARGET_QPS: f64 = 100.0;
+const MAX_NAME_WIDTH: usize = 48;
+/// Invalid arguments, or a report, database or config file that cannot be read or written.
+const EXIT_ERROR: i32 = 1;
+/// No provider answered a single query, most likely because there is no network.
+const EXIT_NO_ANSWERS: i32 = 2;
+/// Even the fastest provider's median is above --fail-threshold.
+const EXIT_TOO_SLOW: i32 = 3;
+/// --check found fewer providers reachable than --min-reachable.
+const EXIT_CHECK_FAILED: i32 = 4;
+/// The longest --check waits for each of its probes, shorter with a shorter --timeout.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  the test ran (and, with --fail-threshold, the fastest median is within it)
+  1  invalid arguments, or a file or database that cannot be read or written
+  2  no provider answered a single query (likely no network)
+  3  the fastest provider's median is above --fail-threshold
+  4  --check: fewer providers reachable than --min-reachable
+
+The diff subcommand has its own: 0 no regression, 1 a regression, 2 an error.";
+/// Width the per-domain matrix and the histograms fit when `COLUMNS` does not give the
+/// terminal's.
+const DEFAULT_TERMINAL_WIDTH: usize = 100;
+/// About how many bars a --histogram has; the exact number depends on the spread.
+const HISTOGRAM_BUCKETS: usize = 10;
+/// Medians of one domain this close to the fastest one tie with it in --winners.
+const WINNER_TIE: Duration = Duration::from_millis(1);
+/// How long looking up a --server hostname may take before the server is skipped.
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest the VPN check may hold up a run, e.g. on a slow `ipconfig`; it is left out after.
+const VPN_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Benchmark popular DNS resolvers", args_conflicts_with_subcommands = true, after_help = EXIT_STATUS_HELP)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Log every query (provider, domain, round, outcome and duration) to stderr; -vv adds
+    /// retries and the resolver's own events
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Also write the -v log to this file (implies -v, but only the file gets it then)
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Read settings from this TOML file instead of ~/.config/dnsspeedtest/config.toml; flags
+    /// given on the command line and DNSSPEEDTEST_* environment variables override it
+    #[arg(long, value_name = "FILE", env = "DNSSPEEDTEST_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Print a commented config file template and exit
+    #[arg(long)]
+    print_default_config: bool,
+
+    /// Print a man page (roff) covering every flag and its default and exit, e.g. for
+    /// `dns_speed_test --man > dns_speed_test.1`
+    #[arg(long)]
+    man: bool,
+
+    /// Print an example JSON report with every field present and exit, for tools reading the
+    /// output; its `schema_version` goes up when a field is renamed or removed
+    #[arg(long)]
+    schema: bool,
+
+    /// Print the built-in providers with their addresses and exit; with --preset, only the ones
+    /// that would be tested
+    #[arg(long)]
+    list_providers: bool,
+
+    /// Check the providers and the network instead of measuring, then exit: one query per
+    /// provider address, the interception check, the system resolver, and whether IPv6, DoT
+    /// (853) and DoH (443) get through
+    #[arg(long, conflicts_with_all = ["dry_run", "watch", "exporter", "daemon", "once", "tui"])]
+    check: bool,
+
+    /// With --check, exit with status 4 if fewer than N providers answer
+    #[arg(long, default_value_t = 1, value_name = "N", requires = "check")]
+    min_reachable: usize,
+
+    /// Work out the providers, domains and settings from the defaults, the config file, the
+    /// environment and the flags, print that plan and exit without sending a query; as JSON with
+    /// --format json
+    #[arg(long, conflicts_with_all = ["tui", "watch", "exporter", "daemon", "once"])]
+    dry_run: bool,
+
+    /// Only test these built-in providers (comma-separated names, case-insensitive); custom
+    /// servers are still tested
+    #[arg(long, value_delimiter = ',', value_name = "NAMES", conflicts_with = "exclude", env = "DNSSPEEDTEST_INCLUDE")]
+    include: Vec<String>,
+
+    /// Skip these providers (comma-separated names, case-insensitive), including custom servers
+    /// and "System"
+    #[arg(long, value_delimiter = ',', value_name = "NAMES", env = "DNSSPEEDTEST_EXCLUDE")]
+    exclude: Vec<String>,
+
+    /// Domains queried in every round; only set from the config file
+    #[arg(skip = TEST_DOMAINS.iter().map(|domain| domain.to_string()).collect::<Vec<_>>())]
+    domains: Vec<String>,
+
+    /// Where --domains-source took `domains` from, for the header and the JSON report
+    #[arg(skip)]
+    domains_origin: Option<String>,
+
+    /// How many entries `domains` had before cleaning, when cleaning dropped some
+    #[arg(skip)]
+    domains_entries: Option<usize>,
+
+    /// What the pre-flight interception check found, for the reports
+    #[arg(skip)]
+    interception: Option<InterceptionCheck>,
+
+    /// The tunnels that are up and the way out to each provider address, for the reports
+    #[arg(skip)]
+    vpn: Option<VpnCheck>,
+
+    /// Test domains the control check left out, for the reports
+    #[arg(skip)]
+    unresolvable_domains: Vec<String>,
+
+    /// The machine's configured nameservers, to point out the current provider in the report
+    #[arg(skip)]
+    nameservers: Vec<std::net::IpAddr>,
+
+    /// The providers read from --providers-file
+    #[arg(skip)]
+    file_providers: Vec<DnsProvider>,
+
+    /// Add a custom DNS server to test, as NAME=IP or just IP (repeatable). A port other than 53
+    /// goes after the address, e.g. 192.168.1.2:5353 or [::1]:5353. A hostname such as
+    /// abc123.dns.nextdns.io is resolved at startup and also used for DoT and DoH. A DoH URL such
+    /// as https://dns.nextdns.io/abc123 is only a DoH endpoint, queried at its path; add @IP to
+    /// use that address instead of resolving the host. Append ;timeout=500ms, ;attempts=N or
+    /// ;cooldown=MS to test this server with its own settings instead of the run's. Several
+    /// servers can also go in one value, separated by commas
+    #[arg(long = "server", value_parser = parse_server, value_delimiter = ',', value_name = "[NAME=]HOST[:PORT]|URL[@IP][;KEY=VALUE]", env = "DNSSPEEDTEST_SERVER")]
+    servers: Vec<DnsProvider>,
+
+    /// Test the providers defined in this TOML (or .json) file: a `providers` list of entries
+    /// with a name, addresses and optionally dot_host, doh_url, doq_host and port
+    #[arg(long, value_name = "FILE", env = "DNSSPEEDTEST_PROVIDERS_FILE")]
+    providers_file: Option<PathBuf>,
+
+    /// Whether the --providers-file entries extend or replace the built-in providers, overriding
+    /// the file's `mode` key [default: extend]
+    #[arg(long, value_enum, value_name = "MODE", requires = "providers_file")]
+    providers_mode: Option<ProvidersMode>,
+
+    /// Resolve --server hostnames with the DNS server at this IP instead of the system resolver
+    #[arg(long, value_name = "IP")]
+    bootstrap: Option<std::net::IpAddr>,
+
+    /// Only test the servers given with --server or --providers-file (or in the config file),
+    /// skipping the built-in providers and the system resolver
+    #[arg(long, env = "DNSSPEEDTEST_ONLY_CUSTOM")]
+    only_custom: bool,
+
+    /// Do not test the system's configured nameservers
+    #[arg(long, env = "DNSSPEEDTEST_NO_SYSTEM")]
+    no_system: bool,
+
+    /// Also test the default gateway, e.g. a router or Pi-hole, if it answers DNS on port 53;
+    /// the summary then says what it adds over querying the fastest public provider directly
+    #[arg(long)]
+    include_gateway: bool,
+
+    /// Also test the DNS servers the network hands out over DHCP, from the current leases, as
+    /// "DHCP (IP)"; they may differ from the ones in use after a VPN or a manual setting
+    #[arg(long, alias = "providers-from-dhcp")]
+    include_dhcp: bool,
+
+    /// If systemd-resolved's stub at 127.0.0.53 is tested, also test the upstream servers it
+    /// forwards to, found with resolvectl, as "IP (via systemd-resolved)"
+    #[arg(long)]
+    resolve_upstreams: bool,
+
+    /// The gateway --include-gateway added as a provider
+    #[arg(skip)]
+    gateway: Option<std::net::IpAddr>,
+
+    /// Flush the operating system's DNS cache before testing (ipconfig /flushdns, dscacheutil
+    /// and mDNSResponder, or resolvectl flush-caches); a failed flush is reported, not fatal
+    #[arg(long)]
+    flush_cache: bool,
+
+    /// Whether --flush-cache succeeded, for the JSON report
+    #[arg(skip)]
+    cache_flushed: bool,
+
+    /// How long the run takes, as a bundle of settings: `fast` is 2 rounds of 5 domains with a
+    /// 50 ms cooldown, 4 providers at a time; `thorough` 10 rounds of every domain, one provider
+    /// at a time. --rounds, --cooldown, --concurrency, the config file and the domain settings
+    /// override it
+    #[arg(long, value_enum, default_value_t = Profile::Default, env = "DNSSPEEDTEST_PROFILE")]
+    profile: Profile,
+
+    /// The header's estimate of how long the rounds take, for the reports
+    #[arg(skip)]
+    estimated_runtime: Duration,
+
+    /// How long the run took from the end of the setup to the results, for the reports
+    #[arg(skip)]
+    elapsed: Option<Duration>,
+
+    /// How long the setup before the rounds took, for the reports
+    #[arg(skip)]
+    setup_elapsed: Option<Duration>,
+
+    /// Number of times every domain is queried per provider
+    #[arg(long, default_value_t = DEFAULT_ROUNDS, value_parser = clap::value_parser!(u32).range(1..), env = "DNSSPEEDTEST_ROUNDS")]
+    rounds: u32,
+
+    /// Run the whole test N times and report every provider's figures over the queries of all
+    /// runs, with the spread of the per-run medians
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["watch", "exporter", "tui"])]
+    repeat: u32,
+
+    /// Seconds to wait between two --repeat runs
+    #[arg(long, default_value_t = 10, value_name = "SECONDS", requires = "repeat")]
+    repeat_pause: u64,
+
+    /// Save every run's results as run-1.json, run-2.json, ... in DIR, for analysis of the runs
+    /// one by one
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["watch", "exporter", "tui"])]
+    save_runs: Option<PathBuf>,
+
+    /// Unmeasured queries sent to each provider before the rounds to set up the connection
+    #[arg(long, default_value_t = 1, value_name = "N", env = "DNSSPEEDTEST_WARMUP")]
+    warmup: u32,
+
+    /// Per-query timeout, e.g. 3, 2.5s or 500ms (plain numbers are seconds, max 60s)
+    #[arg(long, default_value = DEFAULT_TIMEOUT, value_parser = parse_timeout, value_name = "DURATION", env = "DNSSPEEDTEST_TIMEOUT")]
+    timeout: Duration,
+
+    /// Stop a provider's rounds early once its median is known well enough: after 15 answers,
+    /// as soon as the median's 95% confidence interval reaches at most PRECISION from it, e.g.
+    /// 1ms or 10% (the default). Providers with noisy latencies still run every round
+    #[arg(long, value_name = "PRECISION", num_args = 0..=1, default_missing_value = "10%", value_parser = parse_precision)]
+    adaptive: Option<Precision>,
+
+    /// Stop testing a provider after this long, e.g. 30s; its figures cover the queries it got
+    /// through and its row says it was cut short. Checked between queries
+    #[arg(long, value_name = "DURATION", value_parser = parse_budget)]
+    provider_budget: Option<Duration>,
+
+    /// Stop the whole test after this long, e.g. 5m; providers not started by then are listed
+    /// as not tested and the ones under way are cut short as with --provider-budget
+    #[arg(long, value_name = "DURATION", value_parser = parse_budget)]
+    total_budget: Option<Duration>,
+
+    /// Also test the providers that answered nothing in an earlier run; they are skipped for
+    /// --dead-expiry otherwise
+    #[arg(long)]
+    retest_dead: bool,
+
+    /// How long a provider that answered nothing stays skipped, e.g. 12h or 7d
+    #[arg(long, value_name = "DURATION", default_value = "7d", value_parser = parse_expiry)]
+    dead_expiry: Duration,
+
+    /// Time three TCP connections to each provider before the rounds and show the median; the
+    /// result never affects the DNS queries
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, value_parser = clap::builder::BoolishValueParser::new(), value_name = "on|off")]
+    tcp_check: bool,
+
+    /// Over DoT and DoH, time one new connection to each provider before the rounds: TCP
+    /// connect, TLS handshake and its first query, shown next to the steady median of the rounds
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, value_parser = clap::builder::BoolishValueParser::new(), value_name = "on|off")]
+    handshake_check: bool,
+
+    /// Measure the network round trip to each provider before the rounds (three ICMP echoes, or
+    /// TCP connects where ICMP is not permitted or unanswered) and show it next to the resolver
+    /// overhead, the median less that round trip
+    #[arg(long)]
+    ping: bool,
+
+    /// Tries the resolver makes for each query before it counts as timed out (max 10)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=MAX_ATTEMPTS as u64), value_name = "N", env = "DNSSPEEDTEST_ATTEMPTS")]
+    attempts: u64,
+
+    /// Send queries with an EDNS OPT record (a 1232-byte buffer unless --edns-buffer says
+    /// otherwise), or compare both ways as separate rows
+    #[arg(long, value_enum, default_value_t = EdnsMode::Off)]
+    edns: EdnsMode,
+
+    /// UDP payload size advertised with EDNS, 512 to 4096 bytes [default: 1232], or `compare`
+    /// to test every provider without EDNS and at 1232 and 4096 bytes as separate rows (3 rounds
+    /// by default). Some networks drop the fragments of large UDP answers
+    #[arg(long, value_name = "BYTES|compare", value_parser = parse_edns_buffer)]
+    edns_buffer: Option<EdnsBuffer>,
+
+    /// Retry a query that timed out or got SERVFAIL up to N times, with a short backoff, before
+    /// counting it as failed (max 10)
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(..=10), value_name = "N", env = "DNSSPEEDTEST_RETRIES")]
+    retries: u32,
+
+    /// Do not query the failed domains once more after a provider's rounds. By default those
+    /// answered then are listed as recovered rather than failed; their failed queries still
+    /// count against the success rate. Domains that got NXDOMAIN are never retried
+    #[arg(long)]
+    no_final_retry: bool,
+
+    /// Pause between queries in milliseconds (doubled between rounds, max 60000)
+    #[arg(long, default_value_t = DEFAULT_COOLDOWN_MS, value_parser = clap::value_parser!(u64).range(..=MAX_COOLDOWN_MS), value_name = "MS", env = "DNSSPEEDTEST_COOLDOWN")]
+    cooldown: u64,
+
+    /// Send each provider at most N queries per second, spaced evenly, also with
+    /// --parallel-queries; replaces --cooldown. Keeps bursts from tripping resolver rate limits
+    #[arg(long, value_name = "N", value_parser = parse_qps, env = "DNSSPEEDTEST_QPS")]
+    qps: Option<f64>,
+
+    /// Weight later rounds more heavily in the median/mean: round r (0-based) counts factor^r times.
+    /// The default of 1.0 weights every round equally.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_recency_weight, value_name = "FACTOR")]
+    recency_weight: f64,
+
+    /// Leave the fastest and the slowest PERCENT of every provider's answers out of its average,
+    /// min and max (at least 3 answers are kept). The median and percentiles use every answer
+    #[arg(long, default_value_t = 0.0, value_parser = parse_trim, value_name = "PERCENT")]
+    trim: f64,
+
+    /// Count every failed query as an answer that took FACTOR times the timeout (1 if omitted) in
+    /// the average, median, min, max and percentiles, so unreliable providers rank low. The
+    /// success rate is unchanged, and JSON keeps the median of the answers alone
+    #[arg(long, value_name = "FACTOR", num_args = 0..=1, default_missing_value = "1", value_parser = parse_penalty)]
+    penalize_failures: Option<f64>,
+
+    /// Also benchmark each provider's primary/secondary pair as a dual-server config, once with
+    /// both healthy and once with the primary replaced by an address that drops every query, for
+    /// a Failover column next to the median; it never counts towards the ranking
+    #[arg(long, visible_alias = "failover-test")]
+    failover: bool,
+
+    /// Comma-separated list of result columns to show, in order (e.g. provider,median,success),
+    /// or all for every column; CSV output then has the same columns
+    #[arg(long, value_delimiter = ',', value_parser = parse_column, value_name = "LIST", env = "DNSSPEEDTEST_COLUMNS")]
+    columns: Vec<ColumnChoice>,
+
+    /// Show only the N best providers by the --sort metric in the table and summary; the
+    /// unreliable ones only fill the slots the others leave. 0 shows all. JSON and CSV always
+    /// list every provider
+    #[arg(long, value_name = "N", env = "DNSSPEEDTEST_TOP")]
+    top: Option<usize>,
+
+    /// Query known ad/tracker domains against each provider and report the share it blocks
+    #[arg(long)]
+    blocking_test: bool,
+
+    /// Ad/tracker domains used by --blocking-test (comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "DOMAINS", default_values_t = BLOCKING_TEST_DOMAINS.map(String::from), requires = "blocking_test")]
+    blocking_domains: Vec<String>,
+
+    /// Skip the check that nonexistent names get NXDOMAIN rather than a redirect address
+    #[arg(long)]
+    no_nxdomain_check: bool,
+
+    /// Random nonexistent names queried per provider outside the rounds, timing how long the
+    /// resolver takes to say NXDOMAIN (shown with --stats full); 0 skips them
+    #[arg(long, default_value_t = 5, value_name = "N")]
+    nxdomain_probes: u32,
+
+    /// Also query HTTPS (type 65) records, which browsers ask for on every navigation, for every
+    /// test domain after the rounds, and show per provider the share answered correctly
+    /// (records, or a clean NODATA for a domain without any) and their median (with --stats full)
+    #[arg(long)]
+    https_probe: bool,
+
+    /// After the test, try a TCP connection to port 443 of one public address per domain and
+    /// family of every provider's answers, and show per provider how many were reachable; a
+    /// refused connection counts as reachable. Kept out of the latency figures
+    #[arg(long)]
+    verify_answers: bool,
+
+    /// Skip the DNSSEC validation probe (two extra queries per provider)
+    #[arg(long)]
+    no_dnssec_check: bool,
+
+    /// Skip asking each provider which anycast site (POP) serves you (up to three extra queries
+    /// per provider)
+    #[arg(long)]
+    no_pop_check: bool,
+
+    /// Skip asking the providers that have a way to prove it (Cloudflare, Google, OpenDNS, ...)
+    /// whether it really was them that answered (one extra query per provider)
+    #[arg(long)]
+    no_identity_check: bool,
+
+    /// Ask every provider for the encrypted endpoints it designates by DDR (an SVCB query for
+    /// _dns.resolver.arpa, one extra query per provider) and list them under its row
+    #[arg(long)]
+    ddr: bool,
+
+    /// Skip measuring response sizes and counting truncated responses (every test query is
+    /// sent once more over UDP, after the rounds)
+    #[arg(long)]
+    no_size_check: bool,
+
+    /// Skip the pre-flight check for a network that answers plain DNS itself (a query to an
+    /// address without a DNS server and a whoami query to each provider)
+    #[arg(long)]
+    no_interception_check: bool,
+
+    /// Skip resolving every test domain once through the system resolver before the rounds,
+    /// which leaves out the domains that fail there and at the first provider too
+    #[arg(long)]
+    no_control_check: bool,
+
+    /// Probe whether each provider uses QNAME minimisation (via qnamemintest.internet.nl)
+    #[arg(long)]
+    qname_min_check: bool,
+
+    /// Retry queries that time out once more with a much longer timeout, and report the ones
+    /// that eventually answer as slow rather than failed
+    #[arg(long)]
+    timeout_escalation: bool,
+
+    /// Timeout in seconds for the escalated retry
+    #[arg(long, default_value_t = 15, value_name = "SECS", requires = "timeout_escalation")]
+    escalation_timeout: u64,
+
+    /// Print a ready-to-use resolver config snippet for the fastest provider
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    emit_config: Option<ConfigFormat>,
+
+    /// Make the fastest provider the system resolver after the test (asks for confirmation)
+    #[arg(long)]
+    apply: bool,
+
+    /// Only print the commands --apply would run
+    #[arg(long, conflicts_with = "apply")]
+    apply_dry_run: bool,
+
+    /// Apply without asking for confirmation
+    #[arg(long, requires = "apply")]
+    yes: bool,
+
+    /// Also benchmark each provider's DoH endpoint and report whether UDP or DoH is faster
+    #[arg(long, conflicts_with = "protocol")]
+    best_transport: bool,
+
+    /// Never recommend providers below this success rate (percent): they are listed after the
+    /// others and flagged as unreliable. 0 ranks every provider together
+    #[arg(long, default_value_t = DEFAULT_MIN_SUCCESS, value_parser = parse_percent, value_name = "PERCENT", env = "DNSSPEEDTEST_MIN_SUCCESS")]
+    min_success: f64,
+
+    /// Aggressive: ramp up concurrent in-flight queries to each provider (1, 2, 4, ... 128) until
+    /// latency doubles or more than 10% of queries fail, and report where performance breaks down
+    #[arg(long)]
+    saturation_test: bool,
+
+    /// Print the resolver's full error message for every failed query
+    #[arg(long)]
+    show_error_text: bool,
+
+    /// Also print a matrix of every domain's median per provider, marking failed queries
+    #[arg(long)]
+    per_domain: bool,
+
+    /// Also list which provider had the lowest median for each domain, by how much it beat the
+    /// runner-up, and how many domains each provider won
+    #[arg(long)]
+    winners: bool,
+
+    /// Also print a histogram of every provider's answered query latencies
+    #[arg(long)]
+    histogram: bool,
+
+    /// Send a query carrying an RFC 7873 client cookie to each provider and report whether it
+    /// answers with a server cookie
+    #[arg(long)]
+    cookie_check: bool,
+
+    /// Protocol used to query each provider; DoT (port 853), DoH (port 443) and DoQ (UDP port
+    /// 853) skip providers without a known endpoint
+    #[arg(long, value_enum, default_value_t = Protocol::Udp, env = "DNSSPEEDTEST_PROTOCOL")]
+    protocol: Protocol,
+
+    /// With --protocol compare, rank the providers within each protocol instead of all
+    /// provider-protocol rows together
+    #[arg(long)]
+    group_by_protocol: bool,
+
+    /// Also sum up the rows of each organization, such as Cloudflare's variants, families and
+    /// addresses, in one line with their best and worst median and the fastest of them
+    #[arg(long, value_enum, default_value_t = GroupBy::None)]
+    group_by: GroupBy,
+
+    /// List the rows by the local address and interface the kernel sends each provider's
+    /// queries from, so a split-tunnel VPN shows which providers go through the tunnel and
+    /// which go direct
+    #[arg(long, conflicts_with = "group_by_protocol")]
+    split_by_egress: bool,
+
+    /// Number of providers tested at the same time; 1 tests them one after another
+    #[arg(long, default_value_t = DEFAULT_CONCURRENCY, value_parser = clap::value_parser!(u64).range(1..=MAX_CONCURRENCY), env = "DNSSPEEDTEST_CONCURRENCY")]
+    concurrency: u64,
+
+    /// Test every provider fully, even those that neither answered one UDP query nor accepted a
+    /// TCP connection within a second in the pre-flight check, which are otherwise skipped and
+    /// reported as unreachable
+    #[arg(long)]
+    no_preflight: bool,
+
+    /// Queries each provider has in flight at once within a round, like a browser opening a
+    /// page; latencies are then measured under that load. 1 sends them one by one with
+    /// --cooldown in between
+    #[arg(long, default_value_t = 1, value_name = "N", value_parser = clap::value_parser!(u64).range(1..=MAX_PARALLEL_QUERIES))]
+    parallel_queries: u64,
+
+    /// Which variants of the built-in providers to test
+    #[arg(long, value_enum, default_value_t = ProviderSet::Standard, env = "DNSSPEEDTEST_SET")]
+    set: ProviderSet,
+
+    /// Which built-in providers to test: global (the default), eu, us, asia or privacy. The
+    /// preset's providers are then narrowed by --set and --include/--exclude
+    #[arg(long, value_parser = parse_preset, value_name = "NAME", env = "DNSSPEEDTEST_PRESET")]
+    preset: Option<&'static Preset>,
+
+    /// Show every address of a provider as its own row instead of only the best one
+    #[arg(long)]
+    all_ips: bool,
+
+    /// Address family to test: 4, 6, or both (each provider then appears once per family)
+    #[arg(long, value_enum, default_value_t = IpVersion::V4, env = "DNSSPEEDTEST_IP_VERSION")]
+    ip_version: IpVersion,
+
+    /// Send queries and TCP checks from this local address, e.g. to measure over one uplink of
+    /// a multihomed machine; providers' addresses of the other family are skipped
+    #[arg(long, value_name = "ADDRESS", env = "DNSSPEEDTEST_BIND")]
+    bind: Option<std::net::IpAddr>,
+
+    /// Like --bind, with the first global address of the network interface NAME
+    #[arg(long, value_name = "NAME", conflicts_with = "bind")]
+    interface: Option<String>,
+
+    /// Rerun the test every SECONDS and print a compact summary per cycle until interrupted
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["csv", "emit_config", "output", "save_baseline", "compare", "apply", "apply_dry_run"])]
+    watch: Option<u64>,
+
+    /// Rerun the test every --interval and publish the results as Prometheus metrics
+    ///
+    /// Metrics go to an HTTP endpoint (--listen, default 0.0.0.0:9753, path /metrics) and/or a
+    /// file (--textfile). Their names and labels are stable:
+    ///
+    ///   dnsspeedtest_median_ms{provider}                   gauge, last run
+    ///   dnsspeedtest_avg_ms{provider}                      gauge, last run
+    ///   dnsspeedtest_min_ms{provider}                      gauge, last run
+    ///   dnsspeedtest_max_ms{provider}                      gauge, last run
+    ///   dnsspeedtest_success_ratio{provider}               gauge, 0 to 1
+    ///   dnsspeedtest_query_failures_total{provider}        counter since start
+    ///   dnsspeedtest_queries_total{provider}               counter since start
+    ///   dnsspeedtest_last_run_timestamp_seconds            gauge, Unix time
+    ///   dnsspeedtest_last_run_duration_seconds             gauge
+    ///
+    /// The latency gauges are left out for providers that answered no query.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["watch", "csv", "emit_config", "output", "save_baseline", "compare", "apply", "apply_dry_run"])]
+    exporter: bool,
+
+    /// Time between the start of two --exporter or --daemon runs, e.g. 30m, or a number of
+    /// seconds
+    #[arg(long, default_value = "300", value_name = "INTERVAL", value_parser = parse_interval, env = "DNSSPEEDTEST_INTERVAL")]
+    interval: Duration,
+
+    /// Rerun the test every --interval as a service, never prompting, and append one JSON line
+    /// per provider and run to a file per day in --log-dir; SIGTERM or Ctrl+C stops it cleanly
+    #[arg(long, requires = "log_dir", conflicts_with_all = ["watch", "exporter", "tui", "csv", "emit_config", "output", "save_baseline", "compare", "apply", "apply_dry_run"])]
+    daemon: bool,
+
+    /// Like --daemon, but a single run, e.g. under a systemd timer
+    #[arg(long, requires = "log_dir", conflicts_with_all = ["daemon", "watch", "exporter", "tui", "csv", "emit_config", "output", "save_baseline", "compare", "apply", "apply_dry_run"])]
+    once: bool,
+
+    /// With --watch or --daemon, POST a JSON notification to URL when the fastest provider
+    /// changes, the system resolver gets slower than --alert-threshold or a provider's success
+    /// rate drops below --alert-min-success (and when that is over)
+    #[arg(long, value_name = "URL", env = "DNSSPEEDTEST_NOTIFY_WEBHOOK")]
+    notify_webhook: Option<String>,
+
+    /// Like --notify-webhook, but run this command, split at whitespace (no shell), with
+    /// {event}, {provider}, {median}, {success} and {message} replaced, e.g. "notify.sh {provider} {median}"
+    #[arg(long, value_name = "COMMAND")]
+    notify_cmd: Option<String>,
+
+    /// Notify when the system resolver's median is above MS
+    #[arg(long, value_name = "MS", value_parser = parse_fail_threshold)]
+    alert_threshold: Option<f64>,
+
+    /// Notify when a provider answers less than PCT percent of queries [default: --min-success]
+    #[arg(long, value_name = "PCT", value_parser = parse_percent)]
+    alert_min_success: Option<f64>,
+
+    /// Directory of the --daemon and --once logs, dnsspeedtest-YYYY-MM-DD.jsonl (UTC dates)
+    #[arg(long, value_name = "DIR", env = "DNSSPEEDTEST_LOG_DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// Delete --daemon and --once logs of days more than N days back
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), requires = "log_dir")]
+    retention_days: Option<u32>,
+
+    /// Address of the --exporter HTTP endpoint [default: 0.0.0.0:9753 unless --textfile is given]
+    #[arg(long, value_name = "ADDR", requires = "exporter", env = "DNSSPEEDTEST_LISTEN")]
+    listen: Option<std::net::SocketAddr>,
+
+    /// Also write the --exporter metrics to this file (atomically), e.g. for node_exporter's
+    /// textfile collector
+    #[arg(long, value_name = "PATH", requires = "exporter")]
+    textfile: Option<PathBuf>,
+
+    /// Show the run full-screen: results that re-sort as providers finish, progress and the
+    /// latest latencies. q stops early with the results so far, s cycles the sort. Falls back to
+    /// the plain output when stdout is not a terminal
+    #[arg(long, conflicts_with_all = ["watch", "exporter", "json", "csv"])]
+    tui: bool,
+
+    /// Color the table report: medians green, yellow or red, success rates below 100% red and
+    /// the recommended provider bold. JSON and CSV are never colored
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, value_name = "WHEN", env = "DNSSPEEDTEST_COLOR")]
+    color: ColorChoice,
+
+    /// Output format for the final report
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table, env = "DNSSPEEDTEST_FORMAT")]
+    format: OutputFormat,
+
+    /// Shorthand for --format json
+    #[arg(long, conflicts_with = "format")]
+    json: bool,
+
+    /// Print only one summary line, `fastest=NAME ip=ADDRESS median_ms=X success=Y`, or with
+    /// --json only that as an object: no progress lines, table or pause. Errors still go to stderr
+    #[arg(long, visible_alias = "summary-only", conflicts_with_all = ["tui", "watch", "exporter", "pause", "apply", "apply_dry_run"], env = "DNSSPEEDTEST_QUIET")]
+    quiet: bool,
+
+    /// Also write the results as a self-contained HTML page, with a sortable table and a chart
+    /// of the medians, to FILE
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["watch", "exporter"])]
+    html: Option<PathBuf>,
+
+    /// Write one CSV row per provider to FILE, or to stdout if FILE is omitted
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    csv: Option<Option<PathBuf>>,
+
+    /// Append to the --csv file instead of overwriting it; the header is only written when the
+    /// file is new
+    #[arg(long, requires = "csv")]
+    csv_append: bool,
+
+    /// Write the final report (table, JSON or CSV) to FILE instead of stdout; progress lines
+    /// still go to the terminal
+    #[arg(long, value_name = "FILE", env = "DNSSPEEDTEST_OUTPUT")]
+    output: Option<PathBuf>,
+
+    /// Append to the --output file instead of overwriting it; table reports are separated by a
+    /// timestamp header
+    #[arg(long, requires = "output")]
+    append: bool,
+
+    /// Wait for Enter before exiting, even when stdin or stdout is not a terminal
+    #[arg(long, overrides_with = "no_pause")]
+    pause: bool,
+
+    /// Exit right after the report, even on an interactive terminal
+    #[arg(long, overrides_with = "pause", env = "DNSSPEEDTEST_NO_PAUSE")]
+    no_pause: bool,
+
+    /// Include every individual query sample in JSON output
+    #[arg(long)]
+    include_raw: bool,
+
+    /// Write every query to FILE as it completes, one JSON object per line (JSON Lines), with
+    /// its time, provider, address, domain, round, outcome, duration and answer count
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["watch", "exporter", "tui"])]
+    samples: Option<PathBuf>,
+
+    /// After the run, write every query behind the results to FILE (.csv) with its provider,
+    /// domain, round, outcome, error and duration, sorted by provider, round and domain
+    #[arg(long, value_name = "FILE", value_parser = sample_export::parse_target, conflicts_with_all = ["watch", "exporter"])]
+    export_samples: Option<sample_export::Target>,
+
+    /// Also save the results as a JSON baseline for a later --compare
+    #[arg(long, value_name = "FILE")]
+    save_baseline: Option<PathBuf>,
+
+    /// Append the results to this SQLite database (created on first use) for long-term trends
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["watch", "exporter"], env = "DNSSPEEDTEST_DB")]
+    db: Option<PathBuf>,
+
+    /// Print the medians of PROVIDER's last runs from --db and exit, without testing
+    #[arg(long, value_name = "PROVIDER", requires = "db")]
+    history: Option<String>,
+
+    /// Show each provider's average success rate over its runs of the last 7 and 30 days in
+    /// --db next to today's figures, and the 30-day one in the summary line
+    #[arg(long, requires = "db")]
+    with_history: bool,
+
+    /// The long-term success rates --with-history read from --db before this run, by provider
+    #[arg(skip)]
+    reliability: std::collections::HashMap<String, history::Reliability>,
+
+    /// How many runs --history shows
+    #[arg(long, default_value_t = 20, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), requires = "history")]
+    last: u64,
+
+    /// After the run, POST an anonymized copy of the results to URL for a shared dataset: the
+    /// built-in providers' medians and success rates by transport, without any address, custom
+    /// server or detail of this machine beyond --submit-include. The payload is printed first
+    #[arg(long, value_name = "URL", value_parser = submit::parse_url, conflicts_with_all = ["dry_run", "check", "watch", "exporter", "daemon", "once"])]
+    submit: Option<String>,
+
+    /// What --submit may add about this machine: `asn` and `country` of its public address,
+    /// looked up over DNS but not sent itself, and `os` (comma-separated)
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "LIST")]
+    submit_include: Vec<ClientField>,
+
+    /// Print the payload --submit would send after the run, and send nothing
+    #[arg(long, conflicts_with_all = ["dry_run", "check", "watch", "exporter", "daemon", "once"])]
+    submit_dry_run: bool,
+
+    /// Exit with status 1 when --submit fails, for CI; otherwise a failure is only reported
+    #[arg(long, requires = "submit")]
+    submit_required: bool,
+
+    /// Show each provider's median change since a baseline saved with --save-baseline
+    #[arg(long, value_name = "FILE")]
+    compare: Option<PathBuf>,
+
+    /// Exit with status 3 if even the fastest provider's median is above MS milliseconds
+    #[arg(long, value_name = "MS", value_parser = parse_fail_threshold, conflicts_with_all = ["watch", "exporter"])]
+    fail_threshold: Option<f64>,
+
+    /// Mark medians that got slower than the baseline by more than this percentage
+    #[arg(long, default_value_t = 20.0, value_parser = parse_percent, value_name = "PERCENT", requires = "compare")]
+    regression_threshold: f64,
+
+    /// What to rank providers by; `score` combines median, success rate and jitter (see
+    /// --weight-success and --weight-jitter), `median` ranks by latency alone and `p95` or
+    /// `p99` by tail latency
+    #[arg(long, value_enum, default_value_t = SortKey::Score, env = "DNSSPEEDTEST_SORT")]
+    sort: SortKey,
+
+    /// How the "vs Fastest" column shows each provider against the best one: in percent or as a
+    /// difference in --unit. It compares the latency ranked by, the median for the other sorts
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t = Relative::Percent)]
+    relative: Relative,
+
+    /// How much failed queries raise the score: the median is multiplied by 1 + W × the share
+    /// of failed queries
+    #[arg(long, value_name = "W", default_value_t = ScoreWeights::default().success, value_parser = parse_weight)]
+    weight_success: f64,
+
+    /// Milliseconds added to the score per millisecond of jitter
+    #[arg(long, value_name = "W", default_value_t = ScoreWeights::default().jitter, value_parser = parse_weight)]
+    weight_jitter: f64,
+
+    /// List the ranking in reverse, worst provider first
+    #[arg(long)]
+    reverse: bool,
+
+    /// Prefix every query with a random label so it misses the resolver's cache; NXDOMAIN
+    /// answers then count as successes
+    #[arg(long)]
+    cache_bust: bool,
+
+    /// Run every provider both normally and with --cache-bust and show the uncached median next
+    /// to the cached one
+    #[arg(long, conflicts_with = "cache_bust")]
+    compare_cache: bool,
+
+    /// How each provider's queries use their sockets: `reuse` sends them all through one
+    /// resolver; `fresh` builds a new one per query, so every query opens its own socket (and
+    /// over TCP, DoT and DoH its own connection), as some embedded clients do; `compare` tests
+    /// both and shows the fresh median next to the other. A large gap points at NAT or
+    /// conntrack trouble
+    #[arg(long, value_enum, default_value_t = SocketMode::Reuse)]
+    socket_mode: SocketMode,
+
+    /// Record types to query for every domain (comma-separated or repeated); each
+    /// (domain, type) pair counts as one query
+    #[arg(long = "record-type", value_enum, ignore_case = true, value_name = "TYPE", value_delimiter = ',', default_values_t = [QueryType::A], env = "DNSSPEEDTEST_RECORD_TYPE")]
+    record_types: Vec<QueryType>,
+
+    /// What to measure: `ptr` replaces the test domains with reverse (PTR) lookups of --targets;
+    /// `throughput` loads the one provider picked with --include in steps up to --target-qps and
+    /// reports the achieved rate, latency percentiles and failures of every step
+    #[arg(long, value_enum, default_value_t = Mode::Forward, conflicts_with_all = ["cache_bust", "compare_cache"])]
+    mode: Mode,
+
+    /// With --mode throughput, how long to load the provider, e.g. 10s or 1m (at most 5m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_load_duration, default_value = DEFAULT_LOAD_DURATION)]
+    duration: Duration,
+
+    /// With --mode throughput, the queries per second of the last step (at most 1000); the
+    /// steps before it send a quarter, half and three quarters of that
+    #[arg(long, value_name = "QPS", value_parser = parse_target_qps, default_value_t = DEFAULT_TARGET_QPS)]
+    target_qps: f64,
+
+    /// IP addresses reverse-resolved with --mode ptr (comma-separated) [default: well-known
+    /// resolver and CDN addresses]
+    #[arg(long, value_delimiter = ',', value_name = "IPS")]
+    targets: Vec<std::net::IpAddr>,
+
+    /// With --mode ptr, count addresses without a PTR record (NXDOMAIN) as answered rather than
+    /// failed
+    #[arg(long)]
+    missing_ptr_ok: bool,
+
+    /// Test only this domain (repeatable), e.g. one that is slow for you: with more rounds by
+    /// default and the answers of every provider, to see which CDN endpoints they hand out
+    #[arg(long = "domain", value_name = "DOMAIN", value_parser = parse_domain, conflicts_with_all = ["domains_source", "domains_file", "mode"])]
+    single_domains: Vec<String>,
+
+    /// Test the top N domains of the current Tranco list of popular sites (tranco or tranco:N,
+    /// N up to 1000) [default N: 50]. The list is cached and downloaded again after a week
+    #[arg(long, value_name = "SOURCE", value_parser = domains::parse_source, conflicts_with = "mode", env = "DNSSPEEDTEST_DOMAINS_SOURCE")]
+    domains_source: Option<domains::Source>,
+
+    /// Read the Tranco list from this CSV file instead of downloading it; implies
+    /// --domains-source tranco
+    #[arg(long, value_name = "FILE", conflicts_with = "mode", env = "DNSSPEEDTEST_DOMAINS_FILE")]
+    domains_file: Option<PathBuf>,
+
+    /// Download the Tranco list even if the cached copy is less than a week old
+    #[arg(long, requires = "domains_source", conflicts_with = "domains_file")]
+    refresh_domains: bool,
+
+    /// Which statistics to show in the results table; `full` adds p95, p99, standard deviation,
+    /// cold (first round) vs warm (later rounds) medians, answer TTLs and the first measured query
+    #[arg(long, value_enum, default_value_t = StatsMode::Basic, env = "DNSSPEEDTEST_STATS")]
+    stats: StatsMode,
+
+    /// Unit used for all latency output
+    #[arg(long, value_enum, default_value_t = Unit::Ms, env = "DNSSPEEDTEST_UNIT")]
+    unit: Unit,
+
+    /// Break exact ties in the ranking with a permutation derived from --seed, so identical
+    /// data and seed always produce identical ordering
+    #[arg(long)]
+    sort_stable_by_seed: bool,
+
+    /// Query the domains in the configured order every round instead of shuffling them per
+    /// provider and round
+    #[arg(long)]
+    no_shuffle: bool,
+
+    /// Test the providers in the configured order instead of a random one per run
+    #[arg(long)]
+    no_shuffle_providers: bool,
+
+    /// Seed for reproducible randomized behavior: the query and provider order and --sort-stable-by-seed
+    /// (which uses 0 when no seed is given). Without it the order differs on every run
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Compare two saved JSON reports (--save-baseline, --json or --output), largest regression
+    /// first; exits with status 1 if a median regressed by more than --threshold
+    Diff {
+        /// The earlier report
+        old: PathBuf,
+        /// The later report
+        new: PathBuf,
+        /// Regression, in percent of the old median, that counts as a failure
+        #[arg(long, default_value_t = 20.0, value_parser = parse_percent, value_name = "PERCENT")]
+        threshold: f64,
+    },
+    /// Print a completion script for SHELL to stdout, e.g.
+    /// `dns_speed_test completions bash > /etc/bash_completion.d/dns_speed_test`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+fn parse_fail_threshold(s: &str) -> Result<f64, String> {
+    let ms: f64 = s.trim_end_matches("ms").parse().map_err(|_| format!("'{}' is not a number of milliseconds", s))?;
+    if !ms.is_finite() || ms <= 0.0 {
+        return Err("threshold must be greater than zero".to_string());
+    }
+    Ok(ms)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EdnsBuffer {
+    Size(u16),
+    Compare,
+}
+
+fn parse_edns_buffer(s: &str) -> Result<EdnsBuffer, String> {
+    if s.eq_ignore_ascii_case("compare") {
+        return Ok(EdnsBuffer::Compare);
+    }
+    match s.parse::<u16>() {
+        Ok(size) if (512..=4096).contains(&size) => Ok(EdnsBuffer::Size(size)),
+        _ => Err("expected a size from 512 to 4096 bytes or `compare`".to_string()),
+    }
+}
+
+/// The --edns-buffer size, or the default.
+fn edns_buffer_size(args: &Args) -> u16 {
+    match args.edns_buffer {
+        Some(EdnsBuffer::Size(size)) => size,
+        _ => DEFAULT_EDNS_BUFFER,
+    }
+}
+
+fn parse_domain(s: &str) -> Result<String, String> {
+    domains::normalize_one(s).map_err(str::to_string)
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.trim_end_matches('%').parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err("percentage must be between 0 and 100".to_string());
+    }
+    Ok(percent)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Unit {
+    Ms,
+    Us,
+    S,
+    /// Microseconds below a millisecond, whole milliseconds from 100 ms, each value with its unit
+    Auto,
+}
+
+impl Unit {
+    /// The unit of every value, for headings such as "Median (ms)"; `None` for `auto`, whose
+    /// values carry their own.
+    fn label(self) -> Option<&'static str> {
+        match self {
+            Unit::Ms => Some("ms"),
+            Unit::Us => Some("us"),
+            Unit::S => Some("s"),
+            Unit::Auto => None,
+        }
+    }
+
+    /// `title` with the unit after it, "Median (ms)", or alone for `auto`.
+    fn heading(self, title: &str) -> String {
+        match self.label() {
+            Some(label) => format!("{} ({})", title, label),
+            None => title.to_string(),
+        }
+    }
+
+    /// `duration` for a table cell, e.g. "12.30", or "350 us" for `auto`.
+    fn format(self, duration: Duration) -> String {
+        self.format_seconds(duration.as_secs_f64(), false)
+    }
+
+    /// `duration` for a sentence, with its unit, e.g. "12.30 ms".
+    fn format_with_unit(self, duration: Duration) -> String {
+        match self.label() {
+            Some(label) => format!("{} {}", self.format(duration), label),
+            None => self.format(duration),
+        }
+    }
+
+    /// Signed difference `to - from`, e.g. "+1.10".
+    fn format_delta(self, from: Duration, to: Duration) -> String {
+        self.format_seconds(to.as_secs_f64() - from.as_secs_f64(), true)
+    }
+
+    /// Every latency this output shows goes through here; JSON and CSV stay in milliseconds.
+    fn format_seconds(self, seconds: f64, signed: bool) -> String {
+        let (value, decimals, suffix) = match self {
+            Unit::Ms => (seconds * 1000.0, 2, ""),
+            Unit::Us => (seconds * 1_000_000.0, 1, ""),
+            Unit::S => (seconds, 4, ""),
+            // Rounded to the unit it is shown in, so 0.9996 ms is "1.00 ms", not "1000 us".
+            Unit::Auto if (seconds.abs() * 1_000_000.0).round() < 1000.0 => (seconds * 1_000_000.0, 0, " us"),
+            Unit::Auto if (seconds.abs() * 1000.0 * 100.0).round() < 100.0 * 100.0 => (seconds * 1000.0, 2, " ms"),
+            Unit::Auto => (seconds * 1000.0, 0, " ms"),
+        };
+        if signed {
+            format!("{:+.*}{}", decimals, value, suffix)
+        } else {
+            format!("{:.*}{}", decimals, value, suffix)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Relative {
+    /// "+42%"
+    Percent,
+    /// "+12.30 ms"
+    Ms,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum StatsMode {
+    Basic,
+    Full,
+}
+
+/// The steps of a run, logged at -v as each one starts. Configure reads the flags, the config
+/// file and the environment. Setup does everything else that goes over the network: the domain
+/// list download, servers given by hostname, the gateway, DHCP and DDR checks, the control
+/// check of the domains and the interception check. Measure only queries the providers, so a
+/// slow system resolver cannot pass for a slow provider, and Report writes the results;
+/// webhook notifications, which resolve their host, go out after it.
+#[derive(Clone, Copy, Debug)]
+enum Phase {
+    Configure,
+    Setup,
+    Measure,
+    Report,
+}
+
+impl Phase {
+    fn start(self) {
+        tracing::debug!(phase = ?self, "phase started");
+    }
+}
+
+/// --group-by: whether the rows of one organization are also summed up in one line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum GroupBy {
+    /// One line per organization, e.g. every "Cloudflare (…)" row, after the table
+    Provider,
+    /// Only the table, one row per endpoint
+    None,
+}
+
+/// --protocol: one transport for every provider, or all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Protocol {
+    Udp,
+    Tcp,
+    Dot,
+    Doh,
+    Doq,
+    /// Every transport each provider supports, one row per provider and protocol
+    Compare,
+    /// DoT and DoH at the endpoints each provider designates by DDR, else the known ones, one
+    /// row per provider and protocol
+    Auto,
+}
+
+impl Protocol {
+    /// `None` for `Compare`
+    fn transport(self) -> Option<Transport> {
+        match self {
+            Protocol::Udp => Some(Transport::Udp),
+            Protocol::Tcp => Some(Transport::Tcp),
+            Protocol::Dot => Some(Transport::Tls),
+            Protocol::Doh => Some(Transport::Https),
+            Protocol::Doq => Some(Transport::Quic),
+            Protocol::Compare | Protocol::Auto => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Protocol::Compare => "UDP, TCP, DoT, DoH and DoQ",
+            Protocol::Auto => "DoT and DoH (DDR)",
+            _ => self.transport().map_or("", Transport::label),
+        }
+    }
+
+    /// The transports `provider` is tested over.
+    fn transports_for(self, provider: &DnsProvider) -> Vec<Transport> {
+        match self.transport() {
+            Some(transport) => vec![transport],
+            None => Transport::ALL.into_iter()
+                .filter(|&transport| provider.supports(transport))
+                .filter(|&transport| self != Protocol::Auto || matches!(transport, Transport::Tls | Transport::Https))
+                .collect(),
+        }
+    }
+
+    /// Whether a provider with these endpoints can be tested: over DoT or DoH for `Auto`.
+    fn supported_by(self, provider: &DnsProvider) -> bool {
+        match self {
+            Protocol::Auto => provider.supports(Transport::Tls) || provider.supports(Transport::Https),
+            _ => self.transport().is_none_or(|transport| provider.supports(transport)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Profile {
+    /// 2 rounds of 5 domains, 50 ms cooldown, 4 providers at a time
+    Fast,
+    /// The built-in defaults
+    Default,
+    /// 10 rounds of every domain, one provider at a time
+    Thorough,
+}
+
+/// The settings a --profile sets.
+struct ProfileSettings {
+    rounds: u32,
+    /// How many of the test domains are queried; `None` for all
+    domains: Option<usize>,
+    cooldown_ms: u64,
+    concurrency: u64,
+}
+
+impl Profile {
+    fn settings(self) -> ProfileSettings {
+        match self {
+            Profile::Fast => ProfileSettings { rounds: 2, domains: Some(5), cooldown_ms: 50, concurrency: 4 },
+            Profile::Default => ProfileSettings { rounds: DEFAULT_ROUNDS, domains: None, cooldown_ms: DEFAULT_COOLDOWN_MS, concurrency: DEFAULT_CONCURRENCY },
+            Profile::Thorough => ProfileSettings { rounds: 10, domains: None, cooldown_ms: DEFAULT_COOLDOWN_MS, concurrency: 1 },
+        }
+    }
+
+    /// Sets what the profile bundles wherever no flag or environment variable did. Called before
+    /// the config file is read, whose values then override it too.
+    fn apply(self, args: &mut Args, matches: &clap::ArgMatches) {
+        if self == Profile::Default {
+            return;
+        }
+        let settings = self.settings();
+        let given = |id: &str| config::explicit(matches, id);
+        if !given("rounds") {
+            args.rounds = settings.rounds;
+        }
+        if let Some(count) = settings.domains {
+            args.domains.truncate(count);
+        }
+        if !given("cooldown") {
+            args.cooldown = settings.cooldown_ms;
+        }
+        if !given("concurrency") {
+            args.concurrency = settings.concurrency;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Look up the test domains
+    Forward,
+    /// Reverse-resolve IP addresses
+    Ptr,
+    /// Load one provider (--include) with cache-busted queries at a rising rate
+    Throughput,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// The report that goes to --output or stdout at the end of a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Report {
+    Table,
+    Json,
+    Csv,
+    /// --quiet
+    Summary,
+    /// --quiet with JSON output
+    JsonSummary,
+}
+
+impl Report {
+    /// The one place the output flags are reconciled.
+    fn select(args: &Args) -> Report {
+        let csv_to_stdout = matches!(args.csv, Some(None));
+        let json = args.json || args.format == OutputFormat::Json;
+        match (args.quiet, json, csv_to_stdout) {
+            (_, true, true) => usage_error(ErrorKind::ArgumentConflict, "--csv without FILE and JSON output both need stdout"),
+            (true, _, true) => usage_error(ErrorKind::ArgumentConflict, "--csv without FILE and --quiet both need stdout"),
+            (true, true, _) => Report::JsonSummary,
+            (true, false, _) => Report::Summary,
+            (false, true, _) => Report::Json,
+            (false, false, true) => Report::Csv,
+            (false, false, false) => Report::Table,
+        }
+    }
+
+    /// The name of the report in JSON, e.g. "json-summary" for --quiet with JSON output.
+    fn name(self) -> &'static str {
+        match self {
+            Report::Table => "table",
+            Report::Json => "json",
+            Report::Csv => "csv",
+            Report::Summary => "summary",
+            Report::JsonSummary => "json-summary",
+        }
+    }
+
+    /// Anything but the table is read by programs, so it keeps stdout to itself and never waits
+    /// for Enter.
+    fn machine_readable(self) -> bool {
+        self != Report::Table
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ConfigFormat {
+    #[value(name = "resolv.conf")]
+    ResolvConf,
+    SystemdResolved,
+    Unbound,
+}
+
+impl ConfigFormat {
+    fn snippet(self, result: &TestResult) -> String {
+        let mut out = String::new();
+        match self {
+            ConfigFormat::ResolvConf => {
+                out.push_str(&format!("# /etc/resolv.conf - {}\n", result.provider));
+                for address in &result.addresses {
+                    out.push_str(&format!("nameserver {}\n", address));
+                }
+            },
+            ConfigFormat::SystemdResolved => {
+                out.push_str(&format!("# /etc/systemd/resolved.conf - {}\n", result.provider));
+                out.push_str("[Resolve]\n");
+                out.push_str(&format!("DNS={}\n", result.addresses.join(" ")));
+            },
+            ConfigFormat::Unbound => {
+                out.push_str(&format!("# unbound.conf - {}\n", result.provider));
+                out.push_str("forward-zone:\n    name: \".\"\n");
+                for address in &result.addresses {
+                    out.push_str(&format!("    forward-addr: {}\n", address));
+                }
+            },
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+struct Column {
+    name: &'static str,
+    header: &'static str,
+    width: usize,
+    left_align: bool,
+    latency: bool,
+    value: fn(&TestResult, Unit) -> String,
+}
+
+impl Column {
+    fn header(&self, unit: Unit) -> String {
+        if self.latency {
+            unit.heading(self.header)
+        } else {
+            self.header.to_string()
+        }
+    }
+}
+
+const COLUMNS: &[Column] = &[
+    Column { name: "provider", header: "Provider", width: 15, left_align: true, latency: false, value: |r, _| if r.system_resolver { format!("{} *", r.provider) } else { r.provider.clone() } },
+    Column { name: "score", header: "Score", width: 8, left_align: false, latency: false, value: |r, _| r.score.map_or("n/a".to_string(), |score| format!("{:.2}", score)) },
+    Column { name: "median", header: "Median", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.median_duration, unit) },
+    // Filled in from the --compare baseline.
+    Column { name: "delta", header: "vs Baseline", width: 18, left_align: false, latency: true, value: |_, _| "n/a".to_string() },
+    Column { name: "relative", header: "vs Fastest", width: 16, left_align: false, latency: false, value: |_, _| "n/a".to_string() },
+    Column { name: "failover", header: "Failover", width: 14, left_align: false, latency: true, value: |r, unit| opt_latency(r.failover.as_ref().and_then(|failover| failover.failover_median), unit) },
+    Column { name: "avg", header: "Avg", width: 10, left_align: false, latency: true, value: |r, unit| opt_latency(r.avg_duration, unit) },
+    Column { name: "min", header: "Min", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.min_latency, unit) },
+    Column { name: "max", header: "Max", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.max_latency, unit) },
+    Column { name: "p95", header: "P95", width: 10, left_align: false, latency: true, value: |r, unit| opt_latency(r.p95, unit) },
+    Column { name: "p99", header: "P99", width: 10, left_align: false, latency: true, value: |r, unit| opt_latency(r.p99, unit) },
+    Column { name: "stddev", header: "Std Dev", width: 14, left_align: false, latency: true, value: |r, unit| opt_latency(r.std_dev, unit) },
+    Column { name: "cold", header: "Cold", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.cold_median, unit) },
+    Column { name: "warm", header: "Warm", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.warm_median, unit) },
+    Column { name: "nxdomain", header: "NXDOMAIN", width: 14, left_align: false, latency: true, value: |r, unit| opt_latency(r.nxdomain_median, unit) },
+    Column { name: "https", header: "HTTPS", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.https_median, unit) },
+    Column { name: "https_ok", header: "HTTPS OK", width: 10, left_align: false, latency: false, value: |r, _| r.https_success_rate.map_or("n/a".to_string(), |rate| format!("{:.1}%", rate)) },
+    Column { name: "ttl", header: "TTL min/max", width: 14, left_align: false, latency: false, value: |r, _| r.ttl.map_or("n/a".to_string(), |ttl| format!("{}/{}", ttl.min, ttl.max)) },
+    // Its header lists the rounds, e.g. "R1/R2/R3 (ms)".
+    Column { name: "rounds", header: "Rounds", width: 14, left_align: false, latency: true, value: |r, unit| r.round_medians.iter().map(|median| opt_latency(*median, unit)).collect::<Vec<_>>().join("/") },
+    Column { name: "cnames", header: "CNAMEs (avg/max)", width: 17, left_align: false, latency: false, value: |r, _| r.avg_cnames.zip(r.max_cnames).map_or("n/a".to_string(), |(avg, max)| format!("{:.1}/{}", avg, max)) },
+    Column { name: "stability", header: "Stability", width: 10, left_align: false, latency: false, value: |r, _| r.answer_stability.as_ref().map_or("n/a".to_string(), |stability| format!("{:.0}%", stability.percent)) },
+    Column { name: "runsd", header: "Run SD", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.runs.as_ref().and_then(|runs| runs.std_dev), unit) },
+    Column { name: "jitter", header: "Jitter", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.jitter, unit) },
+    Column { name: "uncached", header: "Uncached", width: 14, left_align: false, latency: true, value: |r, unit| opt_latency(r.cache_comparison.as_ref().and_then(|c| c.uncached_median), unit) },
+    Column { name: "fresh", header: "Fresh Socket", width: 18, left_align: false, latency: true, value: |r, unit| opt_latency(r.socket_comparison.as_ref().and_then(|c| c.fresh_median), unit) },
+    Column { name: "rtt", header: "Network RTT", width: 17, left_align: false, latency: true, value: |r, unit| opt_latency(r.network_rtt, unit) },
+    Column { name: "overhead", header: "Resolver Overhead", width: 23, left_align: false, latency: true, value: |r, unit| opt_latency(r.resolver_overhead(), unit) },
+    Column { name: "tcp", header: "TCP Connect", width: 17, left_align: false, latency: true, value: |r, unit| opt_latency(r.tcp_connect, unit) },
+    Column { name: "quic", header: "QUIC Handshake", width: 17, left_align: false, latency: true, value: |r, unit| opt_latency(r.quic_handshake, unit) },
+    // A new DoT or DoH connection, TCP and TLS, then its first query, against the reused one's.
+    Column { name: "connect", header: "New Conn", width: 14, left_align: false, latency: true, value: |r, unit| split_cell(r, unit, ConnectionSplit::connect) },
+    Column { name: "conn_query", header: "1st on New", width: 16, left_align: false, latency: true, value: |r, unit| split_cell(r, unit, |split| split.first_query) },
+    Column { name: "steady", header: "Steady", width: 12, left_align: false, latency: true, value: |r, unit| opt_latency(r.connection_split.as_ref().and_then(|split| split.steady_median), unit) },
+    Column { name: "success", header: "Success Rate", width: 15, left_align: false, latency: false, value: |r, _| format!("{:.1}%", r.success_rate) },
+    Column { name: "success_7d", header: "7d Success", width: 11, left_align: false, latency: false, value: |_, _| "n/a".to_string() },
+    Column { name: "success_30d", header: "30d Success", width: 12, left_align: false, latency: false, value: |_, _| "n/a".to_string() },
+    Column { name: "usable", header: "Usable", width: 8, left_align: false, latency: false, value: |r, _| r.usable_answers.map_or("n/a".to_string(), |usable| format!("{:.1}%", usable.percent)) },
+    Column { name: "loss", header: "Loss", width: 8, left_align: false, latency: false, value: |r, _| r.packet_loss.map_or("n/a".to_string(), |loss| format!("{:.1}%", loss.percent)) },
+    Column { name: "wasted", header: "Fail Wait", width: 14, left_align: false, latency: true, value: |r, unit| opt_latency(r.failure_wait, unit) },
+    Column { name: "qps", header: "Queries/s", width: 10, left_align: false, latency: false, value: |r, _| r.queries_per_second.map_or("n/a".to_string(), |qps| format!("{:.1}", qps)) },
+    Column { name: "retries", header: "Retries", width: 8, left_align: false, latency: false, value: |r, _| r.retries_used.to_string() },
+    Column { name: "resent", header: "Resent", width: 7, left_align: false, latency: false, value: |r, _| r.resent_answers.to_string() },
+    Column { name: "transport", header: "Best Transport", width: 15, left_align: false, latency: false, value: |r, _| r.recommended_transport().map_or("n/a".to_string(), |t| t.label().to_string()) },
+    Column { name: "first", header: "First Query", width: 17, left_align: false, latency: true, value: |r, unit| opt_latency(r.first_query, unit) },
+    Column { name: "dnssec", header: "DNSSEC", width: 8, left_align: false, latency: false, value: |r, _| r.dnssec.map_or("n/a", DnssecStatus::label).to_string() },
+    Column { name: "dns64", header: "DNS64", width: 8, left_align: false, latency: false, value: |r, _| r.dns64.as_ref().map_or("n/a", |dns64| dns64.status.label()).to_string() },
+    Column { name: "pop", header: "POP", width: 14, left_align: false, latency: false, value: |r, _| r.pop.clone().unwrap_or_else(|| "unknown".to_string()) },
+    Column { name: "verified", header: "Verified", width: 9, left_align: false, latency: false, value: |r, _| yes_no(r.verified) },
+    Column { name: "blocked", header: "Blocked", width: 8, left_align: false, latency: false, value: |r, _| r.blocking.as_ref().and_then(|b| b.blocked_percent).map_or("n/a".to_string(), |p| format!("{:.0}%", p)) },
+    Column { name: "size", header: "Avg Size", width: 10, left_align: false, latency: false, value: |r, _| r.response_sizes.as_ref().map_or("n/a".to_string(), |sizes| format!("{:.0} B", sizes.avg_bytes)) },
+    Column { name: "truncated", header: "Truncated", width: 10, left_align: false, latency: false, value: |r, _| r.response_sizes.as_ref().map_or("n/a".to_string(), |sizes| sizes.truncated.to_string()) },
+    Column { name: "cookies", header: "Cookies", width: 8, left_align: false, latency: false, value: |r, _| yes_no(r.dns_cookies) },
+    Column { name: "qmin", header: "QNAME Min", width: 10, left_align: false, latency: false, value: |r, _| yes_no(r.qname_minimization) },
+];
+
+/// A figure of the new-connection check, or the step it failed at; `n/a` over plain DNS.
+fn split_cell(result: &TestResult, unit: Unit, figure: fn(&ConnectionSplit) -> Option<Duration>) -> String {
+    match &result.connection_split {
+        Some(split) => match (figure(split), split.failed_step()) {
+            (Some(duration), _) => unit.format(duration),
+            (None, Some(step)) => format!("{} failed", step),
+            (None, None) => "n/a".to_string(),
+        },
+        None => "n/a".to_string(),
+    }
+}
+
+/// How the network round trips were measured, naming the providers that had to fall back to TCP.
+fn rtt_notes<'a>(out: &mut dyn Write, results: impl Iterator<Item = &'a TestResult>) -> io::Result<()> {
+    let mut icmp = false;
+    let mut not_permitted = Vec::new();
+    let mut no_reply = Vec::new();
+    for result in results {
+        match result.network_rtt_method {
+            Some(RttMethod::Icmp) => icmp = true,
+            Some(RttMethod::TcpIcmpNotPermitted) => not_permitted.push(result.provider.as_str()),
+            Some(RttMethod::TcpIcmpNoReply) => no_reply.push(result.provider.as_str()),
+            None => {},
+        }
+    }
+    if icmp {
+        writeln!(out, "Network RTT: median of three ICMP echoes; resolver overhead is the median less it.")?;
+    }
+    if !not_permitted.is_empty() {
+        writeln!(out, "Network RTT by TCP connect to the DNS port, as this system does not permit ICMP sockets (run with privileges or allow them with net.ipv4.ping_group_range): {}",
+            not_permitted.join(", "))?;
+    }
+    if !no_reply.is_empty() {
+        writeln!(out, "Network RTT by TCP connect to the DNS port, as ICMP echoes got no reply: {}", no_reply.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Why the results cannot be trusted if `check` found interception.
+fn interception_warning(check: &InterceptionCheck) -> Option<String> {
+    if !check.intercepted {
+        return None;
+    }
+    let sign = match &check.shared_egress {
+        Some(egress) if !check.bogus_server_answered => format!("every provider reported the same resolver, {}", egress),
+        _ => "an address without a DNS server answered a query".to_string(),
+    };
+    Some(format!(
+        "this network intercepts plain DNS ({}), so every provider may be answered by the same resolver and the results do not compare them; --protocol dot or doh gets past it",
+        sign
+    ))
+}
+
+/// What the results measure if `check` found queries leaving through a tunnel.
+fn vpn_warning(check: &VpnCheck) -> Option<String> {
+    let mut tunnels: Vec<String> = Vec::new();
+    for egress in check.egress.values() {
+        if let (Some(kind), Some(interface)) = (egress.tunnel, &egress.interface) {
+            let tunnel = if kind == "VPN" { interface.clone() } else { format!("{} ({})", interface, kind) };
+            if !tunnels.contains(&tunnel) {
+                tunnels.push(tunnel);
+            }
+        }
+    }
+    if tunnels.is_empty() {
+        return None;
+    }
+    let through = check.egress.values().filter(|egress| egress.tunnel.is_some()).count();
+    let share = if through == check.egress.len() {
+        "DNS queries to every provider".to_string()
+    } else {
+        format!("DNS queries to {} of {} provider addresses (--split-by-egress shows which)", through, check.egress.len())
+    };
+    Some(format!(
+        "{} leave through the VPN tunnel {}, so their latencies are those of the tunnel and where it exits as much as the providers'",
+        share,
+        tunnels.join(", ")
+    ))
+}
+
+/// The way out the queries of `result` take, by its first address.
+fn egress_of<'a>(result: &TestResult, args: &'a Args) -> Option<&'a Egress> {
+    let address = result.addresses.first()?.parse::<std::net::IpAddr>().ok()?;
+    args.vpn.as_ref()?.egress.get(&address)
+}
+
+/// `egress_of` as a heading for --split-by-egress.
+fn egress_heading(result: &TestResult, args: &Args) -> String {
+    match egress_of(result, args) {
+        Some(egress) => {
+            let interface = egress.interface.as_deref().unwrap_or("unknown interface");
+            match egress.tunnel {
+                Some(kind) => format!("Through {} ({}), from {}", interface, kind, egress.source),
+                None => format!("Direct via {}, from {}", interface, egress.source),
+            }
+        },
+        None => "Way out unknown".to_string(),
+    }
+}
+
+/// `domains` as shown to people, comma-separated.
+fn display_domains(domains: &[String]) -> String {
+    domains.iter().map(|domain| domains::display(domain)).collect::<Vec<_>>().join(", ")
+}
+
+/// "3 timeouts (reddit.com, github.com), 1 SERVFAIL (reddit.com)", most frequent kind first.
+fn failure_summary(result: &TestResult) -> String {
+    let mut kinds: Vec<(&FailureKind, &u32)> = result.failure_kinds.iter().collect();
+    kinds.sort_by_key(|&(_, &count)| std::cmp::Reverse(count));
+    kinds.iter()
+        .map(|&(&kind, &count)| {
+            let mut domains: Vec<String> = Vec::new();
+            for failure in result.failures.iter().filter(|failure| failure.kind == kind) {
+                let domain = domains::display(&failure.domain);
+                if !domains.contains(&domain) {
+                    domains.push(domain);
+                }
+            }
+            format!("{} ({})", kind.describe(count), domains.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn yes_no(value: Option<bool>) -> String {
+    match value {
+        Some(true) => "yes".to_string(),
+        Some(false) => "no".to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// One entry of --columns.
+#[derive(Clone, Copy, Debug)]
+enum ColumnChoice {
+    All,
+    One(&'static Column),
+}
+
+fn parse_column(s: &str) -> Result<ColumnChoice, String> {
+    if s.trim().eq_ignore_ascii_case("all") {
+        return Ok(ColumnChoice::All);
+    }
+    COLUMNS.iter()
+        .find(|column| column.name.eq_ignore_ascii_case(s.trim()))
+        .map(ColumnChoice::One)
+        .ok_or_else(|| format!(
+            "unknown column '{}' (valid columns: all, {})",
+            s,
+            COLUMNS.iter().map(|c| c.name).collect::<Vec<_>>().join(", ")
+        ))
+}
+
+/// The columns --columns asks for, in its order, `all` standing for every column in the
+/// default order; a column named twice is shown once, where it comes first.
+fn chosen_columns(choices: &[ColumnChoice]) -> Vec<&'static Column> {
+    let mut columns: Vec<&'static Column> = Vec::new();
+    for choice in choices {
+        let add: Vec<&'static Column> = match choice {
+            ColumnChoice::All => COLUMNS.iter().collect(),
+            ColumnChoice::One(column) => vec![column],
+        };
+        for column in add {
+            if !columns.iter().any(|chosen| chosen.name == column.name) {
+                columns.push(column);
+            }
+        }
+    }
+    columns
+}
+
+fn parse_preset(s: &str) -> Result<&'static Preset, String> {
+    preset(s).ok_or_else(|| format!(
+        "unknown preset '{}' (valid presets: {})",
+        s,
+        PRESETS.iter().map(|preset| preset.name).collect::<Vec<_>>().join(", ")
+    ))
+}
+
+fn opt_latency(duration: Option<Duration>, unit: Unit) -> String {
+    duration.map_or("n/a".to_string(), |d| unit.format(d))
+}
+
+/// Like `opt_latency`, with the unit.
+fn with_unit(duration: Option<Duration>, unit: Unit) -> String {
+    duration.map_or("n/a".to_string(), |d| unit.format_with_unit(d))
+}
+
+/// Shortens `cell` to `width` characters, marking the cut with an ellipsis.
+fn fit(cell: String, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell;
+    }
+    let mut short: String = cell.chars().take(width - 1).collect();
+    short.push('…');
+    short
+}
+
+/// Pads every cell to its column and only then colors it, so the escape codes do not count
+/// towards the width.
+fn format_row(columns: &[&Column], cells: impl Fn(&Column) -> String, palette: Palette, color: impl Fn(&Column) -> Option<Color>) -> String {
+    columns.iter()
+        .map(|column| {
+            let cell = fit(cells(column), column.width);
+            let padded = if column.left_align {
+                format!("{:<width$}", cell, width = column.width)
+            } else {
+                format!("{:>width$}", cell, width = column.width)
+            };
+            palette.paint(padded, color(column))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Domains down, providers across. Providers that do not fit the terminal width (from
+/// `COLUMNS`) continue in further blocks below.
+fn write_domain_matrix(out: &mut dyn Write, results: &[TestResult], args: &Args) -> io::Result<()> {
+    let Some(first) = results.first() else { return Ok(()) };
+    let width = terminal_width();
+    let domain_width = first.per_domain.iter()
+        .map(|stats| domains::display(&stats.domain).chars().count())
+        .max()
+        .unwrap_or(0)
+        .clamp(6, 30);
+    let cell_width = |result: &TestResult| result.provider.chars().count().clamp(10, 20);
+    let cell = |stats: &DomainStats| match (stats.median, stats.failures) {
+        (None, _) => "failed".to_string(),
+        (Some(median), 0) => args.unit.format(median),
+        (Some(median), failures) => format!("{} !{}", args.unit.format(median), failures),
+    };
+
+    writeln!(out, "\nPer-domain medians ({}!N = N failed queries):", args.unit.label().map_or(String::new(), |unit| format!("{}; ", unit)))?;
+    let mut remaining = results;
+    while !remaining.is_empty() {
+        let mut used = domain_width;
+        let fitting = remaining.iter()
+            .take_while(|result| {
+                used += 1 + cell_width(result);
+                used <= width
+            })
+            .count()
+            .max(1);
+        let (block, rest) = remaining.split_at(fitting);
+        remaining = rest;
+
+        writeln!(out, "{:-<90}", "")?;
+        let header: Vec<String> = block.iter()
+            .map(|result| format!("{:>width$}", fit(result.provider.clone(), cell_width(result)), width = cell_width(result)))
+            .collect();
+        writeln!(out, "{:<domain_width$} {}", "Domain", header.join(" "))?;
+        writeln!(out, "{:-<90}", "")?;
+        for (row, stats) in first.per_domain.iter().enumerate() {
+            let cells: Vec<String> = block.iter()
+                .map(|result| {
+                    let value = result.per_domain.get(row).map_or("n/a".to_string(), cell);
+                    format!("{:>width$}", value, width = cell_width(result))
+                })
+                .collect();
+            writeln!(out, "{:<domain_width$} {}", fit(domains::display(&stats.domain), domain_width), cells.join(" "))?;
+        }
+    }
+    Ok(())
+}
+
+/// One line per organization of `results` for --group-by provider, in ranking order; an
+/// organization none of whose endpoints answered stays in with n/a.
+fn write_groups<'a>(out: &mut dyn Write, results: impl IntoIterator<Item = &'a TestResult>, args: &Args) -> io::Result<()> {
+    let groups = group_by_organization(results);
+    let fmt = |d: Option<Duration>| d.map_or("n/a".to_string(), |d| args.unit.format(d));
+    writeln!(out, "\nBy provider (best and worst median of each organization's endpoints):")?;
+    writeln!(out, "{:-<90}", "")?;
+    writeln!(out, "{:<20} {:>9} {:>12} {:>12}  Fastest endpoint", "Provider", "Endpoints", args.unit.heading("Best"), args.unit.heading("Worst"))?;
+    writeln!(out, "{:-<90}", "")?;
+    for group in &groups {
+        writeln!(out, "{:<20} {:>9} {:>12} {:>12}  {}",
+            fit(group.organization.clone(), 20),
+            group.endpoints,
+            fmt(group.best_median),
+            fmt(group.worst_median),
+            group.fastest_endpoint.as_deref().unwrap_or("n/a (every endpoint failed)")
+        )?;
+    }
+    Ok(())
+}
+
+/// With --winners: the provider with the lowest median for each domain and its margin over the
+/// runner-up, then how many domains each provider won. Medians within `WINNER_TIE` of the
+/// fastest tie; a tie counts for none of them.
+fn write_winners(out: &mut dyn Write, results: &[TestResult], args: &Args) -> io::Result<()> {
+    let Some(first) = results.first() else { return Ok(()) };
+    let domain_width = first.per_domain.iter()
+        .map(|stats| domains::display(&stats.domain).chars().count())
+        .max()
+        .unwrap_or(0)
+        .clamp(6, 30);
+    let mut wins: Vec<(&str, u32, u32)> = Vec::new();
+    let mut failed = Vec::new();
+    let mut decided = 0;
+
+    writeln!(out, "\nWinners per domain (lowest median, and its margin over the runner-up):")?;
+    writeln!(out, "{:-<90}", "")?;
+    writeln!(out, "{:<domain_width$} {:<30} {:>12}  Margin", "Domain", "Winner", args.unit.heading("Median"))?;
+    writeln!(out, "{:-<90}", "")?;
+    for stats in &first.per_domain {
+        let mut medians: Vec<(&str, Duration)> = results.iter()
+            .filter_map(|result| {
+                let median = result.per_domain.iter().find(|other| other.domain == stats.domain)?.median?;
+                Some((result.provider.as_str(), median))
+            })
+            .collect();
+        medians.sort_by_key(|&(_, median)| median);
+        let Some(&(_, fastest)) = medians.first() else {
+            failed.push(domains::display(&stats.domain));
+            continue;
+        };
+        decided += 1;
+        let tied = medians.iter().take_while(|&&(_, median)| median - fastest <= WINNER_TIE).count();
+        let winners: Vec<&str> = medians[..tied].iter().map(|&(provider, _)| provider).collect();
+        for &provider in &winners {
+            let index = match wins.iter().position(|&(name, _, _)| name == provider) {
+                Some(index) => index,
+                None => {
+                    wins.push((provider, 0, 0));
+                    wins.len() - 1
+                },
+            };
+            if tied == 1 {
+                wins[index].1 += 1;
+            } else {
+                wins[index].2 += 1;
+            }
+        }
+        let margin = match medians.get(tied) {
+            Some(&(runner_up, median)) => format!("+{} over {}", args.unit.format_with_unit(median - fastest), runner_up),
+            None if medians.len() == 1 => "the only answer".to_string(),
+            None => "all tied".to_string(),
+        };
+        let winner = if tied > 1 { format!("{} (tie)", winners.join(", ")) } else { winners[0].to_string() };
+        writeln!(out, "{:<domain_width$} {:<30} {:>12}  {}",
+            fit(domains::display(&stats.domain), domain_width), fit(winner, 30), args.unit.format(fastest), margin)?;
+    }
+    if !failed.is_empty() {
+        writeln!(out, "Failed at every provider: {}", failed.join(", "))?;
+    }
+    wins.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+    for (provider, won, ties) in wins {
+        let ties = if ties > 0 { format!(", tied for {} more", ties) } else { String::new() };
+        writeln!(out, "{} wins {}/{} domains{}", provider, won, decided, ties)?;
+    }
+    Ok(())
+}
+
+/// With --verify-answers: how many of every provider's sampled answers could be connected to,
+/// and which could not.
+fn write_answer_reachability<'a>(out: &mut dyn Write, results: impl Iterator<Item = &'a TestResult>) -> io::Result<()> {
+    writeln!(out, "\nAnswer reachability (TCP connect to port 443 of one address per domain and family; not part of the latency figures):")?;
+    for result in results {
+        let Some(found) = &result.answer_reachability else {
+            writeln!(out, "  {}: no public addresses answered", result.provider)?;
+            continue;
+        };
+        writeln!(out, "  {}: {}/{} reachable", result.provider, found.reachable, found.checked)?;
+        for answer in &found.unreachable {
+            writeln!(out, "    Unreachable: {} → {}", domains::display(&answer.domain), answer.address)?;
+        }
+    }
+    Ok(())
+}
+
+/// With --domain: every provider's addresses and TTLs for each query, and whether they agree.
+fn write_answers<'a>(out: &mut dyn Write, results: impl Iterator<Item = &'a TestResult> + Clone) -> io::Result<()> {
+    let Some(first) = results.clone().next() else { return Ok(()) };
+    let name_width = results.clone().map(|result| result.provider.chars().count()).max().unwrap_or(0).clamp(10, 30);
+    for query in first.per_domain.iter().map(|stats| &stats.domain) {
+        writeln!(out, "\nAnswers for {}:", domains::display(query))?;
+        let mut distinct: Vec<&BTreeSet<std::net::IpAddr>> = Vec::new();
+        for result in results.clone() {
+            let stats = result.per_domain.iter().find(|stats| &stats.domain == query);
+            let addresses = result.answers.get(query).filter(|addresses| !addresses.is_empty());
+            let answer = match (addresses, stats) {
+                (Some(addresses), _) => {
+                    if !distinct.contains(&addresses) {
+                        distinct.push(addresses);
+                    }
+                    let list: Vec<String> = addresses.iter().map(std::net::IpAddr::to_string).collect();
+                    let ttl = match stats.and_then(|stats| stats.ttl) {
+                        Some(ttl) if ttl.min == ttl.max => format!(" (TTL {} s)", ttl.min),
+                        Some(ttl) => format!(" (TTL {}–{} s)", ttl.min, ttl.max),
+                        None => String::new(),
+                    };
+                    format!("{}{}", list.join(", "), ttl)
+                },
+                (None, Some(stats)) if stats.median.is_some() => "no addresses".to_string(),
+                (None, Some(_)) => "failed".to_string(),
+                (None, None) => "n/a".to_string(),
+            };
+            writeln!(out, "  {:<name_width$} {}", fit(result.provider.clone(), name_width), answer)?;
+        }
+        if distinct.len() > 1 {
+            writeln!(out, "  The providers returned {} different sets of addresses, e.g. different CDN endpoints.", distinct.len())?;
+        }
+    }
+    Ok(())
+}
+
+/// From `COLUMNS`, which shells set for interactive terminals.
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS").ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+/// One histogram per provider that answered, with bars scaled to its fullest bucket and the
+/// terminal width.
+fn write_histograms(out: &mut dyn Write, results: &[TestResult], args: &Args) -> io::Result<()> {
+    const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    writeln!(out, "\n{}:", args.unit.heading("Latency distribution"))?;
+    for result in results {
+        let durations: Vec<Duration> = result.samples.iter()
+            .filter(|sample| sample.status != SampleStatus::Failed)
+            .filter_map(|sample| sample.duration)
+            .collect();
+        if durations.is_empty() {
+            continue;
+        }
+        let buckets = stats::histogram(&durations, HISTOGRAM_BUCKETS);
+        let labels: Vec<String> = buckets.iter()
+            .map(|bucket| format!("{}–{}", args.unit.format(bucket.start), args.unit.format(bucket.end)))
+            .collect();
+        let label_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+        let count_width = buckets.iter().map(|bucket| bucket.count.to_string().len()).max().unwrap_or(1);
+        // Indent, label, count and the spaces between them.
+        let bar_width = terminal_width().saturating_sub(4 + label_width + 2 + 1 + count_width).max(10);
+        let fullest = buckets.iter().map(|bucket| bucket.count).max().unwrap_or(1);
+
+        writeln!(out, "{} ({} answered):", result.provider, durations.len())?;
+        for (bucket, label) in buckets.iter().zip(labels) {
+            let eighths = bucket.count * bar_width * 8 / fullest;
+            let (full, partial) = (eighths / 8, eighths % 8);
+            let mut bar = "█".repeat(full);
+            if partial > 0 {
+                bar.push(EIGHTHS[partial]);
+            }
+            writeln!(out, "    {:>label_width$}  {:<bar_width$} {:>count_width$}", label, bar, bucket.count)?;
+        }
+    }
+    Ok(())
+}
+
+/// A server followed by its own settings, e.g. "Pi-hole=192.168.1.2;timeout=500ms;attempts=2".
+fn parse_server(s: &str) -> Result<DnsProvider, String> {
+    let mut parts = s.split(';');
+    let mut provider = parse_server_address(parts.next().unwrap_or_default())?;
+    for setting in parts.map(str::trim).filter(|setting| !setting.is_empty()) {
+        let (key, value) = setting.split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| format!("'{}' is not a KEY=VALUE setting", setting))?;
+        match key {
+            "timeout" => provider.timeout = Some(parse_timeout(value)?),
+            "attempts" => provider.attempts = Some(value.parse().ok().filter(|n| (1..=MAX_ATTEMPTS).contains(n))
+                .ok_or_else(|| format!("attempts must be a number from 1 to {}, not '{}'", MAX_ATTEMPTS, value))?),
+            "cooldown" => provider.cooldown = Some(value.strip_suffix("ms").unwrap_or(value).trim().parse().ok()
+                .filter(|&ms| ms <= MAX_COOLDOWN_MS)
+                .map(Duration::from_millis)
+                .ok_or_else(|| format!("cooldown must be a number of milliseconds up to {}, not '{}'", MAX_COOLDOWN_MS, value))?),
+            _ => return Err(format!("unknown server setting '{}', expected timeout, attempts or cooldown", key)),
+        }
+    }
+    Ok(provider)
+}
+
+/// A hostname server has no addresses until `resolve_servers` looks it up; until then the name
+/// is kept as its DoT and DoH host.
+fn parse_server_address(s: &str) -> Result<DnsProvider, String> {
+    let (name, address) = match s.split_once('=') {
+        // An '=' inside a URL is not the name's.
+        Some((name, address)) if !name.contains("://") => (name.trim(), address.trim()),
+        _ => ("", s.trim()),
+    };
+    if address.contains("://") {
+        let (host, path, pinned) = parse_doh_url(address)?;
+        return Ok(DnsProvider {
+            addresses: pinned.into_iter().collect(),
+            doh_host: Some(host.clone()),
+            doh_path: path,
+            ..DnsProvider::new(if name.is_empty() { host } else { name.to_string() }, std::net::Ipv4Addr::UNSPECIFIED.into())
+        });
+    }
+    if let Some((host, port)) = parse_hostname(address)? {
+        let name = if name.is_empty() { address.to_string() } else { with_port(name.to_string(), port) };
+        return Ok(DnsProvider {
+            addresses: Vec::new(),
+            doh_host: Some(host.clone()),
+            dot_host: Some(host),
+            port,
+            ..DnsProvider::new(name, std::net::Ipv4Addr::UNSPECIFIED.into())
+        });
+    }
+    let (primary, port) = parse_address(address)?;
+    let name = match (name.is_empty(), port) {
+        (true, Some(port)) => std::net::SocketAddr::new(primary, port).to_string(),
+        (true, None) => primary.to_string(),
+        (false, _) => with_port(name.to_string(), port),
+    };
+    Ok(DnsProvider { port, ..DnsProvider::new(name, primary) })
+}
+
+/// "192.168.1.2", "192.168.1.2:5353", "::1" or "[::1]:5353"; port 53 counts as none.
+fn parse_address(s: &str) -> Result<(std::net::IpAddr, Option<u16>), String> {
+    if let Ok(ip) = s.parse::<std::net::IpAddr>() {
+        return Ok((ip, None));
+    }
+    let address: std::net::SocketAddr = s.parse()
+        .map_err(|_| format!("'{}' is not an IPv4 or IPv6 address, optionally followed by :PORT", s))?;
+    if address.port() == 0 {
+        return Err(format!("'{}' has no valid port", s));
+    }
+    Ok((address.ip(), Some(address.port()).filter(|&port| port != 53)))
+}
+
+/// "dns.example" or "dns.example:5353" as the host and its port, `None` for an IP address.
+fn parse_hostname(s: &str) -> Result<Option<(String, Option<u16>)>, String> {
+    let (host, port) = match s.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host, Some(port)),
+        _ => (s, None),
+    };
+    let host = host.trim_end_matches('.');
+    // Anything that could be an IP address, or a mistyped one, is left to `parse_address`.
+    if host.parse::<std::net::IpAddr>().is_ok() || host.contains(['[', ']', ':']) || !host.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Ok(None);
+    }
+    if Name::from_ascii(host).is_err() {
+        return Err(format!("'{}' is not a valid hostname", host));
+    }
+    let port = match port {
+        None => None,
+        Some(port) => match port.parse::<u16>() {
+            Ok(0) | Err(_) => return Err(format!("'{}' has no valid port", s)),
+            Ok(port) => Some(port).filter(|&port| port != 53),
+        },
+    };
+    Ok(Some((host.to_ascii_lowercase(), port)))
+}
+
+/// "https://dns.example/path" as its host and path (`None` for /dns-query), optionally pinned to
+/// an address with "@192.0.2.1" or "@2001:db8::1" after it.
+fn parse_doh_url(s: &str) -> Result<(String, Option<String>, Option<std::net::IpAddr>), String> {
+    let rest = match s.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => rest,
+        _ => return Err(format!("'{}' is not an https:// URL; DoH endpoints need https", s)),
+    };
+    let (rest, pinned) = match rest.rsplit_once('@') {
+        Some((rest, ip)) => {
+            let ip = ip.trim_start_matches('[').trim_end_matches(']');
+            let ip = ip.parse::<std::net::IpAddr>().map_err(|_| format!("'{}' after @ in '{}' is not an IP address", ip, s))?;
+            (rest, Some(ip))
+        },
+        None => (rest, None),
+    };
+    if rest.contains(['?', '#']) {
+        return Err(format!("'{}' has a query or fragment; give only the endpoint's path", s));
+    }
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, ""),
+    };
+    let host = match authority.rsplit_once(':') {
+        Some((host, "443")) => host,
+        Some((_, port)) => return Err(format!("'{}' uses port {}; DoH is only queried on port 443", s, port)),
+        None => authority,
+    };
+    let host = host.trim_end_matches('.');
+    if host.parse::<std::net::IpAddr>().is_ok() || host.contains(['[', ']']) {
+        return Err(format!("'{}' needs a hostname for TLS; pin the address with @IP after the URL instead", s));
+    }
+    if host.is_empty() || Name::from_ascii(host).is_err() {
+        return Err(format!("'{}' is not a valid hostname in '{}'", host, s));
+    }
+    let path = match path {
+        "" | "/" => None,
+        path => valid_doh_path(path)?,
+    };
+    Ok((host.to_ascii_lowercase(), path, pinned))
+}
+
+/// Gives the --server entries given by hostname their addresses, looked up with the system
+/// resolver or `bootstrap`, and one provider per family with `--ip-version both`. A name that
+/// does not resolve is left out with a warning so the other servers are still tested.
+async fn resolve_servers(servers: Vec<DnsProvider>, bootstrap: Option<std::net::IpAddr>, ip_version: IpVersion) -> Vec<DnsProvider> {
+    let mut resolved = Vec::new();
+    for server in servers {
+        let host = server.dot_host.clone().or_else(|| server.doh_host.clone());
+        let Some(host) = host.filter(|_| server.addresses.is_empty()) else {
+            resolved.push(server);
+            continue;
+        };
+        let addresses = match tokio::time::timeout(BOOTSTRAP_TIMEOUT, lookup_host(&host, bootstrap)).await {
+            Ok(Ok(addresses)) => addresses,
+            Ok(Err(e)) => {
+                eprintln!("Warning: skipping {}: cannot resolve {}: {}", server.name, host, e);
+                continue;
+            },
+            Err(_) => {
+                eprintln!("Warning: skipping {}: resolving {} timed out", server.name, host);
+                continue;
+            },
+        };
+        let split = split_by_family(DnsProvider { addresses, ..server.clone() }, ip_version);
+        if split.is_empty() {
+            eprintln!("Warning: skipping {}: {} has no address for --ip-version {}", server.name, host,
+                ip_version.to_possible_value().map_or(String::new(), |value| value.get_name().to_string()));
+        }
+        resolved.extend(split);
+    }
+    resolved
+}
+
+/// `provider` limited to the addresses of the --ip-version families, one provider per family
+/// tagged "(v4)" or "(v6)" with `both`; none if it has no address of a wanted family.
+fn split_by_family(provider: DnsProvider, ip_version: IpVersion) -> Vec<DnsProvider> {
+    let families: &[bool] = match ip_version {
+        IpVersion::V4 => &[false],
+        IpVersion::V6 => &[true],
+        IpVersion::Both => &[false, true],
+    };
+    families.iter()
+        .filter_map(|&v6| {
+            let addresses: Vec<std::net::IpAddr> = provider.addresses.iter().copied().filter(|ip| ip.is_ipv6() == v6).collect();
+            if addresses.is_empty() {
+                return None;
+            }
+            let name = match ip_version {
+                IpVersion::Both => format!("{} ({})", provider.name, if v6 { "v6" } else { "v4" }),
+                _ => provider.name.clone(),
+            };
+            Some(DnsProvider { name, addresses, ..provider.clone() })
+        })
+        .collect()
+}
+
+/// "Pi-hole (port 5353)" for a named server off port 53, so rows of the same host differ.
+fn with_port(name: String, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("{} (port {})", name, port),
+        None => name,
+    }
+}
+
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1.0)
+    };
+    let value: f64 = number.trim().parse().map_err(|_| format!("'{}' is not a duration (try 3s or 500ms)", s))?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err("timeout must be greater than zero".to_string());
+    }
+    let timeout = Duration::from_secs_f64(value * scale);
+    if timeout > MAX_TIMEOUT {
+        return Err(format!("timeout must be at most {}s", MAX_TIMEOUT.as_secs()));
+    }
+    Ok(timeout)
+}
+
+fn parse_recency_weight(s: &str) -> Result<f64, String> {
+    let factor: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err("recency weight must be a positive number".to_string());
+    }
+    Ok(factor)
+}
+
+/// The first address of interface `name` that is neither loopback nor link-local.
+fn interface_address(name: &str) -> Result<std::net::IpAddr, String> {
+    let interfaces = if_addrs::get_if_addrs().map_err(|e| format!("cannot list network interfaces: {}", e))?;
+    if !interfaces.iter().any(|interface| interface.name == name) {
+        return Err(format!("no network interface named \"{}\"", name));
+    }
+    interfaces.iter()
+        .filter(|interface| interface.name == name && !interface.is_loopback() && !interface.is_link_local())
+        .map(|interface| interface.ip())
+        .next()
+        .ok_or_else(|| format!("network interface \"{}\" has no global address", name))
+}
+
+fn parse_weight(s: &str) -> Result<f64, String> {
+    let weight: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !weight.is_finite() || weight < 0.0 {
+        return Err("weights must be zero or positive".to_string());
+    }
+    Ok(weight)
+}
+
+fn parse_qps(s: &str) -> Result<f64, String> {
+    let qps: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !qps.is_finite() || qps <= 0.0 {
+        return Err("the rate must be above zero".to_string());
+    }
+    Ok(qps)
+}
+
+/// A number of seconds, as --interval always took, or a duration such as 30m.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let interval = match s.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => humantime::parse_duration(s).map_err(|_| format!("'{}' is not an interval (try 30m or 300)", s))?,
+    };
+    if interval < Duration::from_secs(1) {
+        return Err("the interval must be at least 1s".to_string());
+    }
+    Ok(interval)
+}
+
+fn parse_budget(s: &str) -> Result<Duration, String> {
+    let budget = humantime::parse_duration(s.trim()).map_err(|_| format!("'{}' is not a duration (try 30s or 5m)", s))?;
+    if budget.is_zero() {
+        return Err("the time budget must be above zero".to_string());
+    }
+    Ok(budget)
+}
+
+fn parse_expiry(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s.trim()).map_err(|_| format!("'{}' is not a duration (try 12h or 7d)", s))
+}
+
+fn parse_load_duration(s: &str) -> Result<Duration, String> {
+    let duration = humantime::parse_duration(s.trim()).map_err(|_| format!("'{}' is not a duration (try 10s or 1m)", s))?;
+    if duration < Duration::from_secs(1) {
+        return Err("the load test must run for at least 1s".to_string());
+    }
+    if duration > MAX_LOAD_DURATION {
+        return Err(format!("the load test may run for at most {}s", MAX_LOAD_DURATION.as_secs()));
+    }
+    Ok(duration)
+}
+
+fn parse_target_qps(s: &str) -> Result<f64, String> {
+    let qps = parse_qps(s)?;
+    if qps > MAX_TARGET_QPS {
+        return Err(format!("at most {} queries/s; more is a flood, not a measurement", MAX_TARGET_QPS));
+    }
+    Ok(qps)
+}
+
+fn parse_penalty(s: &str) -> Result<f64, String> {
+    let factor: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err("the factor must be above zero".to_string());
+    }
+    Ok(factor)
+}
+
+/// A percentage of the median such as 10%, or a duration such as 1ms.
+fn parse_precision(s: &str) -> Result<Precision, String> {
+    let s = s.trim();
+    let precision = if s.ends_with('%') {
+        Precision::Percent(parse_percent(s)?)
+    } else {
+        Precision::Within(humantime::parse_duration(s).map_err(|_| format!("'{}' is neither a percentage nor a duration (try 10% or 1ms)", s))?)
+    };
+    if matches!(precision, Precision::Percent(0.0) | Precision::Within(Duration::ZERO)) {
+        return Err("the precision must be above zero".to_string());
+    }
+    Ok(precision)
+}
+
+fn parse_trim(s: &str) -> Result<f64, String> {
+    let percent = parse_percent(s)?;
+    if percent >= 50.0 {
+        return Err("trimming must be below 50%, or nothing would be left".to_string());
+    }
+    Ok(percent)
+}
+
+fn write_table_report(
+    out: &mut dyn Write,
+    results: &[TestResult],
+    excluded: &[TestResult],
+    args: &Args,
+    cfg: &SpeedTestConfig,
+    baseline: Option<&baseline::Baseline>,
+    palette: Palette,
+) -> io::Result<()> {
+    let (all_results, all_excluded) = (results, excluded);
+    let (results, excluded) = top_rows(results, excluded, args.top);
+    let hidden = all_results.len() + all_excluded.len() - results.len() - excluded.len();
+    let sorted_by = match args.sort {
+        SortKey::Score => "score",
+        SortKey::Median => "median speed",
+        SortKey::Avg => "average speed",
+        SortKey::Min => "minimum latency",
+        SortKey::Max => "maximum latency",
+        SortKey::P95 => "p95 latency",
+        SortKey::P99 => "p99 latency",
+        SortKey::Success => "success rate",
+        SortKey::Name => "name",
+        SortKey::Jitter => "jitter",
+    };
+    let mode = if args.cache_bust { ", uncached" } else { "" };
+    let order = if args.reverse { ", reversed" } else { "" };
+    writeln!(out, "\nDetailed Results over {}{} (sorted by {}{}):", args.protocol.label(), mode, sorted_by, order)?;
+    if cfg.cancel.is_cancelled() {
+        writeln!(out, "Partial run: interrupted before all {} providers were tested; only those that finished are shown", cfg.providers.len())?;
+    }
+    if let Some(warning) = args.interception.as_ref().and_then(interception_warning) {
+        writeln!(out, "Warning: {}", warning)?;
+    }
+    if let Some(warning) = args.vpn.as_ref().and_then(vpn_warning) {
+        writeln!(out, "Warning: {}", warning)?;
+    }
+    if !args.unresolvable_domains.is_empty() {
+        let domains: Vec<String> = args.unresolvable_domains.iter().map(|domain| domains::display(domain)).collect();
+        writeln!(out, "Warning: left out test domains that resolve nowhere, not even through the system resolver: {}", domains.join(", "))?;
+    }
+    if let Some(baseline) = baseline {
+        writeln!(out, "Compared with {} from {}", baseline.path.display(), humantime::format_rfc3339_seconds(baseline.time()))?;
+        let differences = baseline.differences(cfg);
+        if !differences.is_empty() {
+            writeln!(out, "Warning: the baseline was measured differently ({}), so the deltas are only indicative", differences.join(", "))?;
+        }
+    }
+    let address_queries = !args.cache_bust && args.record_types.iter().any(|record_type| matches!(record_type, QueryType::A | QueryType::Aaaa));
+    let selected: Vec<&Column> = if args.columns.is_empty() {
+        COLUMNS.iter()
+            .filter(|column| column.name != "cookies" || args.cookie_check)
+            .filter(|column| column.name != "qmin" || args.qname_min_check)
+            .filter(|column| column.name != "retries" || args.retries > 0)
+            .filter(|column| column.name != "resent" || args.attempts > 1 || cfg.providers.iter().any(|provider| provider.attempts.is_some_and(|attempts| attempts > 1)))
+            // Without resends, a lost packet is a timeout in the failure breakdown.
+            .filter(|column| column.name != "loss" || (args.protocol == Protocol::Udp && (args.retries > 0 || args.attempts > 1)))
+            .filter(|column| column.name != "wasted" || results.iter().chain(excluded).any(|result| result.failure_wait.is_some()))
+            .filter(|column| column.name != "runsd" || args.repeat > 1)
+            .filter(|column| column.name != "qps" || args.parallel_queries > 1)
+            .filter(|column| column.name != "tcp" || (args.tcp_check && args.protocol != Protocol::Doq))
+            .filter(|column| column.name != "quic" || (args.tcp_check && results.iter().chain(excluded).any(|result| result.transport == Transport::Quic.label())))
+            .filter(|column| !matches!(column.name, "connect" | "conn_query" | "steady") || results.iter().chain(excluded).any(|result| result.connection_split.is_some()))
+            .filter(|column| !matches!(column.name, "rtt" | "overhead") || args.ping)
+            .filter(|column| column.name != "delta" || baseline.is_some())
+            .filter(|column| column.name != "transport" || args.best_transport)
+            .filter(|column| column.name != "first" || args.protocol != Protocol::Udp || args.stats == StatsMode::Full)
+            .filter(|column| column.name != "uncached" || args.compare_cache)
+            .filter(|column| column.name != "fresh" || args.socket_mode == SocketMode::Compare)
+            .filter(|column| column.name != "failover" || args.failover)
+            .filter(|column| column.name != "dnssec" || !args.no_dnssec_check)
+            .filter(|column| column.name != "dns64" || args.record_types.contains(&QueryType::Aaaa))
+            // The usable share stands in for the resolved one where there are addresses to judge.
+            .filter(|column| match column.name {
+                "usable" => address_queries,
+                "success" => !address_queries || args.stats == StatsMode::Full,
+                _ => true,
+            })
+            .filter(|column| !matches!(column.name, "success_7d" | "success_30d") || args.with_history)
+            .filter(|column| column.name != "pop" || !args.no_pop_check)
+            .filter(|column| column.name != "verified" || !args.no_identity_check)
+            .filter(|column| !matches!(column.name, "size" | "truncated") || !args.no_size_check)
+            .filter(|column| column.name != "blocked" || args.blocking_test)
+            .filter(|column| !matches!(column.name, "p95" | "p99" | "stddev" | "resent" | "cold" | "warm" | "nxdomain" | "https" | "https_ok" | "ttl" | "rounds" | "cnames" | "stability") || args.stats == StatsMode::Full)
+            .filter(|column| column.name != "nxdomain" || args.nxdomain_probes > 0)
+            .filter(|column| !matches!(column.name, "https" | "https_ok") || args.https_probe)
+            .collect()
+    } else {
+        chosen_columns(&args.columns)
+    };
+    // The provider column grows to fit names such as "Cloudflare (Family, v4, 1.0.0.3)", up to
+    // a limit past which names are shortened.
+    let provider_column = COLUMNS.iter().find(|column| column.name == "provider").unwrap();
+    let name_width = results.iter().chain(excluded)
+        .map(|result| provider_name(result, args).chars().count())
+        .max()
+        .unwrap_or(0)
+        .clamp(provider_column.width, MAX_NAME_WIDTH);
+    let header = |column: &Column| match column.name {
+        "rounds" => args.unit.heading(&(1..=args.rounds).map(|round| format!("R{}", round)).collect::<Vec<_>>().join("/")),
+        "relative" => match relative_metric(args.sort) {
+            "median" => column.header.to_string(),
+            metric => format!("{} {}", metric, column.header),
+        },
+        _ => column.header(args.unit),
+    };
+    let sized: Vec<Column> = selected.iter()
+        .map(|&column| match column.name {
+            "provider" => Column { width: name_width, ..*column },
+            // As wide as the longest list, so the rounds line up.
+            "rounds" => {
+                let width = results.iter().chain(excluded)
+                    .map(|result| (column.value)(result, args.unit).chars().count())
+                    .chain([header(column).chars().count()])
+                    .max()
+                    .unwrap_or(column.width);
+                Column { width, ..*column }
+            },
+            _ => *column,
+        })
+        .collect();
+    let columns: Vec<&Column> = sized.iter().collect();
+    let header_row = format_row(&columns, header, Palette::PLAIN, |_| None);
+    let rule = "-".repeat(header_row.trim_end().chars().count());
+    writeln!(out, "{}", rule)?;
+    writeln!(out, "{}", header_row)?;
+    writeln!(out, "{}", rule)?;
+    
+    let threshold = args.regression_threshold / 100.0;
+    let best = best_result(results, args);
+    let cell = |column: &Column, result: &TestResult| match (column.name, baseline) {
+        // "+3.10 (+25%) !" for a change past the regression threshold
+        ("delta", Some(baseline)) => baseline.change(result).map_or("n/a".to_string(), |(before, change)| {
+            // A change means this run has a median.
+            let now = result.median_duration.unwrap_or_default();
+            let (sign, difference) = if now < before { ("-", before - now) } else { ("+", now - before) };
+            let marker = if change > threshold { " !" } else { "" };
+            format!("{}{} ({:+.0}%){}", sign, args.unit.format(difference), change * 100.0, marker)
+        }),
+        ("provider", _) => provider_name(result, args),
+        ("success_7d", _) => history_rate(result, args, |reliability| reliability.week),
+        ("success_30d", _) => history_rate(result, args, |reliability| reliability.month),
+        ("relative", _) if best.is_some_and(|best| std::ptr::eq(best, result)) => "baseline".to_string(),
+        ("relative", _) => relative_to_fastest(result, best, args.sort).map_or("n/a".to_string(), |relative| {
+            match (args.relative, relative.percent) {
+                (Relative::Percent, Some(percent)) => format!("{:+.0}%", percent),
+                // A best value of zero leaves only the difference.
+                _ => format!("{}{}", if relative.ms < 0.0 { "-" } else { "+" },
+                    args.unit.format_with_unit(Duration::from_secs_f64(relative.ms.abs() / 1000.0))),
+            }
+        }),
+        _ if not_tested(result) && column.latency => "n/a".to_string(),
+        _ => (column.value)(result, args.unit),
+    };
+    // A provider that answered nothing gets its name and what happened instead of a row of n/a.
+    let lead = columns.iter().position(|column| column.name == "provider").map_or(0, |i| i + 1);
+    // The unreliable providers follow the others, in their own order.
+    let mut rows: Vec<&TestResult> = results.iter().chain(excluded).collect();
+    let mut headings: Vec<String> = Vec::new();
+    if args.split_by_egress {
+        // Each way out in the order of its best row, the rows keeping theirs within it.
+        let mut split: Vec<(String, &TestResult)> = rows.iter().map(|&result| (egress_heading(result, args), result)).collect();
+        let mut order: Vec<String> = Vec::new();
+        for (heading, _) in &split {
+            if !order.contains(heading) {
+                order.push(heading.clone());
+            }
+        }
+        split.sort_by_key(|(heading, _)| order.iter().position(|way| way == heading));
+        (headings, rows) = split.into_iter().unzip();
+    }
+    for (i, &result) in rows.iter().enumerate() {
+        if args.group_by_protocol && (i == 0 || rows[i - 1].transport != result.transport) {
+            writeln!(out, "{}:", result.transport)?;
+        }
+        if args.split_by_egress && (i == 0 || headings[i - 1] != headings[i]) {
+            writeln!(out, "{}:", headings[i])?;
+        }
+        let color = |column: &Column| match column.name {
+            "provider" if best.is_some_and(|best| std::ptr::eq(best, result)) => Some(Color::Best),
+            "median" => Some(Color::for_median(result.median_duration)),
+            "success" if result.success_rate < 100.0 => Some(Color::Red),
+            _ => None,
+        };
+        match unanswered(result) {
+            Some(note) => {
+                let name = format_row(&columns[..lead], |column| cell(column, result), palette, color);
+                let note = palette.paint(format!("— {}", note), Some(Color::Red));
+                writeln!(out, "{}", [name, note].join(" ").trim_start())?;
+            },
+            None => writeln!(out, "{}", format_row(&columns, |column| cell(column, result), palette, color))?,
+        }
+
+        if let Some(error) = result.connection_split.as_ref().and_then(|split| split.error.as_ref()) {
+            writeln!(out, "    New {} connection failed, {}; the rounds' figures are their own", result.transport, error)?;
+        }
+        if result.unreachable {
+            writeln!(out, "    Unreachable: no answer to a UDP query and no TCP connection within 1 s, so not tested (--no-preflight tests it anyway)")?;
+        }
+        if result.addresses.iter().any(|address| address.parse().is_ok_and(is_local_cache)) {
+            writeln!(out, "    Local cache, not directly comparable: a server on this machine or link answers from its cache and forwards the rest")?;
+        }
+        if result.early_stop {
+            writeln!(out, "    n={} (early stop): the median's 95% confidence interval was within {}", result.completed_queries,
+                args.adaptive.map_or(String::new(), |precision| precision.to_string()))?;
+        }
+        if not_tested(result) {
+            writeln!(out, "    Not tested: the --total-budget ran out first")?;
+        } else if result.budget_exceeded {
+            let queries = result.completed_queries;
+            writeln!(out, "    Cut short by the time budget after {} quer{}; the figures cover those", queries, if queries == 1 { "y" } else { "ies" })?;
+        }
+        if !result.failures.is_empty() {
+            writeln!(out, "    Failures: {}", failure_summary(result))?;
+            if args.show_error_text {
+                for text in &result.error_texts {
+                    writeln!(out, "      {}", text)?;
+                }
+            }
+        }
+        if let Some(&count) = result.failure_kinds.get(&FailureKind::DomainDnssec) {
+            writeln!(out, "    Not counted against it: {} SERVFAIL that a validating provider also got, most likely broken DNSSEC on the domain's side", count)?;
+        }
+        if !result.recovered_domains.is_empty() {
+            writeln!(out, "    Recovered on retry: {}", display_domains(&result.recovered_domains))?;
+        }
+        if !result.any_handling.is_empty() {
+            let kinds: Vec<String> = match result.any_handling.keys().collect::<Vec<_>>()[..] {
+                [only] => vec![only.describe().to_string()],
+                _ => result.any_handling.iter().map(|(kind, count)| format!("{} {}", count, kind.describe())).collect(),
+            };
+            writeln!(out, "    ANY: {}", kinds.join(", "))?;
+        }
+        if let Some(dns64) = result.dns64.as_ref().filter(|dns64| dns64.status == Dns64Status::Yes) {
+            let prefix = dns64.prefix.as_deref().map_or(String::new(), |prefix| format!(" under {}", prefix));
+            if dns64.synthesized.is_empty() {
+                writeln!(out, "    DNS64: synthesizes AAAA answers{}", prefix)?;
+            } else {
+                writeln!(out, "    DNS64: synthesizes AAAA answers{} ({})", prefix, display_domains(&dns64.synthesized))?;
+            }
+        }
+        if args.stats == StatsMode::Full && !result.nxdomain_probe_failures.is_empty() {
+            let kinds: Vec<String> = result.nxdomain_probe_failures.iter().map(|(kind, &count)| kind.describe(count)).collect();
+            writeln!(out, "    Nonexistent names not answered with NXDOMAIN: {}", kinds.join(", "))?;
+        }
+        match result.ddr.as_deref() {
+            Some([]) => writeln!(out, "    No DDR: designates no encrypted endpoints{}", if args.protocol == Protocol::Auto { "; the known ones were tested" } else { "" })?,
+            Some(designated) => writeln!(out, "    DDR: {}", designated.iter().map(DdrEndpoint::describe).collect::<Vec<_>>().join(", "))?,
+            None => {},
+        }
+        if args.stats == StatsMode::Full && !result.https_failures.is_empty() {
+            let kinds: Vec<String> = result.https_failures.iter().map(|(kind, &count)| kind.describe(count)).collect();
+            writeln!(out, "    HTTPS records not answered correctly: {}", kinds.join(", "))?;
+        }
+        if args.stats == StatsMode::Full && !result.unreachable {
+            let timing = &result.timing;
+            writeln!(out, "    Time: {:.2} s in total, {:.2} s measuring ({:.2} s queries + {:.2} s TCP check), {:.2} s cooldown",
+                timing.wall.as_secs_f64(), timing.active().as_secs_f64(), timing.queries.as_secs_f64(),
+                timing.tcp_check.as_secs_f64(), timing.cooldown.as_secs_f64())?;
+        }
+        if !result.slow_domains.is_empty() {
+            writeln!(out, "    Slow but answered after timeout: {}", display_domains(&result.slow_domains))?;
+        }
+        if let Some(stability) = result.answer_stability.as_ref().filter(|stability| !stability.unstable.is_empty()) {
+            let queries: Vec<String> = stability.unstable.iter().take(UNSTABLE_ANSWERS_SHOWN)
+                .map(|answer| format!("{} ({} of {} rounds)", domains::display(&answer.domain), answer.changes, answer.compared))
+                .collect();
+            let more = stability.unstable.len().saturating_sub(UNSTABLE_ANSWERS_SHOWN);
+            writeln!(out, "    Answers replaced completely between rounds: {}{}", queries.join(", "),
+                if more > 0 { format!(" and {} more", more) } else { String::new() })?;
+        }
+        if let Some(legs) = &result.truncated_legs {
+            writeln!(out, "    Truncated over UDP, retried over TCP: {} {} (median {} over UDP + {} over TCP, counted as one)",
+                result.truncated_retries,
+                if result.truncated_retries == 1 { "query" } else { "queries" },
+                args.unit.format_with_unit(legs.udp_median),
+                args.unit.format_with_unit(legs.tcp_median))?;
+        }
+        if let Some(near) = result.near_timeout.filter(|near| near.over_half > 0) {
+            let timeout = humantime::format_duration(result.timeout);
+            writeln!(out, "    Near the timeout: {} of {} answers took over half the {} timeout, {} over 90%",
+                near.over_half, near.answered, timeout, near.over_90_percent)?;
+            if near.over_90_percent > 0 {
+                writeln!(out, "    Warning: {} {} only just beat the timeout: a problem on the path, or raise --timeout",
+                    near.over_90_percent, if near.over_90_percent == 1 { "answer" } else { "answers" })?;
+            }
+        }
+        if let Some(loss) = result.packet_loss.filter(|loss| loss.lost > 0) {
+            writeln!(out, "    Packet loss: {} of {} UDP packets got no response in time ({:.1}%), counting those a resend made up for",
+                loss.lost, loss.sent, loss.percent)?;
+        }
+        if !result.bogon_answers.is_empty() {
+            let pairs: Vec<String> = result.bogon_answers.iter()
+                .map(|bogon| format!("{} → {} ({})", domains::display(&bogon.domain), bogon.address, bogon.range.describe()))
+                .collect();
+            writeln!(out, "    Warning: public domains resolved to non-public addresses: {}", pairs.join(", "))?;
+        }
+        for suspicious in &result.suspicious_answers {
+            writeln!(out, "    Warning: suspicious answer for {}", suspicious)?;
+        }
+        if result.nxdomain_hijacking == Some(true) {
+            writeln!(out, "    Warning: Hijacks NXDOMAIN (returned addresses for names that do not exist)")?;
+        }
+        match (&result.resolver_identity, result.verified) {
+            (Some(identity), Some(false)) => writeln!(out, "    Warning: may not be {} answering: the identity query got {}, which is not theirs (DNS redirected?)", result.provider, identity)?,
+            (None, Some(false)) => writeln!(out, "    Warning: may not be {} answering: the server did not answer their identity query (DNS redirected?)", result.provider)?,
+            (Some(identity), None) if args.stats == StatsMode::Full => writeln!(out, "    Resolver egress: {}", identity)?,
+            _ => {},
+        }
+        if let Some(trend) = result.round_trend.filter(|&trend| trend > ROUND_TREND_WARNING) {
+            writeln!(out, "    Warning: slows down over the run, the last round's median is {:.0}% above the first's (rate limiting?)", trend)?;
+        }
+    }
+    if hidden > 0 {
+        writeln!(out, "…and {} more, use --top 0 to show all", hidden)?;
+    }
+
+    if let Some(baseline) = baseline {
+        let providers: Vec<&str> = all_results.iter().chain(all_excluded).map(|result| result.provider.as_str()).collect();
+        if results.iter().any(|result| baseline.change(result).is_some_and(|(_, change)| change > threshold)) {
+            writeln!(out, "\n! Median more than {}% slower than in the baseline", args.regression_threshold)?;
+        }
+        let new = baseline.new_providers(&providers);
+        if !new.is_empty() {
+            writeln!(out, "Not in the baseline: {}", new.join(", "))?;
+        }
+        let missing = baseline.missing_providers(&providers);
+        if !missing.is_empty() {
+            writeln!(out, "Only in the baseline: {}", missing.join(", "))?;
+        }
+    }
+
+    let fallback: Vec<(&str, &str)> = results.iter()
+        .filter_map(|r| {
+            let (name, percentile) = sort_percentile(args.sort, r)?;
+            (r.success_rate > 0.0 && r.tail_latency(percentile).1).then_some((name, r.provider.as_str()))
+        })
+        .collect();
+    if let Some(&(name, _)) = fallback.first() {
+        let providers: Vec<&str> = fallback.iter().map(|&(_, provider)| provider).collect();
+        writeln!(out, "\nRanked by max instead of {} (fewer than {} answers): {}", name, MIN_PERCENTILE_SAMPLES, providers.join(", "))?;
+    }
+
+    if args.ping {
+        writeln!(out)?;
+        rtt_notes(out, results.iter().chain(excluded))?;
+    }
+
+    if results.iter().chain(excluded).any(|r| r.system_resolver) {
+        writeln!(out, "\n* Currently configured as a system resolver.")?;
+    }
+    if results.iter().chain(excluded).any(|r| r.addresses.iter().any(|a| a == SYSTEMD_RESOLVED_STUB)) {
+        writeln!(out, "  The systemd-resolved stub caches answers and forwards to its own upstream servers, so")?;
+        writeln!(out, "  its numbers are not directly comparable to querying a provider.")?;
+    }
+
+    if args.repeat > 1 {
+        let rows: Vec<&TestResult> = results.iter().chain(excluded).collect();
+        let runs = rows.iter().filter_map(|r| r.runs.as_ref()).map(|runs| runs.runs).max().unwrap_or(1);
+        match rows.first().map(|r| r.samples.len()) {
+            Some(samples) if rows.iter().all(|r| r.samples.len() == samples) => {
+                writeln!(out, "\nPooled over {} runs: {} queries behind every provider's figures", runs, samples)?;
+            },
+            _ => {
+                let counts: Vec<String> = rows.iter().map(|r| format!("{} {}", r.provider, r.samples.len())).collect();
+                writeln!(out, "\nPooled over {} runs; queries behind each provider's figures: {}", runs, counts.join(", "))?;
+            },
+        }
+        writeln!(out, "Run SD: how much a provider's median varied between runs; smaller differences between providers are noise.")?;
+    }
+    if excluded.iter().any(|result| unanswered(result).is_none()) {
+        writeln!(out, "\n(unreliable): success rate below {:.1}% (--min-success), so never recommended.", args.min_success)?;
+    }
+    if results.iter().chain(excluded).any(|result| !result.bogon_answers.is_empty()) {
+        let gap = if excluded.is_empty() { "\n" } else { "" };
+        writeln!(out, "{}(bogon answers): private or reserved addresses for public domains, as from a captive portal or a hijacking resolver; its latencies may not be real lookups.", gap)?;
+    }
+
+    if args.group_by == GroupBy::Provider {
+        write_groups(out, all_results.iter().chain(all_excluded), args)?;
+    }
+    if args.per_domain || args.single_domains.len() > 1 {
+        write_domain_matrix(out, results, args)?;
+    }
+    if !args.single_domains.is_empty() {
+        write_answers(out, results.iter().chain(excluded))?;
+    }
+    if args.winners {
+        write_winners(out, results, args)?;
+    }
+    if args.verify_answers {
+        write_answer_reachability(out, results.iter().chain(excluded))?;
+    }
+    if args.histogram {
+        write_histograms(out, results, args)?;
+    }
+
+    if args.edns == EdnsMode::Compare {
+        writeln!(out, "\nEDNS comparison (without vs with EDNS):")?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<30} {:>14} {:>14} {:>12} {:>12}",
+            "Provider", args.unit.heading("Without"), args.unit.heading("With"), "Success w/o", "Success w/")?;
+        writeln!(out, "{:-<90}", "")?;
+        let mut differs = false;
+        for result in results {
+            let Some(comparison) = &result.edns_comparison else { continue };
+            let marker = if comparison.without_edns_success_rate != result.success_rate {
+                differs = true;
+                " !"
+            } else {
+                ""
+            };
+            writeln!(out, "{:<30} {:>14} {:>14} {:>11.1}% {:>11.1}%{}",
+                fit(result.provider.replace(", EDNS)", ")").replace(" (EDNS)", ""), 30),
+                opt_latency(comparison.without_edns_median, args.unit),
+                opt_latency(result.median_duration, args.unit),
+                comparison.without_edns_success_rate,
+                result.success_rate,
+                marker
+            )?;
+        }
+        if differs {
+            writeln!(out, "! The success rate depends on EDNS; something on the path may mangle EDNS queries.")?;
+        }
+    }
+
+    if args.edns == EdnsMode::CompareBuffers {
+        let sizes: Vec<String> = EDNS_BUFFER_SIZES.iter().map(u16::to_string).collect();
+        writeln!(out, "\nEDNS buffer comparison (no EDNS vs {} bytes):", sizes.join(" vs "))?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<30} {:<10} {:>14} {:>10} {:>10} {:>8}", "Provider", "EDNS", args.unit.heading("Median"), "Success", "Truncated", "Failed")?;
+        writeln!(out, "{:-<90}", "")?;
+        let mut fragments = Vec::new();
+        for comparison in results.iter().chain(excluded).filter_map(|result| result.buffer_comparison.as_ref()) {
+            for (i, run) in comparison.runs.iter().enumerate() {
+                writeln!(out, "{:<30} {:<10} {:>14} {:>9.1}% {:>10} {:>8}",
+                    if i == 0 { fit(comparison.provider.clone(), 30) } else { String::new() },
+                    run.edns_buffer.map_or("off".to_string(), |size| format!("{} B", size)),
+                    opt_latency(run.median, args.unit),
+                    run.success_rate,
+                    run.truncated_retries,
+                    run.failed_queries)?;
+            }
+            // The largest buffer failing where the smallest one works points at dropped fragments.
+            if let (Some(small), Some(large)) = (comparison.runs.get(1), comparison.runs.last()) {
+                if large.success_rate < small.success_rate {
+                    fragments.push(comparison.provider.as_str());
+                }
+            }
+        }
+        if !fragments.is_empty() {
+            writeln!(out, "! More queries fail at {} than at {} bytes for {}; the network may drop fragmented UDP.",
+                EDNS_BUFFER_SIZES[EDNS_BUFFER_SIZES.len() - 1], EDNS_BUFFER_SIZES[0], fragments.join(", "))?;
+        }
+    }
+
+    if args.best_transport {
+        writeln!(out, "\nTransport comparison (UDP vs DoH):")?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<15} {:>14} {:>14} {:>14} {:>12} {:>15}",
+            "Provider", args.unit.heading("UDP"), args.unit.heading("DoH"), args.unit.heading("Delta"),
+            "DoH Success", "Recommended")?;
+        writeln!(out, "{:-<90}", "")?;
+        for result in results {
+            let Some(comparison) = &result.transport_comparison else {
+                writeln!(out, "{:<15} {:>14}", result.provider, "no DoH endpoint")?;
+                continue;
+            };
+            let delta = match (comparison.doh_median, result.median_duration) {
+                (Some(doh), Some(udp)) => {
+                    let delta = doh.as_secs_f64() - udp.as_secs_f64();
+                    format!("{}{}", if delta < 0.0 { "-" } else { "+" }, args.unit.format(Duration::from_secs_f64(delta.abs())))
+                },
+                _ => "n/a".to_string(),
+            };
+            writeln!(out, "{:<15} {:>14} {:>14} {:>14} {:>11.1}% {:>15}",
+                result.provider,
+                opt_latency(result.median_duration, args.unit),
+                opt_latency(comparison.doh_median, args.unit),
+                delta,
+                comparison.doh_success_rate,
+                result.recommended_transport().map_or("n/a", Transport::label)
+            )?;
+        }
+    }
+
+    if args.saturation_test {
+        writeln!(out, "\nSaturation test (concurrent in-flight queries):")?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<15} {:>12} {:>14}  Median per level", "Provider", "Handles well", "Breaks down at")?;
+        writeln!(out, "{:-<90}", "")?;
+        for result in results {
+            let Some(saturation) = &result.saturation else { continue };
+            let handled = saturation.levels.iter()
+                .take_while(|level| Some(level.concurrency) != saturation.breakdown)
+                .last()
+                .map_or("none".to_string(), |level| level.concurrency.to_string());
+            let breakdown = saturation.breakdown.map_or(">128".to_string(), |c| c.to_string());
+            let per_level = saturation.levels.iter()
+                .map(|level| format!("{}: {}", level.concurrency,
+                    level.median.map_or("n/a".to_string(), |d| args.unit.format(d))))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(out, "{:<15} {:>12} {:>14}  {}", result.provider, handled, breakdown, per_level)?;
+        }
+    }
+
+    if args.failover {
+        writeln!(out, "\nFailover (primary unreachable, secondary answering):")?;
+        writeln!(out, "{:-<90}", "")?;
+        writeln!(out, "{:<15} {:>18} {:>14} {:>14} {:>14}",
+            "Provider", "Secondary",
+            args.unit.heading("Healthy"), args.unit.heading("Failover"), args.unit.heading("Penalty"))?;
+        writeln!(out, "{:-<90}", "")?;
+        let fmt = |d: Option<Duration>| d.map_or("n/a".to_string(), |d| args.unit.format(d));
+        for result in results {
+            if let Some(failover) = &result.failover {
+                let penalty = match (failover.healthy_median, failover.failover_median) {
+                    (Some(healthy), Some(degraded)) => Some(degraded.saturating_sub(healthy)),
+                    _ => None,
+                };
+                writeln!(out, "{:<15} {:>18} {:>14} {:>14} {:>14}",
+                    result.provider,
+                    failover.secondary,
+                    fmt(failover.healthy_median),
+                    fmt(failover.failover_median),
+                    fmt(penalty)
+                )?;
+                // Failing over means waiting out the timeout first, unless the network refused
+                // the dead address at once (an ICMP error) instead of dropping the query.
+                if penalty.is_some_and(|penalty| penalty < result.timeout / 2) {
+                    writeln!(out, "    Note: the unreachable primary was refused rather than dropped here, so the timeout path was not measured")?;
+                }
+            }
+        }
+    }
+
+    if let Some(best) = best_result(results, args) {
+        // With the rows grouped, the organization wins, through its fastest endpoint.
+        let winner = match args.group_by {
+            GroupBy::Provider if best.organization != best.provider => format!("{}, fastest at {}", best.organization, best.provider),
+            _ => best.provider.clone(),
+        };
+        // Today's figures, with how the provider held up over the past month next to them.
+        let reliable = match args.reliability.get(&best.provider).and_then(|reliability| reliability.month) {
+            Some(rate) => format!("; {:.1}% over 30 days", rate),
+            None => String::new(),
+        };
+        let (metric, value) = match args.sort {
+            SortKey::Score => ("score", format!("score {}, {} median",
+                best.score.map_or("n/a".to_string(), |score| format!("{:.2}", score)), with_unit(best.median_duration, args.unit))),
+            SortKey::Median | SortKey::Name => ("", format!("{} median", with_unit(best.median_duration, args.unit))),
+            SortKey::Avg => ("average", format!("{} average", with_unit(best.avg_duration, args.unit))),
+            SortKey::Min => ("minimum latency", format!("{} minimum", with_unit(best.min_latency, args.unit))),
+            SortKey::Max => ("maximum latency", format!("{} maximum", with_unit(best.max_latency, args.unit))),
+            SortKey::P95 | SortKey::P99 => {
+                let Some((name, percentile)) = sort_percentile(args.sort, best) else { unreachable!() };
+                let value = match best.tail_latency(percentile) {
+                    (latency, false) => format!("{} {}", with_unit(latency, args.unit), name),
+                    (latency, true) => format!("{} max, too few answers for a {}", with_unit(latency, args.unit), name),
+                };
+                (name, value)
+            },
+            SortKey::Success => ("success rate", format!("{} median", with_unit(best.median_duration, args.unit))),
+            SortKey::Jitter => ("jitter", format!("{} jitter", best.jitter.map_or("n/a".to_string(), |jitter| args.unit.format_with_unit(jitter)))),
+        };
+        if metric.is_empty() {
+            writeln!(out, "\nFastest DNS provider: {} ({}, {:.1}% success rate{})", winner, value, best.success_rate, reliable)?;
+        } else if matches!(args.sort, SortKey::P95 | SortKey::P99) {
+            writeln!(out, "\nBest {}: {} ({}, {:.1}% success rate{})", metric, winner, value, best.success_rate, reliable)?;
+        } else {
+            writeln!(out, "\nBest provider by {}: {} ({}, {:.1}% success rate{})", metric, winner, value, best.success_rate, reliable)?;
+        }
+
+        if let Some(note) = current_provider_note(all_results, best, args) {
+            writeln!(out, "{}", note)?;
+        }
+        if let Some(note) = gateway_note(all_results, args) {
+            writeln!(out, "{}", note)?;
+        }
+
+        if let Some(format) = args.emit_config {
+            write!(out, "\n{}", format.snippet(best))?;
+        }
+    } else if let Some(best) = best_result(excluded, args) {
+        writeln!(out, "\nNo DNS provider reached the {:.1}% success threshold; the best of the unreliable ones is {} ({} median, {:.1}% success rate).",
+            args.min_success, best.provider, with_unit(best.median_duration, args.unit), best.success_rate)?;
+    } else if !excluded.is_empty() {
+        writeln!(out, "\nNo DNS provider reached the {:.1}% success threshold.", args.min_success)?;
+    }
+    if let Some(elapsed) = args.elapsed {
+        write!(out, "\nRun time: {} (estimated {})", rough_duration(elapsed), rough_duration(args.estimated_runtime))?;
+        match args.setup_elapsed {
+            Some(setup) => writeln!(out, "; setup: {:.1} s", setup.as_secs_f64())?,
+            None => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+/// The rows --top leaves in the table: the first `top` ranked results, and the unreliable ones
+/// after them while slots are left. `None` or 0 keeps them all.
+fn top_rows<'a>(results: &'a [TestResult], excluded: &'a [TestResult], top: Option<usize>) -> (&'a [TestResult], &'a [TestResult]) {
+    match top.filter(|&top| top > 0) {
+        Some(top) => {
+            let results = &results[..top.min(results.len())];
+            (results, &excluded[..(top - results.len()).min(excluded.len())])
+        },
+        None => (results, excluded),
+    }
+}
+
+/// Leaves out the test domains that neither the system's nameservers nor the first provider
+/// resolve, which would fail for every provider alike. All of them stay when none resolves,
+/// which says more about the network than about the domains.
+async fn check_domains(args: &mut Args, providers: &[DnsProvider], progress: &mut dyn Write) {
+    let mut controls: Vec<(std::net::IpAddr, u16)> = args.nameservers.iter().map(|&ip| (ip, 53)).collect();
+    if let Some(first) = providers.first() {
+        controls.extend(first.addresses.first().map(|&ip| (ip, first.port_for(Transport::Udp))));
+    }
+    controls.retain(|&(ip, _)| args.bind.is_none_or(|bind| bind.is_ipv4() == ip.is_ipv4()));
+    controls.dedup();
+    if controls.is_empty() {
+        return;
+    }
+    let unresolvable = unresolvable_domains(&args.domains, &controls, args.bind, CONTROL_TIMEOUT).await;
+    if unresolvable.len() == args.domains.len() {
+        writeln!(progress, "Warning: no test domain resolved through the system resolver or the first provider, so all of them are tested").ok();
+        return;
+    }
+    args.domains.retain(|domain| !unresolvable.contains(domain));
+    args.unresolvable_domains = unresolvable;
+}
+
+/// Adds systemd-resolved's upstream servers for --resolve-upstreams when its stub is among
+/// `providers`, leaving out those tested anyway. Without them the stub's row only carries its
+/// local-cache note.
+fn add_resolved_upstreams(providers: &mut Vec<DnsProvider>, progress: &mut dyn Write) {
+    let stub: std::net::IpAddr = SYSTEMD_RESOLVED_STUB.parse().unwrap();
+    if !providers.iter().any(|provider| provider.addresses.contains(&stub)) {
+        writeln!(progress, "Skipping --resolve-upstreams (the systemd-resolved stub, {}, is not tested)", stub).ok();
+        return;
+    }
+    // `None` for the standard one, as in `DnsProvider`.
+    let port = |server: &std::net::SocketAddr| (server.port() != 53).then_some(server.port());
+    let upstreams = match resolved_upstreams() {
+        Ok(upstreams) => upstreams,
+        Err(message) => {
+            writeln!(progress, "Skipping systemd-resolved's upstream servers ({})", message).ok();
+            return;
+        },
+    };
+    let new: Vec<std::net::SocketAddr> = upstreams.into_iter()
+        .filter(|server| !providers.iter().any(|provider| provider.addresses.contains(&server.ip()) && provider.port_for(Transport::Udp) == server.port()))
+        .collect();
+    if new.is_empty() {
+        writeln!(progress, "Skipping systemd-resolved's upstream servers (none that are not tested already)").ok();
+        return;
+    }
+    writeln!(progress, "Also testing systemd-resolved's upstream servers: {}",
+        new.iter().map(|server| with_port(server.ip().to_string(), port(server))).collect::<Vec<_>>().join(", ")).ok();
+    providers.extend(new.into_iter().map(|server| DnsProvider {
+        port: port(&server),
+        ..DnsProvider::new(format!("{} (via systemd-resolved)", with_port(server.ip().to_string(), port(&server))), server.ip())
+    }));
+}
+
+/// How the provider the machine uses now compares with `best`, or, when none of the tested
+/// providers is the system's, which nameserver it uses instead.
+fn current_provider_note(results: &[TestResult], best: &TestResult, args: &Args) -> Option<String> {
+    // The best-ranked row when the current provider was tested over several families or protocols.
+    let Some(current) = results.iter().find(|result| result.system_resolver) else {
+        let nameservers: Vec<String> = args.nameservers.iter().map(|ip| ip.to_string()).collect();
+        return if nameservers.is_empty() {
+            None
+        } else if args.nameservers.iter().all(|&ip| is_local_forwarder(ip)) {
+            Some(format!("Your system resolver, {}, is a local forwarder (a router or caching stub), so the provider behind it is not known.",
+                nameservers.join(", ")))
+        } else {
+            Some(format!("Your system resolver ({}) is none of the tested providers.", nameservers.join(", ")))
+        };
+    };
+    let medians = current.median_duration.zip(best.median_duration).filter(|_| current.success_rate > 0.0);
+    Some(if std::ptr::eq(current, best) {
+        format!("Your current provider, {}, is the fastest.", current.provider)
+    } else if let Some((current_median, best_median)) = medians.filter(|(current, best)| current > best) {
+        format!("Your current provider, {}, is {} slower than the fastest.", current.provider,
+            args.unit.format_with_unit(current_median - best_median))
+    } else if medians.is_none() {
+        format!("Your current provider, {}, answered no queries successfully.", current.provider)
+    } else {
+        format!("Your current provider, {}, is as fast by median but ranks below {}.", current.provider, best.provider)
+    })
+}
+
+/// How the gateway added by --include-gateway compares with the fastest provider queried
+/// directly, i.e. one that is not itself on the local network.
+fn gateway_note(results: &[TestResult], args: &Args) -> Option<String> {
+    let gateway = args.gateway?.to_string();
+    let router = results.iter().find(|result| result.addresses.contains(&gateway)).and_then(|result| result.median_duration)?;
+    let (direct_median, direct) = results.iter()
+        .filter(|result| !result.addresses.iter().any(|address| address.parse().is_ok_and(is_local_forwarder)))
+        .filter_map(|result| Some((result.median_duration?, result)))
+        .min_by_key(|&(median, _)| median)?;
+    Some(if router > direct_median {
+        format!("Your router adds {} over querying {} directly.", args.unit.format_with_unit(router - direct_median), direct.provider)
+    } else {
+        format!("Your router answers {} faster than querying {} directly, most likely from its cache.",
+            args.unit.format_with_unit(direct_median - router), direct.provider)
+    })
+}
+
+/// One of `result`'s long-term success rates from --with-history, "n/a" without enough runs.
+fn history_rate(result: &TestResult, args: &Args, rate: fn(&history::Reliability) -> Option<f64>) -> String {
+    args.reliability.get(&result.provider).and_then(rate).map_or("n/a".to_string(), |rate| format!("{:.1}%", rate))
+}
+
+/// The provider column of `result`, with "(not tested, time budget)" or "(cut short)" when a
+/// time budget ran out, "(unreliable)" below --min-success with some answers and "(bogon
+/// answers)" when it resolved public domains to non-public addresses.
+fn provider_name(result: &TestResult, args: &Args) -> String {
+    let provider_column = COLUMNS.iter().find(|column| column.name == "provider").unwrap();
+    let name = (provider_column.value)(result, args.unit);
+    let mut tags = Vec::new();
+    if not_tested(result) {
+        tags.push("not tested, time budget");
+    } else if result.success_rate < args.min_success && unanswered(result).is_none() {
+        tags.push("unreliable");
+    }
+    if result.budget_exceeded && !not_tested(result) {
+        tags.push("cut short");
+    }
+    if !result.bogon_answers.is_empty() {
+        tags.push("bogon answers");
+    }
+    if tags.is_empty() {
+        name
+    } else {
+        format!("{} ({})", name, tags.join(", "))
+    }
+}
+
+/// What the table shows in place of figures for a provider that answered no query, or failed the
+/// pre-flight check; `None` for one with answers or not tested for the time budget.
+fn unanswered(result: &TestResult) -> Option<String> {
+    if result.unreachable {
+        Some("unreachable, not tested".to_string())
+    } else if result.success_rate == 0.0 && !not_tested(result) {
+        Some(format!("unreachable, 0/{} queries answered", result.completed_queries))
+    } else {
+        None
+    }
+}
+
+/// Whether the run's time budget ran out before `result`'s provider was started.
+fn not_tested(result: &TestResult) -> bool {
+    result.budget_exceeded && result.completed_queries == 0
+}
+
+/// When `provider` last answered nothing over every transport the run would test it over, at
+/// every address, as `state` remembers; `None` if it should be tested.
+fn known_dead(provider: &DnsProvider, args: &Args, state: &dead::DeadProviders) -> Option<SystemTime> {
+    let mut last = None;
+    for transport in args.protocol.transports_for(provider) {
+        for &address in &provider.addresses {
+            let failed = state.failed_at(address, transport.label(), args.dead_expiry)?;
+            last = last.max(Some(failed));
+        }
+    }
+    last
+}
+
+/// The name and value of the percentile `sort` ranks by, `None` for the other sorts.
+fn sort_percentile(sort: SortKey, result: &TestResult) -> Option<(&'static str, Option<Duration>)> {
+    match sort {
+        SortKey::P95 => Some(("p95", result.p95)),
+        SortKey::P99 => Some(("p99", result.p99)),
+        _ => None,
+    }
+}
+
+/// The latency the ranking by `sort` compares, or the median when the ranking is not by latency.
+fn relative_metric(sort: SortKey) -> &'static str {
+    match sort {
+        SortKey::Avg => "avg",
+        SortKey::Min => "min",
+        SortKey::Max => "max",
+        SortKey::P95 => "p95",
+        SortKey::P99 => "p99",
+        SortKey::Jitter => "jitter",
+        SortKey::Score | SortKey::Median | SortKey::Success | SortKey::Name => "median",
+    }
+}
+
+/// `result` against `best` in the latency of `relative_metric`; `None` when either answered no
+/// query or lacks the figure, e.g. jitter.
+fn relative_to_fastest(result: &TestResult, best: Option<&TestResult>, sort: SortKey) -> Option<RelativeToFastest> {
+    let value = |result: &TestResult| -> Option<Duration> {
+        if result.success_rate == 0.0 {
+            return None;
+        }
+        match sort {
+            SortKey::Avg => result.avg_duration,
+            SortKey::Min => result.min_latency,
+            SortKey::Max => result.max_latency,
+            SortKey::P95 => result.tail_latency(result.p95).0,
+            SortKey::P99 => result.tail_latency(result.p99).0,
+            SortKey::Jitter => result.jitter,
+            SortKey::Score | SortKey::Median | SortKey::Success | SortKey::Name => result.median_duration,
+        }
+    };
+    let reference = value(best?)?.as_secs_f64() * 1000.0;
+    let ms = value(result)?.as_secs_f64() * 1000.0 - reference;
+    Some(RelativeToFastest {
+        metric: relative_metric(sort),
+        ms,
+        percent: (reference > 0.0).then(|| ms / reference * 100.0),
+    })
+}
+
+/// The provider the summary line recommends: the top of the ranking, or the fastest by median
+/// when ranking by name, which says nothing about quality. Never one that got no answers.
+fn best_result<'a>(results: &'a [TestResult], args: &Args) -> Option<&'a TestResult> {
+    let mut answered = results.iter().filter(|result| result.success_rate > 0.0);
+    match args.sort {
+        SortKey::Name => answered.min_by_key(|result| result.median_duration),
+        // The best provider-protocol combination, not the best of the first protocol.
+        _ if args.group_by_protocol => answered.min_by(|a, b| compare_by(a, b, args.sort, None)),
+        _ if args.reverse => answered.next_back(),
+        _ => answered.next(),
+    }
+}
+
+/// The providers of `cfg` with an endpoint per protocol `protocol` tests them over.
+fn planned_providers(cfg: &SpeedTestConfig, protocol: Protocol) -> Vec<JsonPlannedProvider<'_>> {
+    cfg.providers.iter()
+        .map(|provider| JsonPlannedProvider {
+            name: &provider.name,
+            addresses: &provider.addresses,
+            endpoints: protocol.transports_for(provider).into_iter()
+                .map(|transport| JsonEndpoint {
+                    protocol: transport.label(),
+                    port: provider.port_for(transport),
+                    host: provider.tls_host(transport),
+                    path: (transport == Transport::Https).then(|| provider.doh_query_path()),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The effective settings of every provider in `cfg` that has some of its own.
+fn provider_settings(cfg: &SpeedTestConfig) -> Vec<JsonProviderSettings<'_>> {
+    cfg.providers.iter()
+        .filter(|provider| provider.has_overrides())
+        .map(|provider| {
+            let own = cfg.for_provider(provider);
+            JsonProviderSettings {
+                provider: &provider.name,
+                timeout_ms: own.timeout.as_secs_f64() * 1000.0,
+                attempts: own.attempts,
+                cooldown_ms: own.cooldown.as_secs_f64() * 1000.0,
+            }
+        })
+        .collect()
+}
+
+/// The run as every structured report sees it: JSON output serializes it and --html renders it.
+fn report_model<'a>(results: &'a [TestResult], excluded: &'a [TestResult], args: &'a Args, cfg: &'a SpeedTestConfig) -> JsonReport<'a> {
+    let best = best_result(results, args);
+    let provider = |result| JsonProvider {
+        result,
+        relative_to_fastest: relative_to_fastest(result, best, args.sort),
+        failover_ms: result.failover.as_ref().and_then(|failover| failover.failover_median).map(|median| median.as_secs_f64() * 1000.0),
+        success_rate_7d: args.reliability.get(&result.provider).and_then(|reliability| reliability.week),
+        success_rate_30d: args.reliability.get(&result.provider).and_then(|reliability| reliability.month),
+        egress: egress_of(result, args).filter(|_| args.split_by_egress),
+        samples: args.include_raw.then(|| result.samples.iter()
+            .map(|sample| JsonSample { provider: &result.provider, sample })
+            .collect()),
+    };
+    JsonReport {
+        schema_version: report::SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs()),
+        config: effective_config(args, cfg),
+        partial: cfg.cancel.is_cancelled(),
+        elapsed_s: args.elapsed.map(|elapsed| elapsed.as_secs_f64()),
+        setup_s: args.setup_elapsed.map(|setup| setup.as_secs_f64()),
+        interception: args.interception.as_ref(),
+        vpn_detected: args.vpn.as_ref().is_some_and(VpnCheck::detected),
+        results: results.iter().map(provider).collect(),
+        excluded: excluded.iter().map(provider).collect(),
+        groups: match args.group_by {
+            GroupBy::Provider => group_by_organization(results.iter().chain(excluded)),
+            GroupBy::None => Vec::new(),
+        },
+    }
+}
+
+/// What the layers of configuration came to for the run of `cfg`.
+fn effective_config<'a>(args: &'a Args, cfg: &'a SpeedTestConfig) -> JsonConfig<'a> {
+    JsonConfig {
+        providers: planned_providers(cfg, args.protocol),
+        output: JsonOutput { report: Report::select(args).name(), file: args.output.as_deref() },
+        profile: match args.profile {
+            Profile::Fast => "fast",
+            Profile::Default => "default",
+            Profile::Thorough => "thorough",
+        },
+        estimated_runtime_s: args.estimated_runtime.as_secs_f64(),
+        rounds: args.rounds,
+        total_queries: measured_queries(args),
+        warmup: args.warmup,
+        timeout_ms: args.timeout.as_secs_f64() * 1000.0,
+        attempts: args.attempts,
+        edns: match args.edns {
+            EdnsMode::Off => "off",
+            EdnsMode::On => "on",
+            EdnsMode::Compare => "compare",
+            EdnsMode::CompareBuffers => "compare-buffers",
+        },
+        edns_buffer: matches!(args.edns, EdnsMode::On | EdnsMode::Compare).then(|| edns_buffer_size(args)),
+        retries: args.retries,
+        cooldown_ms: args.cooldown,
+        qps: args.qps,
+        final_retry: !args.no_final_retry,
+        cache_flushed: args.cache_flushed,
+        preset: selected_preset(args).name,
+        repeat: (args.repeat > 1).then_some(args.repeat),
+        protocol: cfg.protocol_label(),
+        domains: &cfg.domains,
+        domains_display: cfg.domains.iter().any(|domain| domains::display(domain) != *domain)
+            .then(|| cfg.domains.iter().map(|domain| domains::display(domain)).collect()),
+        domains_source: args.domains_origin.as_deref(),
+        unresolvable_domains: &args.unresolvable_domains,
+        shuffle_seed: cfg.shuffle_seed,
+        provider_order: provider_order(cfg).into_iter().map(|index| cfg.providers[index].name.as_str()).collect(),
+        record_types: args.record_types.iter().map(|t| t.record_type().to_string()).collect(),
+        recency_weight: args.recency_weight,
+        penalize_failures: cfg.failure_penalty,
+        adaptive: cfg.adaptive.map(|precision| precision.to_string()),
+        score_weights: cfg.score_weights,
+        parallel_queries: args.parallel_queries,
+        cache_bust: args.cache_bust,
+        socket_mode: match args.socket_mode {
+            SocketMode::Reuse => "reuse",
+            SocketMode::Fresh => "fresh",
+            SocketMode::Compare => "compare",
+        },
+        min_success: args.min_success,
+        provider_settings: provider_settings(cfg),
+    }
+}
+
+fn write_json_report(
+    out: &mut dyn Write,
+    results: &[TestResult],
+    excluded: &[TestResult],
+    args: &Args,
+    cfg: &SpeedTestConfig,
+    pretty: bool,
+) -> io::Result<()> {
+    let report = report_model(results, excluded, args, cfg);
+    let json = if pretty { serde_json::to_string_pretty(&report) } else { serde_json::to_string(&report) };
+    writeln!(out, "{}", json?)
+}
+
+const CSV_HEADER: &str = "provider,median_ms,avg_ms,min_ms,max_ms,success_rate,failed_domains,avg_response_bytes,truncated";
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_row(result: &TestResult) -> String {
+    // Empty for a provider without answers, which has no latencies.
+    let ms = |duration: Option<Duration>| duration.map_or(String::new(), |d| format!("{:.3}", d.as_secs_f64() * 1000.0));
+    [
+        csv_field(&result.provider),
+        ms(result.median_duration),
+        ms(result.avg_duration),
+        ms(result.min_latency),
+        ms(result.max_latency),
+        format!("{:.1}", result.success_rate),
+        csv_field(&result.failed_domains.join(";")),
+        // Empty when sizes were not measured; a measured run without truncation says 0.
+        result.response_sizes.as_ref().map_or(String::new(), |sizes| format!("{:.1}", sizes.avg_bytes)),
+        result.response_sizes.as_ref().map_or(String::new(), |sizes| sizes.truncated.to_string()),
+    ].join(",")
+}
+
+/// `value` as a key=value value, quoted when it would not read back as one word.
+fn summary_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// The --quiet line, or object with `json`. Nothing when no provider answered; the exit status
+/// and stderr say why.
+fn write_summary(out: &mut dyn Write, results: &[TestResult], args: &Args, json: bool) -> io::Result<()> {
+    let Some(best) = best_result(results, args) else {
+        return Ok(());
+    };
+    let ip = best.addresses.first().map(String::as_str);
+    let median_ms = best.median_duration.unwrap_or_default().as_secs_f64() * 1000.0;
+    if json {
+        let summary = JsonSummary { fastest: &best.provider, ip, median_ms, success: best.success_rate };
+        writeln!(out, "{}", serde_json::to_string(&summary)?)
+    } else {
+        writeln!(out, "fastest={} ip={} median_ms={:.2} success={:.1}",
+            summary_value(&best.provider), summary_value(ip.unwrap_or("")), median_ms, best.success_rate)
+    }
+}
+
+/// The fixed CSV columns, or with --columns the chosen ones as the table shows them, latencies
+/// in milliseconds.
+fn write_csv_report(out: &mut dyn Write, results: &[TestResult], excluded: &[TestResult], args: &Args, write_header: bool) -> io::Result<()> {
+    let columns = chosen_columns(&args.columns);
+    if write_header && columns.is_empty() {
+        writeln!(out, "{}", CSV_HEADER)?;
+    } else if write_header {
+        let names: Vec<String> = columns.iter()
+            .map(|column| if column.latency { format!("{}_ms", column.name) } else { column.name.to_string() })
+            .collect();
+        writeln!(out, "{}", names.join(","))?;
+    }
+    for result in results.iter().chain(excluded) {
+        if columns.is_empty() {
+            writeln!(out, "{}", csv_row(result))?;
+        } else {
+            let cells: Vec<String> = columns.iter()
+                .map(|column| match column.name {
+                    // Without the table's " *" marker.
+                    "provider" => csv_field(&result.provider),
+                    _ if result.unreachable && column.latency => String::new(),
+                    _ => csv_field((column.value)(result, Unit::Ms).trim()),
+                })
+                .collect();
+            writeln!(out, "{}", cells.join(","))?;
+        }
+    }
+    out.flush()
+}
+
+/// Runs every provider's test `--repeat` times, pausing `--repeat-pause` in between, and
+/// returns the ranked results of every run. An interrupted run is the last one.
+async fn run_sweeps(
+    cfg: &SpeedTestConfig,
+    args: &Args,
+    progress: &mut (dyn Write + Send),
+    live: bool,
+    samples: Option<&SamplesFile>,
+) -> Vec<Vec<TestResult>> {
+    let mut runs = Vec::new();
+    for sweep in 1..=args.repeat {
+        if args.repeat > 1 {
+            writeln!(progress, "Sweep {}/{}", sweep, args.repeat).ok();
+        }
+        runs.push(run_tests(cfg, args, progress, live, samples).await);
+        if sweep == args.repeat || cfg.cancel.is_cancelled() {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(args.repeat_pause)) => {},
+            _ = cfg.cancel.cancelled() => break,
+        }
+    }
+    runs
+}
+
+/// Writes every run to `dir` as a JSON report of its own. A run that cannot be saved is only
+/// a warning: the pooled report still follows.
+fn save_runs(dir: &Path, runs: &[Vec<TestResult>], args: &Args, cfg: &SpeedTestConfig) {
+    for (index, run) in runs.iter().enumerate() {
+        let path = dir.join(format!("run-{}.json", index + 1));
+        let mut results = run.clone();
+        let excluded = split_excluded(&mut results, args);
+        let saved = std::fs::File::create(&path)
+            .map(io::BufWriter::new)
+            .and_then(|mut file| write_json_report(&mut file, &results, &excluded, args, cfg, true).and_then(|_| file.flush()));
+        if let Err(e) = saved {
+            eprintln!("Warning: cannot save run {} to {}: {}", index + 1, path.display(), e);
+        }
+    }
+}
+
+/// Tests every provider once and returns the ranked results. Progress lines go to `progress`;
+/// with `live` a status line on stdout is redrawn after every query, and every query goes to
+/// `samples`.
+async fn run_tests(
+    cfg: &SpeedTestConfig,
+    args: &Args,
+    progress: &mut (dyn Write + Send),
+    live: bool,
+    samples: Option<&SamplesFile>,
+) -> Vec<TestResult> {
+    // One lock for the output and the fastest median so far, so concurrent providers neither
+    // mangle each other's lines nor both claim to be the fastest.
+    let state = Mutex::new((progress, None::<Duration>));
+    // Providers may run side by side (and the live status line owns the cursor); otherwise each
+    // one gets a "Testing ..." line that its result completes.
+    let sequential = cfg.concurrency == 1 && !live;
+    let clear_status = if live { "\r\x1b[2K" } else { "" };
+    let summary = |result: &TestResult, fastest: &mut Option<Duration>| {
+        if result.unreachable {
+            return "unreachable, skipped".to_string();
+        }
+        if not_tested(result) {
+            return "not tested (time budget)".to_string();
+        }
+        let Some(median) = result.median_duration else {
+            return unanswered(result).unwrap_or_default();
+        };
+        // Only a provider that beats an earlier one is worth pointing out.
+        let hint = match *fastest {
+            Some(previous) if median >= previous => "",
+            previous => {
+                *fastest = Some(median);
+                if previous.is_some() { " (fastest so far)" } else { "" }
+            },
+        };
+        format!("{} (Success rate: {:.1}%){}",
+            args.unit.format_with_unit(median),
+            result.success_rate,
+            hint
+        )
+    };
+
+    let on_progress = |event: Progress| {
+        let mut state = state.lock().unwrap();
+        let (progress, fastest) = &mut *state;
+        match event {
+            Progress::Started(provider) if sequential => {
+                write!(progress, "Testing {}... ", provider.name).ok();
+                progress.flush().ok();
+            },
+            Progress::Started(_) => {},
+            Progress::Query(update) => if live {
+                print!("\r\x1b[2K{} [round {}/{}] {} ({}/{})",
+                    update.provider, update.round + 1, cfg.rounds, domains::display(update.domain), update.query, update.total);
+                io::stdout().flush().ok();
+            },
+            Progress::QueryDone(outcome) => if let Some(samples) = samples {
+                samples.record(&outcome);
+            },
+            Progress::Finished(_, [result]) if sequential => {
+                writeln!(progress, "{}", summary(result, fastest)).ok();
+            },
+            Progress::Finished(_, rows) if sequential => {
+                writeln!(progress).ok();
+                for result in rows {
+                    writeln!(progress, "    {}: {}", result.provider, summary(result, fastest)).ok();
+                }
+            },
+            Progress::Finished(_, rows) => {
+                for result in rows {
+                    writeln!(progress, "{}{}: {}", clear_status, result.provider, summary(result, fastest)).ok();
+                }
+            },
+        }
+    };
+    run_all_with_progress(cfg, &on_progress).await
+}
+
+/// Whether `name` (given to --include or --exclude) selects the provider: either its full name,
+/// e.g. "AdGuard (Family)", or the name without tags, which covers every variant.
+fn matches_name(provider: &str, name: &str) -> bool {
+    let base = provider.split(" (").next().unwrap_or(provider);
+    provider.eq_ignore_ascii_case(name.trim()) || base.eq_ignore_ascii_case(name.trim())
+}
+
+/// Rejects --include/--exclude names that match no built-in provider, custom server or the
+/// system resolver, listing the valid ones, and --include names outside the preset.
+fn check_provider_names(args: &Args) -> Result<(), String> {
+    let mut valid: Vec<String> = Vec::new();
+    let builtin = builtin_catalog().into_iter().flat_map(|(_, provider)| {
+        let base = provider.name.split(" (").next().unwrap_or(&provider.name).to_string();
+        [base, provider.name]
+    });
+    let custom = args.servers.iter().chain(&args.file_providers).map(|server| server.name.clone());
+    for name in builtin.chain(custom).chain(["System".to_string()]) {
+        if !valid.contains(&name) {
+            valid.push(name);
+        }
+    }
+
+    let unknown: Vec<&String> = args.include.iter().chain(&args.exclude)
+        .filter(|name| !valid.iter().any(|valid| valid.eq_ignore_ascii_case(name.trim())))
+        .collect();
+    if !unknown.is_empty() {
+        let unknown: Vec<&str> = unknown.iter().map(|name| name.trim()).collect();
+        return Err(format!(
+            "unknown provider {} (valid names: {}; see --list-providers)",
+            unknown.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", "),
+            valid.join(", ")
+        ));
+    }
+
+    // A built-in provider outside the preset would be silently left out.
+    let preset = selected_preset(args);
+    let custom = |name: &str| args.servers.iter().chain(&args.file_providers).any(|server| matches_name(&server.name, name));
+    for name in &args.include {
+        let Some((_, provider)) = builtin_catalog().into_iter().find(|(_, provider)| matches_name(&provider.name, name)) else {
+            continue;
+        };
+        let base = provider.name.split(" (").next().unwrap_or(&provider.name).to_string();
+        if preset.includes(&base) || custom(name) {
+            continue;
+        }
+        let presets: Vec<&str> = PRESETS.iter().filter(|other| other.includes(&base)).map(|other| other.name).collect();
+        return Err(format!(
+            "{} is not in the {} preset; it is in {} (see --preset)",
+            base, preset.name, presets.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// --preset, or the default one.
+fn selected_preset(args: &Args) -> &'static Preset {
+    args.preset.unwrap_or_else(|| preset(DEFAULT_PRESET).expect("the default preset exists"))
+}
+
+/// Every built-in provider, or with --preset only the ones it and --set select.
+fn print_provider_list(args: &Args) {
+    if let Some(preset) = args.preset {
+        println!("Preset {}: {}", preset.name, preset.description);
+        println!();
+    }
+    println!("{:<28} {:<9} Addresses", "Provider", "Set");
+    println!("{:-<90}", "");
+    let selected = |set: ProviderSet, provider: &DnsProvider| args.preset.is_none_or(|preset| {
+        let base = provider.name.split(" (").next().unwrap_or(&provider.name);
+        preset.includes(base) && (args.set == ProviderSet::All || set == args.set)
+    });
+    for (set, provider) in builtin_catalog().into_iter().filter(|(set, provider)| selected(*set, provider)) {
+        let set = set.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        let addresses: Vec<String> = provider.addresses.iter().map(|ip| ip.to_string()).collect();
+        println!("{:<28} {:<9} {}", provider.name, set, addresses.join(", "));
+        let endpoints: Vec<String> = [("DoT", &provider.dot_host), ("DoH", &provider.doh_host), ("DoQ", &provider.doq_host)].iter()
+            .filter_map(|(label, host)| host.as_ref().map(|host| format!("{} {}", label, host)))
+            .collect();
+        if !endpoints.is_empty() {
+            println!("{:<38} {}", "", endpoints.join(", "));
+        }
+    }
+    if args.preset.is_none() {
+        println!();
+        println!("Presets (--preset NAME; {} is the default):", DEFAULT_PRESET);
+        for preset in PRESETS {
+            println!("  {:<9} {}", preset.name, preset.description);
+        }
+    }
+}
 
-struct DnsProvider {
-    name: &'static str,
-    ip: &'static str,
-}
-
-const DNS_PROVIDERS: &[DnsProvider] = &[
-    DnsProvider { name: "Google", ip: "8.8.8.8" },
-    DnsProvider { name: "Cloudflare", ip: "1.1.1.1" },
-    DnsProvider { name: "Quad9", ip: "9.9.9.9" },
-    DnsProvider { name: "OpenDNS", ip: "208.67.222.222" },
-    DnsProvider { name: "AdGuard", ip: "94.140.14.14" },
-    DnsProvider { name: "Mullvad", ip: "194.242.2.2" },
-    DnsProvider { name: "DNS0", ip: "193.110.81.0" },
-    DnsProvider { name: "NextDNS", ip: "45.90.28.0" },
-    DnsProvider { name: "ControlD", ip: "76.76.2.0" },
-];
+/// How long a query takes in the runtime estimate, a typical answer from a nearby resolver.
+const ESTIMATED_LATENCY: Duration = Duration::from_millis(30);
 
-const TEST_DOMAINS: &[&str] = &[
-    "google.com",
-    "gitlab.com", 
-    "cloudflare.com",
-    "microsoft.com",
-    "github.com",
-    "netflix.com",
-    "amazon.com",
-    "facebook.com",
-    "wikipedia.org",
-    "reddit.com"
-];
+/// The measured queries of one provider over one protocol: every (domain, record type) pair
+/// once per round.
+fn measured_queries(args: &Args) -> u32 {
+    args.rounds * args.domains.len() as u32 * args.record_types.len() as u32
+}
 
-const TEST_ROUNDS: u32 = 5;
-const TIMEOUT_SECS: u64 = 3;
-const COOLDOWN_MS: u64 = 100;
+/// Roughly how long testing `providers` takes, and how much of that is cooldowns: the queries,
+/// at a typical latency, with the cooldowns, the pacing and the providers run side by side,
+/// within the time budgets. The checks before and after the rounds are left out, as is waiting
+/// out timeouts, so a run with slow or dead providers takes longer.
+fn estimated_runtime(providers: &[DnsProvider], args: &Args) -> (Duration, Duration) {
+    let queries = measured_queries(args);
+    let rows = match args.edns {
+        EdnsMode::Compare => 2,
+        EdnsMode::CompareBuffers => 1 + EDNS_BUFFER_SIZES.len() as u32,
+        EdnsMode::Off | EdnsMode::On => 1,
+    } * if args.compare_cache { 2 } else { 1 } * if args.socket_mode == SocketMode::Compare { 2 } else { 1 };
+    let mut sleeping = Duration::ZERO;
+    let mut busy = Duration::ZERO;
+    let per_provider: Vec<Duration> = providers.iter()
+        .map(|provider| {
+            // The cap does the spacing when set, as in the rounds themselves.
+            let cooldown = if args.qps.is_some() { Duration::ZERO } else { provider.cooldown.unwrap_or(Duration::from_millis(args.cooldown)) };
+            let (queries_time, between_queries) = match args.qps {
+                Some(qps) => (Duration::from_secs_f64(1.0 / qps).max(ESTIMATED_LATENCY) * (queries + args.warmup), Duration::ZERO),
+                None if args.parallel_queries > 1 => {
+                    (ESTIMATED_LATENCY * (queries.div_ceil(args.parallel_queries as u32) + args.warmup), Duration::ZERO)
+                },
+                None => (ESTIMATED_LATENCY * (queries + args.warmup), cooldown * (queries + args.warmup)),
+            };
+            let repeats = rows * args.protocol.transports_for(provider).len() as u32;
+            let cooldowns = (between_queries + cooldown * 2 * args.rounds.saturating_sub(1)) * repeats;
+            sleeping += cooldowns;
+            busy += queries_time * repeats;
+            let total = queries_time * repeats + cooldowns;
+            args.provider_budget.map_or(total, |budget| total.min(budget))
+        })
+        .collect();
+    let longest = per_provider.iter().copied().max().unwrap_or_default();
+    let side_by_side = per_provider.iter().sum::<Duration>() / args.concurrency.max(1) as u32;
+    let run = args.total_budget.map_or(side_by_side.max(longest), |budget| side_by_side.max(longest).min(budget));
+    let total = run * args.repeat + Duration::from_secs(args.repeat_pause) * args.repeat.saturating_sub(1);
+    let share = if sleeping.is_zero() { 0.0 } else { sleeping.as_secs_f64() / (sleeping + busy).as_secs_f64() };
+    (total, run.mul_f64(share) * args.repeat)
+}
 
-#[derive(Debug)]
-struct TestResult {
-    provider: String,
-    avg_duration: Duration,
-    min_latency: Duration,
-    max_latency: Duration,
-    success_rate: f64,
-    failed_domains: Vec<String>,
-    median_duration: Duration,
-}
-
-async fn measure_latency(addr: &str) -> Option<Duration> {
-    let start = Instant::now();
-    match tokio::time::timeout(
-        Duration::from_secs(TIMEOUT_SECS),
-        tokio::net::TcpStream::connect(format!("{}:53", addr))
-    ).await {
-        Ok(Ok(mut stream)) => {
-            let _ = stream.shutdown().await;
-            Some(start.elapsed())
-        },
-        _ => None
-    }
-}
-
-async fn test_dns_speed(provider: &DnsProvider) -> TestResult {
-    let mut opts = ResolverOpts::default();
-    opts.timeout = Duration::from_secs(TIMEOUT_SECS);
-    opts.attempts = 1;
-    opts.use_hosts_file = false;
-    opts.cache_size = 0;
-    opts.edns0 = false;
-    
-    let socket_addr = format!("{}:53", provider.ip)
-        .parse::<SocketAddr>()
-        .unwrap();
-    
-    let config = ResolverConfig::from_parts(
-        None,
-        vec![],
-        vec![hickory_resolver::config::NameServerConfig::new(
-            socket_addr,
-            Protocol::Udp
-        )],
+/// A duration to the second, "45 s" or "3 min 20 s".
+fn rough_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    match seconds {
+        0 => "under a second".to_string(),
+        1..60 => format!("{} s", seconds),
+        _ if seconds.is_multiple_of(60) => format!("{} min", seconds / 60),
+        _ => format!("{} min {} s", seconds / 60, seconds % 60),
+    }
+}
+
+/// --dry-run: the providers with their endpoints, the domains and the settings a run would use,
+/// as JSON for the JSON reports.
+fn print_plan(args: &Args, cfg: &SpeedTestConfig, report: Report) {
+    let config = effective_config(args, cfg);
+    if matches!(report, Report::Json | Report::JsonSummary) {
+        let plan = JsonPlan { schema_version: report::SCHEMA_VERSION, version: env!("CARGO_PKG_VERSION"), dry_run: true, config };
+        match serde_json::to_string_pretty(&plan) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize the plan: {}", e);
+                std::process::exit(EXIT_ERROR);
+            },
+        }
+        return;
+    }
+
+    println!("Dry run: nothing was sent. A run with these settings would test:");
+    println!();
+    println!("{:<28} Addresses and endpoints", format!("Providers ({})", config.providers.len()));
+    println!("{:-<90}", "");
+    for provider in &config.providers {
+        let addresses: Vec<String> = provider.addresses.iter().map(|ip| ip.to_string()).collect();
+        let addresses = if addresses.is_empty() { "resolved at the start of the run".to_string() } else { addresses.join(", ") };
+        println!("{:<28} {}", provider.name, addresses);
+        let endpoints: Vec<String> = provider.endpoints.iter()
+            .map(|endpoint| match endpoint.host {
+                Some(host) => format!("{} {}:{}{}", endpoint.protocol, host, endpoint.port, endpoint.path.unwrap_or_default()),
+                None => format!("{} port {}", endpoint.protocol, endpoint.port),
+            })
+            .collect();
+        println!("{:<28} {}", "", endpoints.join(", "));
+    }
+    if args.protocol == Protocol::Auto {
+        println!("{:<28} (the endpoints a provider designates over DDR replace these)", "");
+    }
+    println!();
+    let what = if args.mode == Mode::Ptr { "Addresses" } else { "Domains" };
+    println!("{} ({}, {}): {}", what, config.domains.len(), config.record_types.join(", "), config.domains.join(", "));
+    if let Some(source) = config.domains_source {
+        println!("  from the {}", source);
+    }
+    let warmup = match config.warmup {
+        0 => String::new(),
+        1 => " after 1 warm-up query".to_string(),
+        warmup => format!(" after {} warm-up queries", warmup),
+    };
+    let order = match (config.shuffle_seed, args.seed) {
+        (Some(seed), Some(_)) => format!(", shuffled with seed {}", seed),
+        (Some(_), None) => ", shuffled per provider and round".to_string(),
+        (None, _) => ", in order".to_string(),
+    };
+    println!("Rounds: {}{}{}", config.rounds, warmup, order);
+    println!("Timeout: {} ms, {} attempt{} and {} retr{} per query{}",
+        config.timeout_ms,
+        config.attempts,
+        if config.attempts == 1 { "" } else { "s" },
+        config.retries,
+        if config.retries == 1 { "y" } else { "ies" },
+        if config.final_retry { ", failed domains queried once more at the end" } else { "" },
     );
+    println!("Cooldown: {} ms between queries, {} ms between rounds", config.cooldown_ms, config.cooldown_ms * 2);
+    match config.output.file {
+        Some(path) => println!("Output: {} report to {}", config.output.report, path.display()),
+        None => println!("Output: {} report to stdout", config.output.report),
+    }
+}
 
-    let resolver = TokioAsyncResolver::tokio(config, opts);
-    let mut durations = Vec::new();
-    let mut failed_domains = Vec::new();
-    let mut total_queries = 0;
-
-    let _ = resolver.lookup_ip(Name::from_ascii("example.com").unwrap()).await;
-    sleep(Duration::from_millis(COOLDOWN_MS)).await;
-
-    for round in 0..TEST_ROUNDS {
-        for domain in TEST_DOMAINS {
-            total_queries += 1;
-            
-            let tcp_latency = measure_latency(provider.ip).await;
-            if tcp_latency.is_none() {
-                failed_domains.push(format!("{} (TCP Failed)", domain));
-                continue;
-            }
-            
-            let query_start = Instant::now();
-            match resolver.lookup_ip(Name::from_ascii(domain).unwrap()).await {
-                Ok(_) => {
-                    durations.push(query_start.elapsed());
-                },
-                Err(_) => {
-                    failed_domains.push(domain.to_string());
-                }
+/// --history: one line per run, oldest first, with a bar scaled to the slowest median shown.
+fn print_history(entries: &[history::HistoryEntry], provider: &str, unit: Unit) {
+    if entries.is_empty() {
+        println!("No runs of {} recorded yet.", provider);
+        return;
+    }
+    let slowest = entries.iter().filter_map(|entry| entry.median).max().unwrap_or_default();
+    println!("{:<22} {:>12} {:>8}", "Time", unit.heading("median"), "success");
+    println!("{:-<70}", "");
+    for entry in entries {
+        let time = humantime::format_rfc3339_seconds(entry.time).to_string();
+        let (median, bar) = match entry.median {
+            Some(median) if !slowest.is_zero() => {
+                (unit.format(median), "#".repeat((median.as_secs_f64() / slowest.as_secs_f64() * 25.0).round() as usize))
+            },
+            Some(median) => (unit.format(median), String::new()),
+            None => ("-".to_string(), String::new()),
+        };
+        let line = format!("{:<22} {:>12} {:>7.1}% {}", time, median, entry.success_rate, bar);
+        println!("{}", line.trim_end());
+    }
+}
+
+/// --mode throughput replaces the rounds with a load test, so the flags that only make sense for
+/// those are refused rather than ignored.
+fn check_throughput_args(args: &Args) {
+    if args.include.is_empty() {
+        usage_error(ErrorKind::MissingRequiredArgument, "--mode throughput needs --include to pick the one provider to load");
+    }
+    if matches!(args.protocol, Protocol::Compare | Protocol::Auto) {
+        usage_error(ErrorKind::ArgumentConflict, "--mode throughput loads over one protocol; pick it with --protocol");
+    }
+    let conflicting = [
+        ("--watch", args.watch.is_some()),
+        ("--exporter", args.exporter),
+        ("--daemon or --once", args.daemon || args.once),
+        ("--tui", args.tui),
+        ("--repeat", args.repeat > 1),
+        ("--save-runs", args.save_runs.is_some()),
+        ("--qps (use --target-qps)", args.qps.is_some()),
+        ("--edns compare", matches!(args.edns, EdnsMode::Compare | EdnsMode::CompareBuffers)),
+        ("--best-transport", args.best_transport),
+        ("--csv", args.csv.is_some()),
+        ("--html", args.html.is_some()),
+        ("--samples", args.samples.is_some()),
+        ("--export-samples", args.export_samples.is_some()),
+        ("--db", args.db.is_some()),
+        ("--save-baseline", args.save_baseline.is_some()),
+        ("--compare", args.compare.is_some()),
+        ("--apply", args.apply || args.apply_dry_run),
+        ("--dry-run", args.dry_run),
+    ];
+    if let Some((flag, _)) = conflicting.iter().find(|(_, set)| *set) {
+        usage_error(ErrorKind::ArgumentConflict, format!("{} cannot be used with --mode throughput", flag));
+    }
+}
+
+/// --protocol auto: asks every provider for its designated encrypted endpoints and tests those in
+/// place of the known ones. A provider without a DDR answer keeps the known endpoints.
+async fn use_designated_endpoints(providers: &mut [DnsProvider], args: &Args, progress: &mut dyn Write) {
+    let designations = futures::future::join_all(providers.iter().map(|provider| {
+        discover_ddr(provider.addresses[0], provider.port_for(Transport::Udp), args.bind, DDR_TIMEOUT.min(args.timeout))
+    })).await;
+    for (provider, designated) in providers.iter_mut().zip(designations) {
+        let designated = designated.unwrap_or_default();
+        if provider.use_designated(&designated) {
+            let endpoints: Vec<String> = designated.iter().map(DdrEndpoint::describe).collect();
+            writeln!(progress, "DDR: {} designates {}", provider.name, endpoints.join(", ")).ok();
+        } else if provider.supports(Transport::Tls) || provider.supports(Transport::Https) {
+            writeln!(progress, "DDR: {} designates no testable endpoint; using the known ones", provider.name).ok();
+        }
+    }
+}
+
+/// --check: whether each provider answers a query and what the network lets through, as a
+/// table on stdout. Exits, with status 4 when fewer providers answered than --min-reachable.
+async fn check(args: &Args, providers: Vec<DnsProvider>) -> ! {
+    let timeout = args.timeout.min(CHECK_TIMEOUT);
+    let domain = args.domains.first().map_or("example.com", String::as_str);
+    let checks = futures::future::join_all(providers.iter().map(|provider| check_provider(provider, domain, args.bind, timeout))).await;
+    let cfg = speed_test_config(args, providers);
+    let interception = check_interception(&cfg).await;
+
+    let mut out = io::stdout();
+    let reachable = checks.iter().filter(|addresses| addresses.iter().any(|address| address.latency.is_some())).count();
+    let yes_no = |reachable: Option<bool>| match reachable {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    };
+    println!("\nCheck ({} timeout, one query per address):", humantime::format_duration(timeout));
+    println!("{:-<100}", "");
+    println!("{:<30} {:<40} {:<12} {:>12} {:>4} {:>4}", "Provider", "Address", "Status", args.unit.heading("Latency"), "DoT", "DoH");
+    println!("{:-<100}", "");
+    for (provider, addresses) in cfg.providers.iter().zip(&checks) {
+        for address in addresses {
+            writeln!(out, "{:<30} {:<40} {:<12} {:>12} {:>4} {:>4}",
+                fit(provider.name.clone(), 30),
+                address.address,
+                if address.latency.is_some() { "reachable" } else { "unreachable" },
+                address.latency.map_or("n/a".to_string(), |latency| args.unit.format(latency)),
+                yes_no(address.dot),
+                yes_no(address.doh),
+            ).ok();
+        }
+    }
+
+    println!("\nNetwork:");
+    let nameservers: Vec<String> = args.nameservers.iter()
+        .map(|&ip| if is_local_forwarder(ip) { format!("{} (local forwarder)", ip) } else { ip.to_string() })
+        .collect();
+    println!("    System resolver: {}", if nameservers.is_empty() { "none found".to_string() } else { nameservers.join(", ") });
+    let v6: Vec<&AddressCheck> = checks.iter().flatten().filter(|address| address.address.is_ipv6()).collect();
+    let v6_answered = v6.iter().filter(|address| address.latency.is_some()).count();
+    let ipv6 = if v6_answered > 0 {
+        format!("available ({} of {} IPv6 addresses answered)", v6_answered, v6.len())
+    } else if !ipv6_route(args.bind) {
+        "no route".to_string()
+    } else if v6.is_empty() {
+        "a route, but no IPv6 address was tested".to_string()
+    } else {
+        format!("a route, but none of {} IPv6 addresses answered", v6.len())
+    };
+    println!("    IPv6: {}", ipv6);
+    println!("    Port 53 interception: {}", interception_warning(&interception).unwrap_or_else(|| "none found".to_string()));
+    for (label, port, reachable) in [("DoT", 853, checks.iter().flatten().filter_map(|address| address.dot).collect::<Vec<_>>()),
+                                     ("DoH", 443, checks.iter().flatten().filter_map(|address| address.doh).collect())] {
+        match reachable.len() {
+            0 => println!("    {} ({}): no provider with an endpoint", label, port),
+            tested => println!("    {} ({}): {} of {} addresses accept a connection", label, port, reachable.iter().filter(|&&ok| ok).count(), tested),
+        }
+    }
+
+    println!("\n{} of {} providers reachable", reachable, checks.len());
+    if reachable < args.min_reachable {
+        eprintln!("Fewer providers reachable than --min-reachable {}", args.min_reachable);
+        std::process::exit(EXIT_CHECK_FAILED);
+    }
+    std::process::exit(0);
+}
+
+/// --mode throughput: loads the one provider left after --include and reports every step.
+async fn throughput(args: &Args, providers: Vec<DnsProvider>, report: Report, progress: &mut dyn Write) {
+    if providers.len() != 1 {
+        let names: Vec<&str> = providers.iter().map(|provider| provider.name.as_str()).collect();
+        usage_error(ErrorKind::ArgumentConflict, if names.is_empty() {
+            format!("--include matches no provider to load over {}", args.protocol.label())
+        } else {
+            format!("--mode throughput loads exactly one provider, but --include matches {}: {}", names.len(), names.join(", "))
+        });
+    }
+    let cfg = speed_test_config(args, providers);
+    let provider = &cfg.providers[0];
+    let load = LoadProfile { target_qps: args.target_qps, duration: args.duration };
+    let mut destination = open_destination(args);
+
+    writeln!(progress, "DNS Speed Test (Load test of {} at {} over {}: up to {} queries/s in {} steps, {} s in all)",
+        provider.name, provider.addresses[0], args.protocol.label(), load.target_qps, THROUGHPUT_STEPS, load.duration.as_secs_f64()).ok();
+    eprintln!("\n*** WARNING: sustained load can trip a public resolver's rate limiting and get this address \
+        throttled or blocked for a while; load resolvers you run or have permission to test ***\n");
+
+    // The first Ctrl+C stops the sends and reports the load so far; a second one exits at once.
+    let cancel = cfg.cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+            eprintln!("\nInterrupted, waiting for the queries in flight (press Ctrl+C again to quit)");
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
             }
-            
-            sleep(Duration::from_millis(COOLDOWN_MS)).await;
         }
+    });
+
+    // `progress` cannot be shared with the generator, so the step lines pick its stream again.
+    let quiet = args.quiet;
+    let to_stderr = report.machine_readable() && args.output.is_none();
+    let on_step = move |step: u32, rate: f64| match (quiet, to_stderr) {
+        (true, _) => {},
+        (false, true) => eprintln!("Step {}/{}: {:.1} queries/s", step, THROUGHPUT_STEPS, rate),
+        (false, false) => println!("Step {}/{}: {:.1} queries/s", step, THROUGHPUT_STEPS, rate),
+    };
+    let result = run_throughput(provider, &cfg, load, &on_step).await;
+    writeln!(progress).ok();
+
+    let out = &mut *destination.out;
+    let written = match report {
+        Report::Json | Report::JsonSummary => {
+            let json = if args.append { serde_json::to_string(&result) } else { serde_json::to_string_pretty(&result) };
+            json.map_err(io::Error::from).and_then(|json| writeln!(out, "{}", json))
+        },
+        _ => write_throughput_table(out, &result, args.unit),
+    };
+    if let Err(e) = written.and_then(|_| out.flush()) {
+        match &args.output {
+            Some(path) => eprintln!("Failed to write report to {}: {}", path.display(), e),
+            None => eprintln!("Failed to write report: {}", e),
+        }
+        std::process::exit(EXIT_ERROR);
+    }
+    if !result.stopped_early && result.steps.iter().all(|step| step.answered == 0) {
+        eprintln!("{} answered none of the queries; is the network up?", result.provider);
+        std::process::exit(EXIT_NO_ANSWERS);
+    }
+}
 
-        if round < TEST_ROUNDS - 1 {
-            sleep(Duration::from_millis(COOLDOWN_MS * 2)).await;
+fn write_throughput_table(out: &mut dyn Write, result: &ThroughputResult, unit: Unit) -> io::Result<()> {
+    writeln!(out, "Load test of {} ({}, {}), at most {} queries in flight:", result.provider, result.address, result.transport, result.max_in_flight)?;
+    writeln!(out, "{:-<110}", "")?;
+    writeln!(out, "{:<5} {:>9} {:>7} {:>8} {:>9} {:>10} {:>11} {:>11} {:>11} {:>9} {:>8}",
+        "Step", "Target/s", "Sent", "Skipped", "Answered", "Achieved/s",
+        unit.heading("p50"), unit.heading("p95"), unit.heading("p99"), "Timeouts", "Errors")?;
+    writeln!(out, "{:-<110}", "")?;
+    let latency = |duration: Option<Duration>| duration.map_or("n/a".to_string(), |duration| unit.format(duration));
+    for (i, step) in result.steps.iter().enumerate() {
+        writeln!(out, "{:<5} {:>9.1} {:>7} {:>8} {:>9} {:>10.1} {:>11} {:>11} {:>11} {:>8.1}% {:>7.1}%",
+            i + 1, step.target_qps, step.sent, step.skipped, step.answered, step.achieved_qps,
+            latency(step.p50), latency(step.p95), latency(step.p99), step.timeout_rate, step.error_rate)?;
+    }
+
+    if result.stopped_early {
+        if let Some(last) = result.steps.last() {
+            writeln!(out, "! Stopped early: step {} sent for {:.1} s.", result.steps.len(), last.duration.as_secs_f64())?;
+        }
+    }
+    // Falling behind the schedule, or failing more as the rate rises, is where the limit lies.
+    if let Some((i, _)) = result.steps.iter().enumerate().find(|(_, step)| step.achieved_qps < step.target_qps * 0.9) {
+        writeln!(out, "! From step {} on fewer than 90% of the scheduled queries were answered in time.", i + 1)?;
+    }
+    if let (Some(first), Some(last)) = (result.steps.first(), result.steps.last()) {
+        let failed = |step: &ThroughputStep| step.timeout_rate + step.error_rate;
+        if result.steps.len() > 1 && failed(last) >= failed(first) + 5.0 {
+            writeln!(out, "! Failures rise from {:.1}% to {:.1}% with the load; the provider may be rate-limiting this address.",
+                failed(first), failed(last))?;
         }
     }
+    Ok(())
+}
+
+/// Library settings for the run described by the command line.
+fn speed_test_config(args: &Args, providers: Vec<DnsProvider>) -> SpeedTestConfig {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    SpeedTestConfig {
+        providers,
+        domains: args.domains.clone(),
+        shuffle_seed: (!args.no_shuffle).then_some(seed),
+        provider_order_seed: (!args.no_shuffle_providers).then_some(seed),
+        record_types: args.record_types.clone(),
+        rounds: args.rounds,
+        warmup: args.warmup,
+        timeout: args.timeout,
+        tcp_check: args.tcp_check,
+        handshake_check: args.handshake_check,
+        ping: args.ping,
+        attempts: args.attempts as usize,
+        edns: args.edns,
+        edns_buffer: edns_buffer_size(args),
+        retries: args.retries,
+        cooldown: Duration::from_millis(args.cooldown),
+        recency_weight: args.recency_weight,
+        score_weights: ScoreWeights { success: args.weight_success, jitter: args.weight_jitter },
+        trim: args.trim,
+        failure_penalty: args.penalize_failures,
+        transport: args.protocol.transport().unwrap_or(Transport::Udp),
+        compare_transports: matches!(args.protocol, Protocol::Compare | Protocol::Auto),
+        encrypted_only: args.protocol == Protocol::Auto,
+        group_by_transport: args.group_by_protocol,
+        concurrency: args.concurrency as usize,
+        preflight: !args.no_preflight,
+        parallel_queries: args.parallel_queries as usize,
+        qps: args.qps,
+        final_retry: !args.no_final_retry,
+        all_ips: args.all_ips,
+        cache_bust: args.cache_bust,
+        missing_ptr_ok: args.missing_ptr_ok,
+        compare_cache: args.compare_cache,
+        socket_mode: args.socket_mode,
+        best_transport: args.best_transport,
+        failover: args.failover,
+        dnssec_check: !args.no_dnssec_check,
+        pop_check: !args.no_pop_check,
+        identity_check: !args.no_identity_check,
+        ddr_check: args.ddr || args.protocol == Protocol::Auto,
+        size_check: !args.no_size_check,
+        nxdomain_check: !args.no_nxdomain_check,
+        nxdomain_probes: args.nxdomain_probes,
+        https_probe: args.https_probe,
+        blocking_domains: args.blocking_test.then(|| args.blocking_domains.clone()),
+        qname_min_check: args.qname_min_check,
+        cookie_check: args.cookie_check,
+        saturation_test: args.saturation_test,
+        verify_answers: args.verify_answers,
+        escalation_timeout: args.timeout_escalation.then(|| Duration::from_secs(args.escalation_timeout)),
+        bind: args.bind,
+        sort: args.sort,
+        tie_break_seed: args.sort_stable_by_seed.then_some(args.seed.unwrap_or(0)),
+        adaptive: args.adaptive,
+        provider_budget: args.provider_budget,
+        total_budget: args.total_budget,
+        deadline: None,
+        cancel: CancellationToken::new(),
+    }
+}
 
-    durations.sort();
-    let successful_queries = durations.len();
-    let success_rate = (successful_queries as f64) / (total_queries as f64) * 100.0;
+/// Where `report` goes, opened before the run so a bad path fails early.
+struct Destination {
+    out: Box<dyn Write>,
+    /// Whether the file was new or empty, i.e. whether a header belongs at the top
+    is_new: bool,
+}
 
-    let avg_duration = if !durations.is_empty() {
-        Duration::from_secs_f64(
-            durations.iter().map(|d| d.as_secs_f64()).sum::<f64>() / successful_queries as f64
-        )
-    } else {
-        Duration::from_secs(TIMEOUT_SECS)
+/// Opens --output, or stdout without it; exits when the file cannot be opened.
+fn open_destination(args: &Args) -> Destination {
+    match &args.output {
+        Some(path) => match open_report(path, args.append) {
+            Ok((file, is_new)) => Destination { out: Box::new(file), is_new },
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+                std::process::exit(EXIT_ERROR);
+            },
+        },
+        None => Destination { out: Box::new(io::stdout()), is_new: true },
+    }
+}
+
+/// Writes every report the flags ask for: the side files (--save-baseline, --html, --csv FILE)
+/// and `report` to `destination`. Exits on a failed write.
+fn write_reports(
+    destination: &mut Destination,
+    report: Report,
+    results: &[TestResult],
+    excluded: &[TestResult],
+    args: &Args,
+    cfg: &SpeedTestConfig,
+    baseline: Option<&baseline::Baseline>,
+) {
+    if let Some(path) = &args.save_baseline {
+        let written = open_report(path, false)
+            .and_then(|(mut file, _)| write_json_report(&mut file, results, excluded, args, cfg, true).and_then(|_| file.flush()));
+        if let Err(e) = written {
+            eprintln!("Failed to save baseline to {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    if let Some(target) = &args.export_samples {
+        let all: Vec<&TestResult> = results.iter().chain(excluded).collect();
+        if let Err(e) = sample_export::write(target, &all) {
+            eprintln!("Failed to export the samples to {}: {}", target.path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    if let Some(path) = &args.html {
+        let written = open_report(path, false)
+            .and_then(|(mut file, _)| html::write(&mut file, &report_model(results, excluded, args, cfg), args.unit).and_then(|_| file.flush()));
+        if let Err(e) = written {
+            eprintln!("Failed to write HTML report to {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    if let Some(Some(path)) = &args.csv {
+        let written = open_report(path, args.csv_append)
+            .and_then(|(mut file, is_new)| write_csv_report(&mut file, results, excluded, args, is_new).and_then(|_| file.flush()));
+        if let Err(e) = written {
+            eprintln!("Failed to write CSV to {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+
+    let out = &mut *destination.out;
+    let written = match report {
+        Report::Table => {
+            // Separate accumulated runs in an appended report.
+            let header = if args.append && args.output.is_some() {
+                writeln!(out, "==== {} ====", humantime::format_rfc3339_seconds(SystemTime::now()))
+            } else {
+                Ok(())
+            };
+            header.and_then(|_| write_table_report(out, results, excluded, args, cfg, baseline, Palette::new(args.color, args.output.is_none())))
+        },
+        // Appended JSON reports stay parseable as one object per line.
+        Report::Json => write_json_report(out, results, excluded, args, cfg, !args.append),
+        Report::Csv => write_csv_report(out, results, excluded, args, destination.is_new),
+        Report::Summary => write_summary(out, results, args, false),
+        Report::JsonSummary => write_summary(out, results, args, true),
     };
+    if let Err(e) = written.and_then(|_| out.flush()) {
+        match &args.output {
+            Some(path) => eprintln!("Failed to write report to {}: {}", path.display(), e),
+            None => eprintln!("Failed to write report: {}", e),
+        }
+        std::process::exit(EXIT_ERROR);
+    }
+}
+
+/// Opens `path` for a report, appending or truncating. The flag is whether the file was new or
+/// empty, i.e. whether a header belongs at the top.
+fn open_report(path: &Path, append: bool) -> io::Result<(io::BufWriter<std::fs::File>, bool)> {
+    let is_new = !append || std::fs::metadata(path).map_or(true, |meta| meta.len() == 0);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    Ok((io::BufWriter::new(file), is_new))
+}
+
+/// Moves the unreliable results, below `--min-success`, out of `results` and returns them. A
+/// success rate exactly at the threshold passes.
+fn split_excluded(results: &mut Vec<TestResult>, args: &Args) -> Vec<TestResult> {
+    let (ranked, excluded) = std::mem::take(results).into_iter().partition(|r| r.success_rate >= args.min_success);
+    *results = ranked;
+    excluded
+}
 
-    let min_latency = durations.first().copied().unwrap_or(Duration::from_secs(TIMEOUT_SECS));
-    let max_latency = durations.last().copied().unwrap_or(Duration::from_secs(TIMEOUT_SECS));
-    let median_duration = if !durations.is_empty() {
-        durations[durations.len() / 2]
+/// --submit and --submit-dry-run: prints the payload to stderr, so it never mixes with a report
+/// on stdout, then sends it unless it is a dry run.
+async fn submit_results(results: &[TestResult], args: &Args, cfg: &SpeedTestConfig) -> Result<(), String> {
+    let network = if args.submit_include.iter().any(|field| matches!(field, ClientField::Asn | ClientField::Country)) {
+        let network = client_network(cfg.bind, submit::LOOKUP_TIMEOUT).await;
+        if network.is_none() {
+            eprintln!("Warning: cannot look up this network's ASN and country; they are left out");
+        }
+        network
     } else {
-        Duration::from_secs(TIMEOUT_SECS)
+        None
     };
+    let payload = submission(results, cfg.rounds, &args.submit_include, network.as_ref(), SystemTime::now());
+    let body = serde_json::to_string_pretty(&payload).map_err(|e| e.to_string())?;
+    let Some(url) = args.submit.as_deref().filter(|_| !args.submit_dry_run) else {
+        eprintln!("\nWould submit (--submit-dry-run, nothing sent):\n{}", body);
+        return Ok(());
+    };
+    if payload.results.is_empty() {
+        eprintln!("\nNothing to submit: only built-in providers are submitted, at their own addresses");
+        return Ok(());
+    }
+    eprintln!("\nSubmitting to {}:\n{}", url, body);
+    let (url, sent) = (url.to_string(), body.clone());
+    tokio::task::spawn_blocking(move || submit::send(&url, &sent)).await.map_err(|e| e.to_string())??;
+    eprintln!("Submitted {} results", payload.results.len());
+    Ok(())
+}
+
+/// The --notify-webhook and --notify-cmd notifier, `None` without either.
+fn notifier(args: &Args) -> Option<notify::Notifier> {
+    (args.notify_webhook.is_some() || args.notify_cmd.is_some()).then(|| notify::Notifier::new(notify::Settings {
+        webhook: args.notify_webhook.clone(),
+        command: args.notify_cmd.clone(),
+        threshold_ms: args.alert_threshold,
+        min_success: args.alert_min_success.unwrap_or(args.min_success),
+    }))
+}
+
+/// Reruns the test every `interval` until interrupted, printing a timestamped summary (or one
+/// JSON object per line with --json) and the change in median since the previous cycle.
+async fn watch(cfg: &SpeedTestConfig, args: &Args, interval: Duration) -> ! {
+    let mut previous: HashMap<String, Duration> = HashMap::new();
+    let mut notifier = notifier(args);
+    loop {
+        let cycle_start = Instant::now();
+        let mut results = run_all(cfg).await;
+        let mut excluded = split_excluded(&mut results, args);
+        if args.reverse {
+            results.reverse();
+            excluded.reverse();
+        }
+
+        if args.format == OutputFormat::Json {
+            write_json_report(&mut io::stdout(), &results, &excluded, args, cfg, false).ok();
+        } else {
+            println!("[{}]", humantime::format_rfc3339_seconds(SystemTime::now()));
+            for result in results.iter().chain(&excluded) {
+                let delta = previous.get(&result.provider).copied().zip(result.median_duration)
+                    .map(|(before, now)| format!(" ({})", args.unit.format_delta(before, now)))
+                    .unwrap_or_default();
+                println!("  {:<15} {:>13}{:<12} {:>6.1}%",
+                    result.provider,
+                    with_unit(result.median_duration, args.unit),
+                    delta,
+                    result.success_rate
+                );
+            }
+            println!();
+        }
+        io::stdout().flush().ok();
+        if let Some(notifier) = &mut notifier {
+            let all: Vec<TestResult> = results.iter().chain(&excluded).cloned().collect();
+            notifier.cycle(&all).await;
+        }
+
+        previous = results.iter().chain(&excluded)
+            .filter_map(|result| Some((result.provider.clone(), result.median_duration?)))
+            .collect();
+        sleep(interval.saturating_sub(cycle_start.elapsed())).await;
+    }
+}
 
-    TestResult {
-        provider: provider.name.to_string(),
-        avg_duration,
-        min_latency,
-        max_latency,
-        success_rate,
-        failed_domains,
-        median_duration,
+/// Prints a clap error (or --help and --version) and exits, with [`EXIT_ERROR`] rather than
+/// clap's own status 2, which here means that nothing answered.
+fn exit_with(error: clap::Error) -> ! {
+    error.print().ok();
+    std::process::exit(if error.use_stderr() { EXIT_ERROR } else { 0 });
+}
+
+fn usage_error(kind: ErrorKind, message: impl std::fmt::Display) -> ! {
+    exit_with(Args::command().error(kind, message))
+}
+
+/// The usable domains of `raw` (see `domains::normalize`), warning about the entries dropped.
+fn clean_domains(what: &str, raw: &[String]) -> Vec<String> {
+    let normalized = domains::normalize(raw);
+    if !normalized.dropped.is_empty() {
+        let dropped: Vec<String> = normalized.dropped.iter()
+            .map(|(entry, reason)| format!("\"{}\" ({})", entry, reason))
+            .collect();
+        eprintln!("Warning: dropped {} of the {}: {}", dropped.len(), what, dropped.join(", "));
     }
+    if normalized.domains.is_empty() {
+        usage_error(ErrorKind::InvalidValue, format!("none of the {} is a usable domain name", what));
+    }
+    normalized.domains
 }
 
 #[tokio::main]
 async fn main() {
-    println!("DNS Speed Test (Testing {} domains × {} rounds)\n", TEST_DOMAINS.len(), TEST_ROUNDS);
+    let matches = Args::command().try_get_matches().unwrap_or_else(|e| exit_with(e));
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| exit_with(e));
+    if let Err(message) = logging::init(args.verbose, args.log_file.as_deref()) {
+        usage_error(ErrorKind::InvalidValue, message);
+    }
+    Phase::Configure.start();
+    match &args.command {
+        Some(Command::Diff { old, new, threshold }) => match diff::run(&mut io::stdout(), old, new, *threshold) {
+            Ok(regressed) => std::process::exit(if regressed { 1 } else { 0 }),
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(2);
+            },
+        },
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+            return;
+        },
+        None => {},
+    }
+    if args.man {
+        if let Err(e) = clap_mangen::Man::new(Args::command()).render(&mut io::stdout()) {
+            eprintln!("Failed to write the man page: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+    if args.print_default_config {
+        print!("{}", config::DEFAULT_CONFIG);
+        return;
+    }
+    if args.schema {
+        match report::example() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize the example report: {}", e);
+                std::process::exit(EXIT_ERROR);
+            },
+        }
+        return;
+    }
+    if args.list_providers {
+        print_provider_list(&args);
+        return;
+    }
+    // Still below the config file's `rounds`.
+    if !config::explicit(&matches, "rounds") {
+        if !args.single_domains.is_empty() {
+            args.rounds = SINGLE_DOMAIN_ROUNDS;
+        } else if args.edns_buffer == Some(EdnsBuffer::Compare) {
+            args.rounds = BUFFER_COMPARE_ROUNDS;
+        }
+    }
+    // Layered as built-in defaults < --profile < config file < environment < command-line flags;
+    // clap already puts the flags over their variables.
+    args.profile.apply(&mut args, &matches);
+    if let Some(path) = args.config.clone().or_else(config::default_path) {
+        match config::load(&path, args.config.is_some()) {
+            Ok(Some(file)) => file.apply(&mut args, &matches),
+            Ok(None) => {},
+            Err(message) => usage_error(ErrorKind::InvalidValue, message),
+        }
+    }
+    if let Err(message) = config::apply_env(&mut args) {
+        usage_error(ErrorKind::InvalidValue, message);
+    }
+    // Opened up front so a broken database fails before the run rather than after it.
+    let mut db = args.db.as_deref().map(|path| history::open(path)
+        .unwrap_or_else(|message| usage_error(ErrorKind::InvalidValue, message)));
+    if let (Some(provider), Some(db)) = (&args.history, &db) {
+        match history::provider_history(db, provider, args.last as usize) {
+            Ok(entries) => print_history(&entries, provider, args.unit),
+            Err(e) => {
+                eprintln!("Failed to read history: {}", e);
+                std::process::exit(EXIT_ERROR);
+            },
+        }
+        return;
+    }
+    if let Some(path) = &args.providers_file {
+        let file = load_providers_file(path).unwrap_or_else(|message| usage_error(ErrorKind::InvalidValue, message));
+        args.providers_mode = args.providers_mode.or(file.mode);
+        args.file_providers = file.providers;
+    }
+    if args.only_custom && args.servers.is_empty() && args.file_providers.is_empty() && !args.include_gateway && !args.include_dhcp {
+        usage_error(ErrorKind::MissingRequiredArgument, "--only-custom needs --server, --providers-file, --include-gateway, --include-dhcp or providers in the config file");
+    }
+    if args.mode == Mode::Ptr {
+        if config::explicit(&matches, "record_types") {
+            usage_error(ErrorKind::ArgumentConflict, "--record-type cannot be used with --mode ptr");
+        }
+        args.domains = if args.targets.is_empty() {
+            PTR_TARGETS.iter().map(|ip| ip.to_string()).collect()
+        } else {
+            args.targets.iter().map(std::net::IpAddr::to_string).collect()
+        };
+        args.record_types = vec![QueryType::Ptr];
+    } else if !args.targets.is_empty() || args.missing_ptr_ok {
+        usage_error(ErrorKind::MissingRequiredArgument, "--targets and --missing-ptr-ok need --mode ptr");
+    }
+    let given = |id: &str| config::explicit(&matches, id);
+    if given("interval") && !(args.exporter || args.daemon) {
+        usage_error(ErrorKind::MissingRequiredArgument, "--interval needs --exporter or --daemon");
+    }
+    if args.log_dir.is_some() && !(args.daemon || args.once) {
+        usage_error(ErrorKind::MissingRequiredArgument, "--log-dir needs --daemon or --once");
+    }
+    if !args.submit_include.is_empty() && args.submit.is_none() && !args.submit_dry_run {
+        usage_error(ErrorKind::MissingRequiredArgument, "--submit-include needs --submit or --submit-dry-run");
+    }
+    let notifying = args.notify_webhook.is_some() || args.notify_cmd.is_some();
+    if notifying && !(args.watch.is_some() || args.daemon) {
+        usage_error(ErrorKind::MissingRequiredArgument, "--notify-webhook and --notify-cmd need --watch or --daemon");
+    }
+    if (args.alert_threshold.is_some() || args.alert_min_success.is_some()) && !notifying {
+        usage_error(ErrorKind::MissingRequiredArgument, "--alert-threshold and --alert-min-success need --notify-webhook or --notify-cmd");
+    }
+    if args.mode == Mode::Throughput {
+        check_throughput_args(&args);
+    } else if ["duration", "target_qps"].iter().any(|id| config::explicit(&matches, id)) {
+        usage_error(ErrorKind::MissingRequiredArgument, "--duration and --target-qps need --mode throughput");
+    }
+    match args.edns_buffer {
+        Some(EdnsBuffer::Compare) => {
+            if config::explicit(&matches, "edns") {
+                usage_error(ErrorKind::ArgumentConflict, "--edns-buffer compare sets EDNS itself and cannot be used with --edns");
+            }
+            if args.protocol != Protocol::Udp {
+                usage_error(ErrorKind::ArgumentConflict, "--edns-buffer compare needs --protocol udp, the only protocol with a buffer size");
+            }
+            args.edns = EdnsMode::CompareBuffers;
+        },
+        Some(EdnsBuffer::Size(_)) if args.edns == EdnsMode::Off => {
+            usage_error(ErrorKind::MissingRequiredArgument, "--edns-buffer needs --edns on or --edns compare");
+        },
+        _ => {},
+    }
+    if args.group_by_protocol && !matches!(args.protocol, Protocol::Compare | Protocol::Auto) {
+        usage_error(ErrorKind::MissingRequiredArgument, "--group-by-protocol needs --protocol compare or auto");
+    }
+    if let Err(message) = check_provider_names(&args) {
+        usage_error(ErrorKind::InvalidValue, message);
+    }
+    if let Some(name) = &args.interface {
+        args.bind = Some(interface_address(name).unwrap_or_else(|message| usage_error(ErrorKind::InvalidValue, message)));
+    }
+    // Fails here rather than as a timeout on every query.
+    if let Some(bind) = args.bind {
+        if let Err(e) = std::net::UdpSocket::bind((bind, 0)) {
+            eprintln!("Cannot send from {}: {}; is the address assigned to this machine?", bind, e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    let baseline = args.compare.as_deref().map(|path| baseline::load(path)
+        .unwrap_or_else(|message| usage_error(ErrorKind::InvalidValue, message)));
+    if args.json {
+        args.format = OutputFormat::Json;
+    }
 
-    let mut results = Vec::new();
-    
-    for provider in DNS_PROVIDERS {
-        print!("Testing {}... ", provider.name);
-        let result = test_dns_speed(provider).await;
-        println!("{:.2} ms (Success rate: {:.1}%)", 
-            result.median_duration.as_secs_f64() * 1000.0,
-            result.success_rate
-        );
-        results.push(result);
+    let report = Report::select(&args);
+    // Keep stdout clean for machine-readable output.
+    let mut progress: Box<dyn Write + Send> = if args.quiet {
+        Box::new(io::sink())
+    } else if report.machine_readable() && args.output.is_none() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+
+    // Everything that goes over the network before the rounds, through the system resolver or
+    // to servers other than the tested ones, happens here and is timed apart.
+    Phase::Setup.start();
+    let setup_started = Instant::now();
+    let domain_list = (args.domains_source.is_some() || args.domains_file.is_some()).then(|| {
+        let source = args.domains_source.unwrap_or(domains::Source::Tranco(domains::DEFAULT_COUNT));
+        domains::load(source, args.domains_file.as_deref(), args.refresh_domains, !args.dry_run).unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(EXIT_ERROR);
+        })
+    });
+    if let Some(list) = domain_list {
+        args.domains = list.domains;
+        args.domains_origin = Some(list.origin);
+    }
+    if !args.single_domains.is_empty() {
+        args.domains = args.single_domains.clone();
+    }
+    if args.mode != Mode::Ptr {
+        let entries = args.domains.len();
+        args.domains = clean_domains("test domains", &args.domains);
+        args.domains_entries = (args.domains.len() < entries).then_some(entries);
+    }
+    if args.blocking_test {
+        args.blocking_domains = clean_domains("--blocking-domains", &args.blocking_domains);
+    }
+    let replace_builtin = args.providers_mode == Some(ProvidersMode::Replace);
+    let mut providers: Vec<DnsProvider> = if args.only_custom || replace_builtin {
+        Vec::new()
+    } else {
+        preset_providers(selected_preset(&args), args.set, args.ip_version)
+    };
+    if !args.include.is_empty() {
+        providers.retain(|provider| args.include.iter().any(|name| matches_name(&provider.name, name)));
+    }
+    providers.extend(args.file_providers.iter().cloned().flat_map(|provider| split_by_family(provider, args.ip_version)));
+    // A dry run leaves servers given by hostname unresolved, without addresses.
+    if args.dry_run {
+        providers.extend(args.servers.iter().cloned());
+    } else {
+        providers.extend(resolve_servers(args.servers.clone(), args.bootstrap, args.ip_version).await);
+    }
+    if args.include_gateway {
+        // Before the system resolvers, so a router that is also the system's nameserver is one row.
+        match default_gateway() {
+            None => writeln!(progress, "Skipping the gateway (no default gateway found)").ok(),
+            Some(ip) if providers.iter().any(|provider| provider.addresses.contains(&ip)) => {
+                args.gateway = Some(ip);
+                None
+            },
+            Some(ip) if args.dry_run || answers_dns(ip, 53, args.bind, GATEWAY_PROBE_TIMEOUT).await => {
+                providers.push(DnsProvider::new(format!("Gateway ({})", ip), ip));
+                args.gateway = Some(ip);
+                None
+            },
+            Some(ip) => writeln!(progress, "Skipping the gateway, {} (no DNS answer on port 53)", ip).ok(),
+        };
+    }
+    if args.include_dhcp {
+        // Also before the system resolvers, which then mark a DHCP server in use as theirs.
+        match dhcp_servers() {
+            Err(message) => writeln!(progress, "Warning: skipping the DHCP servers ({})", message).ok(),
+            Ok(servers) if servers.is_empty() => writeln!(progress, "Skipping the DHCP servers (the leases name none)").ok(),
+            Ok(servers) => {
+                let new: Vec<std::net::IpAddr> = servers.into_iter()
+                    .filter(|ip| !providers.iter().any(|provider| provider.addresses.contains(ip)))
+                    .collect();
+                providers.extend(new.into_iter().map(|ip| DnsProvider::new(format!("DHCP ({})", ip), ip)));
+                None
+            },
+        };
+    }
+    args.nameservers = system_nameservers();
+    if !args.no_system && !args.only_custom {
+        add_system_resolvers(&mut providers, &args.nameservers);
+    } else {
+        mark_system_resolvers(&mut providers, &args.nameservers);
+    }
+    if args.resolve_upstreams {
+        add_resolved_upstreams(&mut providers, &mut *progress);
+    }
+    providers.retain(|provider| !args.exclude.iter().any(|name| matches_name(&provider.name, name)));
+    if args.mode == Mode::Throughput {
+        // --include picks among the custom and system resolvers too.
+        providers.retain(|provider| args.include.iter().any(|name| matches_name(&provider.name, name)));
+    }
+    if args.protocol == Protocol::Auto && !args.dry_run {
+        use_designated_endpoints(&mut providers, &args, &mut *progress).await;
+    }
+    let (providers, unsupported): (Vec<DnsProvider>, Vec<DnsProvider>) = providers.into_iter()
+        .partition(|provider| args.protocol.supported_by(provider));
+    // A source address can only reach servers of its own family.
+    let (providers, wrong_family): (Vec<(DnsProvider, bool)>, Vec<_>) = providers.into_iter()
+        .map(|mut provider| {
+            let unresolved = args.dry_run && provider.addresses.is_empty();
+            if let Some(bind) = args.bind {
+                provider.addresses.retain(|ip| ip.is_ipv4() == bind.is_ipv4());
+            }
+            (provider, unresolved)
+        })
+        .partition(|(provider, unresolved)| *unresolved || !provider.addresses.is_empty());
+    let providers: Vec<DnsProvider> = providers.into_iter().map(|(provider, _)| provider).collect();
+    if args.mode == Mode::Throughput {
+        throughput(&args, providers, report, &mut *progress).await;
+        return;
+    }
+    if args.check {
+        check(&args, providers).await;
+    }
+    let mut dead_providers = dead::DeadProviders::load();
+    let (providers, dead): (Vec<(DnsProvider, Option<SystemTime>)>, _) = providers.into_iter()
+        .map(|provider| {
+            let failed = (!args.retest_dead).then(|| known_dead(&provider, &args, &dead_providers)).flatten();
+            (provider, failed)
+        })
+        .partition(|(_, failed)| failed.is_none());
+    let providers: Vec<DnsProvider> = providers.into_iter().map(|(provider, _)| provider).collect();
+    if !args.no_control_check && !args.dry_run && args.mode != Mode::Ptr {
+        check_domains(&mut args, &providers, &mut *progress).await;
+    }
+
+    writeln!(progress, "DNS Speed Test (Testing {} providers, {} {} × {} rounds over {})",
+        providers.len(),
+        args.domains.len(),
+        if args.mode == Mode::Ptr { "addresses" } else { "domains" },
+        args.rounds,
+        args.protocol.label()
+    ).ok();
+    if let Some(origin) = &args.domains_origin {
+        writeln!(progress, "Domains: {}", origin).ok();
+    }
+    if let Some(entries) = args.domains_entries {
+        writeln!(progress, "Domains: {} of {} entries accepted after cleaning", args.domains.len(), entries).ok();
+    }
+    if args.cache_bust {
+        writeln!(progress, "Cache-busting: every query uses a random subdomain, so latencies include full recursive resolution").ok();
+    }
+    if args.recency_weight != 1.0 {
+        writeln!(progress, "Recency weighting: round r counts {}^r", args.recency_weight).ok();
+    }
+    match args.qps {
+        Some(qps) => writeln!(progress, "Pacing: at most {} queries/s per provider, retries and warm-ups included", qps).ok(),
+        None if args.parallel_queries > 1 => writeln!(progress, "Pacing: none within a round, {} ms between rounds", args.cooldown * 2).ok(),
+        None => writeln!(progress, "Pacing: {} ms between queries, {} ms between rounds", args.cooldown, args.cooldown * 2).ok(),
+    };
+    for provider in providers.iter().filter(|provider| provider.has_overrides()) {
+        let own: Vec<String> = [
+            provider.timeout.map(|timeout| format!("{} ms timeout", timeout.as_millis())),
+            provider.attempts.map(|attempts| format!("{} attempt{}", attempts, if attempts == 1 { "" } else { "s" })),
+            provider.cooldown.map(|cooldown| format!("{} ms cooldown", cooldown.as_millis())),
+        ].into_iter().flatten().collect();
+        writeln!(progress, "Own settings for {}: {}", provider.name, own.join(", ")).ok();
+    }
+    let (estimate, cooldowns) = estimated_runtime(&providers, &args);
+    args.estimated_runtime = estimate;
+    if cooldowns > estimate / 2 && args.profile != Profile::Fast {
+        writeln!(progress, "Estimated time: {}, {} of it cooldowns (--profile fast or a lower --cooldown for a quicker run)",
+            rough_duration(estimate), rough_duration(cooldowns)).ok();
+    } else {
+        writeln!(progress, "Estimated time: {}", rough_duration(estimate)).ok();
+    }
+    match args.socket_mode {
+        SocketMode::Reuse => None,
+        SocketMode::Fresh => writeln!(progress, "Sockets: a new resolver per query, so every query opens its own socket; setting it up is not timed").ok(),
+        SocketMode::Compare => writeln!(progress, "Sockets: every provider is also tested with a new resolver per query, shown as the fresh-socket median").ok(),
+    };
+    if args.parallel_queries > 1 {
+        writeln!(progress, "Parallel queries: up to {} in flight per provider, so latencies include queueing under that load", args.parallel_queries).ok();
+    }
+    if let Some(factor) = args.penalize_failures {
+        writeln!(progress, "Failure penalty: every failed query counts as {:.0} ms in the latency statistics", args.timeout.mul_f64(factor).as_secs_f64() * 1000.0).ok();
+    }
+    if args.repeat > 1 {
+        writeln!(progress, "Repeat: {} runs, {} s apart; every provider's figures cover the queries of all of them", args.repeat, args.repeat_pause).ok();
+    }
+    if args.trim > 0.0 {
+        writeln!(progress, "Trimming: average, min and max leave out the fastest and slowest {}% of answers", args.trim).ok();
+    }
+    for provider in &unsupported {
+        match args.protocol {
+            Protocol::Auto => writeln!(progress, "Skipping {} (no DDR and no known DoT or DoH endpoint)", provider.name).ok(),
+            _ => writeln!(progress, "Skipping {} (no known {} endpoint)", provider.name, args.protocol.label()).ok(),
+        };
+    }
+    for (provider, failed) in dead.iter().filter_map(|(provider, failed)| Some((provider, (*failed)?))) {
+        let date = humantime::format_rfc3339_seconds(failed).to_string();
+        writeln!(progress, "Skipping {} (failed completely on {}, use --retest-dead to include)", provider.name, &date[..10]).ok();
+    }
+    if let Some(bind) = args.bind {
+        match &args.interface {
+            Some(name) => writeln!(progress, "Sending from {} ({})", bind, name).ok(),
+            None => writeln!(progress, "Sending from {}", bind).ok(),
+        };
+        let family = if bind.is_ipv4() { "IPv4" } else { "IPv6" };
+        for (provider, _) in &wrong_family {
+            writeln!(progress, "Skipping {} (no {} address to reach from {})", provider.name, family, bind).ok();
+        }
+    }
+    if args.dry_run {
+        writeln!(progress).ok();
+        print_plan(&args, &speed_test_config(&args, providers), report);
+        return;
+    }
+    if args.flush_cache {
+        match flush_dns_cache(&run_command) {
+            Ok(commands) => {
+                writeln!(progress, "Flushed the system's DNS cache ({})", commands).ok();
+                args.cache_flushed = true;
+            },
+            Err(message) => eprintln!("Warning: could not flush the DNS cache, testing anyway: {}", message),
+        }
+    }
+    writeln!(progress).ok();
+    let cfg = speed_test_config(&args, providers);
+
+    // Opened up front so a bad path fails before the run rather than after it.
+    let mut destination = open_destination(&args);
+    let samples = args.samples.as_ref().map(|path| SamplesFile::create(path).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path.display(), e);
+        std::process::exit(EXIT_ERROR);
+    }));
+    if let Some(dir) = &args.save_runs {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
     }
 
-    results.sort_by(|a, b| a.median_duration.cmp(&b.median_duration));
+    // Interception is of port 53, which DoT and DoH do not use.
+    let plain = args.protocol != Protocol::Auto
+        && args.protocol.transport().is_none_or(|transport| matches!(transport, Transport::Udp | Transport::Tcp));
+    if !args.no_interception_check && plain {
+        let check = check_interception(&cfg).await;
+        if let Some(warning) = interception_warning(&check) {
+            eprintln!("\n*** WARNING: {} ***\n", warning);
+        }
+        args.interception = Some(check);
+    }
+    // Best effort: a check that cannot finish in time leaves the run as it is.
+    let addresses: Vec<std::net::IpAddr> = cfg.providers.iter().flat_map(|provider| provider.addresses.iter().copied()).collect();
+    let bind = cfg.bind;
+    if let Ok(Ok(check)) = tokio::time::timeout(VPN_CHECK_TIMEOUT, tokio::task::spawn_blocking(move || check_vpn(addresses, bind))).await {
+        if let Some(warning) = vpn_warning(&check) {
+            eprintln!("\n*** WARNING: {} ***\n", warning);
+        }
+        args.vpn = Some(check);
+    }
+    args.setup_elapsed = Some(setup_started.elapsed());
+    Phase::Measure.start();
+    let started = Instant::now();
 
-    println!("\nDetailed Results (sorted by median speed):");
-    println!("{:-<90}", "");
-    println!("{:<15} {:>10} {:>10} {:>12} {:>12} {:>15}", 
-        "Provider", "Median", "Avg (ms)", "Min (ms)", "Max (ms)", "Success Rate");
-    println!("{:-<90}", "");
-    
-    for result in &results {
-        println!(
-            "{:<15} {:>10.2} {:>10.2} {:>12.2} {:>12.2} {:>14.1}%",
-            result.provider,
-            result.median_duration.as_secs_f64() * 1000.0,
-            result.avg_duration.as_secs_f64() * 1000.0,
-            result.min_latency.as_secs_f64() * 1000.0,
-            result.max_latency.as_secs_f64() * 1000.0,
-            result.success_rate
-        );
-
-        if !result.failed_domains.is_empty() {
-            println!("    Failed domains: {}", result.failed_domains.join(", "));
-        }
-    }
-
-    if let Some(fastest) = results.first() {
-        println!("\nFastest DNS provider: {} ({:.2} ms median, {:.1}% success rate)",
-            fastest.provider,
-            fastest.median_duration.as_secs_f64() * 1000.0,
-            fastest.success_rate
-        );
-    }
-
-    println!("\nPress Enter to exit...");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-}
\ No newline at end of file
+    if args.exporter {
+        // An HTTP endpoint unless only a file was asked for.
+        let listen = match (args.listen, &args.textfile) {
+            (Some(address), _) => Some(address),
+            (None, Some(_)) => None,
+            (None, None) => Some(exporter::DEFAULT_LISTEN),
+        };
+        exporter::run(&cfg, args.interval, listen, args.textfile.as_deref()).await;
+    }
+    if let (true, Some(dir)) = (args.daemon || args.once, &args.log_dir) {
+        daemon::run(&cfg, daemon::LogDir { dir, retention_days: args.retention_days }, args.interval, args.once, notifier(&args)).await;
+    }
+    if let Some(interval) = args.watch {
+        watch(&cfg, &args, Duration::from_secs(interval)).await;
+    }
+
+    // The first Ctrl+C stops the run between queries and reports the providers that finished;
+    // a second one exits at once.
+    let cancel = cfg.cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+            eprintln!("\nInterrupted, reporting the providers that finished (press Ctrl+C again to quit)");
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
+
+    // Live status line, redrawn in place; only on an interactive terminal showing the table.
+    let live = report == Report::Table && io::stdout().is_terminal();
+    let tui = args.tui && live;
+    if args.tui && !tui {
+        eprintln!("Warning: --tui needs stdout to be a terminal; showing the plain output");
+    }
+    let mut results = if tui {
+        let outcome = tui::run(&cfg, args.sort, args.unit).await.unwrap_or_else(|e| {
+            eprintln!("Failed to draw the --tui screen: {}", e);
+            std::process::exit(EXIT_ERROR);
+        });
+        if outcome.stopped {
+            writeln!(progress, "Stopped early: {} of {} providers tested", outcome.tested, cfg.providers.len()).ok();
+        }
+        args.sort = outcome.sort;
+        outcome.results
+    } else if args.repeat > 1 || args.save_runs.is_some() {
+        let mut runs = run_sweeps(&cfg, &args, &mut *progress, live, samples.as_ref()).await;
+        if let Some(dir) = &args.save_runs {
+            save_runs(dir, &runs, &args, &cfg);
+        }
+        if runs.len() > 1 { pool_runs(&runs, &cfg) } else { runs.pop().unwrap_or_default() }
+    } else {
+        run_tests(&cfg, &args, &mut *progress, live, samples.as_ref()).await
+    };
+    // Flushed to disk before the reports, also after Ctrl+C.
+    if let (Some(samples), Some(path)) = (samples, &args.samples) {
+        if let Err(e) = samples.finish() {
+            eprintln!("Warning: cannot write the samples to {}: {}", path.display(), e);
+        }
+    }
+    let mut excluded = split_excluded(&mut results, &args);
+    // Every report lists providers in the same order.
+    if args.reverse {
+        results.reverse();
+        excluded.reverse();
+    }
+
+    args.elapsed = Some(started.elapsed());
+    Phase::Report.start();
+    // Read before this run is added, so the long-term rates are those of the runs before it.
+    if let (true, Some(db), Some(path)) = (args.with_history, &db, &args.db) {
+        match history::reliability(db, SystemTime::now()) {
+            Ok(reliability) => args.reliability = reliability,
+            Err(e) => eprintln!("Warning: cannot read the history in {}: {}", path.display(), e),
+        }
+    }
+    dead_providers.record(&results.iter().chain(&excluded).collect::<Vec<_>>());
+    if let (Some(db), Some(path)) = (&mut db, &args.db) {
+        let all: Vec<&TestResult> = results.iter().chain(&excluded).collect();
+        if let Err(e) = history::record(db, &cfg, &all) {
+            eprintln!("Failed to record the run in {}: {}", path.display(), e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    write_reports(&mut destination, report, &results, &excluded, &args, &cfg, baseline.as_ref());
+    // An interrupted run's figures are not worth sharing.
+    if (args.submit.is_some() || args.submit_dry_run) && !cfg.cancel.is_cancelled() {
+        let all: Vec<TestResult> = results.iter().chain(&excluded).cloned().collect();
+        if let Err(message) = submit_results(&all, &args, &cfg).await {
+            eprintln!("--submit: {}", message);
+            if args.submit_required {
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    if args.apply || args.apply_dry_run {
+        let applied = match best_result(&results, &args) {
+            Some(best) => apply::apply(&mut *progress, best, args.apply_dry_run, args.yes),
+            None => Err("no provider to apply".to_string()),
+        };
+        if let Err(message) = applied {
+            eprintln!("--apply: {}", message);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+    // The pause keeps a double-clicked console window open; scripts, cron and containers must
+    // not hang on it.
+    let pause = if cfg.cancel.is_cancelled() {
+        false
+    } else if args.pause {
+        true
+    } else if args.no_pause {
+        false
+    } else {
+        !report.machine_readable() && io::stdin().is_terminal() && io::stdout().is_terminal()
+    };
+    if pause {
+        println!("\nPress Enter to exit...");
+        // A closed stdin (Ok(0)) or a read error just means there is nothing to wait for.
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).ok();
+    }
+
+    let fastest = results.iter().chain(&excluded).filter_map(|result| result.median_duration).min();
+    match (fastest, args.fail_threshold) {
+        // Nothing finished is no sign of a network problem when the run was interrupted.
+        (None, _) if cfg.cancel.is_cancelled() => {},
+        (None, _) => {
+            eprintln!("No provider answered a single query; is the network up?");
+            std::process::exit(EXIT_NO_ANSWERS);
+        },
+        (Some(fastest), Some(threshold)) if fastest.as_secs_f64() * 1000.0 > threshold => {
+            eprintln!("The fastest median, {:.2} ms, is above the --fail-threshold of {} ms", fastest.as_secs_f64() * 1000.0, threshold);
+            std::process::exit(EXIT_TOO_SLOW);
+        },
+        _ => {},
+    }
+}
+// `Args` lives in the binary, out of reach of the tests in tests/.
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use super::Args;
+
+    #[test]
+    fn the_command_is_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn the_key_flags_exist() {
+        let command = Args::command();
+        for flag in ["rounds", "server", "domain", "format", "output", "timeout", "cooldown", "protocol", "preset", "config", "man", "check", "dry-run"] {
+            assert!(command.get_arguments().any(|arg| arg.get_long() == Some(flag)), "--{} is missing", flag);
+        }
+        assert!(command.find_subcommand("completions").is_some());
+    }
+}
@@ -0,0 +1,1485 @@
+//! The timed rounds against a single provider address.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts, ServerOrderingStrategy};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::error::ProtoError;
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::proto::rr::{DNSClass, Record, RecordType};
+use hickory_resolver::Name;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use futures::stream::{self, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpSocket;
+use tokio::time::sleep;
+use crate::ddr::{self, DDR_PROBE_TIMEOUT};
+use crate::connector::{self, Connector, Resolver, TruncatedQuery, Truncations};
+use crate::pacing::Pacer;
+use crate::ping;
+use crate::handshake;
+use crate::probes::{self, DNSSEC_PROBE_TIMEOUT, IDENTITY_PROBE_TIMEOUT, POP_PROBE_TIMEOUT};
+use crate::quic;
+use crate::raw;
+use crate::providers::{DnsProvider, Transport};
+use crate::results::{
+    usable_answer, AnswerStability, AnswerUsability, AnyHandling, CacheComparison, DnssecStatus, DomainStats, FailoverResult, FailureKind, PacketLoss, QueryFailure, RunSpread, Sample, SampleStatus, SocketComparison, TestResult, Timing, TransportComparison,
+    NearTimeout, Trimming, TruncationLegs, TtlRange, UnstableAnswer,
+};
+use crate::{stats, EdnsMode, Progress, QueryOutcome, QueryProgress, QueryType, SocketMode, SortKey, SpeedTestConfig};
+
+// TEST-NET-1 (RFC 5737): routed nowhere, so queries to it time out instead of being refused.
+const BLACKHOLE_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+// IPv6 documentation prefix (RFC 3849), used the same way for v6 secondaries.
+const BLACKHOLE_IP_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+/// Pause before the first retry of a failed query; it doubles with every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// Least pause before the failed domains are queried again at the end.
+const FINAL_RETRY_PAUSE: Duration = Duration::from_secs(1);
+/// TCP connections opened per provider for its `tcp_connect` figure, or QUIC handshakes for
+/// its `quic_handshake` one over DoQ.
+const TCP_CHECKS: usize = 3;
+/// How long the pre-flight UDP query and TCP connection (QUIC handshake over DoQ) each get.
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) async fn measure_latency(server: SocketAddr, bind: Option<IpAddr>, timeout: Duration) -> Option<Duration> {
+    let socket = if server.is_ipv4() { TcpSocket::new_v4() } else { TcpSocket::new_v6() }.ok()?;
+    if let Some(bind) = bind {
+        socket.bind(SocketAddr::new(bind, 0)).ok()?;
+    }
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, socket.connect(server)).await {
+        Ok(Ok(mut stream)) => {
+            let _ = stream.shutdown().await;
+            Some(start.elapsed())
+        },
+        _ => None
+    }
+}
+
+/// `measure_latency` to the port of `transport`, or over DoQ a QUIC handshake with the
+/// provider's endpoint, which UDP port 853 answers instead of a TCP connect.
+async fn connect_latency(provider: &DnsProvider, ip: IpAddr, transport: Transport, bind: Option<IpAddr>, timeout: Duration) -> Option<Duration> {
+    let server = SocketAddr::new(ip, provider.port_for(transport));
+    match (transport, provider.tls_host(transport)) {
+        (Transport::Quic, Some(host)) => quic::handshake(server, host, bind, timeout).await.ok(),
+        (Transport::Quic, None) => None,
+        _ => measure_latency(server, bind, timeout).await,
+    }
+}
+
+fn seeded_key(seed: u64, name: &str) -> u64 {
+    // FNV-1a: unlike std's hashers its output is fixed across Rust versions and platforms.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in seed.to_le_bytes().iter().chain(name.as_bytes()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub(crate) fn compare_results(a: &TestResult, b: &TestResult, cfg: &SpeedTestConfig) -> Ordering {
+    let group = |result: &TestResult| Transport::ALL.iter().position(|transport| transport.label() == result.transport);
+    let grouped = if cfg.group_by_transport { group(a).cmp(&group(b)) } else { Ordering::Equal };
+    grouped.then_with(|| compare_by(a, b, cfg.sort, cfg.tie_break_seed))
+}
+
+/// Ranking order of two rows by `key`, with the fallbacks described at [`SortKey`] and, with
+/// `tie_break_seed`, a seeded permutation for exact ties.
+pub fn compare_by(a: &TestResult, b: &TestResult, key: SortKey, tie_break_seed: Option<u64>) -> Ordering {
+    // A provider without answers has no latencies, and a name or score says nothing about it.
+    let answered = (a.success_rate == 0.0).cmp(&(b.success_rate == 0.0));
+    // Both answered or neither, so a missing latency is one of two unanswered rows.
+    let primary = match key {
+        // A missing score is the worst.
+        SortKey::Score => match (a.score, b.score) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+        SortKey::Median => a.median_duration.cmp(&b.median_duration),
+        SortKey::Avg => a.avg_duration.cmp(&b.avg_duration),
+        SortKey::Min => a.min_latency.cmp(&b.min_latency),
+        SortKey::Max => a.max_latency.cmp(&b.max_latency),
+        // Equal tails are told apart by the slowest answer.
+        SortKey::P95 => a.tail_latency(a.p95).0.cmp(&b.tail_latency(b.p95).0)
+            .then_with(|| a.max_latency.cmp(&b.max_latency)),
+        SortKey::P99 => a.tail_latency(a.p99).0.cmp(&b.tail_latency(b.p99).0)
+            .then_with(|| a.max_latency.cmp(&b.max_latency)),
+        SortKey::Success => b.success_rate.total_cmp(&a.success_rate),
+        SortKey::Name => a.provider.to_lowercase().cmp(&b.provider.to_lowercase()),
+        // Providers without a jitter figure rank after those with one.
+        SortKey::Jitter => match (a.jitter, b.jitter) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+    };
+    let ordering = answered
+        .then(primary)
+        .then_with(|| a.median_duration.cmp(&b.median_duration))
+        .then_with(|| b.success_rate.total_cmp(&a.success_rate))
+        .then_with(|| a.avg_duration.cmp(&b.avg_duration));
+    let Some(seed) = tie_break_seed else {
+        return ordering;
+    };
+    ordering
+        .then_with(|| seeded_key(seed, &a.provider).cmp(&seeded_key(seed, &b.provider)))
+        .then_with(|| a.provider.cmp(&b.provider))
+}
+
+pub(crate) fn resolver_opts(cfg: &SpeedTestConfig) -> ResolverOpts {
+    let mut opts = ResolverOpts::default();
+    opts.timeout = cfg.timeout;
+    opts.use_hosts_file = false;
+    opts.cache_size = 0;
+    // Only A: by default a failed A lookup falls back to AAAA, whose empty answer would turn a
+    // lost packet into a slow success.
+    opts.ip_strategy = LookupIpStrategy::Ipv4Only;
+    opts.attempts = cfg.attempts;
+    opts.edns0 = cfg.edns == EdnsMode::On;
+    opts
+}
+
+pub(crate) fn build_resolver(
+    ips: &[IpAddr],
+    port: u16,
+    bind: Option<IpAddr>,
+    opts: ResolverOpts,
+    transport: Transport,
+    tls_name: Option<&str>,
+    connector: Connector,
+) -> Resolver {
+    let name_servers: Vec<NameServerConfig> = ips.iter()
+        .map(|ip| {
+            let protocol = match transport {
+                Transport::Udp => Protocol::Udp,
+                Transport::Tcp => Protocol::Tcp,
+                Transport::Tls => Protocol::Tls,
+                Transport::Https => Protocol::Https,
+                Transport::Quic => Protocol::Quic,
+            };
+            let mut name_server = NameServerConfig::new(SocketAddr::new(*ip, port), protocol);
+            name_server.tls_dns_name = tls_name.map(String::from);
+            name_server.bind_addr = bind.map(|bind| SocketAddr::new(bind, 0));
+            name_server
+        })
+        .collect();
+    let config = ResolverConfig::from_parts(None, vec![], name_servers);
+    connector::resolver(config, opts, connector)
+}
+
+async fn median_lookup_time(resolver: &Resolver, cfg: &SpeedTestConfig) -> Option<Duration> {
+    let mut durations = Vec::new();
+    for (domain, record_type) in queries(cfg) {
+        let query_start = Instant::now();
+        if lookup(resolver, domain, record_type, nxdomain_ok(cfg, record_type, false)).await.is_ok() {
+            durations.push(query_start.elapsed());
+        }
+        sleep(cfg.cooldown).await;
+    }
+    durations.sort();
+    stats::percentile(&durations, 50.0)
+}
+
+/// The `warmup` unmeasured lookups of the first test query, as the rounds have them.
+async fn warm_up(resolver: &Resolver, cfg: &SpeedTestConfig) {
+    if let Some((domain, record_type)) = queries(cfg).next().filter(|_| cfg.warmup > 0) {
+        for _ in 0..cfg.warmup {
+            lookup(resolver, domain, record_type, nxdomain_ok(cfg, record_type, false)).await.ok();
+        }
+        sleep(cfg.cooldown).await;
+    }
+}
+
+pub(crate) async fn test_failover(primary: IpAddr, secondary: IpAddr, port: u16, cfg: &SpeedTestConfig) -> FailoverResult {
+    // Query strictly in the configured order, one server at a time, like a typical stub resolver.
+    let mut opts = resolver_opts(cfg);
+    opts.num_concurrent_reqs = 1;
+    opts.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+
+    let healthy = build_resolver(&[primary, secondary], port, cfg.bind, opts.clone(), Transport::Udp, None, Connector::default());
+    warm_up(&healthy, cfg).await;
+    let healthy_median = median_lookup_time(&healthy, cfg).await;
+
+    let blackhole = if secondary.is_ipv6() { BLACKHOLE_IP_V6 } else { BLACKHOLE_IP };
+    let degraded = build_resolver(&[blackhole, secondary], port, cfg.bind, opts, Transport::Udp, None, Connector::default());
+    warm_up(&degraded, cfg).await;
+    let failover_median = median_lookup_time(&degraded, cfg).await;
+
+    FailoverResult {
+        secondary,
+        healthy_median,
+        failover_median,
+    }
+}
+
+/// A successful lookup.
+#[derive(Default)]
+pub(crate) struct Answer {
+    pub(crate) addresses: Vec<IpAddr>,
+    /// `None` for an empty answer
+    ttl: Option<TtlRange>,
+    /// CNAME records followed to reach the answer
+    cnames: u32,
+    /// For ANY, what kind of answer it was
+    any: Option<AnyHandling>,
+}
+
+/// Looks up `domain` for one record type and returns the addresses and TTLs in the answer. A
+/// NODATA answer (the name exists but has no records of this type, e.g. a domain without MX) is
+/// a valid response, not a failure, and so is NXDOMAIN when `nxdomain_ok` is set. For PTR,
+/// `domain` may be the IP address itself.
+pub(crate) async fn lookup(resolver: &Resolver, domain: &str, record_type: RecordType, nxdomain_ok: bool) -> Result<Answer, ResolveError> {
+    lookup_name(resolver, &query_name(domain, record_type)?, record_type, nxdomain_ok).await
+}
+
+/// The name queried for `domain`: for PTR with an address, the address's in-addr.arpa or
+/// ip6.arpa name.
+fn query_name(domain: &str, record_type: RecordType) -> Result<Name, ProtoError> {
+    match (record_type, domain.parse::<IpAddr>()) {
+        (RecordType::PTR, Ok(ip)) => Ok(Name::from(ip)),
+        _ => Name::from_ascii(domain),
+    }
+}
+
+/// `lookup` with the name already parsed.
+async fn lookup_name(resolver: &Resolver, name: &Name, record_type: RecordType, nxdomain_ok: bool) -> Result<Answer, ResolveError> {
+    let result = match record_type {
+        RecordType::A => resolver.lookup_ip(name.clone()).await.map(|lookup| lookup.as_lookup().clone()),
+        _ => resolver.lookup(name.clone(), record_type).await,
+    };
+    match result {
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NoError, .. } => Ok(Answer::default()),
+            ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NXDomain, .. } if nxdomain_ok => Ok(Answer::default()),
+            &ResolveErrorKind::NoRecordsFound { response_code, .. } if record_type == RecordType::ANY => match any_handling(response_code, &[]) {
+                Some(handling) => Ok(Answer { any: Some(handling), ..Answer::default() }),
+                None => Err(error),
+            },
+            _ => Err(error),
+        },
+        Ok(lookup) => Ok(Answer {
+            addresses: lookup.iter().filter_map(|rdata| rdata.ip_addr()).collect(),
+            ttl: TtlRange::span(lookup.records().iter().map(|record| TtlRange { min: record.ttl(), max: record.ttl() })),
+            cnames: lookup.records().iter().filter(|record| record.record_type() == RecordType::CNAME).count() as u32,
+            any: (record_type == RecordType::ANY).then(|| any_handling(ResponseCode::NoError, lookup.records())).flatten(),
+        }),
+    }
+}
+
+/// How a response with `response_code` and `answers` handled an ANY query, `None` if it is a
+/// failure or a plain NODATA. RFC 8482 lets a server decline ANY with a single synthesized
+/// HINFO record, and others answer NOTIMP or REFUSED; all of them are answers.
+pub(crate) fn any_handling(response_code: ResponseCode, answers: &[Record]) -> Option<AnyHandling> {
+    match response_code {
+        ResponseCode::NoError if answers.is_empty() => None,
+        ResponseCode::NoError if answers.iter().all(|record| record.record_type() == RecordType::HINFO) => Some(AnyHandling::Minimal),
+        ResponseCode::NoError => Some(AnyHandling::Full),
+        ResponseCode::NotImp => Some(AnyHandling::NotImplemented),
+        ResponseCode::Refused => Some(AnyHandling::Refused),
+        _ => None,
+    }
+}
+
+/// Whether NXDOMAIN counts as an answer: for cache-busted names, which never exist, and for
+/// addresses without a PTR record when `missing_ptr_ok` is set.
+fn nxdomain_ok(cfg: &SpeedTestConfig, record_type: RecordType, cache_bust: bool) -> bool {
+    cache_bust || (record_type == RecordType::PTR && cfg.missing_ptr_ok)
+}
+
+pub(crate) fn random_label() -> String {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut rng = rand::thread_rng();
+    (0..6).map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char).collect()
+}
+
+/// Name of a (domain, record type) query in reports: just the domain when only A is tested,
+/// so the default output is unchanged, and just the address for reverse lookups.
+fn query_label(domain: &str, record_type: RecordType, cfg: &SpeedTestConfig) -> String {
+    if cfg.record_types == [QueryType::A] || cfg.record_types == [QueryType::Ptr] {
+        domain.to_string()
+    } else {
+        format!("{}/{}", domain, record_type)
+    }
+}
+
+/// Timeouts and SERVFAIL may succeed on a second try; a definite answer such as NXDOMAIN will not.
+fn is_retryable(error: &ResolveError) -> bool {
+    matches!(FailureKind::of(error), FailureKind::Timeout | FailureKind::Servfail)
+}
+
+fn is_timeout(error: &ResolveError) -> bool {
+    FailureKind::of(error) == FailureKind::Timeout
+}
+
+/// The failed queries of a result and their number per kind.
+fn failure_breakdown(failures: &[QueryFailure]) -> BTreeMap<FailureKind, u32> {
+    let mut kinds = BTreeMap::new();
+    for failure in failures {
+        *kinds.entry(failure.kind).or_default() += 1;
+    }
+    kinds
+}
+
+/// Runs the full test against one address of a provider, including the optional DoH, DNSSEC and
+/// cache comparisons.
+pub(crate) async fn test_address(
+    provider: &DnsProvider,
+    ip: IpAddr,
+    cfg: &SpeedTestConfig,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> TestResult {
+    if !has_route(ip, cfg.bind) {
+        return unreachable_result(provider, ip, cfg);
+    }
+    let start = Instant::now();
+    // Before the rounds, so the queries do not queue behind it.
+    let rtt = if cfg.ping {
+        Some(ping::network_rtt(ip, provider.port_for(Transport::Udp), cfg.bind, cfg.timeout).await)
+    } else {
+        None
+    };
+    // Its own connection, so the rounds' pooled one stays the only one they use.
+    let split = if cfg.handshake_check && matches!(cfg.transport, Transport::Tls | Transport::Https) && !past_deadline(cfg) {
+        let domain = cfg.domains.first().map_or("example.com", String::as_str);
+        Some(handshake::probe_connection(provider, ip, cfg.transport, domain, cfg.bind, cfg.timeout).await)
+    } else {
+        None
+    };
+    let mut result = test_dns_speed(provider, ip, cfg, cfg.transport, cfg.cache_bust, on_progress).await;
+    if let Some((network_rtt, method)) = rtt {
+        result.network_rtt = network_rtt;
+        result.network_rtt_method = Some(method);
+    }
+    if let Some(mut split) = split {
+        split.steady_median = result.warm_median.or(result.median_duration.filter(|_| cfg.rounds == 1));
+        result.connection_split = Some(split);
+    }
+    if cfg.cancel.is_cancelled() || past_deadline(cfg) {
+        return result;
+    }
+    if cfg.best_transport && provider.doh_host.is_some() {
+        let doh = test_dns_speed(provider, ip, cfg, Transport::Https, cfg.cache_bust, on_progress).await;
+        result.transport_comparison = Some(TransportComparison {
+            doh_median: doh.median_duration,
+            doh_success_rate: doh.success_rate,
+        });
+    }
+    let tls_host = provider.tls_host(cfg.transport);
+    let doh_path = provider.doh_path.as_deref();
+    if cfg.dnssec_check {
+        let mut opts = resolver_opts(cfg);
+        opts.timeout = DNSSEC_PROBE_TIMEOUT.min(cfg.timeout);
+        let resolver = build_resolver(&[ip], provider.port_for(cfg.transport), cfg.bind, opts, cfg.transport, tls_host, Connector::new(doh_path));
+        result.dnssec = Some(probes::probe_dnssec(&resolver).await);
+    }
+    // One resolver for the probes with the normal timeout, built only if one of them runs.
+    let dns64_check = cfg.record_types.contains(&QueryType::Aaaa);
+    let probes_needed = cfg.nxdomain_check || cfg.nxdomain_probes > 0 || cfg.https_probe || cfg.blocking_domains.is_some() || dns64_check;
+    let probe_resolver = probes_needed.then(|| {
+        build_resolver(&[ip], provider.port_for(cfg.transport), cfg.bind, resolver_opts(cfg), cfg.transport, tls_host, Connector::new(doh_path))
+    });
+    if let Some(resolver) = probe_resolver.as_ref().filter(|_| cfg.nxdomain_check) {
+        result.nxdomain_hijacking = probes::probe_nxdomain_hijacking(resolver).await;
+    }
+    if let Some(resolver) = probe_resolver.as_ref().filter(|_| cfg.nxdomain_probes > 0) {
+        (result.nxdomain_median, result.nxdomain_probe_failures) = probes::probe_negative_latency(resolver, cfg.nxdomain_probes, cfg.cooldown).await;
+    }
+    if let Some(resolver) = probe_resolver.as_ref().filter(|_| cfg.https_probe) {
+        (result.https_success_rate, result.https_median, result.https_failures) = probes::probe_https_records(resolver, &cfg.domains, cfg.cooldown).await;
+    }
+    if cfg.pop_check {
+        result.pop = probes::probe_pop(&provider.name, ip, provider.port_for(Transport::Udp), cfg.bind, POP_PROBE_TIMEOUT.min(cfg.timeout)).await;
+    }
+    if cfg.identity_check {
+        (result.resolver_identity, result.verified) =
+            probes::probe_identity(&provider.name, ip, provider.port_for(Transport::Udp), cfg.bind, IDENTITY_PROBE_TIMEOUT.min(cfg.timeout)).await;
+    }
+    if cfg.ddr_check {
+        let designated = ddr::discover(ip, provider.port_for(Transport::Udp), cfg.bind, DDR_PROBE_TIMEOUT.min(cfg.timeout)).await;
+        result.ddr = Some(designated.unwrap_or_default());
+    }
+    if let Some(resolver) = probe_resolver.as_ref().filter(|_| dns64_check) {
+        let aaaa: Vec<(String, Vec<Ipv6Addr>)> = cfg.domains.iter()
+            .filter_map(|domain| {
+                let addresses = result.answers.get(&query_label(domain, RecordType::AAAA, cfg))?;
+                Some((domain.clone(), addresses.iter().filter_map(|ip| match ip {
+                    IpAddr::V6(address) => Some(*address),
+                    IpAddr::V4(_) => None,
+                }).collect()))
+            })
+            .collect();
+        result.dns64 = Some(probes::probe_dns64(resolver, &aaaa).await);
+    }
+    if let (Some(domains), Some(resolver)) = (&cfg.blocking_domains, &probe_resolver) {
+        result.blocking = Some(probes::probe_blocking(resolver, domains).await);
+    }
+    if cfg.compare_cache {
+        let uncached = test_dns_speed(provider, ip, cfg, cfg.transport, true, on_progress).await;
+        result.cache_comparison = Some(CacheComparison {
+            uncached_median: uncached.median_duration,
+            uncached_success_rate: uncached.success_rate,
+        });
+    }
+    if cfg.socket_mode == SocketMode::Compare {
+        let fresh_cfg = SpeedTestConfig { socket_mode: SocketMode::Fresh, ..cfg.clone() };
+        let fresh = test_dns_speed(provider, ip, &fresh_cfg, cfg.transport, cfg.cache_bust, on_progress).await;
+        result.socket_comparison = Some(SocketComparison {
+            fresh_median: fresh.median_duration,
+            fresh_success_rate: fresh.success_rate,
+        });
+    }
+    result.timing.wall = start.elapsed();
+    result
+}
+
+/// The UDP payload size the queries advertise, `None` without EDNS.
+fn edns_buffer(cfg: &SpeedTestConfig) -> Option<u16> {
+    (cfg.edns == EdnsMode::On).then_some(cfg.edns_buffer)
+}
+
+/// Sleeps for `duration` and adds the time slept to `idle`.
+async fn rest(duration: Duration, idle: &mut Duration) {
+    let start = Instant::now();
+    sleep(duration).await;
+    *idle += start.elapsed();
+}
+
+/// Whether the OS has a route to `ip` from `bind`. Connecting a UDP socket sends nothing but
+/// fails at once when, for example, the machine has no global IPv6 connectivity or `bind` is of
+/// the other address family.
+fn has_route(ip: IpAddr, bind: Option<IpAddr>) -> bool {
+    std::net::UdpSocket::bind(raw::local_address(ip, bind))
+        .and_then(|socket| socket.connect(SocketAddr::new(ip, 53)))
+        .is_ok()
+}
+
+/// What a failed query counts as with --penalize-failures.
+fn failure_penalty(cfg: &SpeedTestConfig) -> Option<Duration> {
+    cfg.failure_penalty.map(|factor| cfg.timeout.mul_f64(factor))
+}
+
+/// Whether any of the provider's addresses answers a UDP query for the root's NS records, with
+/// any response code, or accepts a TCP connection on the port of `cfg.transport` (over DoQ, a
+/// QUIC handshake). Every probe runs at once and gets `PREFLIGHT_TIMEOUT`.
+pub(crate) async fn preflight(provider: &DnsProvider, cfg: &SpeedTestConfig) -> bool {
+    let message = raw::message(Name::root(), RecordType::NS, DNSClass::IN);
+    let probes = provider.addresses.iter().map(|&ip| {
+        let message = &message;
+        async move {
+            let udp = raw::query(ip, provider.port_for(Transport::Udp), cfg.bind, message, PREFLIGHT_TIMEOUT);
+            let connect = connect_latency(provider, ip, cfg.transport, cfg.bind, PREFLIGHT_TIMEOUT);
+            let (udp, connect) = tokio::join!(udp, connect);
+            udp.is_some() || connect.is_some()
+        }
+    });
+    futures::future::join_all(probes).await.into_iter().any(|reachable| reachable)
+}
+
+/// The row of a provider that failed the pre-flight check: nothing was sent, so there are no
+/// samples and, as for any provider without answers, no latencies.
+pub(crate) fn preflight_failed(provider: &DnsProvider, cfg: &SpeedTestConfig) -> TestResult {
+    TestResult {
+        provider: provider.name.clone(),
+        organization: provider.organization().to_string(),
+        system_resolver: provider.system,
+        addresses: provider.addresses.iter().map(IpAddr::to_string).collect(),
+        timeout: cfg.timeout,
+        success_rate: 0.0,
+        round_medians: vec![None; cfg.rounds as usize],
+        unreachable: true,
+        transport: cfg.transport.label().to_string(),
+        edns: cfg.edns == EdnsMode::On,
+        edns_buffer: edns_buffer(cfg),
+        ..Default::default()
+    }
+}
+
+/// The row of a provider the run's time budget ran out before.
+pub(crate) fn not_tested(provider: &DnsProvider, cfg: &SpeedTestConfig) -> TestResult {
+    TestResult { unreachable: false, budget_exceeded: true, ..preflight_failed(provider, cfg) }
+}
+
+/// Whether the time budget has run out, so no more queries should be sent.
+pub(crate) fn past_deadline(cfg: &SpeedTestConfig) -> bool {
+    cfg.deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline)
+}
+
+/// Result for a provider whose address family is unreachable: every query counts as failed.
+fn unreachable_result(provider: &DnsProvider, ip: IpAddr, cfg: &SpeedTestConfig) -> TestResult {
+    let samples = (0..cfg.rounds)
+        .flat_map(|round| queries(cfg).map(move |(domain, record_type)| Sample {
+            domain: domain.to_string(),
+            record_type,
+            round,
+            duration: None,
+            status: SampleStatus::Failed,
+            error: Some(FailureKind::NoRoute),
+            ttl: None,
+            cnames: None,
+        }))
+        .collect::<Vec<_>>();
+    let failures: Vec<QueryFailure> = queries(cfg)
+        .map(|(domain, record_type)| QueryFailure { domain: query_label(domain, record_type, cfg), kind: FailureKind::NoRoute })
+        .collect();
+    TestResult {
+        provider: provider.name.clone(),
+        organization: provider.organization().to_string(),
+        system_resolver: provider.system,
+        addresses: vec![ip.to_string()],
+        per_domain: domain_stats(&samples, cfg),
+        timeout: cfg.timeout,
+        success_rate: 0.0,
+        round_medians: vec![None; cfg.rounds as usize],
+        failed_domains: queries(cfg)
+            .map(|(domain, record_type)| format!("{} (No Route)", query_label(domain, record_type, cfg)))
+            .collect(),
+        failure_kinds: failure_breakdown(&failures),
+        failures,
+        transport: cfg.transport.label().to_string(),
+        edns: cfg.edns == EdnsMode::On,
+        edns_buffer: edns_buffer(cfg),
+        completed_queries: samples.len() as u32,
+        samples,
+        ..Default::default()
+    }
+}
+
+/// Median and failure count of every test query. Only queries answered in time count towards
+/// the median, as in the provider's aggregate figures.
+fn domain_stats(samples: &[Sample], cfg: &SpeedTestConfig) -> Vec<DomainStats> {
+    queries(cfg)
+        .map(|(domain, record_type)| {
+            let query: Vec<&Sample> = samples.iter()
+                .filter(|sample| sample.domain == domain && sample.record_type == record_type)
+                .collect();
+            let mut answered: Vec<Duration> = query.iter()
+                .filter(|sample| sample.status == SampleStatus::Ok)
+                .filter_map(|sample| sample.duration)
+                .collect();
+            answered.sort();
+            DomainStats {
+                domain: query_label(domain, record_type, cfg),
+                median: stats::percentile(&answered, 50.0),
+                failures: (query.len() - answered.len()) as u32,
+                queries: query.len() as u32,
+                ttl: TtlRange::span(query.iter().filter_map(|sample| sample.ttl)),
+                cnames: query.iter().filter(|sample| sample.status == SampleStatus::Ok).filter_map(|sample| sample.cnames).max(),
+            }
+        })
+        .collect()
+}
+
+/// Every (domain, record type) pair queried in one round.
+fn queries(cfg: &SpeedTestConfig) -> impl Iterator<Item = (&str, RecordType)> + '_ {
+    cfg.domains.iter().flat_map(|domain| cfg.record_types.iter().map(move |t| (domain.as_str(), t.record_type())))
+}
+
+/// A query of the rounds with its report label and name worked out once, not per round.
+struct PreparedQuery<'a> {
+    domain: &'a str,
+    record_type: RecordType,
+    label: String,
+    /// Why the domain is not a valid name, which every query of it fails with
+    name: Result<Name, ProtoError>,
+}
+
+impl PreparedQuery<'_> {
+    fn all(cfg: &SpeedTestConfig) -> Vec<PreparedQuery<'_>> {
+        queries(cfg)
+            .map(|(domain, record_type)| PreparedQuery {
+                domain,
+                record_type,
+                label: query_label(domain, record_type, cfg),
+                name: query_name(domain, record_type),
+            })
+            .collect()
+    }
+
+    /// The name to send; with `cache_bust`, under a random first label, so it is unique and
+    /// cannot be answered from cache.
+    fn name(&self, cache_bust: bool) -> Result<Name, ProtoError> {
+        let name = self.name.clone()?;
+        if cache_bust { Name::from_ascii(random_label())?.append_name(&name) } else { Ok(name) }
+    }
+}
+
+/// One round's queries in the order they are sent, each with its position in the configured
+/// order. With `shuffle_seed` set the order is a permutation fixed by the seed, provider and round.
+fn round_order<'q, 'a>(prepared: &'q [PreparedQuery<'a>], cfg: &SpeedTestConfig, provider: &str, round: u32) -> Vec<(usize, &'q PreparedQuery<'a>)> {
+    let mut order: Vec<_> = prepared.iter().enumerate().collect();
+    if let Some(seed) = cfg.shuffle_seed {
+        let mut rng = StdRng::seed_from_u64(seeded_key(seed, &format!("{}/{}", provider, round)));
+        order.shuffle(&mut rng);
+    }
+    order
+}
+
+/// The indices of `cfg.providers` in the order they are tested: as configured, or with
+/// `provider_order_seed` set a permutation fixed by the seed.
+pub fn provider_order(cfg: &SpeedTestConfig) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..cfg.providers.len()).collect();
+    if let Some(seed) = cfg.provider_order_seed {
+        let mut rng = StdRng::seed_from_u64(seeded_key(seed, "providers"));
+        order.shuffle(&mut rng);
+    }
+    order
+}
+
+/// One query of the rounds, once it is answered or has failed for good.
+struct QueryRun<'a> {
+    label: &'a str,
+    /// Position in the configured order, which failures are listed in
+    position: usize,
+    sample: Sample,
+    /// Addresses in the answer
+    addresses: Vec<IpAddr>,
+    retries: u32,
+    /// UDP packets sent for it and those of them that went unanswered
+    packets: (u32, u32),
+    /// Answered only once the resolver had sent it again, in the final try
+    resent: bool,
+    /// "domain (2.3s)" for an answer that only came with the escalation timeout
+    slow: Option<String>,
+    /// Why the query failed
+    error: Option<ResolveError>,
+    /// For a failed query, how long it was waited on from its first try
+    waited: Option<Duration>,
+    /// For ANY, how it was answered
+    any: Option<AnyHandling>,
+}
+
+/// What the queries of one `test_dns_speed` run share.
+struct QueryContext<'a> {
+    provider: &'a DnsProvider,
+    cfg: &'a SpeedTestConfig,
+    resolver: &'a Resolver,
+    escalation_resolver: Option<&'a Resolver>,
+    /// Whether the tool sends the resolver's attempts itself, one packet each
+    udp: bool,
+    cache_bust: bool,
+    /// Shared by the provider's queries in flight, with --qps
+    pacer: Option<&'a Pacer>,
+    /// With `SocketMode::Fresh`, what every query builds its own resolvers from
+    fresh: Option<FreshResolvers<'a>>,
+    /// Queries in the whole run, for progress
+    total: u32,
+    on_progress: &'a (dyn Fn(Progress) + Sync),
+}
+
+/// The server of a `test_dns_speed` run, for building a new resolver per query.
+struct FreshResolvers<'a> {
+    server: SocketAddr,
+    transport: Transport,
+    /// The run's count, which every query's resolvers add to
+    truncations: &'a Arc<Truncations>,
+}
+
+/// How long one try of a query may take with `timeout` per attempt: all of its attempts, and
+/// whatever the resolver does in between, such as resending a truncated answer over TCP. Every
+/// --retries retry and the --timeout-escalation retry is a try with a budget of its own, so a
+/// query that never gets an answer is given up after `retries + 1` budgets at the timeout, the
+/// backoffs between them, and one budget at the escalation timeout.
+fn query_budget(cfg: &SpeedTestConfig, timeout: Duration) -> Duration {
+    timeout * cfg.attempts.max(1) as u32
+}
+
+/// One try of a query, given up at `deadline`. Over UDP the resolver makes a single attempt and
+/// the tool the rest of `attempts`, so every packet that got no response within the timeout is
+/// counted in `packets`; other transports resend inside the resolver. A SERVFAIL or any other
+/// answer is not a loss.
+async fn send(
+    context: &QueryContext<'_>,
+    resolver: &Resolver,
+    (qname, record_type, nxdomain_ok): (&Result<Name, ProtoError>, RecordType, bool),
+    deadline: tokio::time::Instant,
+    packets: &mut (u32, u32),
+) -> Result<Answer, ResolveError> {
+    // A domain that is not a valid name fails without a packet sent.
+    let qname = qname.as_ref().map_err(|error| error.clone())?;
+    // The resolver's own timeouts run per attempt and per connection; this one bounds them all.
+    let lookup = || async {
+        tokio::time::timeout_at(deadline, lookup_name(resolver, qname, record_type, nxdomain_ok)).await
+            .unwrap_or_else(|_| Err(ResolveErrorKind::Timeout.into()))
+    };
+    if !context.udp {
+        return lookup().await;
+    }
+    let cfg = context.cfg;
+    let mut attempt = 1;
+    loop {
+        packets.0 += 1;
+        match lookup().await {
+            Err(error) if is_timeout(&error) => {
+                packets.1 += 1;
+                if attempt >= cfg.attempts || tokio::time::Instant::now() >= deadline || cfg.cancel.is_cancelled() || past_deadline(cfg) {
+                    return Err(error);
+                }
+                attempt += 1;
+            },
+            outcome => return outcome,
+        }
+    }
+}
+
+/// Sends one query with its retries and escalation and times it from its own start, so queries
+/// in flight together each get their own latency. `None` once the run is cancelled or out of
+/// time.
+async fn run_query<'q>(
+    context: &QueryContext<'_>,
+    round: u32,
+    number: u32,
+    (position, prepared): (usize, &'q PreparedQuery<'_>),
+) -> Option<QueryRun<'q>> {
+    let QueryContext { provider, cfg, resolver, escalation_resolver, cache_bust, pacer, total, on_progress, .. } = *context;
+    if cfg.cancel.is_cancelled() || past_deadline(cfg) {
+        return None;
+    }
+    // Built before the clock starts: only the lookup is timed, opening its socket included.
+    let fresh = context.fresh.as_ref()
+        .map(|fresh| QueryResolvers::counting(provider, fresh.server, cfg, fresh.transport, fresh.truncations.clone()));
+    let (resolver, escalation_resolver) = match &fresh {
+        Some(fresh) => (&fresh.resolver, fresh.escalation_resolver.as_ref()),
+        None => (resolver, escalation_resolver),
+    };
+    let PreparedQuery { domain, record_type, ref label, .. } = *prepared;
+    on_progress(Progress::Query(QueryProgress { provider: &provider.name, round, domain: label, query: number, total }));
+
+    let qname = prepared.name(cache_bust);
+    let nxdomain_ok = nxdomain_ok(cfg, record_type, cache_bust);
+    let query = (&qname, record_type, nxdomain_ok);
+    let mut packets = (0, 0);
+    // Only the final attempt is timed, from its own start; a failure from the first one's.
+    let mut attempt = 0;
+    let mut first_start = None;
+    let (query_start, lost_before, outcome) = loop {
+        if let Some(pacer) = pacer {
+            pacer.wait().await;
+        }
+        // The tokio clock, which the deadline is on.
+        let attempt_start = tokio::time::Instant::now();
+        first_start.get_or_insert(attempt_start);
+        let deadline = attempt_start + query_budget(cfg, cfg.timeout);
+        let lost_before = packets.1;
+        match send(context, resolver, query, deadline, &mut packets).await {
+            Err(error) if attempt < cfg.retries && is_retryable(&error) => {
+                tracing::trace!(provider = %provider.name, domain = %label, attempt = attempt + 1, %error, "retrying");
+                sleep(RETRY_BACKOFF.saturating_mul(2u32.saturating_pow(attempt))).await;
+                attempt += 1;
+            },
+            outcome => break (attempt_start, lost_before, outcome),
+        }
+    };
+    let sample = |duration, status, error, answer: Option<&Answer>| Sample {
+        domain: domain.to_string(),
+        record_type,
+        round,
+        duration,
+        status,
+        error,
+        ttl: answer.and_then(|answer| answer.ttl),
+        cnames: answer.map(|answer| answer.cnames),
+    };
+    let mut run = QueryRun {
+        label,
+        position,
+        sample: sample(None, SampleStatus::Failed, None, None),
+        addresses: Vec::new(),
+        retries: attempt,
+        packets,
+        resent: false,
+        slow: None,
+        error: None,
+        waited: None,
+        any: None,
+    };
+    let error = match outcome {
+        Ok(answer) => {
+            let duration = query_start.elapsed();
+            // Other transports resend inside the resolver, which only the time gives away.
+            run.resent = if context.udp { packets.1 > lost_before } else { cfg.attempts > 1 && duration > cfg.timeout };
+            run.sample = sample(Some(duration), SampleStatus::Ok, None, Some(&answer));
+            run.addresses = answer.addresses;
+            run.any = answer.any;
+            return Some(run);
+        },
+        Err(error) => error,
+    };
+    let error = match escalation_resolver {
+        Some(escalation) if is_timeout(&error) => {
+            let retry_start = tokio::time::Instant::now();
+            let deadline = retry_start + query_budget(cfg, cfg.escalation_timeout.unwrap_or(cfg.timeout));
+            match send(context, escalation, query, deadline, &mut run.packets).await {
+                Ok(answer) => {
+                    run.sample = sample(Some(query_start.elapsed()), SampleStatus::Slow, None, Some(&answer));
+                    run.any = answer.any;
+                    run.slow = Some(format!("{} ({:.1}s)", run.label, retry_start.elapsed().as_secs_f64()));
+                    return Some(run);
+                },
+                Err(error) => error,
+            }
+        },
+        _ => error,
+    };
+    run.sample = sample(None, SampleStatus::Failed, Some(FailureKind::of(&error)), None);
+    run.error = Some(error);
+    run.waited = first_start.map(|start| start.elapsed());
+    Some(run)
+}
+
+/// The resolvers one `test_dns_speed` run sends its queries through, built once before the
+/// TCP check and the warm-ups.
+struct QueryResolvers {
+    resolver: Resolver,
+    /// The same server with --escalation-timeout
+    escalation_resolver: Option<Resolver>,
+    /// Truncated UDP answers are retried over TCP in the open, so they can be counted.
+    truncations: Arc<Truncations>,
+}
+
+impl QueryResolvers {
+    fn new(provider: &DnsProvider, server: SocketAddr, cfg: &SpeedTestConfig, transport: Transport) -> QueryResolvers {
+        QueryResolvers::counting(provider, server, cfg, transport, Arc::new(Truncations::default()))
+    }
+
+    /// `new` with the truncated answers counted in `truncations`.
+    fn counting(provider: &DnsProvider, server: SocketAddr, cfg: &SpeedTestConfig, transport: Transport, truncations: Arc<Truncations>) -> QueryResolvers {
+        let tls_name = provider.tls_host(transport);
+        let connector = Connector::new(provider.doh_path.as_deref())
+            .retrying_truncated(truncations.clone())
+            .advertising(cfg.edns_buffer);
+        let mut opts = resolver_opts(cfg);
+        // The tool makes the attempts itself over UDP, to count the lost packets; hickory's
+        // `attempts` are resends on top of the first try.
+        if transport == Transport::Udp {
+            opts.attempts = 0;
+        }
+        let build = |opts, connector| build_resolver(&[server.ip()], server.port(), cfg.bind, opts, transport, tls_name, connector);
+        let escalation_resolver = cfg.escalation_timeout.map(|timeout| {
+            let mut opts = opts.clone();
+            opts.timeout = timeout;
+            build(opts, connector.clone())
+        });
+        QueryResolvers { resolver: build(opts, connector), escalation_resolver, truncations }
+    }
+}
+
+async fn test_dns_speed(
+    provider: &DnsProvider,
+    ip: IpAddr,
+    cfg: &SpeedTestConfig,
+    transport: Transport,
+    cache_bust: bool,
+    on_progress: &(dyn Fn(Progress) + Sync),
+) -> TestResult {
+    let start = Instant::now();
+    let server = SocketAddr::new(ip, provider.port_for(transport));
+    let udp = transport == Transport::Udp;
+    let QueryResolvers { resolver, escalation_resolver, truncations } = QueryResolvers::new(provider, server, cfg, transport);
+    let prepared = PreparedQuery::all(cfg);
+    let total = cfg.rounds * prepared.len() as u32;
+    let mut successes: Vec<(u32, Duration)> = Vec::with_capacity(total as usize);
+    // The same durations kept sorted, for --adaptive.
+    let mut settled: Vec<Duration> = Vec::with_capacity(if cfg.adaptive.is_some() { total as usize } else { 0 });
+    // One entry at the penalty per failed query, with --penalize-failures.
+    let mut penalties: Vec<(u32, Duration)> = Vec::new();
+    let penalty = failure_penalty(cfg);
+    let mut samples = Vec::with_capacity(total as usize);
+    // (round, position in the configured order), failed domain, kind and error text, so the
+    // report lists failures in the same order whether or not the queries were shuffled
+    let mut failures: Vec<((u32, usize), String, FailureKind, String)> = Vec::new();
+    // How long each failed query kept the run waiting.
+    let mut failed_durations: Vec<Duration> = Vec::new();
+    let mut slow_domains = Vec::new();
+    let mut answers: BTreeMap<String, BTreeSet<IpAddr>> = BTreeMap::new();
+    // The same per round, for `answer_stability`.
+    let mut round_answers: BTreeMap<String, BTreeMap<u32, BTreeSet<IpAddr>>> = BTreeMap::new();
+    let mut any_handling: BTreeMap<AnyHandling, u32> = BTreeMap::new();
+    // A and AAAA queries and those answered with a public address, for `usable_answers`. Names
+    // that do not exist and the domains of the blocking test, which a filter should not answer
+    // usefully, are left out.
+    let mut usable_counts: Option<(u32, u32)> = None;
+    let mut total_queries: u32 = 0;
+    let mut retries_used = 0;
+    let mut resent_answers = 0;
+    let mut packets = (0, 0);
+
+    // Checked before the rounds rather than per query, so it neither delays the queries nor
+    // decides whether they count.
+    // The queries, the TCP check and the cooldowns are timed apart, so none of them inflates
+    // another and the rest of `wall` is setup, warm-ups and the final retry.
+    let mut timing = Timing::default();
+    let connect = if cfg.tcp_check && !past_deadline(cfg) {
+        let check_start = Instant::now();
+        let mut connects = Vec::new();
+        for _ in 0..TCP_CHECKS {
+            connects.extend(connect_latency(provider, ip, transport, cfg.bind, cfg.timeout).await);
+        }
+        timing.tcp_check = check_start.elapsed();
+        connects.sort();
+        stats::percentile(&connects, 50.0)
+    } else {
+        None
+    };
+    let (tcp_connect, quic_handshake) = if transport == Transport::Quic { (None, connect) } else { (connect, None) };
+
+    // Warm-ups pay for connection setup (notably the TLS handshake for DoT and DoH) and are
+    // never measured. They use the first test query, so they take the same path as the rounds.
+    let pacer = cfg.qps.map(Pacer::per_second);
+    // The cap does the spacing when set; the cooldown is for runs without one.
+    let cooldown = if pacer.is_some() { Duration::ZERO } else { cfg.cooldown };
+    if let Some(PreparedQuery { name: Ok(name), record_type, .. }) = prepared.first().filter(|_| cfg.warmup > 0 && !past_deadline(cfg)) {
+        for _ in 0..cfg.warmup {
+            if let Some(pacer) = &pacer {
+                pacer.wait().await;
+            }
+            lookup_name(&resolver, name, *record_type, nxdomain_ok(cfg, *record_type, false)).await.ok();
+        }
+        rest(cooldown, &mut timing.cooldown).await;
+        truncations.take();
+    }
+
+    let context = QueryContext {
+        provider,
+        cfg,
+        resolver: &resolver,
+        escalation_resolver: escalation_resolver.as_ref(),
+        udp,
+        cache_bust,
+        pacer: pacer.as_ref(),
+        fresh: (cfg.socket_mode == SocketMode::Fresh).then_some(FreshResolvers { server, transport, truncations: &truncations }),
+        total,
+        on_progress,
+    };
+    let mut record = |run: QueryRun| {
+        let QueryRun { label, position, sample, addresses, retries, packets: (sent, lost), resent, slow, error, waited, any } = run;
+        if let Some(handling) = any {
+            *any_handling.entry(handling).or_default() += 1;
+        }
+        let answer_count = addresses.len();
+        let blocking_domain = cfg.blocking_domains.as_ref().is_some_and(|domains| domains.contains(&sample.domain));
+        if matches!(sample.record_type, RecordType::A | RecordType::AAAA) && !cache_bust && !blocking_domain {
+            let (queries, usable) = usable_counts.get_or_insert((0, 0));
+            *queries += 1;
+            if error.is_none() && sample.status == SampleStatus::Ok && usable_answer(&addresses) {
+                *usable += 1;
+            }
+        }
+        total_queries += 1;
+        retries_used += retries;
+        resent_answers += u32::from(resent);
+        packets.0 += sent;
+        packets.1 += lost;
+        match (sample.duration, &error) {
+            (Some(duration), None) if sample.status == SampleStatus::Ok => {
+                if !cache_bust {
+                    round_answers.entry(label.to_string()).or_default().entry(sample.round).or_default().extend(&addresses);
+                    answers.entry(label.to_string()).or_default().extend(addresses);
+                }
+                successes.push((sample.round, duration));
+                if cfg.adaptive.is_some() {
+                    let at = settled.partition_point(|&settled| settled <= duration);
+                    settled.insert(at, duration);
+                }
+            },
+            (_, Some(error)) => {
+                let kind = FailureKind::of(error);
+                failures.push(((sample.round, position), label.to_string(), kind, format!("{}: {}", label, error)));
+                failed_durations.extend(waited);
+                penalties.extend(penalty.map(|penalty| (sample.round, penalty)));
+            },
+            _ => penalties.extend(penalty.map(|penalty| (sample.round, penalty))),
+        }
+        slow_domains.extend(slow);
+        let outcome = match sample.error {
+            Some(kind) => format!("{:?}", kind),
+            None => format!("{:?}", sample.status),
+        };
+        tracing::debug!(
+            provider = %provider.name,
+            server = %ip,
+            transport = transport.label(),
+            round = sample.round + 1,
+            domain = %label,
+            outcome,
+            duration_ms = sample.duration.map(|duration| duration.as_secs_f64() * 1000.0),
+            "query"
+        );
+        on_progress(Progress::QueryDone(QueryOutcome {
+            provider: &provider.name,
+            address: ip,
+            transport,
+            edns: cfg.edns == EdnsMode::On,
+            edns_buffer: edns_buffer(cfg),
+            cache_bust,
+            domain: label,
+            sample: &sample,
+            answers: answer_count,
+        }));
+        samples.push(sample);
+        // Whether --adaptive may stop here.
+        cfg.adaptive.is_some_and(|precision| stats::median_settled(&settled, precision))
+    };
+
+    // Time spent on the queries without the cooldowns, for `queries_per_second`.
+    let mut busy = Duration::ZERO;
+    let mut early_stop = false;
+    for round in 0..cfg.rounds {
+        let order = round_order(&prepared, cfg, &provider.name, round);
+        let first = round * order.len() as u32 + 1;
+        if cfg.parallel_queries <= 1 {
+            for (index, query) in order.into_iter().enumerate() {
+                let start = Instant::now();
+                let Some(run) = run_query(&context, round, first + index as u32, query).await else { break };
+                busy += start.elapsed();
+                if record(run) {
+                    early_stop = true;
+                    break;
+                }
+                rest(cooldown, &mut timing.cooldown).await;
+            }
+        } else {
+            // Up to `parallel_queries` in flight at a time, like a browser opening a page.
+            let start = Instant::now();
+            // Built up front: a stream that maps lazily trips the Send check of spawned runs.
+            let queries: Vec<_> = order.into_iter().enumerate()
+                .map(|(index, query)| run_query(&context, round, first + index as u32, query))
+                .collect();
+            let mut runs = stream::iter(queries).buffer_unordered(cfg.parallel_queries);
+            while let Some(run) = runs.next().await {
+                if let Some(run) = run {
+                    early_stop |= record(run);
+                }
+            }
+            busy += start.elapsed();
+        }
+        if early_stop || cfg.cancel.is_cancelled() || past_deadline(cfg) {
+            break;
+        }
+
+        if round < cfg.rounds - 1 {
+            rest(cooldown * 2, &mut timing.cooldown).await;
+        }
+    }
+    timing.queries = busy;
+    let budget_exceeded = total_queries < total && past_deadline(cfg);
+    let queries_per_second = (total_queries > 0 && !busy.is_zero()).then(|| f64::from(total_queries) / busy.as_secs_f64());
+
+    let truncated = truncations.take();
+    let truncated_legs = (!truncated.is_empty()).then(|| {
+        let median = |leg: fn(&TruncatedQuery) -> Duration| {
+            let mut durations: Vec<Duration> = truncated.iter().map(leg).collect();
+            durations.sort();
+            stats::percentile(&durations, 50.0).unwrap_or_default()
+        };
+        TruncationLegs { udp_median: median(|query| query.udp), tcp_median: median(|query| query.tcp) }
+    });
+
+    failures.sort_by_key(|&(key, _, _, _)| key);
+    // A blip during the rounds should not mark a domain as broken. NXDOMAIN would only repeat,
+    // and so would the timeouts of a provider that answered nothing.
+    let mut recovered_domains: Vec<String> = Vec::new();
+    let mut retried: Vec<&str> = Vec::new();
+    let final_retry = cfg.final_retry && !successes.is_empty();
+    for ((_, position), label, kind, _) in failures.iter().filter(|_| final_retry) {
+        if *kind == FailureKind::Nxdomain || retried.contains(&label.as_str()) || cfg.cancel.is_cancelled() || past_deadline(cfg) {
+            continue;
+        }
+        if retried.is_empty() {
+            rest(FINAL_RETRY_PAUSE.max(cooldown * 2), &mut timing.cooldown).await;
+        }
+        retried.push(label);
+        let Some(query) = prepared.get(*position) else { continue };
+        let Ok(qname) = query.name(cache_bust) else { continue };
+        if let Some(pacer) = &pacer {
+            pacer.wait().await;
+        }
+        if lookup_name(&resolver, &qname, query.record_type, nxdomain_ok(cfg, query.record_type, cache_bust)).await.is_ok() {
+            recovered_domains.push(label.clone());
+        }
+    }
+    let failed_domains = failures.iter()
+        .map(|(_, domain, _, _)| domain.clone())
+        .filter(|domain| !recovered_domains.contains(domain))
+        .collect();
+    let failed_labels: BTreeSet<&str> = failures.iter().map(|(_, domain, _, _)| domain.as_str()).collect();
+    let answer_stability = answer_stability(&round_answers, &failed_labels);
+    let (failures, error_texts): (Vec<QueryFailure>, Vec<String>) = failures.into_iter()
+        .map(|(_, domain, kind, error)| (QueryFailure { domain, kind }, error))
+        .unzip();
+    // Without warm-ups this includes connection setup.
+    let first_query = samples.first().and_then(|sample| sample.duration);
+    let in_order: Vec<Duration> = successes.iter().map(|&(_, d)| d).collect();
+    let jitter = stats::jitter(&in_order);
+    let successful_queries = successes.len();
+    // Out of time before the first query, nothing was answered.
+    let success_rate = if total_queries == 0 { 0.0 } else { (successful_queries as f64) / (total_queries as f64) * 100.0 };
+    let cnames: Vec<u32> = samples.iter()
+        .filter(|sample| sample.status == SampleStatus::Ok)
+        .filter_map(|sample| sample.cnames)
+        .collect();
+    let avg_cnames = (!cnames.is_empty()).then(|| f64::from(cnames.iter().sum::<u32>()) / cnames.len() as f64);
+    let max_cnames = cnames.iter().copied().max();
+
+    // The probes are queries too, so they are left out once out of time.
+    let in_time = !past_deadline(cfg);
+    let qname_minimization = if cfg.qname_min_check && transport == Transport::Udp && in_time {
+        probes::probe_qname_minimization(&resolver).await
+    } else {
+        None
+    };
+
+    let dns_cookies = if cfg.cookie_check && transport == Transport::Udp && in_time {
+        probes::probe_dns_cookies(ip, provider.port_for(transport), cfg.bind, cfg.timeout).await
+    } else {
+        None
+    };
+
+    // A provider that answered nothing in the rounds would only add a timeout per query.
+    let response_sizes = if cfg.size_check && transport == Transport::Udp && successful_queries > 0 && in_time {
+        let names: Vec<(Name, RecordType)> = queries(cfg)
+            .filter_map(|(domain, record_type)| match (record_type, domain.parse::<IpAddr>()) {
+                (RecordType::PTR, Ok(ip)) => Some((Name::from(ip), record_type)),
+                _ => Name::from_ascii(domain).ok().map(|name| (name, record_type)),
+            })
+            .collect();
+        probes::probe_response_sizes(&names, ip, provider.port_for(transport), cfg.bind, edns_buffer(cfg), cfg.timeout).await
+    } else {
+        None
+    };
+
+    let saturation = if cfg.saturation_test && transport == Transport::Udp && in_time {
+        Some(probes::saturation_test(&resolver, cfg).await)
+    } else {
+        None
+    };
+
+    let result = TestResult {
+        provider: provider.name.clone(),
+        organization: provider.organization().to_string(),
+        system_resolver: provider.system,
+        addresses: vec![ip.to_string()],
+        failed_domains,
+        recovered_domains,
+        failure_kinds: failure_breakdown(&failures),
+        failures,
+        failed_durations,
+        queries_per_second,
+        avg_cnames,
+        max_cnames,
+        failover: None,
+        qname_minimization,
+        dns_cookies,
+        response_sizes,
+        dnssec: None,
+        dns64: None,
+        blocking: None,
+        nxdomain_hijacking: None,
+        pop: None,
+        resolver_identity: None,
+        verified: None,
+        answers,
+        answer_stability,
+        any_handling,
+        usable_answers: usable_counts.map(|(queries, usable)| AnswerUsability::new(queries, usable)),
+        bogon_answers: Vec::new(),
+        suspicious_answers: Vec::new(),
+        slow_domains,
+        error_texts,
+        transport: transport.label().to_string(),
+        first_query,
+        retries_used,
+        resent_answers,
+        truncated_retries: truncated.len() as u32,
+        truncated_legs,
+        packet_loss: udp.then(|| PacketLoss::new(packets.0, packets.1)),
+        budget_exceeded,
+        completed_queries: total_queries,
+        early_stop: early_stop && total_queries < total,
+        per_domain: domain_stats(&samples, cfg),
+        transport_comparison: None,
+        ttl: TtlRange::span(samples.iter().filter_map(|sample| sample.ttl)),
+        tcp_connect,
+        quic_handshake,
+        edns: cfg.edns == EdnsMode::On,
+        edns_buffer: edns_buffer(cfg),
+        edns_comparison: None,
+        cache_comparison: None,
+        saturation,
+        samples,
+        timing: Timing { wall: start.elapsed(), ..timing },
+        ..Default::default()
+    };
+    with_latency_figures(result, successes, penalties, jitter, success_rate, cfg)
+}
+
+/// How steady the addresses of `rounds`, each query's answers per round, stayed. Only a round
+/// whose addresses share none with the previous round's is a change, as CDNs rotate theirs. A
+/// query in `failed` is left out rather than counted as unstable, as is one answered without
+/// addresses (MX and the like) or in fewer than two rounds.
+fn answer_stability(rounds: &BTreeMap<String, BTreeMap<u32, BTreeSet<IpAddr>>>, failed: &BTreeSet<&str>) -> Option<AnswerStability> {
+    let mut stability = AnswerStability::default();
+    for (query, answers) in rounds.iter().filter(|(query, _)| !failed.contains(query.as_str())) {
+        let answers: Vec<&BTreeSet<IpAddr>> = answers.values().filter(|addresses| !addresses.is_empty()).collect();
+        if answers.len() < 2 {
+            continue;
+        }
+        let changes = answers.windows(2).filter(|pair| pair[0].is_disjoint(pair[1])).count() as u32;
+        stability.queries += 1;
+        if changes == 0 {
+            stability.stable += 1;
+        } else {
+            stability.unstable.push(UnstableAnswer { domain: query.clone(), changes, compared: answers.len() as u32 - 1 });
+        }
+    }
+    (stability.queries > 0).then(|| AnswerStability::finish(stability))
+}
+
+impl AnswerStability {
+    /// Sets `percent` and puts the most changed queries first.
+    fn finish(mut self) -> AnswerStability {
+        self.percent = f64::from(self.stable) / f64::from(self.queries) * 100.0;
+        self.unstable.sort_by(|a, b| b.changes.cmp(&a.changes).then_with(|| a.domain.cmp(&b.domain)));
+        self
+    }
+
+    /// Every run's queries together, each compared within its own run.
+    fn pool<'a>(runs: impl IntoIterator<Item = &'a AnswerStability>) -> Option<AnswerStability> {
+        let mut pooled = AnswerStability::default();
+        for run in runs {
+            pooled.queries += run.queries;
+            pooled.stable += run.stable;
+            for answer in &run.unstable {
+                match pooled.unstable.iter_mut().find(|pooled| pooled.domain == answer.domain) {
+                    Some(pooled) => {
+                        pooled.changes += answer.changes;
+                        pooled.compared += answer.compared;
+                    },
+                    None => pooled.unstable.push(answer.clone()),
+                }
+            }
+        }
+        (pooled.queries > 0).then(|| pooled.finish())
+    }
+}
+
+/// One result from the same provider row of several runs, `rows` in run order. The latency
+/// figures, failures and answers cover every run's queries; the probes are the first run's.
+pub(crate) fn pool(rows: &[&TestResult], cfg: &SpeedTestConfig) -> TestResult {
+    let first = rows[0];
+    // Failures count as the provider's own timeout, as in each run.
+    let cfg = &SpeedTestConfig { timeout: first.timeout, ..cfg.clone() };
+    let samples: Vec<Sample> = rows.iter().flat_map(|row| row.samples.iter().cloned()).collect();
+    let answered = |sample: &&Sample| sample.status == SampleStatus::Ok && sample.duration.is_some();
+    let successes: Vec<(u32, Duration)> = samples.iter().filter(answered)
+        .filter_map(|sample| Some((sample.round, sample.duration?)))
+        .collect();
+    let penalties: Vec<(u32, Duration)> = match failure_penalty(cfg) {
+        Some(penalty) => samples.iter().filter(|sample| !answered(sample)).map(|sample| (sample.round, penalty)).collect(),
+        None => Vec::new(),
+    };
+    let success_rate = self::success_rate(&samples);
+    // Within each run, as the queries completed.
+    let jitter = stats::jitter(&successes.iter().map(|&(_, d)| d).collect::<Vec<_>>());
+
+    let mut recovered_domains: Vec<String> = Vec::new();
+    for domain in rows.iter().flat_map(|row| &row.recovered_domains) {
+        if !recovered_domains.contains(domain) {
+            recovered_domains.push(domain.clone());
+        }
+    }
+    let failures: Vec<QueryFailure> = rows.iter().flat_map(|row| row.failures.iter().cloned()).collect();
+    let mut answers = first.answers.clone();
+    for (query, addresses) in rows[1..].iter().flat_map(|row| &row.answers) {
+        answers.entry(query.clone()).or_default().extend(addresses);
+    }
+    let cnames: Vec<u32> = samples.iter().filter(answered).filter_map(|sample| sample.cnames).collect();
+    let rates: Vec<f64> = rows.iter().filter_map(|row| row.queries_per_second).collect();
+    let run_medians: Vec<Option<Duration>> = rows.iter()
+        .map(|row| row.median_duration)
+        .collect();
+    let spread = RunSpread {
+        runs: rows.len() as u32,
+        std_dev: stats::std_dev(&run_medians.iter().flatten().copied().collect::<Vec<_>>()),
+        run_medians,
+    };
+
+    let pooled = TestResult {
+        failed_domains: rows.iter().flat_map(|row| row.failed_domains.iter().cloned()).collect(),
+        recovered_domains,
+        failure_kinds: failure_breakdown(&failures),
+        failures,
+        failed_durations: rows.iter().flat_map(|row| row.failed_durations.iter().copied()).collect(),
+        error_texts: rows.iter().flat_map(|row| row.error_texts.iter().cloned()).collect(),
+        slow_domains: rows.iter().flat_map(|row| row.slow_domains.iter().cloned()).collect(),
+        retries_used: rows.iter().map(|row| row.retries_used).sum(),
+        resent_answers: rows.iter().map(|row| row.resent_answers).sum(),
+        unreachable: rows.iter().all(|row| row.unreachable),
+        budget_exceeded: rows.iter().any(|row| row.budget_exceeded),
+        early_stop: rows.iter().any(|row| row.early_stop),
+        completed_queries: rows.iter().map(|row| row.completed_queries).sum(),
+        truncated_retries: rows.iter().map(|row| row.truncated_retries).sum(),
+        packet_loss: first.packet_loss.map(|_| PacketLoss::sum(rows.iter().filter_map(|row| row.packet_loss.as_ref()))),
+        timing: rows.iter().map(|row| row.timing).sum(),
+        queries_per_second: (!rates.is_empty()).then(|| rates.iter().sum::<f64>() / rates.len() as f64),
+        avg_cnames: (!cnames.is_empty()).then(|| f64::from(cnames.iter().sum::<u32>()) / cnames.len() as f64),
+        max_cnames: cnames.iter().copied().max(),
+        answers,
+        answer_stability: AnswerStability::pool(rows.iter().filter_map(|row| row.answer_stability.as_ref())),
+        usable_answers: first.usable_answers.map(|_| AnswerUsability::sum(rows.iter().filter_map(|row| row.usable_answers.as_ref()))),
+        any_handling: rows.iter().flat_map(|row| &row.any_handling).fold(BTreeMap::new(), |mut kinds, (&kind, &count)| {
+            *kinds.entry(kind).or_default() += count;
+            kinds
+        }),
+        // Flagged again across the pooled providers.
+        bogon_answers: Vec::new(),
+        suspicious_answers: Vec::new(),
+        per_domain: domain_stats(&samples, cfg),
+        ttl: TtlRange::span(samples.iter().filter_map(|sample| sample.ttl)),
+        runs: Some(spread),
+        samples,
+        ..first.clone()
+    };
+    with_latency_figures(pooled, successes, penalties, jitter, success_rate, cfg)
+}
+
+/// Share of `samples` that were answered, leaving out the SERVFAIL of domains with broken DNSSEC.
+fn success_rate(samples: &[Sample]) -> f64 {
+    let counted = samples.iter().filter(|sample| sample.error != Some(FailureKind::DomainDnssec)).count();
+    let answered = samples.iter().filter(|sample| sample.status == SampleStatus::Ok && sample.duration.is_some()).count();
+    if counted == 0 { 0.0 } else { answered as f64 / counted as f64 * 100.0 }
+}
+
+/// Marks the SERVFAIL of a query as [`FailureKind::DomainDnssec`] when a provider the DNSSEC
+/// probe found validating, at other addresses, got SERVFAIL for it in the same run too, and
+/// takes those queries out of the success rate and the score. Providers that do not validate
+/// answer such domains, so without this the validating ones would look less reliable for doing
+/// their job. With the DNSSEC check off no provider is known to validate and nothing changes.
+pub(crate) fn discount_dnssec_servfails(results: &mut [TestResult], cfg: &SpeedTestConfig) {
+    let servfailed = |result: &TestResult, label: &str| result.failures.iter()
+        .any(|failure| failure.domain == label && matches!(failure.kind, FailureKind::Servfail | FailureKind::DomainDnssec));
+    let broken: Vec<BTreeSet<String>> = results.iter().enumerate()
+        .map(|(i, result)| result.failures.iter()
+            .filter(|failure| failure.kind == FailureKind::Servfail)
+            .filter(|failure| results.iter().enumerate().any(|(j, other)| {
+                j != i && other.addresses != result.addresses && other.dnssec == Some(DnssecStatus::Validating) && servfailed(other, &failure.domain)
+            }))
+            .map(|failure| failure.domain.clone())
+            .collect())
+        .collect();
+    for (result, labels) in results.iter_mut().zip(broken).filter(|(_, labels)| !labels.is_empty()) {
+        for failure in result.failures.iter_mut().filter(|failure| failure.kind == FailureKind::Servfail && labels.contains(&failure.domain)) {
+            failure.kind = FailureKind::DomainDnssec;
+        }
+        // Samples name the domain and record type apart; the label joins them unless only one
+        // type is tested.
+        for sample in result.samples.iter_mut().filter(|sample| sample.error == Some(FailureKind::Servfail)) {
+            if labels.contains(&sample.domain) || labels.contains(&format!("{}/{}", sample.domain, sample.record_type)) {
+                sample.error = Some(FailureKind::DomainDnssec);
+            }
+        }
+        result.failure_kinds = failure_breakdown(&result.failures);
+        result.success_rate = success_rate(&result.samples);
+        // As `with_latency_figures` scores: on the median of the answers.
+        let answered_median = result.optimistic_median.or(result.median_duration)
+            .filter(|_| result.success_rate > 0.0)
+            .unwrap_or(result.timeout);
+        result.score = stats::score(answered_median, result.success_rate, result.jitter, cfg.score_weights);
+    }
+}
+
+/// `result` with the figures computed from the latencies filled in: `successes` are the
+/// answered queries and `penalties` the failed ones counted at the failure penalty, each with
+/// its round.
+fn with_latency_figures(
+    result: TestResult,
+    mut successes: Vec<(u32, Duration)>,
+    mut penalties: Vec<(u32, Duration)>,
+    jitter: Option<Duration>,
+    success_rate: f64,
+    cfg: &SpeedTestConfig,
+) -> TestResult {
+    successes.sort_by_key(|&(_, duration)| duration);
+    let weight = |round: u32| cfg.recency_weight.powi(round as i32);
+    let weights: Vec<f64> = successes.iter().map(|&(round, _)| weight(round)).collect();
+    let answered_median = stats::weighted_median(&successes, &weights);
+
+    // The latency statistics see the penalties as answers, next to real ones: a provider whose
+    // queries all failed has no latencies, not the penalty.
+    let mut measured = successes.clone();
+    if !successes.is_empty() {
+        measured.append(&mut penalties);
+    }
+    measured.sort_by_key(|&(_, duration)| duration);
+    let measured_weights: Vec<f64> = measured.iter().map(|&(round, _)| weight(round)).collect();
+
+    // Outliers left out by `trim` only affect these three.
+    let kept = stats::trim(&measured, cfg.trim);
+    let kept_weights: Vec<f64> = kept.iter().map(|&(round, _)| weight(round)).collect();
+    let avg_duration = (!kept.is_empty()).then(|| Duration::from_secs_f64(
+        kept.iter().zip(&kept_weights).map(|(&(_, d), w)| d.as_secs_f64() * w).sum::<f64>()
+            / kept_weights.iter().sum::<f64>()
+    ));
+
+    let min_latency = kept.first().map(|&(_, d)| d);
+    let max_latency = kept.last().map(|&(_, d)| d);
+    let trimming = (cfg.trim > 0.0).then_some(Trimming { samples: measured.len(), kept: kept.len() });
+    let median_duration = stats::weighted_median(&measured, &measured_weights);
+    let sorted: Vec<Duration> = measured.iter().map(|&(_, d)| d).collect();
+    let p95 = stats::percentile(&sorted, 95.0);
+    let p99 = stats::percentile(&sorted, 99.0);
+    let std_dev = stats::std_dev(&sorted);
+    // Round 0 is the first time the provider sees each domain; later rounds can hit its cache.
+    let (cold, warm): (Vec<_>, Vec<_>) = successes.iter().partition(|&&(round, _)| round == 0);
+    let cold_median = stats::percentile(&cold.iter().map(|&&(_, d)| d).collect::<Vec<_>>(), 50.0);
+    let warm_median = stats::percentile(&warm.iter().map(|&&(_, d)| d).collect::<Vec<_>>(), 50.0);
+    // `successes` is sorted by duration, so each round's share is too.
+    let round_medians: Vec<Option<Duration>> = (0..cfg.rounds)
+        .map(|round| {
+            let durations: Vec<Duration> = successes.iter().filter(|&&(r, _)| r == round).map(|&(_, d)| d).collect();
+            stats::percentile(&durations, 50.0)
+        })
+        .collect();
+    let round_trend = stats::trend(&round_medians);
+    let answers: Vec<Duration> = successes.iter().map(|&(_, d)| d).collect();
+    let (over_half, over_90_percent) = stats::near_timeout(&answers, cfg.timeout);
+    let near_timeout = (!answers.is_empty()).then_some(NearTimeout {
+        answered: answers.len() as u32,
+        over_half: over_half as u32,
+        over_90_percent: over_90_percent as u32,
+    });
+
+    TestResult {
+        avg_duration,
+        min_latency,
+        max_latency,
+        success_rate,
+        median_duration,
+        near_timeout,
+        timeout: cfg.timeout,
+        optimistic_median: failure_penalty(cfg).and(answered_median),
+        // The score weighs in failures itself, so it uses the median of the answers.
+        score: stats::score(answered_median.unwrap_or(cfg.timeout), success_rate, jitter, cfg.score_weights),
+        trimming,
+        latency_samples: sorted.len() as u32,
+        p95,
+        p99,
+        std_dev,
+        jitter,
+        round_medians,
+        round_trend,
+        cold_median,
+        warm_median,
+        failure_wait: (!result.failed_durations.is_empty())
+            .then(|| result.failed_durations.iter().sum::<Duration>() / result.failed_durations.len() as u32),
+        ..result
+    }
+}
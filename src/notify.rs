@@ -0,0 +1,266 @@
+//! --notify-webhook and --notify-cmd: tell someone when a --watch or --daemon run changes
+//! something that matters, instead of having them read every cycle.
+//!
+//! Three rules are checked after every cycle: the fastest provider changed, the system resolver's
+//! median crossed --alert-threshold, and a provider's success rate fell below
+//! --alert-min-success. A rule fires once its new state has held for `CONFIRM_CYCLES` cycles in a
+//! row, so one flappy cycle does not notify, and again only when the state changes back.
+//!
+//! The webhook gets a POST with a JSON body:
+//!
+//! ```json
+//! {"event":"fastest_changed","provider":"Quad9","previous":"Cloudflare","median_ms":11.8,"success_rate":100.0,"message":"Quad9 is now the fastest provider (was Cloudflare)","timestamp":"2024-05-01T12:00:00.123Z"}
+//! ```
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+use dns_speed_test::TestResult;
+use serde::Serialize;
+
+/// Cycles a new state has to hold before its rule fires.
+const CONFIRM_CYCLES: u32 = 2;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Undelivered notifications kept for the next cycle at most; older ones are dropped first.
+const MAX_PENDING: usize = 20;
+
+/// Where notifications go and what triggers them.
+pub struct Settings {
+    pub webhook: Option<String>,
+    /// Split at whitespace, not run through a shell; see `command_line`
+    pub command: Option<String>,
+    /// Median, in milliseconds, above which the system resolver counts as slow
+    pub threshold_ms: Option<f64>,
+    /// Success rate, in percent, below which a provider counts as failing
+    pub min_success: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    FastestChanged,
+    SystemSlow,
+    SystemRecovered,
+    SuccessLow,
+    SuccessRecovered,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::FastestChanged => "fastest_changed",
+            Event::SystemSlow => "system_slow",
+            Event::SystemRecovered => "system_recovered",
+            Event::SuccessLow => "success_low",
+            Event::SuccessRecovered => "success_recovered",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Notification {
+    event: Event,
+    provider: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_ms: Option<f64>,
+    success_rate: f64,
+    message: String,
+    #[serde(serialize_with = "crate::samples_file::rfc3339")]
+    timestamp: SystemTime,
+}
+
+/// The last notified state of one rule, and a different state waiting for confirmation.
+#[derive(Debug)]
+struct Debounced<T> {
+    settled: Option<T>,
+    pending: Option<(T, u32)>,
+}
+
+impl<T: Clone + PartialEq> Debounced<T> {
+    fn new(settled: Option<T>) -> Debounced<T> {
+        Debounced { settled, pending: None }
+    }
+
+    /// Takes this cycle's state; returns the previous settled state when `state` has now held for
+    /// `CONFIRM_CYCLES` cycles and replaces it.
+    fn update(&mut self, state: T) -> Option<Option<T>> {
+        if self.settled.as_ref() == Some(&state) {
+            self.pending = None;
+            return None;
+        }
+        let cycles = match &self.pending {
+            Some((pending, cycles)) if *pending == state => cycles + 1,
+            _ => 1,
+        };
+        if cycles < CONFIRM_CYCLES && self.settled.is_some() {
+            self.pending = Some((state, cycles));
+            return None;
+        }
+        self.pending = None;
+        Some(self.settled.replace(state))
+    }
+}
+
+pub struct Notifier {
+    settings: Settings,
+    fastest: Debounced<String>,
+    system_slow: Debounced<bool>,
+    /// Per provider, whether its success rate is below the floor
+    failing: HashMap<String, Debounced<bool>>,
+    /// Notifications whose delivery failed, retried after the next cycle
+    pending: Vec<Notification>,
+}
+
+impl Notifier {
+    pub fn new(settings: Settings) -> Notifier {
+        Notifier {
+            settings,
+            // The first cycle's fastest provider is where the run starts, not a change.
+            fastest: Debounced::new(None),
+            system_slow: Debounced::new(Some(false)),
+            failing: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Checks the rules against one cycle's results and delivers what fired with what is left
+    /// over from earlier cycles. Delivery failures are printed and kept for the next call.
+    pub async fn cycle(&mut self, results: &[TestResult]) {
+        let now = SystemTime::now();
+        let median_ms = |result: &TestResult| result.median_duration.map(|median| median.as_secs_f64() * 1000.0);
+        let floor = self.settings.min_success;
+        let mut fired = Vec::new();
+
+        // Fastest by median among the providers reliable enough to recommend.
+        let best = results.iter()
+            .filter(|result| result.success_rate > 0.0 && result.success_rate >= floor)
+            .filter_map(|result| Some((result.median_duration?, result)))
+            .min_by_key(|&(median, _)| median)
+            .map(|(_, result)| result);
+        if let Some(best) = best {
+            if let Some(Some(previous)) = self.fastest.update(best.provider.clone()) {
+                fired.push(Notification {
+                    event: Event::FastestChanged,
+                    provider: best.provider.clone(),
+                    message: format!("{} is now the fastest provider (was {})", best.provider, previous),
+                    previous: Some(previous),
+                    median_ms: median_ms(best),
+                    success_rate: best.success_rate,
+                    timestamp: now,
+                });
+            }
+        }
+
+        if let Some(threshold) = self.settings.threshold_ms {
+            // The best-ranked row when the system resolver was tested more than once.
+            if let Some(system) = results.iter().find(|result| result.system_resolver) {
+                let slow = median_ms(system).is_none_or(|median| median > threshold);
+                if let Some(Some(_)) = self.system_slow.update(slow) {
+                    let median = median_ms(system);
+                    fired.push(Notification {
+                        event: if slow { Event::SystemSlow } else { Event::SystemRecovered },
+                        provider: system.provider.clone(),
+                        previous: None,
+                        message: match (slow, median) {
+                            (true, Some(median)) => format!("Your system resolver, {}, is slow: {:.2} ms median, above {} ms", system.provider, median, threshold),
+                            (true, None) => format!("Your system resolver, {}, answered no queries", system.provider),
+                            (false, _) => format!("Your system resolver, {}, is back below {} ms", system.provider, threshold),
+                        },
+                        median_ms: median,
+                        success_rate: system.success_rate,
+                        timestamp: now,
+                    });
+                }
+            }
+        }
+
+        for result in results {
+            let low = result.success_rate < floor;
+            let rule = self.failing.entry(result.provider.clone()).or_insert_with(|| Debounced::new(Some(false)));
+            if let Some(Some(_)) = rule.update(low) {
+                fired.push(Notification {
+                    event: if low { Event::SuccessLow } else { Event::SuccessRecovered },
+                    provider: result.provider.clone(),
+                    previous: None,
+                    median_ms: median_ms(result),
+                    success_rate: result.success_rate,
+                    message: if low {
+                        format!("{} answers only {:.1}% of queries, below {}%", result.provider, result.success_rate, floor)
+                    } else {
+                        format!("{} is back at {:.1}% of queries answered", result.provider, result.success_rate)
+                    },
+                    timestamp: now,
+                });
+            }
+        }
+        // Providers no longer tested do not keep their state.
+        self.failing.retain(|provider, _| results.iter().any(|result| &result.provider == provider));
+
+        self.pending.extend(fired);
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        if excess > 0 {
+            eprintln!("Warning: dropping {} undelivered notifications", excess);
+            self.pending.drain(..excess);
+        }
+        let mut undelivered = Vec::new();
+        for notification in std::mem::take(&mut self.pending) {
+            if let Err(message) = self.deliver(&notification).await {
+                eprintln!("Failed to deliver the {} notification, retrying after the next cycle: {}", notification.event.name(), message);
+                undelivered.push(notification);
+            }
+        }
+        self.pending = undelivered;
+    }
+
+    /// Sends `notification` everywhere configured. A failure anywhere fails it as a whole, so a
+    /// retry may reach a working target twice.
+    async fn deliver(&self, notification: &Notification) -> Result<(), String> {
+        if let Some(url) = &self.settings.webhook {
+            let body = serde_json::to_string(notification).map_err(|e| e.to_string())?;
+            let url = url.clone();
+            tokio::task::spawn_blocking(move || {
+                ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build()
+                    .post(&url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&body)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }).await.map_err(|e| e.to_string())??;
+        }
+        if let Some(template) = &self.settings.command {
+            let argv = command_line(template, notification);
+            let Some((program, arguments)) = argv.split_first() else { return Ok(()) };
+            let status = tokio::process::Command::from(command(program, arguments))
+                .status()
+                .await
+                .map_err(|e| format!("cannot run {}: {}", program, e))?;
+            if !status.success() {
+                return Err(format!("`{}` failed: {}", argv.join(" "), status));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn command(program: &str, arguments: &[String]) -> Command {
+    let mut command = Command::new(program);
+    command.args(arguments).stdin(Stdio::null());
+    command
+}
+
+/// `template` split at whitespace, with {event}, {provider}, {median}, {success} and {message}
+/// replaced in every word. Without a shell, a provider name with spaces stays one argument.
+fn command_line(template: &str, notification: &Notification) -> Vec<String> {
+    let median = notification.median_ms.map_or("n/a".to_string(), |median| format!("{:.2}", median));
+    let success = format!("{:.1}", notification.success_rate);
+    template.split_whitespace()
+        .map(|word| word
+            .replace("{event}", notification.event.name())
+            .replace("{provider}", &notification.provider)
+            .replace("{median}", &median)
+            .replace("{success}", &success)
+            .replace("{message}", &notification.message))
+        .collect()
+}
@@ -0,0 +1,33 @@
+//! The --qps cap: queries to a provider are spaced evenly, however many are in flight.
+
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// Hands out send times at least `interval` apart to every task that shares it.
+pub struct Pacer {
+    interval: Duration,
+    /// The earliest time the next query may go out
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    /// At most `qps` queries per second.
+    pub fn per_second(qps: f64) -> Pacer {
+        Pacer { interval: Duration::from_secs_f64(1.0 / qps), next: Mutex::new(None) }
+    }
+
+    /// Waits for this query's slot. Slots are taken in call order, so concurrent callers queue
+    /// up behind each other instead of all going out at once.
+    pub async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            // An idle period is not saved up for a burst later.
+            let slot = next.map_or(now, |next| next.max(now));
+            *next = Some(slot + self.interval);
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
@@ -0,0 +1,104 @@
+//! `ping`: the network round trip to an address without DNS in it, so a slow provider can be
+//! told apart from a far-away one. ICMP echo where the system permits an ICMP socket, otherwise
+//! (or when the echoes go unanswered) TCP connects, like `tcp_check`.
+
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use crate::measure::measure_latency;
+use crate::results::RttMethod;
+use crate::stats;
+
+/// Echoes (or connects) per address; the median is reported.
+const PINGS: u16 = 3;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// An ICMP socket: unprivileged datagram ("ping") sockets where the system has them, which
+/// fill in the ID and checksum themselves, else a raw socket, which needs privileges.
+fn icmp_socket(ip: IpAddr) -> io::Result<(Socket, bool)> {
+    let (domain, protocol) = if ip.is_ipv4() { (Domain::IPV4, Protocol::ICMPV4) } else { (Domain::IPV6, Protocol::ICMPV6) };
+    match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(socket) => Ok((socket, false)),
+        Err(_) => Socket::new(domain, Type::RAW, Some(protocol)).map(|socket| (socket, true)),
+    }
+}
+
+/// The Internet checksum (RFC 1071); ICMPv6's covers a pseudo-header and is left to the kernel.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Sends `PINGS` echo requests one after another and returns the round trips of those answered
+/// within `timeout`. `Err` only when no ICMP socket can be opened.
+fn echo(ip: IpAddr, bind: Option<IpAddr>, timeout: Duration) -> io::Result<Vec<Duration>> {
+    let (mut socket, raw) = icmp_socket(ip)?;
+    if let Some(bind) = bind {
+        socket.bind(&SocketAddr::new(bind, 0).into())?;
+    }
+    let target: SockAddr = SocketAddr::new(ip, 0).into();
+    let id: u16 = rand::random();
+    let (request, reply) = if ip.is_ipv4() { (ECHO_REQUEST_V4, ECHO_REPLY_V4) } else { (ECHO_REQUEST_V6, ECHO_REPLY_V6) };
+    let mut rtts = Vec::new();
+    for sequence in 0..PINGS {
+        let mut packet = [request, 0, 0, 0, (id >> 8) as u8, id as u8, (sequence >> 8) as u8, sequence as u8];
+        if ip.is_ipv4() {
+            let sum = checksum(&packet);
+            packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        let start = Instant::now();
+        if socket.send_to(&packet, &target).is_err() {
+            continue;
+        }
+        let mut buffer = [0u8; 1500];
+        while let Some(left) = timeout.checked_sub(start.elapsed()).filter(|left| !left.is_zero()) {
+            socket.set_read_timeout(Some(left))?;
+            let Ok(len) = socket.read(&mut buffer) else { break };
+            // Raw IPv4 sockets hand over the IP header too.
+            let offset = if raw && ip.is_ipv4() { usize::from(buffer[0] & 0x0f) * 4 } else { 0 };
+            let Some(icmp) = buffer.get(offset..len).filter(|icmp| icmp.len() >= 8) else { continue };
+            // A datagram socket's ID is the kernel's, and it only passes on replies to it.
+            let ours = !raw || icmp[4..6] == packet[4..6];
+            if icmp[0] == reply && ours && icmp[6..8] == packet[6..8] {
+                rtts.push(start.elapsed());
+                break;
+            }
+        }
+    }
+    Ok(rtts)
+}
+
+/// The median round trip to `ip` and how it was measured, falling back to TCP connects to
+/// `tcp_port` when ICMP is not permitted or gets no reply.
+pub(crate) async fn network_rtt(ip: IpAddr, tcp_port: u16, bind: Option<IpAddr>, timeout: Duration) -> (Option<Duration>, RttMethod) {
+    let echoes = tokio::task::spawn_blocking(move || echo(ip, bind, timeout)).await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+    let method = match echoes {
+        Ok(mut rtts) if !rtts.is_empty() => {
+            rtts.sort();
+            return (stats::percentile(&rtts, 50.0), RttMethod::Icmp);
+        },
+        Ok(_) => RttMethod::TcpIcmpNoReply,
+        Err(e) => {
+            tracing::debug!(server = %ip, error = %e, "no ICMP socket, pinging over TCP");
+            RttMethod::TcpIcmpNotPermitted
+        },
+    };
+    let server = SocketAddr::new(ip, tcp_port);
+    let mut connects = Vec::new();
+    for _ in 0..PINGS {
+        connects.extend(measure_latency(server, bind, timeout).await);
+    }
+    connects.sort();
+    (stats::percentile(&connects, 50.0), method)
+}
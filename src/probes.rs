@@ -0,0 +1,488 @@
+//! Checks of resolver behaviour that run outside the timed rounds.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use hickory_resolver::proto::op::{Edns, ResponseCode};
+use hickory_resolver::proto::rr::{DNSClass, RData, RecordType};
+use hickory_resolver::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_resolver::Name;
+use tokio::time::sleep;
+use crate::connector::Resolver;
+use crate::measure::{lookup, random_label};
+use crate::raw;
+use crate::providers::{DnsProvider, Transport};
+use crate::results::{BlockOutcome, BlockingResult, BlockingSample, Dns64, Dns64Status, DnssecStatus, FailureKind, InterceptionCheck, ResponseSizes, SaturationLevel, SaturationResult};
+use crate::{stats, SpeedTestConfig, DEFAULT_EDNS_BUFFER};
+
+// Deliberately broken signatures vs. a correctly signed zone.
+const DNSSEC_BROKEN_PROBE: &str = "dnssec-failed.org.";
+const DNSSEC_GOOD_PROBE: &str = "isc.org.";
+pub(crate) const DNSSEC_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const NXDOMAIN_PROBES: usize = 3;
+/// Signed TLDs under which `probe_negative_latency` asks for random names.
+const NEGATIVE_PROBE_ZONES: &[&str] = &["com", "org", "net"];
+const QNAME_MIN_PROBE: &str = "qnamemintest.internet.nl.";
+const SATURATION_LEVELS: &[usize] = &[1, 2, 4, 8, 16, 32, 64, 128];
+const SATURATION_BATCHES: usize = 3;
+/// A name with A records only, which a DNS64 answers AAAA queries for anyway (RFC 7050).
+const DNS64_DISCOVERY_NAME: &str = "ipv4only.arpa.";
+const DNS64_DISCOVERY_ADDRESSES: [Ipv4Addr; 2] = [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+/// The Well-Known Prefix, 64:ff9b::/96 (RFC 6052)
+const NAT64_WELL_KNOWN_PREFIX: (Ipv6Addr, u8) = (Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0), 96);
+/// The prefix lengths RFC 6052 allows
+const NAT64_PREFIX_LENGTHS: [u8; 6] = [32, 40, 48, 56, 64, 96];
+pub(crate) const POP_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Names some providers answer with the name of the server or site that handled the query,
+/// tried before the generic CHAOS names.
+const RESOLVER_INFO: &[(&str, &str, DNSClass)] = &[
+    ("NextDNS", "test.nextdns.io.", DNSClass::IN),
+];
+const INTERCEPTION_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Control queries in flight at a time, so a long domain list does not flood the control
+const CONTROL_QUERIES_IN_FLIGHT: usize = 16;
+/// Documentation addresses (RFC 5737, RFC 3849): nothing answers there unless the network
+/// answers for it.
+const BOGUS_SERVERS: [IpAddr; 2] = [
+    IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+    IpAddr::V6(std::net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+];
+/// Answered by Google's name servers with the address of the resolver that asked.
+const WHOAMI_NAME: &str = "o-o.myaddr.l.google.com.";
+/// The conventional server identification queries (RFC 4892), answered by most anycast
+/// resolvers with the name of the instance, e.g. "AMS" or "res200.fra.rrdns.pch.net".
+const POP_NAMES: &[&str] = &["id.server.", "hostname.bind."];
+pub(crate) const IDENTITY_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Answered by Akamai's name servers with the address of the resolver that asked.
+const AKAMAI_WHOAMI: &str = "whoami.akamai.net.";
+/// Where the resolvers of Google Public DNS query from (www.gstatic.com/ipranges/publicdns.json,
+/// summarized).
+const GOOGLE_EGRESS: &[&str] = &[
+    "74.125.0.0/16", "108.177.0.0/17", "142.250.0.0/15", "172.217.0.0/16", "172.253.0.0/16", "173.194.0.0/16",
+    "2404:6800::/32", "2607:f8b0::/32", "2800:3f0::/32", "2a00:1450::/32", "2c0f:fb50::/32",
+];
+/// The address blocks of Cisco's OpenDNS.
+const OPENDNS_EGRESS: &[&str] = &["146.112.0.0/16", "208.67.216.0/21", "2620:119::/32", "2620:0:cc0::/45"];
+
+/// How a provider can be asked to prove that it answered, not something on the path.
+enum Identity {
+    /// A CHAOS TXT name only the provider's own servers answer
+    Chaos(&'static str),
+    /// A name whose authoritative servers answer with the address of the resolver that asked,
+    /// checked against the provider's address blocks; with none known it is only shown
+    Egress { name: &'static str, record_type: RecordType, ranges: &'static [&'static str] },
+}
+
+/// The providers with a known identity query, matched by name prefix like `RESOLVER_INFO`.
+const IDENTITIES: &[(&str, Identity)] = &[
+    ("Cloudflare", Identity::Chaos("whoami.cloudflare.")),
+    ("Google", Identity::Egress { name: WHOAMI_NAME, record_type: RecordType::TXT, ranges: GOOGLE_EGRESS }),
+    ("OpenDNS", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: OPENDNS_EGRESS }),
+    ("Quad9", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: &[] }),
+    ("AdGuard", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: &[] }),
+    ("Mullvad", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: &[] }),
+    ("NextDNS", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: &[] }),
+    ("ControlD", Identity::Egress { name: AKAMAI_WHOAMI, record_type: RecordType::A, ranges: &[] }),
+];
+
+pub(crate) async fn probe_dns_cookies(ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> Option<bool> {
+    let client_cookie: [u8; 8] = rand::random();
+    let mut edns = Edns::new();
+    edns.set_max_payload(DEFAULT_EDNS_BUFFER);
+    edns.options_mut().insert(EdnsOption::Unknown(u16::from(EdnsCode::Cookie), client_cookie.to_vec()));
+
+    let mut message = raw::message(Name::from_ascii("example.com.").unwrap(), RecordType::A, DNSClass::IN);
+    message.set_edns(edns);
+
+    // A cookie-aware server echoes our 8-byte client cookie followed by its own 8-32 byte server cookie.
+    let response = raw::query(ip, port, bind, &message, timeout).await?;
+    let cookie = response.extensions().as_ref().and_then(|edns| edns.option(EdnsCode::Cookie));
+    Some(matches!(cookie,
+        Some(EdnsOption::Unknown(_, data)) if data.len() >= 16 && data.starts_with(&client_cookie)))
+}
+
+/// Sends each of `queries` once over UDP, with EDNS at `edns_buffer` bytes as the rounds do, and
+/// measures the responses. A truncated response is fetched again over TCP, as a resolver would,
+/// and counts with its full size. `None` if nothing was answered.
+pub(crate) async fn probe_response_sizes(
+    queries: &[(Name, RecordType)],
+    ip: IpAddr,
+    port: u16,
+    bind: Option<IpAddr>,
+    edns_buffer: Option<u16>,
+    timeout: Duration,
+) -> Option<ResponseSizes> {
+    let (mut sizes, mut truncated) = (Vec::new(), 0);
+    for (name, record_type) in queries {
+        let mut message = raw::message(name.clone(), *record_type, DNSClass::IN);
+        if let Some(size) = edns_buffer {
+            let mut options = Edns::new();
+            options.set_max_payload(size);
+            message.set_edns(options);
+        }
+        let Some((response, size)) = raw::query_sized(ip, port, bind, &message, timeout).await else { continue };
+        if !response.truncated() {
+            sizes.push(size);
+            continue;
+        }
+        truncated += 1;
+        if let Some((_, size)) = raw::query_tcp(ip, port, bind, &message, timeout).await {
+            sizes.push(size);
+        }
+    }
+    if sizes.is_empty() {
+        return None;
+    }
+    Some(ResponseSizes {
+        responses: sizes.len() as u32,
+        avg_bytes: sizes.iter().sum::<usize>() as f64 / sizes.len() as f64,
+        max_bytes: sizes.iter().copied().max().unwrap_or(0),
+        truncated,
+    })
+}
+
+/// Looks for two signs of interception: an answer from an address where no DNS server runs, and
+/// different providers reporting the same resolver for the whoami query. Variants of one
+/// provider, e.g. "Cloudflare (Family)", may share resolvers, so only distinct names count, and
+/// system resolvers are left out.
+pub(crate) async fn probe_interception(providers: &[DnsProvider], bind: Option<IpAddr>) -> InterceptionCheck {
+    let bogus = BOGUS_SERVERS.iter()
+        .find(|server| bind.is_none_or(|bind| bind.is_ipv4() == server.is_ipv4()))
+        .copied()
+        .unwrap_or(BOGUS_SERVERS[0]);
+
+    let mut names: Vec<&str> = Vec::new();
+    let mut asked = Vec::new();
+    for provider in providers.iter().filter(|provider| !provider.system) {
+        let name = provider.name.split(" (").next().unwrap_or(&provider.name);
+        let Some(&ip) = provider.addresses.first() else { continue };
+        if names.contains(&name) {
+            continue;
+        }
+        names.push(name);
+        asked.push(raw::txt(ip, provider.port_for(Transport::Udp), bind, WHOAMI_NAME, DNSClass::IN, INTERCEPTION_PROBE_TIMEOUT));
+    }
+    let message = raw::message(Name::from_ascii("example.com.").unwrap(), RecordType::A, DNSClass::IN);
+    let (answer, egress) = tokio::join!(raw::query(bogus, 53, bind, &message, INTERCEPTION_PROBE_TIMEOUT), join_all(asked));
+    let bogus_server_answered = answer.is_some();
+    let egress: Vec<String> = egress.into_iter().flatten().collect();
+    let shared_egress = match egress.split_first() {
+        Some((first, rest)) if !rest.is_empty() && rest.iter().all(|other| other == first) => Some(first.clone()),
+        _ => None,
+    };
+    InterceptionCheck {
+        intercepted: bogus_server_answered || shared_egress.is_some(),
+        bogus_server_answered,
+        shared_egress,
+    }
+}
+
+/// The domains that none of `controls` resolves: an A query for each, sent to the controls in
+/// turn until one answers NOERROR, records or not. NXDOMAIN, SERVFAIL or no answer within
+/// `timeout` from every one of them leaves the domain in the list, in the order of `domains`.
+pub(crate) async fn probe_control_domains(domains: &[String], controls: &[(IpAddr, u16)], bind: Option<IpAddr>, timeout: Duration) -> Vec<String> {
+    let checks = domains.iter()
+        .map(|domain| async move {
+            let Ok(name) = Name::from_ascii(format!("{}.", domain.trim_end_matches('.'))) else { return false };
+            let message = raw::message(name, RecordType::A, DNSClass::IN);
+            for &(ip, port) in controls {
+                if raw::query(ip, port, bind, &message, timeout).await.is_some_and(|answer| answer.response_code() == ResponseCode::NoError) {
+                    return true;
+                }
+            }
+            false
+        });
+    let resolved: Vec<bool> = stream::iter(checks).buffered(CONTROL_QUERIES_IN_FLIGHT).collect().await;
+    domains.iter().zip(resolved)
+        .filter(|&(_, resolved)| !resolved)
+        .map(|(domain, _)| domain.clone())
+        .collect()
+}
+
+/// Identifies the anycast site (point of presence) of `provider` that answers at `ip`; `None`
+/// if it answers none of the identification queries.
+pub(crate) async fn probe_pop(provider: &str, ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> Option<String> {
+    let specific = RESOLVER_INFO.iter()
+        .filter(|(name, _, _)| provider.starts_with(name))
+        .map(|&(_, qname, class)| (qname, class));
+    for (qname, class) in specific.chain(POP_NAMES.iter().map(|&qname| (qname, DNSClass::CH))) {
+        if let Some(pop) = raw::txt(ip, port, bind, qname, class, timeout).await {
+            return Some(pop);
+        }
+    }
+    None
+}
+
+/// Whether `ip` is in `range`, e.g. "2001:db8::/32". A malformed range contains nothing.
+fn in_range(ip: IpAddr, range: &str) -> bool {
+    let Some((network, bits)) = range.split_once('/') else { return false };
+    let (Ok(network), Ok(bits)) = (network.parse::<IpAddr>(), bits.parse::<u32>()) else { return false };
+    let (ip, network, width) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => (u128::from(u32::from(ip)), u128::from(u32::from(network)), 32),
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    if bits > width {
+        return false;
+    }
+    let shift = width - bits;
+    shift >= 128 || ip >> shift == network >> shift
+}
+
+/// Asks the server at `ip` to prove it is `provider` (see `IDENTITIES`). Returns what it
+/// reported, its address when it says one and otherwise its own answer, and whether that proves
+/// the provider answered: `Some(false)` for an address outside the provider's blocks or a refusal
+/// of the provider's own CHAOS name, `None` for a provider without an identity query, without
+/// known blocks or without an answer.
+pub(crate) async fn probe_identity(provider: &str, ip: IpAddr, port: u16, bind: Option<IpAddr>, timeout: Duration) -> (Option<String>, Option<bool>) {
+    let Some((_, identity)) = IDENTITIES.iter().find(|(name, _)| provider.starts_with(name)) else { return (None, None) };
+    match *identity {
+        Identity::Chaos(qname) => {
+            let Ok(name) = Name::from_ascii(qname) else { return (None, None) };
+            let Some(response) = raw::query(ip, port, bind, &raw::message(name, RecordType::TXT, DNSClass::CH), timeout).await else {
+                return (None, None);
+            };
+            // Any other server answers the name with an error or nothing.
+            let text = response.answers().iter().find_map(|record| match record.data() {
+                Some(RData::TXT(txt)) => txt.txt_data().first().map(|data| String::from_utf8_lossy(data).into_owned()),
+                _ => None,
+            });
+            let verified = text.is_some();
+            (text, Some(verified))
+        },
+        Identity::Egress { name, record_type, ranges } => {
+            let Ok(qname) = Name::from_ascii(name) else { return (None, None) };
+            let Some(response) = raw::query(ip, port, bind, &raw::message(qname, record_type, DNSClass::IN), timeout).await else {
+                return (None, None);
+            };
+            // Google's TXT also carries the client subnet; the address is the record that parses.
+            let egress = response.answers().iter().find_map(|record| match record.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                Some(RData::TXT(txt)) => txt.txt_data().iter().find_map(|data| std::str::from_utf8(data).ok()?.trim().parse().ok()),
+                _ => None,
+            });
+            let Some(egress) = egress else { return (None, None) };
+            let verified = (!ranges.is_empty()).then(|| ranges.iter().any(|range| in_range(egress, range)));
+            (Some(egress.to_string()), verified)
+        },
+    }
+}
+
+/// Queries names that cannot exist. `Some(true)` if any of them gets an address back, i.e. the
+/// provider rewrites NXDOMAIN; `None` if no answer was conclusive.
+pub(crate) async fn probe_nxdomain_hijacking(resolver: &Resolver) -> Option<bool> {
+    let mut clean = false;
+    for _ in 0..NXDOMAIN_PROBES {
+        let name = format!("{}{}.com.", random_label(), random_label());
+        match resolver.lookup_ip(name.as_str()).await {
+            Ok(lookup) if lookup.iter().next().is_some() => return Some(true),
+            Err(error) if FailureKind::of(&error) == FailureKind::Nxdomain => clean = true,
+            _ => {},
+        }
+    }
+    clean.then_some(false)
+}
+
+/// Times `count` queries for random names that cannot exist, each under one of
+/// `NEGATIVE_PROBE_ZONES` in turn. NXDOMAIN is the answer here; anything else, including
+/// records, fails as its kind. Returns the median time to NXDOMAIN and the failures.
+pub(crate) async fn probe_negative_latency(resolver: &Resolver, count: u32, cooldown: Duration) -> (Option<Duration>, BTreeMap<FailureKind, u32>) {
+    let mut durations = Vec::new();
+    let mut failures = BTreeMap::new();
+    for zone in NEGATIVE_PROBE_ZONES.iter().cycle().take(count as usize) {
+        let name = format!("{}-{}.{}.", random_label(), random_label(), zone);
+        let start = Instant::now();
+        let kind = match resolver.lookup_ip(name.as_str()).await {
+            Ok(_) => FailureKind::Answered,
+            Err(error) => FailureKind::of(&error),
+        };
+        if kind == FailureKind::Nxdomain {
+            durations.push(start.elapsed());
+        } else {
+            *failures.entry(kind).or_default() += 1;
+        }
+        sleep(cooldown).await;
+    }
+    durations.sort();
+    (stats::percentile(&durations, 50.0), failures)
+}
+
+/// Queries HTTPS (type 65) records for each of `domains`, as browsers do on every navigation.
+/// Records and a clean NODATA, for a domain that has none, are correct answers; SERVFAIL,
+/// NXDOMAIN for a name that exists, a timeout or any other error are not. Returns the share
+/// answered correctly, their median time and the failures per kind; reverse lookup targets are
+/// left out.
+pub(crate) async fn probe_https_records(resolver: &Resolver, domains: &[String], cooldown: Duration) -> (Option<f64>, Option<Duration>, BTreeMap<FailureKind, u32>) {
+    let mut durations = Vec::new();
+    let mut failures = BTreeMap::new();
+    let mut queried = 0;
+    for domain in domains.iter().filter(|domain| domain.parse::<IpAddr>().is_err()) {
+        queried += 1;
+        let start = Instant::now();
+        match lookup(resolver, domain, RecordType::HTTPS, false).await {
+            Ok(_) => durations.push(start.elapsed()),
+            Err(error) => *failures.entry(FailureKind::of(&error)).or_default() += 1,
+        }
+        sleep(cooldown).await;
+    }
+    durations.sort();
+    let success_rate = (queried > 0).then(|| durations.len() as f64 / f64::from(queried) * 100.0);
+    (success_rate, stats::percentile(&durations, 50.0), failures)
+}
+
+pub(crate) async fn probe_blocking(resolver: &Resolver, domains: &[String]) -> BlockingResult {
+    let mut outcomes = Vec::new();
+    for domain in domains {
+        let outcome = match resolver.lookup_ip(domain.as_str()).await {
+            // Only a non-empty answer of sentinel addresses is a block; an empty one says nothing.
+            Ok(lookup) => match lookup.iter().collect::<Vec<IpAddr>>() {
+                answer if answer.is_empty() => BlockOutcome::Unknown,
+                answer if answer.iter().all(IpAddr::is_unspecified) => BlockOutcome::Blocked,
+                _ => BlockOutcome::Resolved,
+            },
+            Err(error) => match FailureKind::of(&error) {
+                FailureKind::Nxdomain | FailureKind::Refused => BlockOutcome::Blocked,
+                // Timeouts and other errors say nothing about filtering.
+                _ => BlockOutcome::Unknown,
+            },
+        };
+        outcomes.push(BlockingSample { domain: domain.clone(), outcome });
+    }
+    let count = |wanted| outcomes.iter().filter(|s| s.outcome == wanted).count();
+    let (blocked, resolved) = (count(BlockOutcome::Blocked), count(BlockOutcome::Resolved));
+    BlockingResult {
+        blocked_percent: (blocked + resolved > 0)
+            .then(|| blocked as f64 / (blocked + resolved) as f64 * 100.0),
+        outcomes,
+    }
+}
+
+pub(crate) async fn probe_dnssec(resolver: &Resolver) -> DnssecStatus {
+    // A validating resolver must refuse to answer for a zone with broken signatures, but
+    // SERVFAIL only means something if it resolves a correctly signed zone.
+    let good = resolver.lookup_ip(DNSSEC_GOOD_PROBE).await;
+    let broken = resolver.lookup_ip(DNSSEC_BROKEN_PROBE).await;
+    match (good, broken) {
+        (Ok(_), Err(error)) if FailureKind::of(&error) == FailureKind::Servfail => DnssecStatus::Validating,
+        (_, Ok(_)) => DnssecStatus::NotValidating,
+        _ => DnssecStatus::Unknown,
+    }
+}
+
+/// Whether the resolver synthesizes AAAA answers, from `aaaa`, the AAAA answers of the test
+/// domains in the rounds. ipv4only.arpa has no AAAA records, so an answer for it is synthesized
+/// and gives the prefix. An answer of a test domain counts only if the address under the
+/// well-known or discovered prefix embeds one of the domain's A answers, which a native
+/// address that happens to start like one does not.
+pub(crate) async fn probe_dns64(resolver: &Resolver, aaaa: &[(String, Vec<Ipv6Addr>)]) -> Dns64 {
+    // `None` without a conclusive answer, `Some(None)` for an answer without synthesis.
+    let discovered = lookup(resolver, DNS64_DISCOVERY_NAME, RecordType::AAAA, false).await.ok().map(|answer| {
+        answer.addresses.iter().find_map(|&ip| match ip {
+            IpAddr::V6(address) => NAT64_PREFIX_LENGTHS.iter()
+                .find(|&&length| DNS64_DISCOVERY_ADDRESSES.contains(&embedded_ipv4(address, length)))
+                .map(|&length| (nat64_prefix(address, length), length)),
+            IpAddr::V4(_) => None,
+        })
+    });
+    let prefixes: Vec<(Ipv6Addr, u8)> = std::iter::once(NAT64_WELL_KNOWN_PREFIX).chain(discovered.flatten()).collect();
+
+    let mut prefix = discovered.flatten();
+    let mut synthesized = Vec::new();
+    for (domain, addresses) in aaaa {
+        let candidates: Vec<(Ipv6Addr, u8)> = addresses.iter()
+            .flat_map(|&address| prefixes.iter().filter(move |&&(net, length)| nat64_prefix(address, length) == net).map(move |&(_, length)| (address, length)))
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        let Ok(answer) = lookup(resolver, domain, RecordType::A, false).await else { continue };
+        let found = candidates.iter().find(|&&(address, length)| answer.addresses.contains(&IpAddr::V4(embedded_ipv4(address, length))));
+        if let Some(&(address, length)) = found {
+            prefix.get_or_insert((nat64_prefix(address, length), length));
+            synthesized.push(domain.clone());
+        }
+    }
+
+    let status = if prefix.is_some() {
+        Dns64Status::Yes
+    } else if discovered.is_some() {
+        Dns64Status::No
+    } else {
+        Dns64Status::Unknown
+    };
+    Dns64 { status, prefix: prefix.map(|(net, length)| format!("{}/{}", net, length)), synthesized }
+}
+
+/// The IPv4 address `address` carries after a NAT64 prefix of `length` bits: the 32 bits that
+/// follow it, skipping bits 64 to 71, which RFC 6052 reserves.
+fn embedded_ipv4(address: Ipv6Addr, length: u8) -> Ipv4Addr {
+    let octets = address.octets();
+    let mut embedded = (usize::from(length / 8)..16).filter(|&i| i != 8).map(|i| octets[i]);
+    let mut next = || embedded.next().unwrap_or_default();
+    Ipv4Addr::new(next(), next(), next(), next())
+}
+
+/// The first `length` bits of `address`.
+fn nat64_prefix(address: Ipv6Addr, length: u8) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(address) & (u128::MAX << (128 - u32::from(length))))
+}
+
+pub(crate) async fn probe_qname_minimization(resolver: &Resolver) -> Option<bool> {
+    // The detector's authoritative servers answer "HOORAY - ..." only when the intermediate
+    // labels were queried separately, i.e. when the resolver minimised the query name.
+    let lookup = resolver.txt_lookup(QNAME_MIN_PROBE).await.ok()?;
+    let text = lookup.iter().next()?.to_string();
+    if text.starts_with("HOORAY") {
+        Some(true)
+    } else if text.starts_with("NO") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub(crate) async fn saturation_test(resolver: &Resolver, cfg: &SpeedTestConfig) -> SaturationResult {
+    let mut levels: Vec<SaturationLevel> = Vec::new();
+    let mut breakdown = None;
+
+    for &concurrency in SATURATION_LEVELS {
+        let mut durations = Vec::new();
+        let mut errors = 0;
+        for _ in 0..SATURATION_BATCHES {
+            let lookups = cfg.domains.iter().cycle().take(concurrency).map(|domain| async move {
+                let query_start = Instant::now();
+                let name = Name::from_ascii(domain).map_err(|_| ())?;
+                resolver.lookup_ip(name).await.map(|_| query_start.elapsed()).map_err(|_| ())
+            });
+            for outcome in join_all(lookups).await {
+                match outcome {
+                    Ok(duration) => durations.push(duration),
+                    Err(_) => errors += 1,
+                }
+            }
+            sleep(cfg.cooldown).await;
+        }
+        durations.sort();
+        let level = SaturationLevel {
+            concurrency,
+            median: stats::percentile(&durations, 50.0),
+            error_rate: errors as f64 / (concurrency * SATURATION_BATCHES) as f64 * 100.0,
+        };
+
+        let baseline = levels.first().and_then(|first| first.median);
+        let degraded = level.error_rate > 10.0 || match (baseline, level.median) {
+            (Some(baseline), Some(median)) => median > baseline * 2,
+            _ => true,
+        };
+        levels.push(level);
+        if degraded {
+            breakdown = Some(concurrency);
+            break;
+        }
+    }
+
+    SaturationResult { levels, breakdown }
+}
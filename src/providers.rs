@@ -0,0 +1,566 @@
+use std::net::IpAddr;
+use std::time::Duration;
+use clap::ValueEnum;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::Name;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::ddr::DdrEndpoint;
+
+/// systemd-resolved's local stub listener, which answers from its own cache.
+pub const SYSTEMD_RESOLVED_STUB: &str = "127.0.0.53";
+
+struct ProviderEntry {
+    name: &'static str,
+    /// Filtering variant of the provider's service, e.g. "Family"; `None` for the standard one
+    variant: Option<&'static str>,
+    set: ProviderSet,
+    primary: &'static str,
+    secondary: Option<&'static str>,
+    primary_v6: Option<&'static str>,
+    secondary_v6: Option<&'static str>,
+    doh_host: Option<&'static str>,
+    dot_host: Option<&'static str>,
+    doq_host: Option<&'static str>,
+}
+
+/// A resolver to benchmark. Deserializes from what it serializes to; only `name` and
+/// `addresses` are required, and `doh_host` can also be given as a `doh_url` such as
+/// `https://dns.example/dns-query`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsProvider {
+    pub name: String,
+    /// Who runs the provider, e.g. "Cloudflare" for each of its variants and families, which
+    /// `--group-by provider` sums up in one line; the name up to its tags when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+    /// Anycast addresses of the provider, primary first
+    pub addresses: Vec<IpAddr>,
+    /// Hostname of the DNS-over-HTTPS endpoint, queried at `https://<host>/dns-query`
+    #[serde(default, alias = "doh_url", deserialize_with = "doh_host")]
+    pub doh_host: Option<String>,
+    /// Path of the DoH endpoint when it is not `/dns-query`, e.g. a NextDNS profile's `/abc123`
+    #[serde(default, deserialize_with = "doh_path")]
+    pub doh_path: Option<String>,
+    /// Hostname presented for DNS-over-TLS
+    #[serde(default, deserialize_with = "host")]
+    pub dot_host: Option<String>,
+    /// Hostname presented for DNS-over-QUIC
+    #[serde(default, deserialize_with = "host")]
+    pub doq_host: Option<String>,
+    /// Port of the DoQ endpoint when it is not 853, e.g. the 8853 of some AdGuard Home setups
+    #[serde(default, deserialize_with = "doq_port")]
+    pub doq_port: Option<u16>,
+    /// Whether this is one of the machine's configured nameservers
+    #[serde(default)]
+    pub system: bool,
+    /// Port of plain DNS (UDP and TCP) when it is not 53, e.g. for a local test instance
+    #[serde(default, deserialize_with = "port")]
+    pub port: Option<u16>,
+    /// Per-query timeout instead of the run's, e.g. `"500ms"` or a number of seconds
+    #[serde(default, serialize_with = "serialize_timeout", deserialize_with = "timeout")]
+    pub timeout: Option<Duration>,
+    /// Tries per query instead of the run's (1–10)
+    #[serde(default, deserialize_with = "attempts")]
+    pub attempts: Option<usize>,
+    /// Pause between queries instead of the run's, in milliseconds or as e.g. `"50ms"`
+    #[serde(default, serialize_with = "serialize_cooldown", deserialize_with = "cooldown")]
+    pub cooldown: Option<Duration>,
+}
+
+fn valid_host(host: &str) -> Result<String, String> {
+    if host.is_empty() || host.parse::<IpAddr>().is_ok() || Name::from_ascii(host).is_err() {
+        return Err(format!("\"{}\" is not a hostname", host));
+    }
+    Ok(host.to_string())
+}
+
+fn host<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|host| valid_host(&host))
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+/// A hostname, or an https URL of it with the `/dns-query` path, the only one queried.
+fn doh_host<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let Some(value) = Option::<String>::deserialize(deserializer)? else { return Ok(None) };
+    let host = match value.strip_prefix("https://") {
+        Some(rest) => match rest.split_once('/') {
+            Some((host, "dns-query")) | Some((host, "dns-query/")) => host,
+            None => rest,
+            Some(_) => return Err(D::Error::custom(format!("{}: give a path other than /dns-query as doh_path", value))),
+        },
+        None if value.contains("://") => return Err(D::Error::custom(format!("{}: a DoH URL needs https://", value))),
+        None => &value,
+    };
+    valid_host(host).map(Some).map_err(D::Error::custom)
+}
+
+/// An absolute path; `/dns-query` is stored as no path.
+fn doh_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|path| valid_doh_path(&path))
+        .transpose()
+        .map_err(D::Error::custom)
+        .map(Option::flatten)
+}
+
+/// `path` if it can go in a DoH URL, `None` for the default `/dns-query`.
+pub fn valid_doh_path(path: &str) -> Result<Option<String>, String> {
+    if !path.starts_with('/') || path.contains(|c: char| c.is_whitespace() || c.is_control() || c == '#') {
+        return Err(format!("\"{}\" is not a URL path", path));
+    }
+    Ok(Some(path.to_string()).filter(|path| path != DEFAULT_DOH_PATH))
+}
+
+/// The path hickory queries, and the one built-in providers use.
+const DEFAULT_DOH_PATH: &str = "/dns-query";
+
+/// Port 53 is stored as no port, like everywhere else.
+fn port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    match Option::<u16>::deserialize(deserializer)? {
+        Some(0) => Err(D::Error::custom("port 0 is not a valid port")),
+        port => Ok(port.filter(|&port| port != 53)),
+    }
+}
+
+/// Port 853 is stored as no port, like 53 for plain DNS.
+fn doq_port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    match Option::<u16>::deserialize(deserializer)? {
+        Some(0) => Err(D::Error::custom("port 0 is not a valid port")),
+        port => Ok(port.filter(|&port| port != Transport::Quic.port())),
+    }
+}
+
+/// Most tries per query a provider can ask for, as with `--attempts`.
+pub const MAX_ATTEMPTS: usize = 10;
+
+/// A string such as "500ms", or a number of `unit`s.
+fn duration<'de, D: Deserializer<'de>>(deserializer: D, unit: Duration) -> Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(f64),
+        Text(String),
+    }
+    let duration = match Option::<Value>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Value::Number(n)) if n.is_finite() && n >= 0.0 => unit.mul_f64(n),
+        Some(Value::Number(n)) => return Err(D::Error::custom(format!("{} is not a duration", n))),
+        Some(Value::Text(text)) => humantime::parse_duration(text.trim())
+            .map_err(|_| D::Error::custom(format!("\"{}\" is not a duration (try \"500ms\")", text)))?,
+    };
+    Ok(Some(duration))
+}
+
+fn timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    match duration(deserializer, Duration::from_secs(1))? {
+        Some(timeout) if timeout.is_zero() => Err(D::Error::custom("timeout must be greater than zero")),
+        timeout => Ok(timeout),
+    }
+}
+
+fn serialize_timeout<S: Serializer>(timeout: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    timeout.map(|timeout| humantime::format_duration(timeout).to_string()).serialize(serializer)
+}
+
+fn cooldown<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    duration(deserializer, Duration::from_millis(1))
+}
+
+fn serialize_cooldown<S: Serializer>(cooldown: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    cooldown.map(|cooldown| cooldown.as_millis() as u64).serialize(serializer)
+}
+
+fn attempts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    match Option::<usize>::deserialize(deserializer)? {
+        Some(attempts) if !(1..=MAX_ATTEMPTS).contains(&attempts) => {
+            Err(D::Error::custom(format!("attempts must be between 1 and {}", MAX_ATTEMPTS)))
+        },
+        attempts => Ok(attempts),
+    }
+}
+
+impl DnsProvider {
+    /// Provider with a single plain-DNS address and no encrypted endpoints.
+    pub fn new(name: impl Into<String>, address: IpAddr) -> DnsProvider {
+        DnsProvider {
+            name: name.into(),
+            organization: None,
+            addresses: vec![address],
+            doh_host: None,
+            doh_path: None,
+            dot_host: None,
+            doq_host: None,
+            doq_port: None,
+            system: false,
+            port: None,
+            timeout: None,
+            attempts: None,
+            cooldown: None,
+        }
+    }
+
+    /// The organization the provider's rows are grouped under: `organization`, or the name
+    /// without its tags, so "Example (v4)" and "Example (v6)" go together.
+    pub fn organization(&self) -> &str {
+        self.organization.as_deref().unwrap_or_else(|| self.name.split(" (").next().unwrap_or(&self.name))
+    }
+
+    /// Whether the provider has a timeout, attempts or cooldown of its own.
+    pub fn has_overrides(&self) -> bool {
+        self.timeout.is_some() || self.attempts.is_some() || self.cooldown.is_some()
+    }
+
+    /// Port to query over `transport`: `port` for plain DNS, `doq_port` for DoQ, otherwise the
+    /// transport's standard port.
+    pub fn port_for(&self, transport: Transport) -> u16 {
+        match transport {
+            Transport::Udp | Transport::Tcp => self.port.unwrap_or(transport.port()),
+            Transport::Quic => self.doq_port.unwrap_or(transport.port()),
+            Transport::Tls | Transport::Https => transport.port(),
+        }
+    }
+
+    /// The path of the DoH endpoint.
+    pub fn doh_query_path(&self) -> &str {
+        self.doh_path.as_deref().unwrap_or(DEFAULT_DOH_PATH)
+    }
+
+    /// Hostname presented for TLS-based transports; `None` for plain DNS or when the provider
+    /// has no endpoint for the transport.
+    pub fn tls_host(&self, transport: Transport) -> Option<&str> {
+        match transport {
+            Transport::Udp | Transport::Tcp => None,
+            Transport::Tls => self.dot_host.as_deref(),
+            Transport::Https => self.doh_host.as_deref(),
+            Transport::Quic => self.doq_host.as_deref(),
+        }
+    }
+
+    /// Takes the preferred DoT and DoH endpoint of `designated` (see [`crate::discover_ddr`]) in
+    /// place of the known ones. Endpoints on other than the standard ports are left out, as
+    /// DoT and DoH are only tested on those. Returns whether any was taken.
+    pub fn use_designated(&mut self, designated: &[DdrEndpoint]) -> bool {
+        let mut taken = false;
+        for transport in [Transport::Tls, Transport::Https] {
+            let Some(endpoint) = designated.iter()
+                .find(|endpoint| endpoint.protocol.transport() == Some(transport) && endpoint.port == transport.port())
+            else { continue };
+            match transport {
+                Transport::Tls => self.dot_host = Some(endpoint.host.clone()),
+                _ => {
+                    self.doh_host = Some(endpoint.host.clone());
+                    self.doh_path = endpoint.doh_path.as_deref().and_then(|path| valid_doh_path(path).ok().flatten());
+                },
+            }
+            taken = true;
+        }
+        taken
+    }
+
+    /// Whether the provider can be queried over `transport`: plain DNS always, DoT, DoH and DoQ
+    /// only with a known endpoint.
+    pub fn supports(&self, transport: Transport) -> bool {
+        matches!(transport, Transport::Udp | Transport::Tcp) || self.tls_host(transport).is_some()
+    }
+}
+
+impl ProviderEntry {
+    /// Provider using this entry's addresses of one family, or `None` if it has none. The variant
+    /// and, with `label` set, the family are appended to the name, e.g. "Cloudflare (Family, v6)".
+    fn provider(&self, v6: bool, label: bool) -> Option<DnsProvider> {
+        let (primary, secondary) = if v6 {
+            (self.primary_v6?, self.secondary_v6)
+        } else {
+            (self.primary, self.secondary)
+        };
+        Some(DnsProvider {
+            name: {
+                let family = label.then_some(if v6 { "v6" } else { "v4" });
+                let tags: Vec<&str> = self.variant.into_iter().chain(family).collect();
+                if tags.is_empty() {
+                    self.name.to_string()
+                } else {
+                    format!("{} ({})", self.name, tags.join(", "))
+                }
+            },
+            organization: Some(self.name.to_string()),
+            addresses: std::iter::once(primary).chain(secondary).map(|ip| ip.parse().unwrap()).collect(),
+            doh_host: self.doh_host.map(String::from),
+            doh_path: None,
+            dot_host: self.dot_host.map(String::from),
+            doq_host: self.doq_host.map(String::from),
+            doq_port: None,
+            system: false,
+            port: None,
+            timeout: None,
+            attempts: None,
+            cooldown: None,
+        })
+    }
+}
+
+const DNS_PROVIDERS: &[ProviderEntry] = &[
+    ProviderEntry { name: "Google", variant: None, set: ProviderSet::Standard, primary: "8.8.8.8", secondary: Some("8.8.4.4"), primary_v6: Some("2001:4860:4860::8888"), secondary_v6: Some("2001:4860:4860::8844"), doh_host: Some("dns.google"), dot_host: Some("dns.google"), doq_host: None },
+    ProviderEntry { name: "Cloudflare", variant: None, set: ProviderSet::Standard, primary: "1.1.1.1", secondary: Some("1.0.0.1"), primary_v6: Some("2606:4700:4700::1111"), secondary_v6: Some("2606:4700:4700::1001"), doh_host: Some("cloudflare-dns.com"), dot_host: Some("one.one.one.one"), doq_host: None },
+    ProviderEntry { name: "Quad9", variant: None, set: ProviderSet::Standard, primary: "9.9.9.9", secondary: Some("149.112.112.112"), primary_v6: Some("2620:fe::fe"), secondary_v6: Some("2620:fe::9"), doh_host: Some("dns.quad9.net"), dot_host: Some("dns.quad9.net"), doq_host: None },
+    ProviderEntry { name: "OpenDNS", variant: None, set: ProviderSet::Standard, primary: "208.67.222.222", secondary: Some("208.67.220.220"), primary_v6: Some("2620:119:35::35"), secondary_v6: Some("2620:119:53::53"), doh_host: Some("doh.opendns.com"), dot_host: None, doq_host: None },
+    ProviderEntry { name: "AdGuard", variant: None, set: ProviderSet::Standard, primary: "94.140.14.14", secondary: Some("94.140.15.15"), primary_v6: Some("2a10:50c0::ad1:ff"), secondary_v6: Some("2a10:50c0::ad2:ff"), doh_host: Some("dns.adguard-dns.com"), dot_host: Some("dns.adguard-dns.com"), doq_host: Some("dns.adguard-dns.com") },
+    ProviderEntry { name: "Mullvad", variant: None, set: ProviderSet::Standard, primary: "194.242.2.2", secondary: None, primary_v6: Some("2a07:e340::2"), secondary_v6: None, doh_host: Some("dns.mullvad.net"), dot_host: Some("dns.mullvad.net"), doq_host: None },
+    ProviderEntry { name: "DNS0", variant: None, set: ProviderSet::Standard, primary: "193.110.81.0", secondary: Some("185.253.5.0"), primary_v6: Some("2a0f:fc80::"), secondary_v6: Some("2a0f:fc81::"), doh_host: Some("dns0.eu"), dot_host: Some("dns0.eu"), doq_host: Some("dns0.eu") },
+    ProviderEntry { name: "NextDNS", variant: None, set: ProviderSet::Standard, primary: "45.90.28.0", secondary: Some("45.90.30.0"), primary_v6: Some("2a07:a8c0::"), secondary_v6: Some("2a07:a8c1::"), doh_host: Some("dns.nextdns.io"), dot_host: Some("dns.nextdns.io"), doq_host: Some("dns.nextdns.io") },
+    ProviderEntry { name: "ControlD", variant: None, set: ProviderSet::Standard, primary: "76.76.2.0", secondary: Some("76.76.10.0"), primary_v6: Some("2606:1a40::"), secondary_v6: Some("2606:1a40:1::"), doh_host: None, dot_host: Some("p0.freedns.controld.com"), doq_host: Some("p0.freedns.controld.com") },
+    ProviderEntry { name: "Cloudflare", variant: Some("Malware"), set: ProviderSet::Filtered, primary: "1.1.1.2", secondary: Some("1.0.0.2"), primary_v6: Some("2606:4700:4700::1112"), secondary_v6: Some("2606:4700:4700::1002"), doh_host: Some("security.cloudflare-dns.com"), dot_host: Some("security.cloudflare-dns.com"), doq_host: None },
+    ProviderEntry { name: "Cloudflare", variant: Some("Family"), set: ProviderSet::Family, primary: "1.1.1.3", secondary: Some("1.0.0.3"), primary_v6: Some("2606:4700:4700::1113"), secondary_v6: Some("2606:4700:4700::1003"), doh_host: Some("family.cloudflare-dns.com"), dot_host: Some("family.cloudflare-dns.com"), doq_host: None },
+    ProviderEntry { name: "Quad9", variant: Some("ECS"), set: ProviderSet::Filtered, primary: "9.9.9.11", secondary: Some("149.112.112.11"), primary_v6: Some("2620:fe::11"), secondary_v6: Some("2620:fe::fe:11"), doh_host: Some("dns11.quad9.net"), dot_host: Some("dns11.quad9.net"), doq_host: None },
+    ProviderEntry { name: "OpenDNS", variant: Some("FamilyShield"), set: ProviderSet::Family, primary: "208.67.222.123", secondary: Some("208.67.220.123"), primary_v6: Some("2620:119:35::123"), secondary_v6: Some("2620:119:53::123"), doh_host: Some("doh.familyshield.opendns.com"), dot_host: None, doq_host: None },
+    ProviderEntry { name: "AdGuard", variant: Some("Family"), set: ProviderSet::Family, primary: "94.140.14.15", secondary: Some("94.140.15.16"), primary_v6: Some("2a10:50c0::bad1:ff"), secondary_v6: Some("2a10:50c0::bad2:ff"), doh_host: Some("family.adguard-dns.com"), dot_host: Some("family.adguard-dns.com"), doq_host: Some("family.adguard-dns.com") },
+    ProviderEntry { name: "Mullvad", variant: Some("Adblock"), set: ProviderSet::Filtered, primary: "194.242.2.3", secondary: None, primary_v6: Some("2a07:e340::3"), secondary_v6: None, doh_host: Some("adblock.dns.mullvad.net"), dot_host: Some("adblock.dns.mullvad.net"), doq_host: None },
+    ProviderEntry { name: "Mullvad", variant: Some("Family"), set: ProviderSet::Family, primary: "194.242.2.6", secondary: None, primary_v6: Some("2a07:e340::6"), secondary_v6: None, doh_host: Some("family.dns.mullvad.net"), dot_host: Some("family.dns.mullvad.net"), doq_host: None },
+    ProviderEntry { name: "DNS0", variant: Some("Zero"), set: ProviderSet::Filtered, primary: "193.110.81.9", secondary: Some("185.253.5.9"), primary_v6: Some("2a0f:fc80::9"), secondary_v6: Some("2a0f:fc81::9"), doh_host: Some("zero.dns0.eu"), dot_host: Some("zero.dns0.eu"), doq_host: Some("zero.dns0.eu") },
+    ProviderEntry { name: "DNS0", variant: Some("Kids"), set: ProviderSet::Family, primary: "193.110.81.1", secondary: Some("185.253.5.1"), primary_v6: Some("2a0f:fc80::1"), secondary_v6: Some("2a0f:fc81::1"), doh_host: Some("kids.dns0.eu"), dot_host: Some("kids.dns0.eu"), doq_host: Some("kids.dns0.eu") },
+    ProviderEntry { name: "ControlD", variant: Some("Malware"), set: ProviderSet::Filtered, primary: "76.76.2.1", secondary: Some("76.76.10.1"), primary_v6: Some("2606:1a40::1"), secondary_v6: Some("2606:1a40:1::1"), doh_host: None, dot_host: Some("p1.freedns.controld.com"), doq_host: Some("p1.freedns.controld.com") },
+    ProviderEntry { name: "ControlD", variant: Some("Family"), set: ProviderSet::Family, primary: "76.76.2.4", secondary: Some("76.76.10.4"), primary_v6: Some("2606:1a40::4"), secondary_v6: Some("2606:1a40:1::4"), doh_host: None, dot_host: Some("family.freedns.controld.com"), doq_host: Some("family.freedns.controld.com") },
+    // Regional resolvers, only tested through a --preset that names them.
+    ProviderEntry { name: "DNS4EU", variant: None, set: ProviderSet::Standard, primary: "86.54.11.100", secondary: Some("86.54.11.200"), primary_v6: Some("2a13:1001::86:54:11:100"), secondary_v6: Some("2a13:1001::86:54:11:200"), doh_host: Some("unfiltered.joindns4.eu"), dot_host: Some("unfiltered.joindns4.eu"), doq_host: None },
+    ProviderEntry { name: "DNS4EU", variant: Some("Protective"), set: ProviderSet::Filtered, primary: "86.54.11.1", secondary: Some("86.54.11.201"), primary_v6: Some("2a13:1001::86:54:11:1"), secondary_v6: Some("2a13:1001::86:54:11:201"), doh_host: Some("protective.joindns4.eu"), dot_host: Some("protective.joindns4.eu"), doq_host: None },
+    ProviderEntry { name: "DNS.SB", variant: None, set: ProviderSet::Standard, primary: "185.222.222.222", secondary: Some("45.11.45.11"), primary_v6: Some("2a09::"), secondary_v6: Some("2a11::"), doh_host: Some("doh.dns.sb"), dot_host: Some("dot.sb"), doq_host: None },
+    ProviderEntry { name: "Yandex", variant: None, set: ProviderSet::Standard, primary: "77.88.8.8", secondary: Some("77.88.8.1"), primary_v6: Some("2a02:6b8::feed:0ff"), secondary_v6: Some("2a02:6b8:0:1::feed:0ff"), doh_host: Some("common.dot.dns.yandex.net"), dot_host: Some("common.dot.dns.yandex.net"), doq_host: None },
+    ProviderEntry { name: "CIRA Shield", variant: None, set: ProviderSet::Standard, primary: "149.112.121.10", secondary: Some("149.112.122.10"), primary_v6: Some("2620:10a:80bb::10"), secondary_v6: Some("2620:10a:80bc::10"), doh_host: Some("private.canadianshield.cira.ca"), dot_host: Some("private.canadianshield.cira.ca"), doq_host: None },
+    ProviderEntry { name: "Level3", variant: None, set: ProviderSet::Standard, primary: "4.2.2.1", secondary: Some("4.2.2.2"), primary_v6: None, secondary_v6: None, doh_host: None, dot_host: None, doq_host: None },
+    ProviderEntry { name: "AliDNS", variant: None, set: ProviderSet::Standard, primary: "223.5.5.5", secondary: Some("223.6.6.6"), primary_v6: Some("2400:3200::1"), secondary_v6: Some("2400:3200:baba::1"), doh_host: Some("dns.alidns.com"), dot_host: Some("dns.alidns.com"), doq_host: None },
+    ProviderEntry { name: "DNSPod", variant: None, set: ProviderSet::Standard, primary: "119.29.29.29", secondary: None, primary_v6: Some("2402:4e00::"), secondary_v6: None, doh_host: Some("doh.pub"), dot_host: Some("dot.pub"), doq_host: None },
+    ProviderEntry { name: "114DNS", variant: None, set: ProviderSet::Standard, primary: "114.114.114.114", secondary: Some("114.114.115.115"), primary_v6: None, secondary_v6: None, doh_host: None, dot_host: None, doq_host: None },
+];
+
+/// A named selection of the built-in providers, by base name; `--set` still picks which of
+/// their variants are tested.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The built-in providers it tests by name, each with every variant of the chosen set
+    pub providers: &'static [&'static str],
+}
+
+impl Preset {
+    /// Whether the provider called `name` (without its variant) is part of this preset.
+    pub fn includes(&self, name: &str) -> bool {
+        self.providers.contains(&name)
+    }
+}
+
+/// The preset tested without --preset.
+pub const DEFAULT_PRESET: &str = "global";
+
+/// Every preset. Any provider named here needs an entry in `DNS_PROVIDERS`.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "global",
+        description: "Large anycast providers with resolvers on every continent",
+        providers: &["Google", "Cloudflare", "Quad9", "OpenDNS", "AdGuard", "Mullvad", "DNS0", "NextDNS", "ControlD"],
+    },
+    Preset {
+        name: "eu",
+        description: "Providers run from Europe, with the largest global ones to compare",
+        providers: &["DNS4EU", "DNS0", "Quad9", "Mullvad", "AdGuard", "DNS.SB", "Yandex", "Cloudflare", "Google"],
+    },
+    Preset {
+        name: "us",
+        description: "North American providers",
+        providers: &["Google", "Cloudflare", "Quad9", "OpenDNS", "NextDNS", "ControlD", "CIRA Shield", "Level3"],
+    },
+    Preset {
+        name: "asia",
+        description: "Providers with resolvers in mainland China and Russia, with the largest global ones to compare",
+        providers: &["AliDNS", "DNSPod", "114DNS", "Yandex", "Cloudflare", "Google", "Quad9"],
+    },
+    Preset {
+        name: "privacy",
+        description: "Providers that do not log queries or sell query data",
+        providers: &["Mullvad", "Quad9", "DNS0", "DNS.SB", "CIRA Shield", "Cloudflare"],
+    },
+];
+
+/// The preset called `name`, ignoring case.
+pub fn preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name.trim()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProviderSet {
+    /// The providers' regular resolvers
+    Standard,
+    /// Variants that block malware and/or ads
+    Filtered,
+    /// Variants that also block adult content
+    Family,
+    /// Every variant
+    All,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IpVersion {
+    #[value(name = "4")]
+    V4,
+    #[value(name = "6")]
+    V6,
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    #[value(name = "dot")]
+    Tls,
+    #[value(name = "doh")]
+    Https,
+    #[value(name = "doq")]
+    Quic,
+}
+
+impl Transport {
+    /// Every transport, from plain to encrypted.
+    pub const ALL: [Transport; 5] = [Transport::Udp, Transport::Tcp, Transport::Tls, Transport::Https, Transport::Quic];
+
+    pub fn port(self) -> u16 {
+        match self {
+            Transport::Udp | Transport::Tcp => 53,
+            Transport::Tls | Transport::Quic => 853,
+            Transport::Https => 443,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Transport::Udp => "UDP",
+            Transport::Tcp => "TCP",
+            Transport::Tls => "DoT",
+            Transport::Https => "DoH",
+            Transport::Quic => "DoQ",
+        }
+    }
+}
+
+/// The built-in providers of the default preset and `set`, once per address family of
+/// `ip_version`. With both families each name is tagged, e.g. "Cloudflare (v4)" and
+/// "Cloudflare (v6)".
+pub fn builtin_providers(set: ProviderSet, ip_version: IpVersion) -> Vec<DnsProvider> {
+    let default = preset(DEFAULT_PRESET).expect("the default preset exists");
+    preset_providers(default, set, ip_version)
+}
+
+/// Like [`builtin_providers`], with the providers of `preset`.
+pub fn preset_providers(preset: &Preset, set: ProviderSet, ip_version: IpVersion) -> Vec<DnsProvider> {
+    let families: &[bool] = match ip_version {
+        IpVersion::V4 => &[false],
+        IpVersion::V6 => &[true],
+        IpVersion::Both => &[false, true],
+    };
+    let label = families.len() > 1;
+    DNS_PROVIDERS.iter()
+        .filter(|entry| preset.includes(entry.name) && (set == ProviderSet::All || entry.set == set))
+        .flat_map(|entry| families.iter().filter_map(move |&v6| entry.provider(v6, label)))
+        .collect()
+}
+
+/// Every built-in provider with the set it belongs to and the addresses of both families, IPv4
+/// first.
+pub fn builtin_catalog() -> Vec<(ProviderSet, DnsProvider)> {
+    DNS_PROVIDERS.iter()
+        .filter_map(|entry| {
+            let mut provider = entry.provider(false, false)?;
+            if let Some(v6) = entry.provider(true, false) {
+                provider.addresses.extend(v6.addresses);
+            }
+            Some((entry.set, provider))
+        })
+        .collect()
+}
+
+/// The machine's configured nameservers, without duplicates: from resolv.conf on Unix and the
+/// network adapters' settings (GetAdaptersAddresses) on Windows. Empty if they cannot be read.
+pub fn system_nameservers() -> Vec<IpAddr> {
+    let Ok((config, _)) = read_system_conf() else {
+        return Vec::new();
+    };
+    let mut ips: Vec<IpAddr> = Vec::new();
+    for ip in config.name_servers().iter().map(|ns| ns.socket_addr.ip()) {
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    }
+    ips
+}
+
+/// Whether a nameserver at `ip` is on the machine or the local network, such as a router or a
+/// caching stub, which forwards to an upstream provider rather than being one.
+pub fn is_local_forwarder(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+                || ip.to_ipv4_mapped().is_some_and(|v4| is_local_forwarder(IpAddr::V4(v4)))
+        },
+    }
+}
+
+/// Whether a server at `ip` is on this machine or its link, such as a caching stub like
+/// systemd-resolved or dnsmasq, whose cached answers are not comparable with a provider's.
+pub fn is_local_cache(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xffc0) == 0xfe80
+            || ip.to_ipv4_mapped().is_some_and(|v4| is_local_cache(IpAddr::V4(v4))),
+    }
+}
+
+/// Marks the providers that are one of `nameservers`, and returns the nameservers that matched
+/// none. A built-in provider also matches through the addresses of its other family, so the
+/// IPv4 row of Cloudflare is marked when the system uses 2606:4700:4700::1111.
+pub fn mark_system_resolvers(providers: &mut [DnsProvider], nameservers: &[IpAddr]) -> Vec<IpAddr> {
+    let mut unmatched = Vec::new();
+    for &ip in nameservers {
+        let siblings: Vec<IpAddr> = DNS_PROVIDERS.iter()
+            .filter_map(|entry| {
+                let addresses: Vec<IpAddr> = [false, true].iter()
+                    .filter_map(|&v6| entry.provider(v6, false))
+                    .flat_map(|provider| provider.addresses)
+                    .collect();
+                addresses.contains(&ip).then_some(addresses)
+            })
+            .flatten()
+            .chain([ip])
+            .collect();
+        let mut matched = false;
+        for provider in providers.iter_mut().filter(|p| p.addresses.iter().any(|a| siblings.contains(a))) {
+            provider.system = true;
+            matched = true;
+        }
+        if !matched {
+            unmatched.push(ip);
+        }
+    }
+    unmatched
+}
+
+/// Adds `nameservers`, the machine's, to `providers`. Nameservers that are already in the list
+/// are only marked as the system's rather than tested twice.
+pub fn add_system_resolvers(providers: &mut Vec<DnsProvider>, nameservers: &[IpAddr]) {
+    for ip in mark_system_resolvers(providers, nameservers) {
+        providers.push(DnsProvider {
+            name: if ip.to_string() == SYSTEMD_RESOLVED_STUB {
+                format!("System ({}, systemd-resolved stub)", ip)
+            } else {
+                format!("System ({})", ip)
+            },
+            system: true,
+            ..DnsProvider::new("", ip)
+        });
+    }
+}
@@ -0,0 +1,107 @@
+//! --providers-file: a curated list of resolvers, in TOML or (with a .json extension) JSON, that
+//! extends or replaces the built-in providers.
+//!
+//! ```toml
+//! mode = "replace"  # or "extend", the default
+//!
+//! [[providers]]
+//! name = "My ISP"
+//! addresses = ["203.0.113.53", "2001:db8::53"]
+//! dot_host = "dns.isp.example"
+//! doh_url = "https://dns.isp.example/dns-query"
+//! # doh_path = "/my-profile"  # when the endpoint is not at /dns-query
+//! doq_host = "dns.isp.example"
+//! # doq_port = 8853  # when the DoQ endpoint is not on 853
+//! port = 5353  # plain DNS only
+//! timeout = "500ms"  # these three replace the run's settings for this provider
+//! attempts = 2
+//! cooldown = 20  # ms
+//! ```
+//!
+//! Each entry is read as a [`DnsProvider`], which also accepts its own serialized form.
+
+use std::path::Path;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::DnsProvider;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvidersMode {
+    /// Test the file's providers along with the built-in ones
+    #[default]
+    Extend,
+    /// Test the file's providers instead of the built-in ones
+    Replace,
+}
+
+pub struct ProvidersFile {
+    /// `None` when the file does not say
+    pub mode: Option<ProvidersMode>,
+    pub providers: Vec<DnsProvider>,
+}
+
+/// Reads `path`. Errors name the file and, for a bad entry, its number and name.
+pub(crate) fn load(path: &Path) -> Result<ProvidersFile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    // Both formats become a JSON value first, so the entries can be checked one at a time.
+    let value: Value = if json {
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+    } else {
+        toml::from_str(&text).map_err(|e| describe_toml_error(path, &text, &e))?
+    };
+    let Value::Object(mut top) = value else {
+        return Err(format!("{}: expected a table with `mode` and `providers`", path.display()));
+    };
+    if let Some(key) = top.keys().find(|key| !matches!(key.as_str(), "mode" | "providers")) {
+        return Err(format!("{}: unknown key `{}`, expected `mode` or `providers`", path.display(), key));
+    }
+    let mode = top.remove("mode")
+        .map(ProvidersMode::deserialize)
+        .transpose()
+        .map_err(|e| format!("{}, `mode`: {}", path.display(), e))?;
+    let entries = match top.remove("providers") {
+        Some(Value::Array(entries)) if !entries.is_empty() => entries,
+        _ => return Err(format!("{}: no providers; add at least one [[providers]] entry", path.display())),
+    };
+
+    let mut providers: Vec<DnsProvider> = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let name = entry.get("name").and_then(Value::as_str).map(|name| format!(" (\"{}\")", name)).unwrap_or_default();
+        let at = |message: String| format!("{}, provider {}{}: {}", path.display(), index + 1, name, message);
+        let provider = DnsProvider::deserialize(entry).map_err(|e| at(e.to_string()))?;
+        if provider.name.trim().is_empty() {
+            return Err(at("the name is empty".to_string()));
+        }
+        if provider.addresses.is_empty() {
+            return Err(at("at least one address is needed".to_string()));
+        }
+        if providers.iter().any(|other| other.name == provider.name) {
+            return Err(at("another provider already has this name".to_string()));
+        }
+        providers.push(provider);
+    }
+    Ok(ProvidersFile { mode, providers })
+}
+
+/// "config.toml, line 3, `rounds`: rounds must be at least 1", instead of toml's multi-line
+/// report.
+pub fn describe_toml_error(path: &Path, text: &str, error: &toml::de::Error) -> String {
+    let Some(span) = error.span() else {
+        return format!("{}: {}", path.display(), error.message());
+    };
+    let line = text[..span.start.min(text.len())].matches('\n').count() + 1;
+    let key = text.lines().nth(line - 1)
+        .and_then(|line| line.split_once('='))
+        .map(|(key, _)| key.trim())
+        .filter(|key| !key.is_empty() && !key.starts_with(['#', '[']));
+    match key {
+        // Unknown-field messages already name the key.
+        Some(key) if !error.message().contains(&format!("`{}`", key)) => {
+            format!("{}, line {}, `{}`: {}", path.display(), line, key, error.message())
+        },
+        _ => format!("{}, line {}: {}", path.display(), line, error.message()),
+    }
+}
@@ -0,0 +1,27 @@
+//! DNS-over-QUIC (RFC 9250) before the rounds: one QUIC handshake, with the certificate checked
+//! for the endpoint's hostname and the "doq" ALPN, stands in for the TCP connects of the other
+//! transports, which a DoQ server on UDP port 853 does not answer. The queries themselves go
+//! through hickory like every other transport's.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use hickory_resolver::proto::quic::QuicClientStream;
+use hickory_resolver::proto::xfer::DnsRequestSender;
+use crate::raw;
+
+/// How long a QUIC handshake with `server` for `host` took, from `bind` if given, or why it
+/// failed within `timeout`. The connection is closed again right after.
+pub(crate) async fn handshake(server: SocketAddr, host: &str, bind: Option<IpAddr>, timeout: Duration) -> Result<Duration, String> {
+    let mut builder = QuicClientStream::builder();
+    builder.bind_addr(raw::local_address(server.ip(), bind));
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, builder.build(server, host.to_string())).await {
+        Ok(Ok(mut stream)) => {
+            let elapsed = start.elapsed();
+            stream.shutdown();
+            Ok(elapsed)
+        },
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("timed out after {}", humantime::format_duration(timeout))),
+    }
+}
@@ -0,0 +1,81 @@
+//! Hand-built queries for what the resolver API cannot express, such as EDNS options or the
+//! CHAOS class. They go straight to the server over UDP, without retries or caching.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use hickory_resolver::proto::op::{Message, Query};
+use hickory_resolver::proto::rr::{DNSClass, RData, RecordType};
+use hickory_resolver::Name;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, UdpSocket};
+
+/// A query for `name` with a random ID, recursion desired and nothing else set.
+pub(crate) fn message(name: Name, record_type: RecordType, class: DNSClass) -> Message {
+    let mut query = Query::query(name, record_type);
+    query.set_query_class(class);
+    let mut message = Message::new();
+    message.set_id(rand::random())
+        .set_recursion_desired(true)
+        .add_query(query);
+    message
+}
+
+/// Where queries to `ip` are sent from: `bind` if set, else any address of `ip`'s family, with
+/// a port picked by the OS.
+pub(crate) fn local_address(ip: IpAddr, bind: Option<IpAddr>) -> SocketAddr {
+    let any = if ip.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+    SocketAddr::new(bind.unwrap_or(any), 0)
+}
+
+/// Sends `message` to `ip`:`port` from `bind` and waits up to `timeout` for the response with
+/// its ID.
+pub(crate) async fn query(ip: IpAddr, port: u16, bind: Option<IpAddr>, message: &Message, timeout: Duration) -> Option<Message> {
+    query_sized(ip, port, bind, message, timeout).await.map(|(response, _)| response)
+}
+
+/// [`query`], also returning the size of the response in bytes.
+pub(crate) async fn query_sized(ip: IpAddr, port: u16, bind: Option<IpAddr>, message: &Message, timeout: Duration) -> Option<(Message, usize)> {
+    let server = SocketAddr::new(ip, port);
+    let socket = UdpSocket::bind(local_address(ip, bind)).await.ok()?;
+    socket.send_to(&message.to_vec().ok()?, server).await.ok()?;
+
+    let mut buf = vec![0u8; 4096];
+    let len = tokio::time::timeout(timeout, socket.recv(&mut buf)).await.ok()?.ok()?;
+    Message::from_vec(&buf[..len]).ok()
+        .filter(|response| response.id() == message.id())
+        .map(|response| (response, len))
+}
+
+/// [`query_sized`] over TCP, where responses are never truncated.
+pub(crate) async fn query_tcp(ip: IpAddr, port: u16, bind: Option<IpAddr>, message: &Message, timeout: Duration) -> Option<(Message, usize)> {
+    let socket = if ip.is_ipv4() { TcpSocket::new_v4() } else { TcpSocket::new_v6() }.ok()?;
+    socket.bind(local_address(ip, bind)).ok()?;
+    let bytes = message.to_vec().ok()?;
+    let exchange = async {
+        let mut stream = socket.connect(SocketAddr::new(ip, port)).await.ok()?;
+        // Over TCP every message is preceded by its length.
+        let mut framed = u16::try_from(bytes.len()).ok()?.to_be_bytes().to_vec();
+        framed.extend_from_slice(&bytes);
+        stream.write_all(&framed).await.ok()?;
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await.ok()?;
+        let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        stream.read_exact(&mut buf).await.ok()?;
+        Message::from_vec(&buf).ok()
+            .filter(|response| response.id() == message.id())
+            .map(|response| (response, buf.len()))
+    };
+    tokio::time::timeout(timeout, exchange).await.ok()?
+}
+
+/// The first TXT string in the answer to a TXT query for `name` in `class`.
+pub(crate) async fn txt(ip: IpAddr, port: u16, bind: Option<IpAddr>, name: &str, class: DNSClass, timeout: Duration) -> Option<String> {
+    let message = message(Name::from_ascii(name).ok()?, RecordType::TXT, class);
+    let response = query(ip, port, bind, &message, timeout).await?;
+    response.answers().iter()
+        .find_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => txt.txt_data().first().map(|data| String::from_utf8_lossy(data).into_owned()),
+            _ => None,
+        })
+        .filter(|text| !text.trim().is_empty())
+}
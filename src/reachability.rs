@@ -0,0 +1,104 @@
+//! --verify-answers: whether the addresses a provider answers with lead anywhere from this
+//! network. A filtering resolver may return AAAA records for block pages it only serves over
+//! IPv4, say, which resolve fast and connect nowhere.
+//!
+//! Per provider, one public address per domain and family of its answers gets a TCP connect to
+//! port 443. A refused connection counts as reachable: the network path worked. Each address is
+//! tried once however many providers returned it, and the connects are paced, so the sites see
+//! a handful of connections rather than one per provider.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use futures::stream::{self, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpSocket;
+use crate::pacing::Pacer;
+use crate::results::{classify_address, AnswerReachability, TestResult, UnreachableAnswer};
+use crate::SpeedTestConfig;
+
+const HTTPS_PORT: u16 = 443;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// New connections per second across the whole check
+const CONNECTS_PER_SECOND: f64 = 10.0;
+/// Connections open at a time
+const CONNECTS_IN_FLIGHT: usize = 8;
+
+/// Sets `answer_reachability` of every result with answers.
+pub(crate) async fn verify_answers(results: &mut [TestResult], cfg: &SpeedTestConfig) {
+    let samples: Vec<Vec<(String, IpAddr)>> = results.iter().map(|result| sample(result, cfg.bind)).collect();
+    let mut addresses: Vec<IpAddr> = samples.iter().flatten().map(|&(_, ip)| ip).collect();
+    addresses.sort();
+    addresses.dedup();
+
+    let pacer = Pacer::per_second(CONNECTS_PER_SECOND);
+    let connects: Vec<_> = addresses.into_iter()
+        .map(|ip| {
+            let pacer = &pacer;
+            async move {
+                if cfg.cancel.is_cancelled() {
+                    return None;
+                }
+                pacer.wait().await;
+                Some((ip, reachable(ip, cfg.bind).await))
+            }
+        })
+        .collect();
+    let outcomes: HashMap<IpAddr, bool> = stream::iter(connects)
+        .buffer_unordered(CONNECTS_IN_FLIGHT)
+        .filter_map(|outcome| async move { outcome })
+        .collect()
+        .await;
+    if cfg.cancel.is_cancelled() {
+        return;
+    }
+
+    for (result, sample) in results.iter_mut().zip(samples) {
+        if sample.is_empty() {
+            continue;
+        }
+        let mut found = AnswerReachability { checked: sample.len() as u32, ..Default::default() };
+        for (domain, address) in sample {
+            if outcomes.get(&address).copied().unwrap_or(true) {
+                found.reachable += 1;
+            } else {
+                found.unreachable.push(UnreachableAnswer { domain, address });
+            }
+        }
+        result.answer_reachability = Some(found);
+    }
+}
+
+/// The first public address of each family in every domain's answers, in domain order. With a
+/// source address, only those of its family.
+fn sample(result: &TestResult, bind: Option<IpAddr>) -> Vec<(String, IpAddr)> {
+    let mut sample = Vec::new();
+    for (domain, addresses) in &result.answers {
+        for v6 in [false, true] {
+            let first = addresses.iter()
+                .filter(|ip| ip.is_ipv6() == v6 && bind.is_none_or(|bind| bind.is_ipv6() == v6))
+                .find(|&&ip| classify_address(ip).is_none());
+            sample.extend(first.map(|&ip| (domain.clone(), ip)));
+        }
+    }
+    sample
+}
+
+/// Whether port 443 of `ip` accepts or refuses a connection within the timeout.
+async fn reachable(ip: IpAddr, bind: Option<IpAddr>) -> bool {
+    let Ok(socket) = (if ip.is_ipv4() { TcpSocket::new_v4() } else { TcpSocket::new_v6() }) else { return false };
+    if let Some(bind) = bind {
+        if socket.bind(SocketAddr::new(bind, 0)).is_err() {
+            return false;
+        }
+    }
+    match tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(SocketAddr::new(ip, HTTPS_PORT))).await {
+        Ok(Ok(mut stream)) => {
+            let _ = stream.shutdown().await;
+            true
+        },
+        Ok(Err(e)) => matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset),
+        Err(_) => false,
+    }
+}
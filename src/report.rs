@@ -0,0 +1,437 @@
+//! The layout of the JSON output: the serde model every JSON report, --dry-run plan and
+//! --quiet summary is written from, and the example document --schema prints, which is built
+//! from the same types so it cannot drift from what a run writes.
+//!
+//! `schema_version` is the contract for tools reading the output. Renaming or removing a field,
+//! or changing its type or unit, bumps [`SCHEMA_VERSION`]; a new field does not, so readers
+//! should ignore fields they do not know. tests/snapshots has the fields of every version's
+//! example with their types, which tests/schema.rs holds the example to.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::Duration;
+use hickory_resolver::proto::rr::RecordType;
+use serde::Serialize;
+use dns_speed_test::{
+    stats::ScoreWeights, group_by_organization, AddressRange, AnswerReachability, AnswerStability, AnswerUsability, AnyHandling, BlockOutcome, BlockingResult, ConnectionSplit, BlockingSample, BogonAnswer, BufferComparison, BufferRun,
+    CacheComparison, DdrEndpoint, DdrProtocol, Dns64, Dns64Status, DnssecStatus, DomainStats, EdnsComparison, Egress, FailoverResult, FailureKind, InterceptionCheck, NearTimeout, PacketLoss, ProviderGroup, QueryFailure,
+    ResponseSizes, RttMethod, RunSpread, Sample, SampleStatus, SaturationLevel, SaturationResult, SocketComparison, TestResult, Timing, TransportComparison, Trimming, TruncationLegs,
+    TtlRange, UnreachableAnswer, UnstableAnswer,
+};
+
+/// Layout version of the JSON output, written as `schema_version`. New fields keep the version;
+/// it only goes up for changes that older readers could not simply ignore. Version 2 has `null`
+/// rather than the timeout for the latencies of a provider without answers.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A provider's latency next to the best provider's, for the "vs Fastest" column and the JSON
+/// `relative_to_fastest`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct RelativeToFastest {
+    /// The latency compared, see `relative_metric`
+    pub metric: &'static str,
+    /// Milliseconds slower than the best provider, negative when faster (possible when the
+    /// ranking is not by latency)
+    pub ms: f64,
+    /// The same in percent of the best provider's value; `None` when that is zero
+    pub percent: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct JsonReport<'a> {
+    pub schema_version: u32,
+    pub version: &'static str,
+    /// Seconds since the Unix epoch at which the report was written
+    pub timestamp: u64,
+    pub config: JsonConfig<'a>,
+    /// Only present, as `true`, when the run was interrupted
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Seconds from the end of the setup to the results, to set against
+    /// `config.estimated_runtime_s`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_s: Option<f64>,
+    /// Seconds the setup before the rounds took: downloads, lookups of the tool's own and checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_s: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interception: Option<&'a InterceptionCheck>,
+    /// Whether queries to any provider address leave through a VPN tunnel, as far as the tunnel
+    /// interfaces and the kernel's choice of source address tell
+    pub vpn_detected: bool,
+    pub results: Vec<JsonProvider<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<JsonProvider<'a>>,
+    /// With --group-by provider, one summary per organization of the rows above, excluded ones
+    /// included
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ProviderGroup>,
+}
+
+/// The effective configuration of a run, after the config file, the environment and the flags:
+/// in the metadata of the JSON report and what --dry-run prints.
+#[derive(Serialize)]
+pub struct JsonConfig<'a> {
+    pub rounds: u32,
+    /// Measured queries per provider and protocol: rounds × domains × record types
+    pub total_queries: u32,
+    pub warmup: u32,
+    pub timeout_ms: f64,
+    pub attempts: u64,
+    pub edns: &'static str,
+    /// Bytes advertised with EDNS; left out without it and when comparing sizes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edns_buffer: Option<u16>,
+    pub retries: u32,
+    pub cooldown_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qps: Option<f64>,
+    pub final_retry: bool,
+    pub cache_flushed: bool,
+    pub preset: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u32>,
+    pub protocol: &'static str,
+    pub domains: &'a [String],
+    /// `domains` in Unicode, when any is internationalized; `domains` are the queried forms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domains_display: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domains_source: Option<&'a str>,
+    /// Test domains left out before the rounds because they resolved neither through the
+    /// system resolver nor at the first provider; no result counts them
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub unresolvable_domains: &'a [String],
+    pub shuffle_seed: Option<u64>,
+    /// The providers in the order they were tested, which --no-shuffle-providers keeps as
+    /// configured
+    pub provider_order: Vec<&'a str>,
+    pub record_types: Vec<String>,
+    pub recency_weight: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub penalize_failures: Option<f64>,
+    /// The --adaptive precision, e.g. "±10%"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<String>,
+    pub score_weights: ScoreWeights,
+    pub parallel_queries: u64,
+    pub cache_bust: bool,
+    /// "reuse", "fresh" or "compare"
+    pub socket_mode: &'static str,
+    pub min_success: f64,
+    /// What the settings came to for the providers with their own
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub provider_settings: Vec<JsonProviderSettings<'a>>,
+    pub output: JsonOutput<'a>,
+    pub profile: &'static str,
+    /// The header's estimate of how long the rounds take
+    pub estimated_runtime_s: f64,
+    /// The providers as they are tested
+    pub providers: Vec<JsonPlannedProvider<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonPlannedProvider<'a> {
+    pub name: &'a str,
+    /// Primary first; empty in a dry run for a server given by hostname, which a run resolves first
+    pub addresses: &'a [IpAddr],
+    /// One per protocol it is tested over
+    pub endpoints: Vec<JsonEndpoint<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonEndpoint<'a> {
+    pub protocol: &'static str,
+    pub port: u16,
+    /// The TLS name, for DoT and DoH
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<&'a str>,
+    /// The DoH path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<&'a str>,
+}
+
+/// Where the report goes.
+#[derive(Serialize)]
+pub struct JsonOutput<'a> {
+    /// "table", "json", "csv", "summary" or "json-summary"
+    pub report: &'static str,
+    /// The --output file; stdout without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<&'a Path>,
+}
+
+#[derive(Serialize)]
+pub struct JsonProviderSettings<'a> {
+    pub provider: &'a str,
+    pub timeout_ms: f64,
+    pub attempts: usize,
+    pub cooldown_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct JsonProvider<'a> {
+    #[serde(flatten)]
+    pub result: &'a TestResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_to_fastest: Option<RelativeToFastest>,
+    /// With --failover, the median with the primary address dropping every query, as
+    /// `failover.failover_median_ms` has it; next to `median_ms` for comparison
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_ms: Option<f64>,
+    /// With --with-history, the average success rate of the provider's runs of the last 7 and
+    /// 30 days in --db, this one not included; left out without enough runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_rate_7d: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub success_rate_30d: Option<f64>,
+    /// With --split-by-egress, the way out its queries take; left out where it is not known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub egress: Option<&'a Egress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<JsonSample<'a>>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonSample<'a> {
+    pub provider: &'a str,
+    #[serde(flatten)]
+    pub sample: &'a Sample,
+}
+
+#[derive(Serialize)]
+pub struct JsonSummary<'a> {
+    pub fastest: &'a str,
+    pub ip: Option<&'a str>,
+    pub median_ms: f64,
+    pub success: f64,
+}
+
+/// What --dry-run prints with a JSON report.
+#[derive(Serialize)]
+pub struct JsonPlan<'a> {
+    pub schema_version: u32,
+    pub version: &'static str,
+    pub dry_run: bool,
+    pub config: JsonConfig<'a>,
+}
+
+/// A report with every field present, as --schema prints it: one provider with each part of a
+/// result filled in, its raw samples and an interrupted run's metadata. The values are made up
+/// but plausible; which fields a real run leaves out is documented on the fields.
+pub fn example() -> serde_json::Result<String> {
+    let ms = Duration::from_millis;
+    let domains = vec!["example.com".to_string(), "example.org".to_string()];
+    let unresolvable = vec!["expired.example".to_string()];
+    let addresses = [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 54))];
+    let answer = IpAddr::V4(Ipv4Addr::new(93, 184, 215, 14));
+    let ttl = TtlRange { min: 60, max: 300 };
+    let samples: Vec<Sample> = (0..2)
+        .map(|round| Sample {
+            domain: domains[0].clone(),
+            record_type: RecordType::A,
+            round,
+            duration: Some(ms(12 + u64::from(round))),
+            status: SampleStatus::Ok,
+            error: None,
+            ttl: Some(ttl),
+            cnames: Some(1),
+        })
+        .chain([Sample {
+            domain: domains[1].clone(),
+            record_type: RecordType::A,
+            round: 0,
+            duration: None,
+            status: SampleStatus::Failed,
+            error: Some(FailureKind::Timeout),
+            ttl: None,
+            cnames: None,
+        }])
+        .collect();
+    let egress = Egress { source: IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2)), interface: Some("wg0".to_string()), tunnel: Some("WireGuard") };
+    let interception = InterceptionCheck { intercepted: true, bogus_server_answered: true, shared_egress: Some("198.51.100.7".to_string()) };
+    let result = TestResult {
+        provider: "Example DNS".to_string(),
+        organization: "Example".to_string(),
+        addresses: addresses.iter().map(IpAddr::to_string).collect(),
+        system_resolver: true,
+        avg_duration: Some(ms(13)),
+        min_latency: Some(ms(12)),
+        max_latency: Some(ms(14)),
+        success_rate: 75.0,
+        usable_answers: Some(AnswerUsability::new(4, 3)),
+        unreachable: true,
+        budget_exceeded: true,
+        completed_queries: 4,
+        early_stop: true,
+        retries_used: 1,
+        resent_answers: 1,
+        truncated_retries: 1,
+        truncated_legs: Some(TruncationLegs { udp_median: ms(11), tcp_median: ms(20) }),
+        packet_loss: Some(PacketLoss::new(5, 1)),
+        failed_domains: vec![domains[1].clone()],
+        recovered_domains: vec![domains[1].clone()],
+        failure_kinds: BTreeMap::from([(FailureKind::Timeout, 1)]),
+        failures: vec![QueryFailure { domain: domains[1].clone(), kind: FailureKind::Timeout }],
+        failed_durations: vec![ms(2000)],
+        failure_wait: Some(ms(2000)),
+        median_duration: Some(ms(13)),
+        near_timeout: Some(NearTimeout { answered: 3, over_half: 1, over_90_percent: 0 }),
+        timeout: Duration::from_secs(2),
+        optimistic_median: Some(ms(12)),
+        queries_per_second: Some(75.0),
+        timing: Timing { wall: ms(900), queries: ms(40), tcp_check: ms(30), cooldown: ms(500) },
+        score: Some(14.2),
+        latency_samples: 3,
+        p95: Some(ms(14)),
+        p99: Some(ms(14)),
+        std_dev: Some(ms(1)),
+        jitter: Some(ms(1)),
+        round_medians: vec![Some(ms(12)), None],
+        round_trend: Some(8.3),
+        avg_cnames: Some(1.0),
+        max_cnames: Some(1),
+        cold_median: Some(ms(12)),
+        warm_median: Some(ms(13)),
+        transport: "UDP".to_string(),
+        first_query: Some(ms(12)),
+        failover: Some(FailoverResult { secondary: addresses[1], healthy_median: Some(ms(13)), failover_median: Some(ms(2013)) }),
+        qname_minimization: Some(true),
+        dns_cookies: Some(true),
+        response_sizes: Some(ResponseSizes { responses: 2, avg_bytes: 120.5, max_bytes: 152, truncated: 1 }),
+        dnssec: Some(DnssecStatus::Validating),
+        dns64: Some(Dns64 { status: Dns64Status::Yes, prefix: Some("64:ff9b::/96".to_string()), synthesized: vec!["github.com".to_string()] }),
+        blocking: Some(BlockingResult {
+            blocked_percent: Some(100.0),
+            outcomes: vec![BlockingSample { domain: "ads.example".to_string(), outcome: BlockOutcome::Blocked }],
+        }),
+        nxdomain_hijacking: Some(false),
+        nxdomain_median: Some(ms(15)),
+        nxdomain_probe_failures: BTreeMap::from([(FailureKind::Answered, 1)]),
+        https_success_rate: Some(100.0),
+        https_median: Some(ms(14)),
+        https_failures: BTreeMap::from([(FailureKind::Servfail, 1)]),
+        pop: Some("ams1".to_string()),
+        resolver_identity: Some("198.51.100.7".to_string()),
+        verified: Some(true),
+        ddr: Some(vec![DdrEndpoint {
+            protocol: DdrProtocol::Doh,
+            priority: 1,
+            host: "dns.example".to_string(),
+            port: 443,
+            doh_path: Some("/dns-query".to_string()),
+            addresses: vec![addresses[0]],
+        }]),
+        answers: BTreeMap::from([(domains[0].clone(), BTreeSet::from([answer]))]),
+        any_handling: BTreeMap::from([(AnyHandling::Minimal, 1)]),
+        answer_stability: Some(AnswerStability {
+            queries: 1,
+            stable: 0,
+            percent: 0.0,
+            unstable: vec![UnstableAnswer { domain: domains[0].clone(), changes: 1, compared: 1 }],
+        }),
+        bogon_answers: vec![BogonAnswer { domain: domains[0].clone(), address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), range: AddressRange::Private }],
+        suspicious_answers: vec![format!("{}: addresses no other provider returned", domains[0])],
+        answer_reachability: Some(AnswerReachability {
+            checked: 2,
+            reachable: 1,
+            unreachable: vec![UnreachableAnswer { domain: domains[0].clone(), address: answer }],
+        }),
+        slow_domains: vec![domains[1].clone()],
+        error_texts: vec![format!("{}: request timed out", domains[1])],
+        transport_comparison: Some(TransportComparison { doh_median: Some(ms(25)), doh_success_rate: 100.0 }),
+        cache_comparison: Some(CacheComparison { uncached_median: Some(ms(40)), uncached_success_rate: 100.0 }),
+        socket_comparison: Some(SocketComparison { fresh_median: Some(ms(14)), fresh_success_rate: 100.0 }),
+        tcp_connect: Some(ms(10)),
+        quic_handshake: None,
+        connection_split: Some(ConnectionSplit {
+            tcp: Some(ms(10)),
+            tls: Some(ms(21)),
+            first_query: Some(ms(14)),
+            steady_median: Some(ms(12)),
+            error: None,
+        }),
+        network_rtt: Some(ms(9)),
+        network_rtt_method: Some(RttMethod::Icmp),
+        ttl: Some(ttl),
+        edns: true,
+        edns_buffer: Some(1232),
+        edns_comparison: Some(EdnsComparison { without_edns_median: Some(ms(12)), without_edns_success_rate: 100.0 }),
+        buffer_comparison: Some(BufferComparison {
+            provider: "Example DNS".to_string(),
+            runs: vec![BufferRun { edns_buffer: Some(1232), median: Some(ms(13)), success_rate: 75.0, truncated_retries: 1, failed_queries: 1 }],
+        }),
+        saturation: Some(SaturationResult {
+            levels: vec![SaturationLevel { concurrency: 8, median: Some(ms(15)), error_rate: 0.0 }],
+            breakdown: Some(64),
+        }),
+        trimming: Some(Trimming { samples: 3, kept: 3 }),
+        runs: Some(RunSpread { runs: 2, run_medians: vec![Some(ms(12)), None], std_dev: Some(ms(1)) }),
+        per_domain: vec![DomainStats { domain: domains[0].clone(), median: Some(ms(12)), failures: 0, queries: 2, ttl: Some(ttl), cnames: Some(1) }],
+        samples,
+    };
+    let report = JsonReport {
+        schema_version: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        timestamp: 1_714_521_600,
+        config: JsonConfig {
+            rounds: 2,
+            total_queries: 4,
+            warmup: 1,
+            timeout_ms: 2000.0,
+            attempts: 1,
+            edns: "on",
+            edns_buffer: Some(1232),
+            retries: 1,
+            cooldown_ms: 100,
+            qps: Some(50.0),
+            final_retry: true,
+            cache_flushed: true,
+            preset: "default",
+            repeat: Some(2),
+            protocol: "UDP",
+            domains: &domains,
+            domains_display: Some(domains.clone()),
+            domains_source: Some("Tranco top 2"),
+            unresolvable_domains: &unresolvable,
+            shuffle_seed: Some(42),
+            provider_order: vec![&result.provider],
+            record_types: vec!["A".to_string()],
+            recency_weight: 1.0,
+            penalize_failures: Some(2.0),
+            adaptive: Some("±10%".to_string()),
+            score_weights: ScoreWeights::default(),
+            parallel_queries: 1,
+            cache_bust: false,
+            socket_mode: "compare",
+            min_success: 50.0,
+            provider_settings: vec![JsonProviderSettings { provider: &result.provider, timeout_ms: 2000.0, attempts: 1, cooldown_ms: 100.0 }],
+            output: JsonOutput { report: "json", file: Some(Path::new("report.json")) },
+            profile: "default",
+            estimated_runtime_s: 1.2,
+            providers: vec![JsonPlannedProvider {
+                name: &result.provider,
+                addresses: &addresses,
+                endpoints: vec![JsonEndpoint { protocol: "DoH", port: 443, host: Some("dns.example"), path: Some("/dns-query") }],
+            }],
+        },
+        partial: true,
+        elapsed_s: Some(1.4),
+        setup_s: Some(0.3),
+        interception: Some(&interception),
+        vpn_detected: true,
+        results: vec![JsonProvider {
+            result: &result,
+            relative_to_fastest: Some(RelativeToFastest { metric: "median", ms: 0.0, percent: Some(0.0) }),
+            failover_ms: Some(2013.0),
+            success_rate_7d: Some(100.0),
+            success_rate_30d: Some(99.8),
+            egress: Some(&egress),
+            samples: Some(result.samples.iter().map(|sample| JsonSample { provider: &result.provider, sample }).collect()),
+        }],
+        excluded: vec![JsonProvider { result: &result, relative_to_fastest: None, failover_ms: None, success_rate_7d: None, success_rate_30d: None, egress: None, samples: None }],
+        groups: group_by_organization([&result]),
+    };
+    serde_json::to_string_pretty(&report)
+}
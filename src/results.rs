@@ -0,0 +1,1064 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::Duration;
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::error::ProtoErrorKind;
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::proto::rr::RecordType;
+use serde::{Serialize, Serializer};
+use crate::ddr::DdrEndpoint;
+use crate::providers::Transport;
+
+/// Everything measured for one address of a provider. Latencies of failed queries are not
+/// included; a provider without a single answer has none (`None`, `null` in JSON).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TestResult {
+    pub provider: String,
+    /// Who runs the provider, the same for each of its variants, families and addresses
+    pub organization: String,
+    pub addresses: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub system_resolver: bool,
+    #[serde(rename = "avg_ms", serialize_with = "serialize_opt_ms")]
+    pub avg_duration: Option<Duration>,
+    #[serde(rename = "min_ms", serialize_with = "serialize_opt_ms")]
+    pub min_latency: Option<Duration>,
+    #[serde(rename = "max_ms", serialize_with = "serialize_opt_ms")]
+    pub max_latency: Option<Duration>,
+    /// Share of the queries resolved, i.e. answered without an error, records or not
+    pub success_rate: f64,
+    /// How many A and AAAA queries got a usable answer; `None` without any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usable_answers: Option<AnswerUsability>,
+    /// Neither a UDP query nor a TCP connection got through in the pre-flight check, so the
+    /// provider was not tested
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unreachable: bool,
+    /// The provider's time budget ran out before its queries were done, or the run's before it
+    /// was started; the figures cover `completed_queries`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub budget_exceeded: bool,
+    /// Queries of the rounds that were sent and answered or failed
+    pub completed_queries: u32,
+    /// --adaptive stopped the rounds once the median was known well enough; the figures cover
+    /// `completed_queries`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub early_stop: bool,
+    /// Retries of failed queries over the whole run, whether or not they succeeded
+    pub retries_used: u32,
+    /// Queries answered only after the resolver sent them again within their --attempts, a
+    /// layer below `retries_used`, which starts a query over once all its attempts failed.
+    /// Over UDP the lost packets before the answer tell; over other transports, an answer
+    /// slower than the timeout of one attempt
+    pub resent_answers: u32,
+    /// Queries whose UDP response was truncated and that were sent again over TCP; their
+    /// latency is that of both
+    pub truncated_retries: u32,
+    /// How long the two legs of those queries took, `None` without any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_legs: Option<TruncationLegs>,
+    /// UDP packets the rounds' queries sent and how many of them got no response; `None` over
+    /// other transports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_loss: Option<PacketLoss>,
+    /// One entry per failed query, leaving out the domains in `recovered_domains`
+    pub failed_domains: Vec<String>,
+    /// Domains that failed in the rounds but were answered when queried again at the end; their
+    /// failed queries still count in `success_rate` and `failures`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recovered_domains: Vec<String>,
+    /// Number of failed queries per kind of error; those of `domain_dnssec` do not count in
+    /// `success_rate`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub failure_kinds: BTreeMap<FailureKind, u32>,
+    /// Every failed query with its kind of error, in the order of `failed_domains`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<QueryFailure>,
+    /// How long each failed query was waited on before it was given up, from its first try to
+    /// its last, retries and escalation included
+    #[serde(skip)]
+    pub failed_durations: Vec<Duration>,
+    /// The mean of `failed_durations`: what a failure costs whoever waits for the answer.
+    /// `None` without failures
+    #[serde(rename = "failure_wait_ms", serialize_with = "serialize_opt_ms", skip_serializing_if = "Option::is_none")]
+    pub failure_wait: Option<Duration>,
+    #[serde(rename = "median_ms", serialize_with = "serialize_opt_ms")]
+    pub median_duration: Option<Duration>,
+    /// Answers that took a large share of `timeout`; `None` without answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub near_timeout: Option<NearTimeout>,
+    /// Timeout the queries were sent with, the provider's own or the run's; what failed
+    /// queries count as without answers, as recorded in the run's settings
+    #[serde(skip)]
+    pub timeout: Duration,
+    /// The median of the answers alone when failures are penalized, which then count in
+    /// `median_duration`; `None` otherwise
+    #[serde(rename = "optimistic_median_ms", serialize_with = "serialize_opt_ms", skip_serializing_if = "Option::is_none")]
+    pub optimistic_median: Option<Duration>,
+    /// Queries completed per second of querying, cooldowns left out
+    pub queries_per_second: Option<f64>,
+    /// Where the time spent testing the provider went
+    pub timing: Timing,
+    /// Composite of median, success rate and jitter, lower is better; `None` without answers
+    pub score: Option<f64>,
+    /// Latencies behind the percentiles: the answered queries, plus the failed ones when
+    /// failures are penalized
+    pub latency_samples: u32,
+    #[serde(rename = "p95_ms", serialize_with = "serialize_opt_ms")]
+    pub p95: Option<Duration>,
+    #[serde(rename = "p99_ms", serialize_with = "serialize_opt_ms")]
+    pub p99: Option<Duration>,
+    #[serde(rename = "std_dev_ms", serialize_with = "serialize_opt_ms")]
+    pub std_dev: Option<Duration>,
+    #[serde(rename = "jitter_ms", serialize_with = "serialize_opt_ms")]
+    pub jitter: Option<Duration>,
+    /// Median of every round, `None` for a round without answers
+    #[serde(rename = "round_medians_ms", serialize_with = "serialize_ms_list")]
+    pub round_medians: Vec<Option<Duration>>,
+    /// Percentage by which the median of the last round with answers is above that of the first,
+    /// negative if it got faster; `None` with fewer than two such rounds
+    #[serde(rename = "round_trend_percent")]
+    pub round_trend: Option<f64>,
+    /// Average and longest CNAME chain of the answered queries; `None` without answers
+    pub avg_cnames: Option<f64>,
+    pub max_cnames: Option<u32>,
+    #[serde(rename = "cold_median_ms", serialize_with = "serialize_opt_ms")]
+    pub cold_median: Option<Duration>,
+    #[serde(rename = "warm_median_ms", serialize_with = "serialize_opt_ms")]
+    pub warm_median: Option<Duration>,
+    pub transport: String,
+    /// Duration of the first measured query, `None` if it failed
+    #[serde(rename = "first_query_ms", serialize_with = "serialize_opt_ms")]
+    pub first_query: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover: Option<FailoverResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qname_minimization: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_cookies: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_sizes: Option<ResponseSizes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dnssec: Option<DnssecStatus>,
+    /// Whether AAAA answers are synthesized for NAT64; checked only when AAAA is queried
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns64: Option<Dns64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocking: Option<BlockingResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nxdomain_hijacking: Option<bool>,
+    /// Median time to NXDOMAIN for random names that cannot exist, measured apart from the
+    /// rounds; `None` if no probe got NXDOMAIN or the probes were off
+    #[serde(rename = "nxdomain_median_ms", serialize_with = "serialize_opt_ms")]
+    pub nxdomain_median: Option<Duration>,
+    /// What the probes for nonexistent names got instead of NXDOMAIN, per kind
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub nxdomain_probe_failures: BTreeMap<FailureKind, u32>,
+    /// Share of the test domains whose HTTPS (type 65) query got records or a clean NODATA,
+    /// measured apart from the rounds; `None` if the probe was off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_success_rate: Option<f64>,
+    /// Median time of the correctly answered HTTPS queries
+    #[serde(rename = "https_median_ms", serialize_with = "serialize_opt_ms", skip_serializing_if = "Option::is_none")]
+    pub https_median: Option<Duration>,
+    /// How the other HTTPS queries failed, per kind
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub https_failures: BTreeMap<FailureKind, u32>,
+    /// Name of the anycast site that answered the identification queries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pop: Option<String>,
+    /// What the provider's identity query reported: the resolver's egress address, or for a
+    /// CHAOS name the provider's own answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolver_identity: Option<String>,
+    /// Whether the identity query proves the provider itself answered; `None` (unknown) for a
+    /// provider without one, without known address blocks or without an answer
+    pub verified: Option<bool>,
+    /// The encrypted endpoints the provider designates by DDR, preferred first; empty when it
+    /// designates none ("no DDR"), `None` when it was not asked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ddr: Option<Vec<DdrEndpoint>>,
+    /// Addresses returned for each test query across all rounds
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub answers: BTreeMap<String, BTreeSet<IpAddr>>,
+    /// Answers for public domains in private, loopback or other non-public ranges, as a captive
+    /// portal or a hijacking resolver gives
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bogon_answers: Vec<BogonAnswer>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suspicious_answers: Vec<String>,
+    /// Whether a sample of the answered addresses accepts connections, with --verify-answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_reachability: Option<AnswerReachability>,
+    /// How steady the answered addresses stayed from round to round; `None` when no query was
+    /// answered with addresses in two rounds without failing in any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_stability: Option<AnswerStability>,
+    /// How the ANY queries were answered, per kind; they count as answered in `success_rate`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub any_handling: BTreeMap<AnyHandling, u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slow_domains: Vec<String>,
+    #[serde(rename = "errors", skip_serializing_if = "Vec::is_empty")]
+    pub error_texts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport_comparison: Option<TransportComparison>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_comparison: Option<CacheComparison>,
+    /// With `SocketMode::Compare`: the same address with a new resolver per query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket_comparison: Option<SocketComparison>,
+    /// Median time to open a TCP connection to the transport's port, checked a few times before
+    /// the rounds; `None` if every attempt failed, the check was off or over DoQ
+    #[serde(rename = "tcp_connect_ms", serialize_with = "serialize_opt_ms")]
+    pub tcp_connect: Option<Duration>,
+    /// Over DoQ, the same for a QUIC handshake with the endpoint in place of the TCP connection
+    #[serde(rename = "quic_handshake_ms", serialize_with = "serialize_opt_ms", skip_serializing_if = "Option::is_none")]
+    pub quic_handshake: Option<Duration>,
+    /// Over DoT and DoH with `handshake_check`: one new connection's TCP, TLS and first query
+    /// apart, and the steady median of the rounds; `None` over plain DNS or with the check off
+    pub connection_split: Option<ConnectionSplit>,
+    /// With `ping`: median round trip to the address without DNS, checked a few times before the
+    /// rounds; `None` if nothing answered or the check was off
+    #[serde(rename = "network_rtt_ms", serialize_with = "serialize_opt_ms")]
+    pub network_rtt: Option<Duration>,
+    /// How `network_rtt` was measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_rtt_method: Option<RttMethod>,
+    /// TTLs of all answers; `None` if no query got records back
+    pub ttl: Option<TtlRange>,
+    /// Whether the queries carried an EDNS OPT record
+    pub edns: bool,
+    /// UDP payload size the queries advertised, `None` without EDNS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edns_buffer: Option<u16>,
+    /// With `EdnsMode::Compare`, on the row measured with EDNS: the same address without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edns_comparison: Option<EdnsComparison>,
+    /// With `EdnsMode::CompareBuffers`, on the row measured without EDNS: it and the same
+    /// address with each buffer size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_comparison: Option<BufferComparison>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saturation: Option<SaturationResult>,
+    /// With `SpeedTestConfig::trim`, how many answers the average, minimum and maximum used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trimming: Option<Trimming>,
+    /// With several runs pooled, how the provider's runs compared; `None` for a single run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runs: Option<RunSpread>,
+    /// Figures for every test query, in the configured order
+    pub per_domain: Vec<DomainStats>,
+    #[serde(skip)]
+    pub samples: Vec<Sample>,
+}
+
+/// The runs behind a pooled result, whose figures cover the queries of all of them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunSpread {
+    pub runs: u32,
+    /// Median of each run, `None` for a run without answers
+    #[serde(rename = "run_medians_ms", serialize_with = "serialize_ms_list")]
+    pub run_medians: Vec<Option<Duration>>,
+    /// Standard deviation of the run medians: differences between providers smaller than this
+    /// are run-to-run noise. `None` with fewer than two runs with answers
+    #[serde(rename = "run_median_std_dev_ms", serialize_with = "serialize_opt_ms")]
+    pub std_dev: Option<Duration>,
+}
+
+/// Answered queries before and after leaving out the fastest and slowest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Trimming {
+    pub samples: usize,
+    pub kept: usize,
+}
+
+/// Results of one test query (a domain, or a domain and record type) across all rounds.
+#[derive(Clone, Debug, Serialize)]
+pub struct DomainStats {
+    /// The query as reported in `failed_domains`, e.g. "github.com" or "github.com/MX"
+    pub domain: String,
+    /// Median of the successful queries, `None` if none succeeded
+    #[serde(rename = "median_ms", serialize_with = "serialize_opt_ms")]
+    pub median: Option<Duration>,
+    /// Queries that were not answered within the timeout
+    pub failures: u32,
+    pub queries: u32,
+    pub ttl: Option<TtlRange>,
+    /// Longest CNAME chain in the answers, `None` if none succeeded
+    pub cnames: Option<u32>,
+}
+
+/// One measured query.
+#[derive(Clone, Debug, Serialize)]
+pub struct Sample {
+    pub domain: String,
+    #[serde(serialize_with = "serialize_display")]
+    pub record_type: RecordType,
+    pub round: u32,
+    #[serde(rename = "ms", serialize_with = "serialize_opt_ms")]
+    pub duration: Option<Duration>,
+    pub status: SampleStatus,
+    /// Why a failed query failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<FailureKind>,
+    /// TTLs of the answer records; `None` for failed queries and empty answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<TtlRange>,
+    /// CNAME records in the answer; `None` for failed queries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnames: Option<u32>,
+}
+
+/// Smallest and largest TTL seen, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TtlRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl TtlRange {
+    /// The range covering all of `ranges`, `None` if there are none.
+    pub fn span(ranges: impl IntoIterator<Item = TtlRange>) -> Option<TtlRange> {
+        ranges.into_iter().reduce(|a, b| TtlRange { min: a.min.min(b.min), max: a.max.max(b.max) })
+    }
+}
+
+/// A failed query, as listed in `failed_domains`.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryFailure {
+    pub domain: String,
+    pub kind: FailureKind,
+}
+
+/// How a `network_rtt` was measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RttMethod {
+    /// ICMP echo requests
+    Icmp,
+    /// TCP connections to the DNS port, as the system does not permit ICMP sockets
+    TcpIcmpNotPermitted,
+    /// TCP connections to the DNS port, as the ICMP echoes got no reply
+    TcpIcmpNoReply,
+}
+
+/// Why a query failed. Whether an NXDOMAIN answer is a failure at all depends on the query:
+/// for cache-busted names, which never exist, it is the expected answer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// No answer within the timeout
+    Timeout,
+    /// The name does not exist
+    Nxdomain,
+    /// Records for a name that cannot exist, i.e. a rewritten NXDOMAIN (only the probes for
+    /// nonexistent names count this)
+    Answered,
+    Servfail,
+    /// SERVFAIL for a domain a validating provider of the same run also got SERVFAIL for: most
+    /// likely the domain's DNSSEC signatures are broken, which is no fault of the provider, so
+    /// these queries are left out of `success_rate`
+    DomainDnssec,
+    Refused,
+    /// Any other error response code, e.g. NOTIMP or FORMERR
+    OtherResponse,
+    /// The server's port is closed (ICMP port unreachable or TCP reset)
+    ConnectionRefused,
+    /// The address cannot be reached from this machine at all
+    NoRoute,
+    /// Any other network or TLS failure
+    Network,
+    Other,
+}
+
+impl FailureKind {
+    pub fn of(error: &ResolveError) -> FailureKind {
+        let io = |error: &std::io::Error| match error.kind() {
+            std::io::ErrorKind::TimedOut => FailureKind::Timeout,
+            std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset => FailureKind::ConnectionRefused,
+            std::io::ErrorKind::NetworkUnreachable | std::io::ErrorKind::HostUnreachable => FailureKind::NoRoute,
+            _ => FailureKind::Network,
+        };
+        match error.kind() {
+            ResolveErrorKind::Timeout => FailureKind::Timeout,
+            ResolveErrorKind::NoRecordsFound { response_code, .. } => match *response_code {
+                ResponseCode::NXDomain => FailureKind::Nxdomain,
+                ResponseCode::ServFail => FailureKind::Servfail,
+                ResponseCode::Refused => FailureKind::Refused,
+                _ => FailureKind::OtherResponse,
+            },
+            ResolveErrorKind::Io(error) => io(error),
+            ResolveErrorKind::Proto(proto) => match proto.kind() {
+                ProtoErrorKind::Timeout => FailureKind::Timeout,
+                ProtoErrorKind::Io(error) => io(error),
+                _ => FailureKind::Network,
+            },
+            ResolveErrorKind::NoConnections => FailureKind::Network,
+            _ => FailureKind::Other,
+        }
+    }
+
+    /// E.g. "1 timeout", "3 timeouts" or "2 SERVFAIL".
+    pub fn describe(self, count: u32) -> String {
+        let (one, many) = match self {
+            FailureKind::Timeout => ("timeout", "timeouts"),
+            FailureKind::Nxdomain => ("NXDOMAIN", "NXDOMAIN"),
+            FailureKind::Answered => ("answer", "answers"),
+            FailureKind::Servfail => ("SERVFAIL", "SERVFAIL"),
+            FailureKind::DomainDnssec => ("SERVFAIL from broken DNSSEC", "SERVFAIL from broken DNSSEC"),
+            FailureKind::Refused => ("REFUSED", "REFUSED"),
+            FailureKind::OtherResponse => ("other error response", "other error responses"),
+            FailureKind::ConnectionRefused => ("connection refused", "connections refused"),
+            FailureKind::NoRoute => ("no route", "no route"),
+            FailureKind::Network => ("network error", "network errors"),
+            FailureKind::Other => ("other error", "other errors"),
+        };
+        format!("{} {}", count, if count == 1 { one } else { many })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleStatus {
+    Ok,
+    Slow,
+    Failed,
+}
+
+/// How a provider answered an ANY query, which RFC 8482 lets it decline. Each of these is an
+/// answer; only timeouts and other errors are failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnyHandling {
+    /// The records of the name
+    Full,
+    /// A synthesized HINFO record, the minimal response of RFC 8482
+    Minimal,
+    /// NOTIMP
+    NotImplemented,
+    /// REFUSED
+    Refused,
+}
+
+impl AnyHandling {
+    pub fn describe(self) -> &'static str {
+        match self {
+            AnyHandling::Full => "full answer",
+            AnyHandling::Minimal => "minimal (RFC 8482)",
+            AnyHandling::NotImplemented => "NOTIMP",
+            AnyHandling::Refused => "REFUSED",
+        }
+    }
+}
+
+/// Outcome of the check for a network that answers port-53 traffic itself.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InterceptionCheck {
+    /// Either sign below was seen
+    pub intercepted: bool,
+    /// A query to a documentation address, where no DNS server runs, was answered
+    pub bogus_server_answered: bool,
+    /// The resolver address every provider reported for the whoami query, when they all agree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_egress: Option<String>,
+}
+
+/// Medians of the two legs of the queries retried over TCP after a truncated UDP response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TruncationLegs {
+    /// Until the truncated response
+    #[serde(rename = "udp_median_ms", serialize_with = "serialize_ms")]
+    pub udp_median: Duration,
+    /// From then until the answer over TCP, connection setup included
+    #[serde(rename = "tcp_median_ms", serialize_with = "serialize_ms")]
+    pub tcp_median: Duration,
+}
+
+/// Where the time of a DoT or DoH client that opens a new connection per query goes, from one
+/// connection made apart from the resolver's, next to the queries on its reused one.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectionSplit {
+    /// Until the TCP connection was open
+    #[serde(rename = "tcp_connect_ms", serialize_with = "serialize_opt_ms")]
+    pub tcp: Option<Duration>,
+    /// From then until the TLS handshake was done
+    #[serde(rename = "tls_handshake_ms", serialize_with = "serialize_opt_ms")]
+    pub tls: Option<Duration>,
+    /// From then until the answer to the first query on the connection, for DoH the HTTP/2
+    /// handshake included
+    #[serde(rename = "first_query_ms", serialize_with = "serialize_opt_ms")]
+    pub first_query: Option<Duration>,
+    /// Median of the rounds after the first, on the resolver's reused connection, or of all of
+    /// them with one round
+    #[serde(rename = "steady_median_ms", serialize_with = "serialize_opt_ms")]
+    pub steady_median: Option<Duration>,
+    /// Why the connection or its query failed, e.g. "TLS handshake: invalid peer certificate"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ConnectionSplit {
+    /// TCP and TLS together: what every new connection costs before its first query.
+    pub fn connect(&self) -> Option<Duration> {
+        Some(self.tcp? + self.tls?)
+    }
+
+    /// The step that failed, for a table cell: "TCP", "TLS" or "query".
+    pub fn failed_step(&self) -> Option<&'static str> {
+        self.error.as_ref()?;
+        Some(if self.tcp.is_none() { "TCP" } else if self.tls.is_none() { "TLS" } else { "query" })
+    }
+}
+
+/// UDP packets counted one by one rather than per query: a packet without a response within
+/// the timeout is lost even when a resend or a retry of its query was answered, and a SERVFAIL
+/// is a response like any other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PacketLoss {
+    pub sent: u32,
+    pub lost: u32,
+    /// `lost` as a share of `sent`, 0 when nothing was sent
+    pub percent: f64,
+}
+
+impl PacketLoss {
+    pub fn new(sent: u32, lost: u32) -> PacketLoss {
+        let percent = if sent == 0 { 0.0 } else { f64::from(lost) / f64::from(sent) * 100.0 };
+        PacketLoss { sent, lost, percent }
+    }
+
+    /// Both counts of several results over the same transport together.
+    pub(crate) fn sum<'a>(losses: impl Iterator<Item = &'a PacketLoss>) -> PacketLoss {
+        let (sent, lost) = losses.fold((0, 0), |(sent, lost), loss| (sent + loss.sent, lost + loss.lost));
+        PacketLoss::new(sent, lost)
+    }
+}
+
+/// The rows of one organization, as `--group-by provider` sums them up: the best and the worst
+/// median among its endpoints that answered, and which endpoint had the best.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ProviderGroup {
+    pub organization: String,
+    /// Rows of the organization, those that failed every query included
+    pub endpoints: u32,
+    /// Provider column of the fastest row; `None` when none answered
+    pub fastest_endpoint: Option<String>,
+    #[serde(rename = "best_median_ms", serialize_with = "serialize_opt_ms")]
+    pub best_median: Option<Duration>,
+    #[serde(rename = "worst_median_ms", serialize_with = "serialize_opt_ms")]
+    pub worst_median: Option<Duration>,
+}
+
+/// One group per organization of `results`, in the order of its first row, so the ranking of
+/// the rows carries over to the groups.
+pub fn group_by_organization<'a>(results: impl IntoIterator<Item = &'a TestResult>) -> Vec<ProviderGroup> {
+    let mut groups: Vec<ProviderGroup> = Vec::new();
+    for result in results {
+        let index = match groups.iter().position(|group| group.organization == result.organization) {
+            Some(index) => index,
+            None => {
+                groups.push(ProviderGroup { organization: result.organization.clone(), ..Default::default() });
+                groups.len() - 1
+            },
+        };
+        let group = &mut groups[index];
+        group.endpoints += 1;
+        let Some(median) = result.median_duration else { continue };
+        if group.best_median.is_none_or(|best| median < best) {
+            group.best_median = Some(median);
+            group.fastest_endpoint = Some(result.provider.clone());
+        }
+        if group.worst_median.is_none_or(|worst| median > worst) {
+            group.worst_median = Some(median);
+        }
+    }
+    groups
+}
+
+/// Answers close to the timeout, a provider on the edge of failing queries: its median can look
+/// fine while some answers only just make it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct NearTimeout {
+    pub answered: u32,
+    /// Answers that took more than half the timeout
+    pub over_half: u32,
+    /// Answers that took more than 90% of it, a subset of `over_half`
+    pub over_90_percent: u32,
+}
+
+/// A and AAAA queries whose answer has at least one public address, which an empty answer or
+/// a blocking sentinel such as 0.0.0.0 does not: resolved, but of no use for connecting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct AnswerUsability {
+    pub queries: u32,
+    pub usable: u32,
+    /// `usable` as a share of `queries`, 0 without queries
+    pub percent: f64,
+}
+
+impl AnswerUsability {
+    pub fn new(queries: u32, usable: u32) -> AnswerUsability {
+        let percent = if queries == 0 { 0.0 } else { f64::from(usable) / f64::from(queries) * 100.0 };
+        AnswerUsability { queries, usable, percent }
+    }
+
+    /// Both counts of several results together.
+    pub(crate) fn sum<'a>(counts: impl Iterator<Item = &'a AnswerUsability>) -> AnswerUsability {
+        let (queries, usable) = counts.fold((0, 0), |(queries, usable), count| (queries + count.queries, usable + count.usable));
+        AnswerUsability::new(queries, usable)
+    }
+}
+
+/// Whether `addresses` has one worth connecting to: an address in none of the ranges of
+/// `classify_address`.
+pub fn usable_answer(addresses: &[IpAddr]) -> bool {
+    addresses.iter().any(|&ip| classify_address(ip).is_none())
+}
+
+/// Time spent testing a provider, summed over its addresses (and runs, when pooled).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Timing {
+    /// From the start of the provider's test to its end, probes and checks included
+    #[serde(rename = "wall_ms", serialize_with = "serialize_ms")]
+    pub wall: Duration,
+    /// Measured queries of the rounds; what `queries_per_second` is over
+    #[serde(rename = "queries_ms", serialize_with = "serialize_ms")]
+    pub queries: Duration,
+    /// TCP connects (QUIC handshakes over DoQ) of `tcp_check`, before the rounds
+    #[serde(rename = "tcp_check_ms", serialize_with = "serialize_ms")]
+    pub tcp_check: Duration,
+    /// Cooldowns between queries and rounds
+    #[serde(rename = "cooldown_ms", serialize_with = "serialize_ms")]
+    pub cooldown: Duration,
+}
+
+impl Timing {
+    /// The time spent measuring: the queries and the TCP check.
+    pub fn active(&self) -> Duration {
+        self.queries + self.tcp_check
+    }
+}
+
+impl std::ops::Add for Timing {
+    type Output = Timing;
+
+    fn add(self, other: Timing) -> Timing {
+        Timing {
+            wall: self.wall + other.wall,
+            queries: self.queries + other.queries,
+            tcp_check: self.tcp_check + other.tcp_check,
+            cooldown: self.cooldown + other.cooldown,
+        }
+    }
+}
+
+impl std::iter::Sum for Timing {
+    fn sum<I: Iterator<Item = Timing>>(iter: I) -> Timing {
+        iter.fold(Timing::default(), |total, timing| total + timing)
+    }
+}
+
+/// Sizes of the responses to the test queries, each sent once more after the rounds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResponseSizes {
+    /// Responses received, full ones fetched over TCP after truncation included
+    pub responses: u32,
+    pub avg_bytes: f64,
+    pub max_bytes: usize,
+    /// Responses with the TC bit set, which a resolver has to retry over TCP
+    pub truncated: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SaturationResult {
+    pub levels: Vec<SaturationLevel>,
+    pub breakdown: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SaturationLevel {
+    pub concurrency: usize,
+    #[serde(rename = "median_ms", serialize_with = "serialize_opt_ms")]
+    pub median: Option<Duration>,
+    pub error_rate: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FailoverResult {
+    pub secondary: IpAddr,
+    #[serde(rename = "healthy_median_ms", serialize_with = "serialize_opt_ms")]
+    pub healthy_median: Option<Duration>,
+    #[serde(rename = "failover_median_ms", serialize_with = "serialize_opt_ms")]
+    pub failover_median: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransportComparison {
+    #[serde(rename = "doh_median_ms", serialize_with = "serialize_opt_ms")]
+    pub doh_median: Option<Duration>,
+    pub doh_success_rate: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EdnsComparison {
+    #[serde(rename = "without_edns_median_ms", serialize_with = "serialize_opt_ms")]
+    pub without_edns_median: Option<Duration>,
+    pub without_edns_success_rate: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BufferComparison {
+    /// The row's name without the EDNS tag
+    pub provider: String,
+    /// Without EDNS first, then by buffer size
+    pub runs: Vec<BufferRun>,
+}
+
+/// One EDNS setting of a [`BufferComparison`].
+#[derive(Clone, Debug, Serialize)]
+pub struct BufferRun {
+    /// `None` without EDNS
+    pub edns_buffer: Option<u16>,
+    #[serde(rename = "median_ms", serialize_with = "serialize_opt_ms")]
+    pub median: Option<Duration>,
+    pub success_rate: f64,
+    pub truncated_retries: u32,
+    pub failed_queries: u32,
+}
+
+impl BufferRun {
+    pub(crate) fn of(result: &TestResult) -> BufferRun {
+        BufferRun {
+            edns_buffer: result.edns_buffer,
+            median: result.median_duration,
+            success_rate: result.success_rate,
+            truncated_retries: result.truncated_retries,
+            failed_queries: result.failures.len() as u32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockingResult {
+    /// Share of the domains with a definite outcome that were blocked
+    pub blocked_percent: Option<f64>,
+    pub outcomes: Vec<BlockingSample>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockingSample {
+    pub domain: String,
+    pub outcome: BlockOutcome,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockOutcome {
+    Blocked,
+    Resolved,
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnssecStatus {
+    Validating,
+    NotValidating,
+    Unknown,
+}
+
+impl DnssecStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            DnssecStatus::Validating => "yes",
+            DnssecStatus::NotValidating => "no",
+            DnssecStatus::Unknown => "unknown",
+        }
+    }
+}
+
+/// DNS64 (RFC 6147): AAAA answers a resolver makes up from A records, for IPv6-only networks
+/// that reach IPv4 through NAT64.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Dns64 {
+    pub status: Dns64Status,
+    /// The NAT64 prefix, e.g. "64:ff9b::/96", from ipv4only.arpa (RFC 7050) or the answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// The test domains whose AAAA answers embed one of their A answers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub synthesized: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dns64Status {
+    Yes,
+    No,
+    Unknown,
+}
+
+impl Dns64Status {
+    pub fn label(self) -> &'static str {
+        match self {
+            Dns64Status::Yes => "yes",
+            Dns64Status::No => "no",
+            Dns64Status::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheComparison {
+    #[serde(rename = "uncached_median_ms", serialize_with = "serialize_opt_ms")]
+    pub uncached_median: Option<Duration>,
+    pub uncached_success_rate: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SocketComparison {
+    #[serde(rename = "fresh_median_ms", serialize_with = "serialize_opt_ms")]
+    pub fresh_median: Option<Duration>,
+    pub fresh_success_rate: f64,
+}
+
+fn serialize_ms<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn serialize_ms_list<S: Serializer>(durations: &[Option<Duration>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(durations.iter().map(|duration| duration.map(|duration| duration.as_secs_f64() * 1000.0)))
+}
+
+fn serialize_display<T: std::fmt::Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub(crate) fn serialize_opt_ms<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_ms(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Latencies a percentile needs to rank providers by; with fewer, p95 and p99 are little more
+/// than the slowest answer, so `max_latency` ranks instead.
+pub const MIN_PERCENTILE_SAMPLES: u32 = 20;
+
+impl TestResult {
+    /// `percentile`, this result's `p95` or `p99`, for ranking, or `max_latency` with `true`
+    /// when it rests on fewer than [`MIN_PERCENTILE_SAMPLES`] latencies; `None` without answers.
+    pub fn tail_latency(&self, percentile: Option<Duration>) -> (Option<Duration>, bool) {
+        match percentile {
+            Some(latency) if self.latency_samples >= MIN_PERCENTILE_SAMPLES => (Some(latency), false),
+            _ => (self.max_latency, true),
+        }
+    }
+
+    /// Time the resolver itself took: the median less the network round trip, floored at zero.
+    /// `None` without a round trip or without an answer.
+    pub fn resolver_overhead(&self) -> Option<Duration> {
+        Some(self.median_duration?.saturating_sub(self.network_rtt?))
+    }
+
+    /// UDP or DoH, whichever was faster, when the transport comparison ran.
+    pub fn recommended_transport(&self) -> Option<Transport> {
+        let comparison = self.transport_comparison.as_ref()?;
+        if comparison.doh_success_rate == 0.0 {
+            return Some(Transport::Udp);
+        }
+        let faster = |udp: Duration| comparison.doh_median.is_some_and(|doh| doh < udp);
+        if self.median_duration.is_none_or(faster) {
+            Some(Transport::Https)
+        } else {
+            Some(Transport::Udp)
+        }
+    }
+}
+
+/// A range of addresses that a public domain should never resolve to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressRange {
+    /// 0.0.0.0 or `::`, what filtering resolvers answer for a blocked domain
+    Unspecified,
+    /// 0.0.0.0/8 other than 0.0.0.0
+    ThisNetwork,
+    /// RFC 1918: 10/8, 172.16/12 and 192.168/16
+    Private,
+    /// 100.64/10, carrier-grade NAT
+    SharedAddressSpace,
+    /// 127/8 and `::1`
+    Loopback,
+    /// 169.254/16 and fe80::/10
+    LinkLocal,
+    /// 192.0.2/24, 198.51.100/24, 203.0.113/24, 2001:db8::/32 and 3fff::/20
+    Documentation,
+    /// 198.18/15, for benchmarking
+    Benchmarking,
+    /// fc00::/7
+    UniqueLocal,
+    /// 192.0.0/24, 240/4 and 255.255.255.255
+    Reserved,
+}
+
+impl AddressRange {
+    pub fn describe(self) -> &'static str {
+        match self {
+            AddressRange::Unspecified => "unspecified",
+            AddressRange::ThisNetwork => "this network",
+            AddressRange::Private => "private",
+            AddressRange::SharedAddressSpace => "carrier-grade NAT",
+            AddressRange::Loopback => "loopback",
+            AddressRange::LinkLocal => "link-local",
+            AddressRange::Documentation => "documentation",
+            AddressRange::Benchmarking => "benchmarking",
+            AddressRange::UniqueLocal => "unique local",
+            AddressRange::Reserved => "reserved",
+        }
+    }
+}
+
+/// The non-public range `ip` is in, `None` for a public address. IPv4-mapped IPv6 addresses are
+/// classified as the IPv4 address they map.
+pub fn classify_address(ip: IpAddr) -> Option<AddressRange> {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(match (a, b, c) {
+                _ if ip.is_unspecified() => AddressRange::Unspecified,
+                (0, ..) => AddressRange::ThisNetwork,
+                _ if ip.is_private() => AddressRange::Private,
+                (100, 64..=127, _) => AddressRange::SharedAddressSpace,
+                _ if ip.is_loopback() => AddressRange::Loopback,
+                _ if ip.is_link_local() => AddressRange::LinkLocal,
+                _ if ip.is_documentation() => AddressRange::Documentation,
+                (198, 18 | 19, _) => AddressRange::Benchmarking,
+                (192, 0, 0) | (240..=255, ..) => AddressRange::Reserved,
+                _ => return None,
+            })
+        },
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return classify_address(IpAddr::V4(v4));
+            }
+            let [first, second, ..] = ip.segments();
+            Some(match first {
+                _ if ip.is_unspecified() => AddressRange::Unspecified,
+                _ if ip.is_loopback() => AddressRange::Loopback,
+                _ if first & 0xffc0 == 0xfe80 => AddressRange::LinkLocal,
+                _ if first & 0xfe00 == 0xfc00 => AddressRange::UniqueLocal,
+                0x2001 if second == 0x0db8 => AddressRange::Documentation,
+                _ if first & 0xfff0 == 0x3ff0 => AddressRange::Documentation,
+                _ => return None,
+            })
+        },
+    }
+}
+
+/// An address in a non-public range returned for a public test domain.
+#[derive(Clone, Debug, Serialize)]
+pub struct BogonAnswer {
+    /// The query, as in `answers`
+    pub domain: String,
+    pub address: IpAddr,
+    pub range: AddressRange,
+}
+
+/// What connecting to port 443 of one public address per domain and family of a provider's
+/// answers found. Kept apart from the latency figures: it times nothing, and the sites rather
+/// than the provider answer it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AnswerReachability {
+    pub checked: u32,
+    /// Connected, or refused: either way the network path to the host works
+    pub reachable: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unreachable: Vec<UnreachableAnswer>,
+}
+
+/// Whether the test queries got at least one of the same addresses back round after round.
+/// CDNs rotate through their addresses, so only an answer that shares none with the one before
+/// counts as a change; a resolver that replaces them every time breaks connection reuse.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AnswerStability {
+    /// Queries answered with addresses in at least two rounds and failed in none
+    pub queries: u32,
+    /// Those whose answers always shared an address with the previous round's
+    pub stable: u32,
+    /// `stable` as a share of `queries`
+    pub percent: f64,
+    /// The other queries, most changes first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unstable: Vec<UnstableAnswer>,
+}
+
+/// A query whose answers were replaced completely between rounds.
+#[derive(Clone, Debug, Serialize)]
+pub struct UnstableAnswer {
+    /// The query, as in `answers`
+    pub domain: String,
+    /// Rounds whose addresses shared none with the previous round's
+    pub changes: u32,
+    /// Rounds compared with the one before
+    pub compared: u32,
+}
+
+/// An answered address that neither accepted nor refused a connection in time.
+#[derive(Clone, Debug, Serialize)]
+pub struct UnreachableAnswer {
+    /// The query, as in `answers`
+    pub domain: String,
+    pub address: IpAddr,
+}
+
+/// Names under these suffixes, and single-label names, are meant to resolve inside a network,
+/// so private answers for them are no sign of a hijacking resolver.
+const INTERNAL_SUFFIXES: &[&str] = &["local", "localhost", "internal", "home.arpa", "lan", "home", "corp", "intranet"];
+
+fn is_public_domain(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    domain.contains('.') && !INTERNAL_SUFFIXES.iter().any(|suffix| {
+        domain == *suffix || domain.strip_suffix(suffix).is_some_and(|rest| rest.ends_with('.'))
+    })
+}
+
+/// Compares the answers each provider returned and records, per provider, answers in private or
+/// reserved ranges for public domains and notes answers for a domain that share nothing with
+/// any other provider. CDNs hand out different
+/// addresses per resolver, so an outlier only counts when it is the single one for that domain.
+pub(crate) fn flag_suspicious_answers(results: &mut [TestResult]) {
+    let domains: BTreeSet<String> = results.iter().flat_map(|r| r.answers.keys().cloned()).collect();
+    for domain in &domains {
+        let answered: Vec<usize> = (0..results.len())
+            .filter(|&i| results[i].answers.get(domain).is_some_and(|ips| !ips.is_empty()))
+            .collect();
+        let outliers: Vec<usize> = answered.iter().copied()
+            .filter(|&i| {
+                let own = &results[i].answers[domain];
+                answered.iter().all(|&j| j == i || results[j].answers[domain].is_disjoint(own))
+            })
+            .collect();
+        let lone_outlier = match outliers[..] {
+            [i] if answered.len() >= 3 => Some(i),
+            _ => None,
+        };
+        // The query label is "domain" or "domain/AAAA".
+        let public = is_public_domain(domain.split('/').next().unwrap_or(domain));
+        for &i in &answered {
+            // 0.0.0.0 is how filtering resolvers answer for blocked domains.
+            let bogons: Vec<BogonAnswer> = results[i].answers[domain].iter()
+                .filter_map(|&address| Some((address, classify_address(address)?)))
+                .filter(|&(_, range)| public && range != AddressRange::Unspecified)
+                .map(|(address, range)| BogonAnswer { domain: domain.clone(), address, range })
+                .collect();
+            if !bogons.is_empty() {
+                results[i].bogon_answers.extend(bogons);
+            } else if lone_outlier == Some(i) {
+                let note = format!("{}: addresses no other provider returned", domain);
+                results[i].suspicious_answers.push(note);
+            }
+        }
+    }
+}
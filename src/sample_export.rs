@@ -0,0 +1,91 @@
+//! --export-samples: every measured query of the run, written in bulk once the run is over for
+//! R, pandas and the like. Unlike --samples these are the very samples the report's figures
+//! were computed from, failed queries included, in a fixed order: by provider, round, domain
+//! and record type.
+
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use dns_speed_test::{Sample, SampleStatus, TestResult};
+use serde::Serialize;
+
+/// The formats a sample export can be written in, chosen by the file's extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+}
+
+/// Where --export-samples writes and in which format.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+/// Parses the --export-samples path, rejecting extensions without a writer.
+pub fn parse_target(s: &str) -> Result<Target, String> {
+    let path = PathBuf::from(s);
+    let extension = path.extension().and_then(OsStr::to_str).map(str::to_ascii_lowercase);
+    let format = match extension.as_deref() {
+        Some("csv") => Format::Csv,
+        Some("parquet") => return Err("Parquet is not supported yet; use a .csv file".to_string()),
+        _ => return Err("expected a .csv file".to_string()),
+    };
+    Ok(Target { path, format })
+}
+
+/// One sample with the row it belongs to.
+struct Row<'a> {
+    result: &'a TestResult,
+    sample: &'a Sample,
+}
+
+/// The samples of `results` in export order.
+fn rows<'a>(results: &[&'a TestResult]) -> Vec<Row<'a>> {
+    let mut rows: Vec<Row> = results.iter()
+        .flat_map(|&result| result.samples.iter().map(move |sample| Row { result, sample }))
+        .collect();
+    // Stable, so the queries of one provider, round and domain keep the order they were
+    // pooled in with --repeat.
+    rows.sort_by(|a, b| (&a.result.provider, a.sample.round, &a.sample.domain, u16::from(a.sample.record_type))
+        .cmp(&(&b.result.provider, b.sample.round, &b.sample.domain, u16::from(b.sample.record_type))));
+    rows
+}
+
+/// Writes the samples of `results` to `target`, replacing the file.
+pub fn write(target: &Target, results: &[&TestResult]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(std::fs::File::create(&target.path)?);
+    match target.format {
+        Format::Csv => write_csv(&mut out, &rows(results))?,
+    }
+    out.flush()
+}
+
+/// How serde spells a unit variant, e.g. "timeout", as in the JSON report.
+fn name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok().and_then(|value| value.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+const CSV_HEADER: &str = "provider,transport,domain,record_type,round,outcome,error,duration_ms,ttl_min,ttl_max,cnames";
+
+/// One line per sample. `round` is 0-based, as in `--include-raw`; a failed query has its kind
+/// of error and no duration.
+fn write_csv(out: &mut dyn Write, rows: &[Row]) -> io::Result<()> {
+    writeln!(out, "{}", CSV_HEADER)?;
+    for Row { result, sample } in rows {
+        writeln!(out, "{}", [
+            crate::csv_field(&result.provider),
+            result.transport.clone(),
+            crate::csv_field(&sample.domain),
+            sample.record_type.to_string(),
+            sample.round.to_string(),
+            name(&sample.status),
+            sample.error.filter(|_| sample.status == SampleStatus::Failed).map(|kind| name(&kind)).unwrap_or_default(),
+            sample.duration.map(|duration| format!("{:.6}", duration.as_secs_f64() * 1000.0)).unwrap_or_default(),
+            sample.ttl.map(|ttl| ttl.min.to_string()).unwrap_or_default(),
+            sample.ttl.map(|ttl| ttl.max.to_string()).unwrap_or_default(),
+            sample.cnames.map(|cnames| cnames.to_string()).unwrap_or_default(),
+        ].join(","))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! --samples: every measured query as it completes, one JSON object per line (JSON Lines) for
+//! jq or pandas. A thread of its own writes the lines, so file IO stays off the queries.
+//!
+//! ```json
+//! {"timestamp":"2024-05-01T12:00:00.123Z","provider":"Cloudflare","address":"1.1.1.1","protocol":"UDP","domain":"google.com","record_type":"A","round":0,"outcome":"ok","duration_ms":11.8,"answers":1}
+//! ```
+//!
+//! `round` is 0-based, as in `--include-raw`. Queries of an EDNS or cache-busted pass say so
+//! with `"edns": true` (and the buffer size as `"edns_buffer"`) or `"cache_bust": true`, so
+//! every report row's queries can be told apart.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+use dns_speed_test::{FailureKind, QueryOutcome, SampleStatus};
+use serde::{Serialize, Serializer};
+
+#[derive(Serialize)]
+struct Record {
+    #[serde(serialize_with = "rfc3339")]
+    timestamp: SystemTime,
+    provider: String,
+    address: IpAddr,
+    protocol: &'static str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    edns: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edns_buffer: Option<u16>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cache_bust: bool,
+    domain: String,
+    record_type: String,
+    round: u32,
+    outcome: Outcome,
+    duration_ms: Option<f64>,
+    /// Addresses in the answer; `None` for a failed query
+    answers: Option<usize>,
+}
+
+/// "ok" or "slow" for an answer, the kind of error, e.g. "timeout", for a failure.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Outcome {
+    Answered(SampleStatus),
+    Failed(FailureKind),
+}
+
+pub fn rfc3339<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
+
+pub struct SamplesFile {
+    sender: Sender<Record>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl SamplesFile {
+    /// Creates (or truncates) `path` and starts the thread that writes to it.
+    pub fn create(path: &Path) -> io::Result<SamplesFile> {
+        let file = File::create(path)?;
+        let (sender, records) = mpsc::channel();
+        let writer = std::thread::spawn(move || write(file, records));
+        Ok(SamplesFile { sender, writer })
+    }
+
+    /// Queues the query for writing. After a write error the rest are dropped, and `finish`
+    /// reports the error.
+    pub fn record(&self, outcome: &QueryOutcome) {
+        let sample = outcome.sample;
+        let answered = sample.status != SampleStatus::Failed;
+        let _ = self.sender.send(Record {
+            timestamp: SystemTime::now(),
+            provider: outcome.provider.to_string(),
+            address: outcome.address,
+            protocol: outcome.transport.label(),
+            edns: outcome.edns,
+            edns_buffer: outcome.edns_buffer,
+            cache_bust: outcome.cache_bust,
+            domain: sample.domain.clone(),
+            record_type: sample.record_type.to_string(),
+            round: sample.round,
+            outcome: match sample.error {
+                Some(kind) if !answered => Outcome::Failed(kind),
+                _ => Outcome::Answered(sample.status),
+            },
+            duration_ms: sample.duration.map(|duration| duration.as_secs_f64() * 1000.0),
+            answers: answered.then_some(outcome.answers),
+        });
+    }
+
+    /// Writes what is still queued, then flushes the file to disk.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.writer.join().unwrap_or_else(|_| Err(io::Error::other("the samples writer panicked")))
+    }
+}
+
+/// Writes the records as they come, flushing whenever none are waiting, so a run killed
+/// outright loses at most the queries still in the channel.
+fn write(file: File, records: Receiver<Record>) -> io::Result<()> {
+    let mut out = BufWriter::new(&file);
+    loop {
+        let record = match records.try_recv() {
+            Ok(record) => record,
+            Err(TryRecvError::Empty) => {
+                out.flush()?;
+                match records.recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                }
+            },
+            Err(TryRecvError::Disconnected) => break,
+        };
+        serde_json::to_writer(&mut out, &record)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    drop(out);
+    file.sync_all()
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+use serde::Serialize;
+
+/// Median of `samples` (sorted by duration) where each sample counts with its weight. When the
+/// cumulative weight lands exactly on the halfway point, the two middle samples are averaged, so
+/// equal weights give the usual median for even-length input.
+pub fn weighted_median(samples: &[(u32, Duration)], weights: &[f64]) -> Option<Duration> {
+    let half = weights.iter().sum::<f64>() / 2.0;
+    let mut cumulative = 0.0;
+    for (i, (&(_, duration), weight)) in samples.iter().zip(weights).enumerate() {
+        cumulative += weight;
+        if (cumulative - half).abs() <= half * 1e-9 {
+            return Some(match samples.get(i + 1) {
+                Some(&(_, next)) => (duration + next) / 2,
+                None => duration,
+            });
+        }
+        if cumulative > half {
+            return Some(duration);
+        }
+    }
+    samples.last().map(|&(_, d)| d)
+}
+
+/// `p`-th percentile (0–100) of `sorted`, linearly interpolated between the closest ranks.
+/// `None` for an empty slice; a single sample is every percentile.
+pub fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    let last = sorted.len().checked_sub(1)?;
+    let rank = p / 100.0 * last as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let fraction = rank - lower as f64;
+    let low = sorted[lower].as_secs_f64();
+    let high = sorted[upper.min(last)].as_secs_f64();
+    Some(Duration::from_secs_f64(low + (high - low) * fraction))
+}
+
+/// Sample standard deviation; `None` with fewer than two samples.
+pub fn std_dev(durations: &[Duration]) -> Option<Duration> {
+    if durations.len() < 2 {
+        return None;
+    }
+    let n = durations.len() as f64;
+    let mean = durations.iter().map(Duration::as_secs_f64).sum::<f64>() / n;
+    let variance = durations.iter()
+        .map(|d| (d.as_secs_f64() - mean).powi(2))
+        .sum::<f64>() / (n - 1.0);
+    Some(Duration::from_secs_f64(variance.sqrt()))
+}
+
+/// Jitter as the mean absolute difference between consecutive durations, which must be in the
+/// order the queries were made; `None` with fewer than two samples.
+pub fn jitter(durations: &[Duration]) -> Option<Duration> {
+    if durations.len() < 2 {
+        return None;
+    }
+    let total: f64 = durations.windows(2)
+        .map(|pair| (pair[1].as_secs_f64() - pair[0].as_secs_f64()).abs())
+        .sum();
+    Some(Duration::from_secs_f64(total / (durations.len() - 1) as f64))
+}
+
+/// Percentage change from the first to the last of `medians` that is not `None`; `None` with
+/// fewer than two.
+pub fn trend(medians: &[Option<Duration>]) -> Option<f64> {
+    let mut answered = medians.iter().flatten();
+    let first = answered.next()?.as_secs_f64();
+    let last = answered.last()?.as_secs_f64();
+    (first > 0.0).then(|| (last / first - 1.0) * 100.0)
+}
+
+/// How much failures and jitter weigh in [`score`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ScoreWeights {
+    /// Multiplies the median by `1 + success × failure share`, so with 10 a provider failing 5%
+    /// of its queries scores as if its median were 1.5 times as long
+    pub success: f64,
+    /// Milliseconds added per millisecond of jitter
+    pub jitter: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> ScoreWeights {
+        ScoreWeights { success: 10.0, jitter: 0.5 }
+    }
+}
+
+/// One figure for latency, reliability and consistency, lower is better:
+/// `median_ms × (1 + weights.success × failure share) + weights.jitter × jitter_ms`. A missing
+/// jitter (fewer than two answers) adds nothing. `None`, the worst score, when no query was
+/// answered, since the median is then only the timeout.
+pub fn score(median: Duration, success_rate: f64, jitter: Option<Duration>, weights: ScoreWeights) -> Option<f64> {
+    if success_rate <= 0.0 {
+        return None;
+    }
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let failed = (1.0 - success_rate / 100.0).clamp(0.0, 1.0);
+    Some(ms(median) * (1.0 + weights.success * failed) + weights.jitter * jitter.map_or(0.0, ms))
+}
+
+/// Answers --adaptive waits for before it considers stopping a provider early.
+pub const ADAPTIVE_MIN_SAMPLES: usize = 15;
+
+/// How close to the median its confidence interval has to be for --adaptive to stop early.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    /// At most this far from the median on either side
+    Within(Duration),
+    /// At most this percentage of the median on either side
+    Percent(f64),
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Precision::Within(limit) => write!(f, "±{} ms", limit.as_secs_f64() * 1000.0),
+            Precision::Percent(percent) => write!(f, "±{}%", percent),
+        }
+    }
+}
+
+/// How many of `durations` took more than half, and more than 90%, of `timeout`: answers that
+/// were close to failing. A duration exactly at a threshold is not over it.
+pub fn near_timeout(durations: &[Duration], timeout: Duration) -> (usize, usize) {
+    let over = |share: f64| durations.iter().filter(|&&duration| duration.as_secs_f64() > timeout.as_secs_f64() * share).count();
+    (over(0.5), over(0.9))
+}
+
+/// An approximate 95% confidence interval for the median of `sorted`, from the order statistics
+/// at ranks `n/2 ∓ 0.98·√n` (the normal approximation of the binomial), so it makes no
+/// assumption about how the latencies are distributed. `None` for an empty slice.
+pub fn median_interval(sorted: &[Duration]) -> Option<(Duration, Duration)> {
+    let n = sorted.len();
+    if n == 0 {
+        return None;
+    }
+    let spread = 0.98 * (n as f64).sqrt();
+    // 1-based ranks; negative values saturate to 0 in the cast.
+    let lower = ((n as f64 / 2.0 - spread).floor() as usize).clamp(1, n);
+    let upper = ((1.0 + n as f64 / 2.0 + spread).ceil() as usize).clamp(1, n);
+    Some((sorted[lower - 1], sorted[upper - 1]))
+}
+
+/// Whether `sorted` has at least [`ADAPTIVE_MIN_SAMPLES`] entries and pins the median down to
+/// `precision`, so more queries would hardly move it.
+pub fn median_settled(sorted: &[Duration], precision: Precision) -> bool {
+    if sorted.len() < ADAPTIVE_MIN_SAMPLES {
+        return false;
+    }
+    let (Some(median), Some((low, high))) = (percentile(sorted, 50.0), median_interval(sorted)) else { return false };
+    let reach = median.saturating_sub(low).max(high.saturating_sub(median));
+    match precision {
+        Precision::Within(limit) => reach <= limit,
+        Precision::Percent(percent) => reach.as_secs_f64() <= median.as_secs_f64() * percent / 100.0,
+    }
+}
+
+/// The middle of `sorted` without its lowest and highest `percent` percent (0–50), rounded
+/// down. At least 3 entries are kept, or all of them if there are fewer.
+pub fn trim<T>(sorted: &[T], percent: f64) -> &[T] {
+    let drop = (sorted.len() as f64 * percent.clamp(0.0, 50.0) / 100.0).floor() as usize;
+    let drop = drop.min(sorted.len().saturating_sub(3) / 2);
+    &sorted[drop..sorted.len() - drop]
+}
+
+/// One bar of [`histogram`]: the samples from `start` up to, but not including, `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bucket {
+    pub start: Duration,
+    pub end: Duration,
+    pub count: usize,
+}
+
+/// Counts `durations` into buckets of a round width (1, 2 or 5 times a power of ten) chosen so
+/// that roughly `target` of them span the samples. Empty buckets between the fastest and the
+/// slowest sample are kept, since the gaps are what shows two clusters apart. No samples give no
+/// buckets; identical ones share a single bucket.
+pub fn histogram(durations: &[Duration], target: usize) -> Vec<Bucket> {
+    let (Some(&min), Some(&max)) = (durations.iter().min(), durations.iter().max()) else {
+        return Vec::new();
+    };
+    let (min, max) = (min.as_nanos(), max.as_nanos());
+    let target = target.max(1) as u128;
+    // Identical samples get a bucket scaled to their own size rather than a sliver of a
+    // nanosecond.
+    let raw = if max > min { (max - min).div_ceil(target) } else { (min / target).max(1) };
+    let mut power = 1;
+    while power * 10 <= raw {
+        power *= 10;
+    }
+    let step = [1, 2, 5, 10].into_iter().map(|factor| factor * power).find(|&step| step >= raw).unwrap_or(10 * power);
+
+    let first = min / step * step;
+    let mut buckets: Vec<Bucket> = (0..=(max - first) / step)
+        .map(|i| {
+            let start = first + i * step;
+            Bucket { start: nanos(start), end: nanos(start + step), count: 0 }
+        })
+        .collect();
+    for duration in durations {
+        buckets[((duration.as_nanos() - first) / step) as usize].count += 1;
+    }
+    buckets
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+}
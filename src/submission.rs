@@ -0,0 +1,138 @@
+//! --submit: the results as they may leave the machine, for a shared dataset of runs. Only the
+//! built-in providers go out, under their catalog names and with figures alone; whatever the
+//! user configured (custom servers, names, their addresses) and the machine's own addresses are
+//! left out, whatever they look like. The client is described only by what --submit-include
+//! allows.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use clap::ValueEnum;
+use hickory_resolver::proto::rr::DNSClass;
+use serde::Serialize;
+use crate::providers::builtin_catalog;
+use crate::raw;
+use crate::results::TestResult;
+
+/// Layout version of the submission, independent of the JSON report's.
+pub const SUBMISSION_VERSION: u32 = 1;
+/// Tells the address the query came from: Cloudflare answers `whoami.cloudflare` in CHAOS with it.
+const WHOAMI_SERVER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(1, 1, 1, 1));
+const WHOAMI_NAME: &str = "whoami.cloudflare.";
+/// Team Cymru's IP-to-ASN mapping, asked for the reversed address under this zone.
+const ORIGIN_ZONE: &str = "origin.asn.cymru.com.";
+
+/// Information about the client that --submit-include may add to a submission.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ClientField {
+    /// The autonomous system number of the public address, e.g. 3320
+    Asn,
+    /// The country code registered for the public address, e.g. "DE"
+    Country,
+    /// The operating system, e.g. "linux"
+    Os,
+}
+
+/// The network the machine reaches the internet from. Its public address is looked up to find
+/// these and never kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientNetwork {
+    pub asn: u32,
+    pub country: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Submission {
+    pub submission_version: u32,
+    pub version: &'static str,
+    /// The day of the run in UTC, e.g. "2024-05-01"
+    pub date: String,
+    pub rounds: u32,
+    pub client: Client,
+    pub results: Vec<SubmittedResult>,
+}
+
+/// Only what --submit-include allows; the rest is absent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Client {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<&'static str>,
+}
+
+/// One built-in provider's figures over one transport.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SubmittedResult {
+    /// The name in the built-in catalog, not the one the run showed
+    pub provider: String,
+    /// "v4" or "v6"
+    pub ip_version: &'static str,
+    pub transport: String,
+    pub median_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub success_rate: f64,
+    pub queries: u32,
+}
+
+/// The submission of `results`, each kept only as [`redact`] allows, with the client fields of
+/// `include` that are known: `network` has the ASN and country when they could be looked up.
+pub fn submission(results: &[TestResult], rounds: u32, include: &[ClientField], network: Option<&ClientNetwork>, now: SystemTime) -> Submission {
+    let client = Client {
+        asn: network.filter(|_| include.contains(&ClientField::Asn)).map(|network| network.asn),
+        country: network.filter(|_| include.contains(&ClientField::Country)).map(|network| network.country.clone()),
+        os: include.contains(&ClientField::Os).then_some(std::env::consts::OS),
+    };
+    Submission {
+        submission_version: SUBMISSION_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        date: humantime::format_rfc3339_seconds(now).to_string()[..10].to_string(),
+        rounds,
+        client,
+        results: results.iter().filter_map(redact).collect(),
+    }
+}
+
+/// `result` as it may be submitted: only when every one of its addresses belongs to one and the
+/// same built-in provider, under that provider's catalog name. A custom server, the system
+/// resolver at a local address, a router or a built-in name on another address gives `None`.
+pub fn redact(result: &TestResult) -> Option<SubmittedResult> {
+    let addresses: Vec<IpAddr> = result.addresses.iter().map(|address| address.parse().ok()).collect::<Option<_>>()?;
+    let first = *addresses.first()?;
+    let catalog = builtin_catalog();
+    let (_, provider) = catalog.iter().find(|(_, provider)| provider.addresses.contains(&first))?;
+    if !addresses.iter().all(|address| provider.addresses.contains(address) && address.is_ipv4() == first.is_ipv4()) {
+        return None;
+    }
+    let ms = |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.0);
+    Some(SubmittedResult {
+        provider: provider.name.clone(),
+        ip_version: if first.is_ipv4() { "v4" } else { "v6" },
+        transport: result.transport.clone(),
+        median_ms: ms(result.median_duration),
+        p95_ms: ms(result.p95),
+        success_rate: result.success_rate,
+        queries: result.completed_queries,
+    })
+}
+
+/// Looks up the ASN and country of the machine's public IPv4 address over DNS, each query
+/// within `timeout`; `None` if either step gets no answer.
+pub(crate) async fn client_network(bind: Option<IpAddr>, timeout: Duration) -> Option<ClientNetwork> {
+    let public = raw::txt(WHOAMI_SERVER, 53, bind, WHOAMI_NAME, DNSClass::CH, timeout).await?;
+    let IpAddr::V4(public) = public.trim().parse::<IpAddr>().ok()? else { return None };
+    let octets = public.octets();
+    let name = format!("{}.{}.{}.{}.{}", octets[3], octets[2], octets[1], octets[0], ORIGIN_ZONE);
+    let origin = raw::txt(WHOAMI_SERVER, 53, bind, &name, DNSClass::IN, timeout).await?;
+    parse_origin(&origin)
+}
+
+/// "3320 | 91.0.0.0/10 | DE | ripencc | 2009-06-24", of which the first ASN (there may be
+/// several, separated by spaces) and the country.
+fn parse_origin(origin: &str) -> Option<ClientNetwork> {
+    let fields: Vec<&str> = origin.split('|').map(str::trim).collect();
+    let asn = fields.first()?.split_whitespace().next()?.parse().ok()?;
+    let country = fields.get(2).filter(|country| country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()))?;
+    Some(ClientNetwork { asn, country: country.to_ascii_uppercase() })
+}
@@ -0,0 +1,40 @@
+//! --submit: POSTs the redacted results (the library's `submission`) to a URL the user gives.
+//! There is no default endpoint and nothing is sent without the flag; a failure is reported
+//! but leaves the local results and the exit status alone unless --submit-required is set.
+
+use std::time::Duration;
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// For each of the DNS queries that find the ASN and country.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const ATTEMPTS: u32 = 3;
+/// Waited after the first failed attempt, and once more after each further one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// An http or https URL for --submit.
+pub fn parse_url(value: &str) -> Result<String, String> {
+    let url = value.trim();
+    match url.split_once("://") {
+        Some((scheme, rest)) if matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https") && !rest.is_empty() => Ok(url.to_string()),
+        _ => Err(format!("\"{}\" is not an http:// or https:// URL", value)),
+    }
+}
+
+/// POSTs `body` as JSON to `url`, up to `ATTEMPTS` times while the server cannot be reached or
+/// answers 429 or a 5xx status. Any other status is final.
+pub fn send(url: &str, body: &str) -> Result<(), String> {
+    let agent = ureq::AgentBuilder::new().timeout(SUBMIT_TIMEOUT).build();
+    let mut attempt = 1;
+    loop {
+        let error = match agent.post(url).set("Content-Type", "application/json").send_string(body) {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(status, _)) if status != 429 && status < 500 => return Err(format!("the server answered {}", status)),
+            Err(e) => e.to_string(),
+        };
+        if attempt == ATTEMPTS {
+            return Err(format!("{} (after {} attempts)", error, ATTEMPTS));
+        }
+        std::thread::sleep(RETRY_DELAY * attempt);
+        attempt += 1;
+    }
+}
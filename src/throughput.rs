@@ -0,0 +1,200 @@
+//! `--mode throughput`: one provider under sustained load. An open-loop generator sends
+//! cache-busted queries on a fixed schedule, whether or not earlier ones were answered, as many
+//! independent clients would, in steps of rising rate up to the target. A send whose slot comes
+//! while `max_in_flight` queries are still waiting is skipped and counted, so a slow provider
+//! cannot stretch the schedule.
+
+use std::net::IpAddr;
+use std::time::Duration;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hickory_resolver::proto::rr::RecordType;
+use serde::{Serialize, Serializer};
+use tokio::time::{sleep_until, Instant};
+use crate::measure::{build_resolver, lookup, random_label, resolver_opts};
+use crate::connector::Connector;
+use crate::providers::DnsProvider;
+use crate::results::{serialize_opt_ms, FailureKind};
+use crate::{stats, SpeedTestConfig};
+
+/// The highest rate a throughput test may ask for; more would be a flood, not a measurement.
+pub const MAX_TARGET_QPS: f64 = 1000.0;
+/// The load rises in this many equal steps, the last at the target rate.
+pub const THROUGHPUT_STEPS: u32 = 4;
+/// Queries waiting for an answer at most, however slow the provider gets.
+const MAX_IN_FLIGHT: usize = 512;
+
+/// How hard and how long to load the provider.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadProfile {
+    /// The rate of the last step, at most `MAX_TARGET_QPS`
+    pub target_qps: f64,
+    /// The whole test, split evenly between the steps
+    pub duration: Duration,
+}
+
+/// One provider's behaviour under load, step by step.
+#[derive(Clone, Debug, Serialize)]
+pub struct ThroughputResult {
+    pub provider: String,
+    pub address: IpAddr,
+    pub transport: String,
+    pub target_qps: f64,
+    #[serde(rename = "duration_s", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    pub max_in_flight: usize,
+    pub steps: Vec<ThroughputStep>,
+    /// Cut short by `SpeedTestConfig::cancel`; the last step covers the time it ran
+    pub stopped_early: bool,
+}
+
+/// The queries sent during one step of the load, wherever their answers fell.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ThroughputStep {
+    pub target_qps: f64,
+    /// How long the step sent queries for
+    #[serde(rename = "duration_s", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    pub sent: u32,
+    /// Sends left out because `max_in_flight` queries were still waiting
+    pub skipped: u32,
+    pub answered: u32,
+    pub timeouts: u32,
+    /// Failures other than timeouts, e.g. SERVFAIL or REFUSED from a rate limiter
+    pub errors: u32,
+    /// Answers per second of the step
+    pub achieved_qps: f64,
+    #[serde(rename = "p50_ms", serialize_with = "serialize_opt_ms")]
+    pub p50: Option<Duration>,
+    #[serde(rename = "p95_ms", serialize_with = "serialize_opt_ms")]
+    pub p95: Option<Duration>,
+    #[serde(rename = "p99_ms", serialize_with = "serialize_opt_ms")]
+    pub p99: Option<Duration>,
+    /// Percentage of the sent queries that timed out
+    pub timeout_rate: f64,
+    /// Percentage of the sent queries that failed otherwise
+    pub error_rate: f64,
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// A step's tally while its queries are still coming back.
+#[derive(Default)]
+struct Tally {
+    step: ThroughputStep,
+    latencies: Vec<Duration>,
+}
+
+impl Tally {
+    fn finish(mut self) -> ThroughputStep {
+        self.latencies.sort();
+        let step = self.step;
+        let sent = f64::from(step.sent.max(1));
+        ThroughputStep {
+            achieved_qps: f64::from(step.answered) / step.duration.as_secs_f64().max(f64::EPSILON),
+            p50: stats::percentile(&self.latencies, 50.0),
+            p95: stats::percentile(&self.latencies, 95.0),
+            p99: stats::percentile(&self.latencies, 99.0),
+            timeout_rate: f64::from(step.timeouts) / sent * 100.0,
+            error_rate: f64::from(step.errors) / sent * 100.0,
+            ..step
+        }
+    }
+}
+
+/// Loads the first address of `provider` over `cfg.transport` with cache-busted A queries for
+/// the test domains, `load.target_qps` at most. `on_step` gets the number and rate of every step
+/// as it starts. Cancelling `cfg.cancel` stops the sends at once; the queries in flight are
+/// still waited for, so the last step is complete as far as it got.
+pub async fn run_throughput(
+    provider: &DnsProvider,
+    cfg: &SpeedTestConfig,
+    load: LoadProfile,
+    on_step: &(dyn Fn(u32, f64) + Sync),
+) -> ThroughputResult {
+    let cfg = &*cfg.for_provider(provider);
+    let ip = provider.addresses[0];
+    let transport = cfg.transport;
+    let resolver = build_resolver(
+        &[ip], provider.port_for(transport), cfg.bind, resolver_opts(cfg), transport, provider.tls_host(transport),
+        Connector::new(provider.doh_path.as_deref()),
+    );
+    let target_qps = load.target_qps.min(MAX_TARGET_QPS);
+    let step_duration = load.duration / THROUGHPUT_STEPS;
+    // Enough for every query of the last step to wait out the timeout.
+    let max_in_flight = ((target_qps * cfg.timeout.as_secs_f64()).ceil() as usize).clamp(1, MAX_IN_FLIGHT);
+
+    let query = |step: usize, qname: String| {
+        let resolver = &resolver;
+        async move {
+            let start = Instant::now();
+            let outcome = lookup(resolver, &qname, RecordType::A, true).await.map_err(|e| FailureKind::of(&e));
+            (step, start.elapsed(), outcome)
+        }
+    };
+    let mut domains = cfg.domains.iter().cycle();
+    let mut tallies: Vec<Tally> = Vec::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut stopped_early = false;
+    let record = |tallies: &mut Vec<Tally>, (step, duration, outcome): (usize, Duration, Result<_, FailureKind>)| {
+        let tally: &mut Tally = &mut tallies[step];
+        match outcome {
+            Ok(_) => {
+                tally.step.answered += 1;
+                tally.latencies.push(duration);
+            },
+            Err(FailureKind::Timeout) => tally.step.timeouts += 1,
+            Err(_) => tally.step.errors += 1,
+        }
+    };
+
+    for step in 0..THROUGHPUT_STEPS as usize {
+        let rate = target_qps * (step + 1) as f64 / f64::from(THROUGHPUT_STEPS);
+        let interval = Duration::from_secs_f64(1.0 / rate);
+        tallies.push(Tally { step: ThroughputStep { target_qps: rate, ..Default::default() }, latencies: Vec::new() });
+        on_step(step as u32 + 1, rate);
+        let start = Instant::now();
+        let end = start + step_duration;
+        let mut next = start;
+        while next < end {
+            tokio::select! {
+                biased;
+                _ = cfg.cancel.cancelled() => {
+                    stopped_early = true;
+                    break;
+                },
+                Some(done) = in_flight.next(), if !in_flight.is_empty() => record(&mut tallies, done),
+                _ = sleep_until(next) => {
+                    if in_flight.len() < max_in_flight {
+                        let domain = domains.next().map_or("example.com", String::as_str);
+                        in_flight.push(query(step, format!("{}.{}", random_label(), domain)));
+                        tallies[step].step.sent += 1;
+                    } else {
+                        tallies[step].step.skipped += 1;
+                    }
+                    next += interval;
+                },
+            }
+        }
+        // The rest of this step's answers come in while the next one sends.
+        tallies[step].step.duration = start.elapsed().min(step_duration);
+        if stopped_early {
+            break;
+        }
+    }
+    while let Some(done) = in_flight.next().await {
+        record(&mut tallies, done);
+    }
+
+    ThroughputResult {
+        provider: provider.name.clone(),
+        address: ip,
+        transport: transport.label().to_string(),
+        target_qps,
+        duration: load.duration,
+        max_in_flight,
+        steps: tallies.into_iter().map(Tally::finish).collect(),
+        stopped_early,
+    }
+}
@@ -0,0 +1,306 @@
+//! --tui: a full-screen view of the run, with results that fill in and re-sort as providers
+//! finish, overall progress and the latest latencies of the provider being tested.
+
+use std::time::Duration;
+use clap::ValueEnum;
+use dns_speed_test::{compare_by, run_all_with_progress, Progress, SampleStatus, SortKey, SpeedTestConfig, TestResult};
+use ratatui::crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use crate::Unit;
+
+/// Latencies kept per provider for the sparkline; older ones scroll out.
+const SPARKLINE_LENGTH: usize = 200;
+/// How long the key reader waits for input before checking whether the run is over.
+const KEY_POLL: Duration = Duration::from_millis(100);
+
+/// What the run and the keyboard report to the screen, owned so it can cross the channel.
+enum Event {
+    Started(String),
+    Query { provider: String, query: u32, total: u32 },
+    QueryDone { provider: String, duration: Option<Duration> },
+    Finished(String, Vec<TestResult>),
+    Quit,
+    CycleSort,
+}
+
+/// A provider still being tested.
+struct Running {
+    name: String,
+    query: u32,
+    total: u32,
+    /// Answered queries, in order
+    latencies: Vec<Duration>,
+    failed: u32,
+}
+
+/// How the run ended.
+pub struct Outcome {
+    /// Ranked by `sort`; only the providers that finished if the run was stopped
+    pub results: Vec<TestResult>,
+    /// The sort in effect when the view closed
+    pub sort: SortKey,
+    pub stopped: bool,
+    /// Providers that finished, which with several rows per provider is fewer than `results`
+    pub tested: usize,
+}
+
+struct App {
+    providers: usize,
+    unit: Unit,
+    seed: Option<u64>,
+    sort: SortKey,
+    finished: Vec<TestResult>,
+    finished_providers: usize,
+    running: Vec<Running>,
+    /// The provider whose query completed last, shown in the sparkline
+    current: Option<String>,
+}
+
+impl App {
+    /// Applies one event; returns false when the user asked to stop.
+    fn handle(&mut self, event: Event) -> bool {
+        match event {
+            Event::Started(name) => {
+                self.current.get_or_insert_with(|| name.clone());
+                self.running.push(Running { name, query: 0, total: 0, latencies: Vec::new(), failed: 0 });
+            },
+            Event::Query { provider, query, total } => {
+                if let Some(running) = self.running.iter_mut().find(|running| running.name == provider) {
+                    running.query = query;
+                    running.total = total;
+                }
+            },
+            Event::QueryDone { provider, duration } => {
+                if let Some(running) = self.running.iter_mut().find(|running| running.name == provider) {
+                    match duration {
+                        Some(duration) => running.latencies.push(duration),
+                        None => running.failed += 1,
+                    }
+                    self.current = Some(provider);
+                }
+            },
+            Event::Finished(provider, rows) => {
+                self.running.retain(|running| running.name != provider);
+                self.finished.extend(rows);
+                self.finished_providers += 1;
+                self.sort_results();
+            },
+            Event::CycleSort => {
+                let keys = SortKey::value_variants();
+                let next = keys.iter().position(|&key| key == self.sort).map_or(0, |index| (index + 1) % keys.len());
+                self.sort = keys[next];
+                self.sort_results();
+            },
+            Event::Quit => return false,
+        }
+        true
+    }
+
+    fn sort_results(&mut self) {
+        let (sort, seed) = (self.sort, self.seed);
+        self.finished.sort_by(|a, b| compare_by(a, b, sort, seed));
+    }
+
+    /// Finished providers count whole, running ones by the share of their queries sent.
+    fn progress(&self) -> f64 {
+        let running: f64 = self.running.iter()
+            .filter(|running| running.total > 0)
+            .map(|running| f64::from(running.query.min(running.total)) / f64::from(running.total))
+            .sum();
+        ((self.finished_providers as f64 + running) / self.providers.max(1) as f64).min(1.0)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [title, gauge, table, sparkline] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(7),
+        ]).areas(frame.area());
+
+        let sort = self.sort.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        frame.render_widget(
+            Paragraph::new(format!("DNS Speed Test    q: stop and show the results    s: sort (by {})", sort)),
+            title,
+        );
+
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Progress"))
+                .ratio(self.progress())
+                .label(format!("{}/{} providers", self.finished_providers, self.providers)),
+            gauge,
+        );
+
+        let unit = self.unit;
+        let latency = |duration: Duration| unit.format(duration);
+        let mut rows: Vec<Row> = self.finished.iter().map(|result| {
+            let value = |duration: Option<Duration>| duration.map_or("-".to_string(), latency);
+            Row::new(vec![
+                result.provider.clone(),
+                result.score.map_or("-".to_string(), |score| format!("{:.2}", score)),
+                value(result.median_duration),
+                value(result.avg_duration),
+                value(result.min_latency),
+                value(result.max_latency),
+                format!("{:.1}%", result.success_rate),
+                "done".to_string(),
+            ])
+        }).collect();
+        // Live numbers of the providers still running, below the ranked ones.
+        rows.extend(self.running.iter().map(|running| {
+            let mut sorted = running.latencies.clone();
+            sorted.sort();
+            let median = sorted.get(sorted.len() / 2).map_or("-".to_string(), |&median| latency(median));
+            let queries = running.latencies.len() as u32 + running.failed;
+            let success = if queries == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.1}%", f64::from(running.latencies.len() as u32) / f64::from(queries) * 100.0)
+            };
+            Row::new(vec![
+                running.name.clone(),
+                String::new(),
+                median,
+                String::new(),
+                sorted.first().map_or(String::new(), |&min| latency(min)),
+                sorted.last().map_or(String::new(), |&max| latency(max)),
+                success,
+                format!("testing {}/{}", running.query, running.total),
+            ]).style(Style::default().add_modifier(Modifier::DIM))
+        }));
+        let header = Row::new(vec![
+            "Provider".to_string(),
+            "Score".to_string(),
+            unit.heading("Median"),
+            unit.heading("Avg"),
+            unit.heading("Min"),
+            unit.heading("Max"),
+            "Success".to_string(),
+            "Status".to_string(),
+        ]).style(Style::default().add_modifier(Modifier::BOLD));
+        let widths = [
+            Constraint::Min(24),
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(9),
+            Constraint::Length(16),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths).header(header).block(Block::bordered().title("Results")),
+            table,
+        );
+
+        let current = self.current.as_ref()
+            .and_then(|name| self.running.iter().find(|running| &running.name == name));
+        let (name, data): (&str, Vec<u64>) = match current {
+            Some(running) => {
+                let start = running.latencies.len().saturating_sub(SPARKLINE_LENGTH);
+                (&running.name, running.latencies[start..].iter().map(|latency| latency.as_micros() as u64).collect())
+            },
+            None => ("", Vec::new()),
+        };
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!("Recent latencies {}", name)))
+                .data(&data),
+            sparkline,
+        );
+    }
+}
+
+/// Forwards q, Esc and Ctrl-C as `Quit` and s as `CycleSort` until the screen goes away.
+fn read_keys(events: UnboundedSender<Event>) {
+    while !events.is_closed() {
+        if !event::poll(KEY_POLL).unwrap_or(false) {
+            continue;
+        }
+        let Ok(event::Event::Key(key)) = event::read() else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let event = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => Event::Quit,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Event::Quit,
+            KeyCode::Char('s') => Event::CycleSort,
+            _ => continue,
+        };
+        events.send(event).ok();
+    }
+}
+
+/// Runs the test behind the full-screen view and closes it when the run is over or the user
+/// stops it. The terminal is restored before returning, also on a stopped run.
+pub async fn run(cfg: &SpeedTestConfig, sort: SortKey, unit: Unit) -> std::io::Result<Outcome> {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let run_events = sender.clone();
+    let run_cfg = cfg.clone();
+    let mut test = tokio::spawn(async move {
+        let on_progress = |progress: Progress| {
+            let event = match progress {
+                Progress::Started(provider) => Event::Started(provider.name.clone()),
+                Progress::Query(update) => Event::Query {
+                    provider: update.provider.to_string(),
+                    query: update.query,
+                    total: update.total,
+                },
+                Progress::QueryDone(outcome) => Event::QueryDone {
+                    provider: outcome.provider.to_string(),
+                    duration: outcome.sample.duration.filter(|_| outcome.sample.status != SampleStatus::Failed),
+                },
+                Progress::Finished(provider, rows) => Event::Finished(provider.name.clone(), rows.to_vec()),
+            };
+            run_events.send(event).ok();
+        };
+        run_all_with_progress(&run_cfg, &on_progress).await
+    });
+    let keys = std::thread::spawn(move || read_keys(sender));
+
+    let mut terminal = ratatui::init();
+    let mut app = App {
+        providers: cfg.providers.len(),
+        unit,
+        seed: cfg.tie_break_seed,
+        sort,
+        finished: Vec::new(),
+        finished_providers: 0,
+        running: Vec::new(),
+        current: None,
+    };
+    let drawn = loop {
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(e);
+        }
+        tokio::select! {
+            results = &mut test => break Ok(results.ok()),
+            Some(event) = events.recv() => if !app.handle(event) {
+                // Marks the report as partial; no need to wait for the queries in flight.
+                cfg.cancel.cancel();
+                test.abort();
+                break Ok(None);
+            },
+        }
+    };
+    ratatui::restore();
+    // Closing the channel ends the key reader at its next poll.
+    drop(events);
+    keys.join().ok();
+
+    // Complete results keep the run's ranking unless another sort was picked.
+    Ok(match drawn? {
+        Some(mut results) => {
+            if app.sort != sort {
+                results.sort_by(|a, b| compare_by(a, b, app.sort, cfg.tie_break_seed));
+            }
+            Outcome { results, sort: app.sort, stopped: false, tested: app.providers }
+        },
+        None => Outcome { tested: app.finished_providers, results: app.finished, sort: app.sort, stopped: true },
+    })
+}
@@ -0,0 +1,79 @@
+//! --resolve-upstreams: the servers systemd-resolved forwards to, so they can be tested next to
+//! its stub at 127.0.0.53, whose cache makes it look faster than any provider. `resolvectl dns`
+//! lists them; without it, the resolv.conf that systemd-resolved keeps of its upstream servers
+//! does.
+
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+
+const DNS_PORT: u16 = 53;
+
+/// The upstream servers systemd-resolved keeps listed here, whichever resolv.conf mode it is in.
+const UPSTREAM_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// systemd-resolved's upstream servers, in the order it lists them. An error says why they
+/// cannot be found; none are configured if the list is empty.
+pub(crate) fn discover() -> Result<Vec<SocketAddr>, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("systemd-resolved only runs on Linux".to_string());
+    }
+    discover_with(output, |path| std::fs::read_to_string(path).ok())
+}
+
+/// `discover` with the command runner and file reader passed in.
+pub(crate) fn discover_with(run: impl Fn(&[&str]) -> Option<String>, read: impl Fn(&str) -> Option<String>) -> Result<Vec<SocketAddr>, String> {
+    if let Some(listing) = run(&["resolvectl", "dns"]) {
+        return Ok(parse_resolvectl_dns(&listing));
+    }
+    read(UPSTREAM_RESOLV_CONF)
+        .map(|text| parse_resolv_conf(&text))
+        .ok_or_else(|| format!("resolvectl did not run and {} cannot be read", UPSTREAM_RESOLV_CONF))
+}
+
+/// What `argv` wrote to stdout, if it ran and succeeded.
+fn output(argv: &[&str]) -> Option<String> {
+    let output = Command::new(argv[0]).args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The servers of `resolvectl dns`, global ones first, without duplicates:
+///
+/// ```text
+/// Global: 1.1.1.1#cloudflare-dns.com
+/// Link 2 (eth0): 192.168.1.1 fe80::1%eth0 [2001:db8::53]:5353
+/// ```
+///
+/// The server name after `#` is dropped. Addresses with a scope such as `%eth0` are left out,
+/// as testing them would need the interface.
+pub fn parse_resolvectl_dns(listing: &str) -> Vec<SocketAddr> {
+    let mut servers = Vec::new();
+    for line in listing.lines() {
+        // The label ends at the first colon; IPv6 addresses come after it.
+        let Some((_, list)) = line.split_once(':') else { continue };
+        for word in list.split_whitespace() {
+            let address = word.split('#').next().unwrap_or_default();
+            let server = address.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DNS_PORT)).or_else(|_| address.parse::<SocketAddr>());
+            if let Some(server) = server.ok().filter(|server| !servers.contains(server)) {
+                servers.push(server);
+            }
+        }
+    }
+    servers
+}
+
+/// The `nameserver` lines of a resolv.conf.
+pub fn parse_resolv_conf(text: &str) -> Vec<SocketAddr> {
+    let mut servers = Vec::new();
+    for line in text.lines() {
+        if let ["nameserver", address] = line.split_whitespace().collect::<Vec<_>>()[..] {
+            if let Some(server) = address.parse().ok().map(|ip| SocketAddr::new(ip, DNS_PORT)).filter(|server| !servers.contains(server)) {
+                servers.push(server);
+            }
+        }
+    }
+    servers
+}
@@ -0,0 +1,297 @@
+//! Whether the queries of a run leave through a VPN tunnel, which would make the comparison
+//! measure the tunnel and where it exits more than the providers. Two things are looked at: the
+//! tunnel interfaces that are up, found per platform (the kernel's interface types on Linux,
+//! `ifconfig` on macOS and the BSDs, `ipconfig /all` on Windows), and the local address the
+//! kernel picks for each provider address. Everything here is best effort: what cannot be read
+//! is left out, and nothing is sent.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::process::{Command, Stdio};
+use serde::Serialize;
+
+/// Where Linux describes each interface, by name.
+const SYS_CLASS_NET: &str = "/sys/class/net";
+/// ARPHRD_PPP and ARPHRD_NONE, which WireGuard and tun devices have, from `if_arp.h`.
+const ARPHRD_PPP: u16 = 512;
+const ARPHRD_NONE: u16 = 65534;
+/// ARPHRD_TUNNEL, ARPHRD_TUNNEL6, ARPHRD_SIT, ARPHRD_IPGRE and ARPHRD_IP6GRE: IP in IP.
+const ARPHRD_IP_TUNNELS: &[u16] = &[768, 769, 776, 778, 823];
+/// Names, or parts of adapter descriptions, of the common VPN clients, lower case.
+const KNOWN_CLIENTS: &[(&str, &str)] = &[
+    ("wireguard", "WireGuard"),
+    ("tailscale", "Tailscale"),
+    ("zerotier", "ZeroTier"),
+    ("nordlynx", "NordVPN"),
+    ("mullvad", "Mullvad"),
+    ("proton", "Proton VPN"),
+    ("openvpn", "OpenVPN"),
+    ("tap-windows", "OpenVPN"),
+    ("anyconnect", "Cisco AnyConnect"),
+    ("fortinet", "FortiClient"),
+    ("pangp", "GlobalProtect"),
+    ("globalprotect", "GlobalProtect"),
+];
+/// Interface name prefixes of tunnels on Unix, with what they are unless a client is known.
+const TUNNEL_PREFIXES: &[(&str, &str)] = &[
+    ("wg", "WireGuard"),
+    ("zt", "ZeroTier"),
+    ("tailscale", "Tailscale"),
+    ("utun", "VPN"),
+    ("tun", "VPN"),
+    ("tap", "VPN"),
+    ("ipsec", "IPsec"),
+    ("ppp", "PPP"),
+];
+
+/// A tunnel interface that is up and has an address.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Tunnel {
+    pub interface: String,
+    /// The VPN client or kind of tunnel, e.g. "WireGuard", or "VPN" when it cannot be told
+    pub kind: &'static str,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// How queries to one provider address leave the machine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Egress {
+    /// The local address the kernel picks for them
+    pub source: IpAddr,
+    /// The interface that has `source`; `None` if it could not be found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// The kind of tunnel `interface` is, `None` for a direct path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<&'static str>,
+}
+
+/// What [`check_vpn`](crate::check_vpn) found.
+#[derive(Clone, Debug, Default)]
+pub struct VpnCheck {
+    pub tunnels: Vec<Tunnel>,
+    /// Per provider address, leaving out those the kernel has no route to
+    pub egress: BTreeMap<IpAddr, Egress>,
+}
+
+impl VpnCheck {
+    /// Whether queries to any of the addresses go through a tunnel.
+    pub fn detected(&self) -> bool {
+        self.egress.values().any(|egress| egress.tunnel.is_some())
+    }
+}
+
+pub(crate) fn check(addresses: impl IntoIterator<Item = IpAddr>, bind: Option<IpAddr>) -> VpnCheck {
+    let tunnels = tunnels();
+    let interfaces: Vec<(String, IpAddr)> = if_addrs::get_if_addrs().unwrap_or_default().into_iter()
+        .map(|interface| (interface.name.clone(), interface.ip()))
+        .collect();
+    let mut egress = BTreeMap::new();
+    for address in addresses {
+        if egress.contains_key(&address) {
+            continue;
+        }
+        let Some(source) = source_for(address, bind) else { continue };
+        let interface = interfaces.iter().find(|(_, ip)| *ip == source).map(|(name, _)| name.clone());
+        let tunnel = tunnels.iter()
+            .find(|tunnel| tunnel.addresses.contains(&source) || interface.as_ref() == Some(&tunnel.interface))
+            .map(|tunnel| tunnel.kind);
+        egress.insert(address, Egress { source, interface, tunnel });
+    }
+    VpnCheck { tunnels, egress }
+}
+
+/// The local address the kernel would send a packet to `destination` from, from `bind` if
+/// given. Connecting a UDP socket picks the route without sending anything.
+fn source_for(destination: IpAddr, bind: Option<IpAddr>) -> Option<IpAddr> {
+    let local = match bind {
+        Some(bind) if bind.is_ipv4() != destination.is_ipv4() => return None,
+        Some(bind) => bind,
+        None if destination.is_ipv4() => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        None => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).ok()?;
+    socket.connect(SocketAddr::new(destination, 53)).ok()?;
+    socket.local_addr().ok().map(|address| address.ip())
+}
+
+/// The tunnel interfaces that are up, as far as this platform tells.
+fn tunnels() -> Vec<Tunnel> {
+    if cfg!(target_os = "linux") {
+        linux_tunnels()
+    } else if cfg!(windows) {
+        output(&["ipconfig", "/all"]).map(|output| parse_ipconfig(&output)).unwrap_or_default()
+    } else if cfg!(unix) {
+        output(&["ifconfig"]).map(|output| parse_ifconfig(&output)).unwrap_or_default()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The interfaces with an address whose type in /sys/class/net is a tunnel's.
+fn linux_tunnels() -> Vec<Tunnel> {
+    let mut tunnels: Vec<Tunnel> = Vec::new();
+    for interface in if_addrs::get_if_addrs().unwrap_or_default() {
+        if interface.is_loopback() {
+            continue;
+        }
+        if let Some(tunnel) = tunnels.iter_mut().find(|tunnel| tunnel.interface == interface.name) {
+            tunnel.addresses.push(interface.ip());
+            continue;
+        }
+        let arphrd = std::fs::read_to_string(format!("{}/{}/type", SYS_CLASS_NET, interface.name)).ok();
+        if let Some(kind) = linux_tunnel(&interface.name, arphrd.as_deref()) {
+            tunnels.push(Tunnel { interface: interface.name.clone(), kind, addresses: vec![interface.ip()] });
+        }
+    }
+    tunnels
+}
+
+/// What the Linux interface `name` is a tunnel of, given the text of its
+/// `/sys/class/net/<name>/type` (e.g. "65534\n"), `None` if it is none. Without the type, by
+/// the name alone.
+pub fn linux_tunnel(name: &str, arphrd: Option<&str>) -> Option<&'static str> {
+    let Some(arphrd) = arphrd.and_then(|arphrd| arphrd.trim().parse::<u16>().ok()) else {
+        return by_prefix(name);
+    };
+    let generic = match arphrd {
+        ARPHRD_PPP => "PPP",
+        ARPHRD_NONE => "VPN",
+        _ if ARPHRD_IP_TUNNELS.contains(&arphrd) => "IP tunnel",
+        _ => return None,
+    };
+    Some(client(name).or_else(|| by_prefix(name)).unwrap_or(generic))
+}
+
+/// The tunnels in the output of `ifconfig` on macOS and the BSDs: interfaces that are up and
+/// running, point-to-point or named like a tunnel, with an address other than a link-local one
+/// (macOS keeps a few `utun` interfaces with only those for its own services). The address
+/// lines are indented with a tab:
+///
+/// ```text
+/// utun4: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1280
+///     inet 100.101.102.103 --> 100.101.102.103 netmask 0xffffffff
+/// ```
+pub fn parse_ifconfig(output: &str) -> Vec<Tunnel> {
+    let mut tunnels = Vec::new();
+    let mut current: Option<(Tunnel, bool)> = None;
+    let finish = |current: Option<(Tunnel, bool)>, tunnels: &mut Vec<Tunnel>| {
+        if let Some((tunnel, true)) = current.filter(|(tunnel, _)| !tunnel.addresses.is_empty()) {
+            tunnels.push(tunnel);
+        }
+    };
+    for line in output.lines() {
+        // An interface's header starts at the left margin.
+        if !line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+            finish(current.take(), &mut tunnels);
+            let Some((name, rest)) = line.split_once(": ") else { continue };
+            let flags = rest.split_once('<').and_then(|(_, flags)| flags.split_once('>')).map_or("", |(flags, _)| flags);
+            let flags: Vec<&str> = flags.split(',').collect();
+            let up = flags.contains(&"UP") && flags.contains(&"RUNNING");
+            let kind = client(name).or_else(|| by_prefix(name)).or_else(|| flags.contains(&"POINTOPOINT").then_some("VPN"));
+            current = kind.map(|kind| (Tunnel { interface: name.to_string(), kind, addresses: Vec::new() }, up));
+            continue;
+        }
+        let Some((tunnel, _)) = current.as_mut() else { continue };
+        let mut words = line.split_whitespace();
+        if let (Some("inet" | "inet6"), Some(address)) = (words.next(), words.next()) {
+            // Scoped IPv6 addresses carry their interface, e.g. "fe80::1%utun0".
+            match address.split('%').next().unwrap_or(address).parse::<IpAddr>() {
+                Ok(address) if !link_local(address) => tunnel.addresses.push(address),
+                _ => {},
+            }
+        }
+    }
+    finish(current, &mut tunnels);
+    for tunnel in &mut tunnels {
+        // Tailscale on macOS is one `utun` among others, told apart by its CGNAT address.
+        if tunnel.kind == "VPN" && tunnel.addresses.iter().any(tailscale_address) {
+            tunnel.kind = "Tailscale";
+        }
+    }
+    tunnels
+}
+
+/// The tunnels in the output of `ipconfig /all`: adapters whose name or description is that of
+/// a VPN client, or PPP adapters, with their addresses:
+///
+/// ```text
+/// Unknown adapter Tailscale:
+///
+///    Description . . . . . . . . . . . : Tailscale Tunnel
+///    IPv4 Address. . . . . . . . . . . : 100.101.102.103(Preferred)
+/// ```
+///
+/// The labels are those of an English Windows; disconnected adapters have no addresses and are
+/// left out.
+pub fn parse_ipconfig(output: &str) -> Vec<Tunnel> {
+    let mut tunnels = Vec::new();
+    // The adapter's name, whether it is a PPP adapter, its description and addresses.
+    let mut current: Option<(String, bool, String, Vec<IpAddr>)> = None;
+    let finish = |current: Option<(String, bool, String, Vec<IpAddr>)>, tunnels: &mut Vec<Tunnel>| {
+        let Some((interface, ppp, description, addresses)) = current.filter(|(_, _, _, addresses)| !addresses.is_empty()) else { return };
+        let kind = client(&description).or_else(|| client(&interface)).or_else(|| ppp.then_some("PPP"));
+        if let Some(kind) = kind {
+            tunnels.push(Tunnel { interface, kind, addresses });
+        }
+    };
+    for line in output.lines() {
+        if !line.starts_with(' ') && !line.trim().is_empty() {
+            finish(current.take(), &mut tunnels);
+            // "Ethernet adapter Ethernet 2:", "PPP adapter Office VPN:"
+            current = line.trim().trim_end_matches(':').split_once(" adapter ")
+                .map(|(kind, name)| (name.to_string(), kind == "PPP", String::new(), Vec::new()));
+            continue;
+        }
+        let Some((_, _, description, addresses)) = current.as_mut() else { continue };
+        let Some((label, value)) = line.trim().split_once(" : ") else { continue };
+        let label = label.trim_end_matches([' ', '.']);
+        if label == "Description" {
+            *description = value.trim().to_string();
+        } else if label.ends_with("IPv4 Address") || label.ends_with("IPv6 Address") {
+            // "100.101.102.103(Preferred)", "fe80::1%12(Preferred)"
+            let address = value.trim().split(['(', '%']).next().unwrap_or_default();
+            match address.parse::<IpAddr>() {
+                Ok(address) if !link_local(address) => addresses.push(address),
+                _ => {},
+            }
+        }
+    }
+    finish(current, &mut tunnels);
+    tunnels
+}
+
+/// The VPN client whose name `text` contains, e.g. an interface name or adapter description.
+fn client(text: &str) -> Option<&'static str> {
+    let text = text.to_ascii_lowercase();
+    KNOWN_CLIENTS.iter().find(|(needle, _)| text.contains(needle)).map(|&(_, kind)| kind)
+}
+
+/// What a Unix interface named like a tunnel, e.g. "wg0" or "utun3", is.
+fn by_prefix(name: &str) -> Option<&'static str> {
+    TUNNEL_PREFIXES.iter()
+        .find(|(prefix, _)| name.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_digit())))
+        .map(|&(_, kind)| kind)
+}
+
+fn link_local(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+/// Tailscale gives its nodes addresses in 100.64.0.0/10.
+fn tailscale_address(address: &IpAddr) -> bool {
+    matches!(address, IpAddr::V4(v4) if v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64)
+}
+
+/// What `argv` wrote to stdout, if it ran and succeeded.
+fn output(argv: &[&str]) -> Option<String> {
+    let output = Command::new(argv[0]).args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
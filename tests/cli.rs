@@ -0,0 +1,669 @@
+//! The binary itself: where its settings come from, how it takes the domains it is given and
+//! what its exit status says. Every run gets
+//! a home of its own, so no config file, history or DNSSPEEDTEST_* variable of the machine
+//! running the tests gets in.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use common::{silent_server, Behavior, MockServer};
+use serde_json::Value;
+
+/// A home directory for one run of `test`, empty but for `config`, a config.toml if given.
+fn home(test: &str, config: Option<&str>) -> PathBuf {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let home = std::env::temp_dir().join(format!("dnsspeedtest-{}-{}-{}", test, std::process::id(), run));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(home.join("dnsspeedtest")).unwrap();
+    if let Some(config) = config {
+        std::fs::write(home.join("dnsspeedtest").join("config.toml"), config).unwrap();
+    }
+    home
+}
+
+/// The binary with `args`, living in `home`, which is its working directory too, with only the
+/// variables of `env` set, and stdin from /dev/null.
+fn command(home: &Path, env: &[(&str, &str)], args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dns_speed_test"));
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("DNSSPEEDTEST_") {
+            command.env_remove(key);
+        }
+    }
+    for dir in ["HOME", "XDG_CONFIG_HOME", "XDG_CACHE_HOME", "XDG_DATA_HOME", "XDG_STATE_HOME", "APPDATA", "LOCALAPPDATA"] {
+        command.env(dir, home);
+    }
+    command.envs(env.iter().copied()).args(args).current_dir(home).stdin(Stdio::null());
+    command
+}
+
+/// `command` run to its end.
+fn run(home: &Path, env: &[(&str, &str)], args: &[&str]) -> Output {
+    command(home, env, args).output().unwrap()
+}
+
+/// The `config` of the --dry-run plan a run with `config.toml`, `env` and `args` would make.
+fn plan(test: &str, config: Option<&str>, env: &[(&str, &str)], args: &[&str]) -> Value {
+    let home = home(test, config);
+    let output = run(&home, env, &[&["--dry-run", "--format", "json", "--no-system"], args].concat());
+    std::fs::remove_dir_all(&home).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    plan["config"].clone()
+}
+
+fn providers(plan: &Value) -> Vec<String> {
+    plan["providers"].as_array().unwrap().iter().map(|provider| provider["name"].as_str().unwrap().to_string()).collect()
+}
+
+const ROUNDS_FILE: &str = "rounds = 7\n";
+const SERVER_FILE: &str = "[[providers]]\nname = \"File\"\naddresses = [\"127.0.0.1\"]\n";
+
+#[test]
+fn rounds_default_without_file_variable_or_flag() {
+    assert_eq!(plan("rounds-default", None, &[], &[])["rounds"], 5);
+}
+
+#[test]
+fn rounds_come_from_the_file() {
+    assert_eq!(plan("rounds-file", Some(ROUNDS_FILE), &[], &[])["rounds"], 7);
+}
+
+#[test]
+fn the_rounds_variable_overrides_the_file() {
+    assert_eq!(plan("rounds-env", Some(ROUNDS_FILE), &[("DNSSPEEDTEST_ROUNDS", "8")], &[])["rounds"], 8);
+}
+
+#[test]
+fn the_rounds_flag_overrides_the_variable() {
+    assert_eq!(plan("rounds-flag", Some(ROUNDS_FILE), &[("DNSSPEEDTEST_ROUNDS", "8")], &["--rounds", "9"])["rounds"], 9);
+}
+
+/// The rounds, cooldown and number of domains a plan came to.
+fn pacing(plan: &Value) -> (u64, u64, usize) {
+    (plan["rounds"].as_u64().unwrap(), plan["cooldown_ms"].as_u64().unwrap(), plan["domains"].as_array().unwrap().len())
+}
+
+#[test]
+fn a_profile_sets_the_rounds_cooldown_and_domains() {
+    assert_eq!(pacing(&plan("profile-fast", None, &[], &["--profile", "fast"])), (2, 50, 5));
+    assert_eq!(pacing(&plan("profile-thorough", None, &[], &["--profile", "thorough"])), (10, 100, 10));
+    assert_eq!(pacing(&plan("profile-env", None, &[("DNSSPEEDTEST_PROFILE", "fast")], &[])), (2, 50, 5));
+    assert_eq!(plan("profile-name", None, &[], &["--profile", "fast"])["profile"], "fast");
+}
+
+#[test]
+fn the_file_overrides_the_profile() {
+    assert_eq!(pacing(&plan("profile-file", Some("rounds = 7\ncooldown = 20\n"), &[], &["--profile", "fast"])), (7, 20, 5));
+}
+
+#[test]
+fn the_variables_override_the_profile_and_the_file() {
+    let env = [("DNSSPEEDTEST_ROUNDS", "8"), ("DNSSPEEDTEST_COOLDOWN", "30")];
+    assert_eq!(pacing(&plan("profile-env-vars", None, &env, &["--profile", "fast"])), (8, 30, 5));
+    assert_eq!(pacing(&plan("profile-env-file", Some("rounds = 7\ncooldown = 20\n"), &env, &["--profile", "fast"])), (8, 30, 5));
+}
+
+#[test]
+fn the_flags_override_the_profile_the_file_and_the_variables() {
+    let env = [("DNSSPEEDTEST_ROUNDS", "8"), ("DNSSPEEDTEST_COOLDOWN", "30")];
+    let flags = ["--profile", "fast", "--rounds", "9", "--cooldown", "40"];
+    assert_eq!(pacing(&plan("profile-flags", Some("rounds = 7\ncooldown = 20\n"), &env, &flags)), (9, 40, 5));
+    // A flag left out leaves the layer below it in charge.
+    assert_eq!(pacing(&plan("profile-one-flag", Some("cooldown = 20\n"), &[], &["--profile", "fast", "--rounds", "9"])), (9, 20, 5));
+}
+
+#[test]
+fn servers_default_to_the_built_in_providers() {
+    let names = providers(&plan("server-default", None, &[], &[]));
+    assert!(names.iter().any(|name| name == "Google"), "{:?}", names);
+}
+
+#[test]
+fn servers_come_from_the_file() {
+    let names = providers(&plan("server-file", Some(SERVER_FILE), &[], &["--only-custom"]));
+    assert_eq!(names, ["File"]);
+}
+
+#[test]
+fn the_server_variable_overrides_the_file() {
+    let names = providers(&plan("server-env", Some(SERVER_FILE), &[("DNSSPEEDTEST_SERVER", "Env=127.0.0.2")], &["--only-custom"]));
+    assert_eq!(names, ["Env"]);
+}
+
+#[test]
+fn the_server_flag_overrides_the_variable() {
+    let env = [("DNSSPEEDTEST_SERVER", "Env=127.0.0.2")];
+    let names = providers(&plan("server-flag", Some(SERVER_FILE), &env, &["--only-custom", "--server", "Flag=127.0.0.3"]));
+    assert_eq!(names, ["Flag"]);
+}
+
+#[test]
+fn the_server_variable_is_a_comma_separated_list() {
+    let names = providers(&plan("server-list", None, &[("DNSSPEEDTEST_SERVER", "One=127.0.0.1,Two=127.0.0.2:5353")], &["--only-custom"]));
+    assert_eq!(names, ["One", "Two (port 5353)"]);
+}
+
+#[test]
+fn the_server_variable_is_checked_like_the_flag() {
+    let home = home("server-invalid", None);
+    let list = "One=127.0.0.1,Two=127.0.0.2:0";
+    let from_env = run(&home, &[("DNSSPEEDTEST_SERVER", list)], &["--dry-run", "--no-system"]);
+    let from_flag = run(&home, &[], &["--dry-run", "--no-system", "--server", list]);
+    std::fs::remove_dir_all(&home).unwrap();
+    assert_eq!((from_env.status.code(), from_flag.status.code()), (Some(1), Some(1)));
+    let stderr = String::from_utf8_lossy(&from_env.stderr);
+    assert!(stderr.contains("'127.0.0.2:0' has no valid port"), "{}", stderr);
+    assert_eq!(from_env.stderr, from_flag.stderr);
+}
+
+fn provider_order(plan: &Value) -> Vec<String> {
+    plan["provider_order"].as_array().unwrap().iter().map(|name| name.as_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn the_same_seed_tests_the_providers_in_the_same_order() {
+    let first = provider_order(&plan("order-seed", None, &[], &["--seed", "42"]));
+    let again = provider_order(&plan("order-seed-again", None, &[], &["--seed", "42"]));
+    assert_eq!(first, again);
+    let other = provider_order(&plan("order-seed-other", None, &[], &["--seed", "43"]));
+    assert_ne!(other, first);
+    // Every provider exactly once, whatever the seed.
+    let mut configured = providers(&plan("order-configured", None, &[], &[]));
+    configured.sort();
+    for mut order in [first, other] {
+        order.sort();
+        assert_eq!(order, configured);
+    }
+}
+
+#[test]
+fn no_shuffle_providers_keeps_the_configured_order() {
+    let fixed = plan("order-fixed", None, &[], &["--no-shuffle-providers", "--seed", "42"]);
+    assert_eq!(provider_order(&fixed), providers(&fixed));
+    let shuffled = plan("order-shuffled", None, &[], &["--seed", "42"]);
+    assert_ne!(provider_order(&fixed), provider_order(&shuffled));
+}
+
+/// The first line of what the binary says when it refuses a dry run with `config.toml`, `env`
+/// and `args`, checking that it exits with 1.
+fn rejected(test: &str, config: Option<&str>, env: &[(&str, &str)], args: &[&str]) -> String {
+    let home = home(test, config);
+    let output = run(&home, env, &[&["--dry-run", "--no-system"], args].concat());
+    std::fs::remove_dir_all(&home).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?} accepted", args);
+    String::from_utf8_lossy(&output.stderr).lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn zero_rounds_are_rejected_wherever_they_come_from() {
+    assert_eq!(rejected("rounds-zero", None, &[], &["--rounds", "0"]), "error: invalid value '0' for '--rounds <ROUNDS>': 0 is not in 1..=4294967295");
+    assert!(rejected("rounds-zero-env", None, &[("DNSSPEEDTEST_ROUNDS", "0")], &[]).contains("0 is not in 1..=4294967295"));
+    assert!(rejected("rounds-zero-file", Some("rounds = 0\n"), &[], &[]).ends_with("`rounds`: rounds must be at least 1"));
+}
+
+#[test]
+fn the_cooldown_stops_at_a_minute() {
+    assert_eq!(plan("cooldown-max", None, &[], &["--cooldown", "60000"])["cooldown_ms"], 60000);
+    assert_eq!(plan("cooldown-min", None, &[], &["--cooldown", "0"])["cooldown_ms"], 0);
+    assert!(rejected("cooldown-over", None, &[], &["--cooldown", "60001"]).ends_with("60001 is not in 0..=60000"));
+    assert!(rejected("cooldown-negative", None, &[], &["--cooldown=-1"]).contains("'-1'"));
+}
+
+#[test]
+fn the_timeout_is_more_than_zero_and_at_most_a_minute() {
+    assert_eq!(plan("timeout-max", None, &[], &["--timeout", "60s"])["timeout_ms"], 60000.0);
+    assert_eq!(plan("timeout-ms", None, &[], &["--timeout", "500ms"])["timeout_ms"], 500.0);
+    assert!(rejected("timeout-over", None, &[], &["--timeout", "61s"]).ends_with("timeout must be at most 60s"));
+    assert!(rejected("timeout-zero", None, &[], &["--timeout", "0"]).ends_with("timeout must be greater than zero"));
+}
+
+#[test]
+fn the_total_queries_are_rounds_times_domains_times_record_types() {
+    let two_domains = ["--domain", "example.com", "--domain", "example.org"];
+    let plan_of = |test, extra: &[&str]| plan(test, None, &[], &[&two_domains[..], extra].concat());
+    // --domain raises the default rounds, which the total follows.
+    let plan = plan_of("total-default", &[]);
+    assert_eq!(plan["total_queries"].as_u64(), plan["rounds"].as_u64().map(|rounds| rounds * 2));
+    let plan = plan_of("total-rounds", &["--rounds", "3"]);
+    assert_eq!((plan["rounds"].as_u64(), plan["total_queries"].as_u64()), (Some(3), Some(3 * 2)));
+    assert_eq!(plan_of("total-types", &["--rounds", "3", "--record-type", "A,AAAA,MX"])["total_queries"], 3 * 2 * 3);
+}
+
+/// The arguments of a quick run of one query to `server`, a --server value, alone, without the
+/// checks that would reach beyond it.
+fn quick_run(server: &str) -> Vec<&str> {
+    vec![
+        "--no-system", "--only-custom", "--server", server, "--domain", "example.com", "--rounds", "1", "--warmup", "0",
+        "--cooldown", "0", "--timeout", "300ms", "--tcp-check", "off", "--no-preflight", "--no-interception-check",
+        "--no-control-check", "--no-nxdomain-check", "--no-dnssec-check", "--no-pop-check", "--no-identity-check",
+        "--no-size-check", "--no-final-retry",
+    ]
+}
+
+/// A quiet `quick_run` against `address` with `extra` on top.
+fn run_against(test: &str, address: SocketAddr, extra: &[&str]) -> Output {
+    let home = home(test, None);
+    let server = format!("Mock={}", address);
+    let output = run(&home, &[], &[&quick_run(&server)[..], &["--quiet"], extra].concat());
+    std::fs::remove_dir_all(&home).unwrap();
+    output
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_answered_run_exits_with_0() {
+    let server = MockServer::start(Behavior::default()).await;
+    let output = run_against("exit-ok", server.address, &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(server.udp_queries() > 0);
+}
+
+#[test]
+fn a_bogus_server_exits_with_1() {
+    let home = home("exit-bogus", None);
+    let output = run(&home, &[], &["--no-system", "--server", "Bogus=127.0.0.1:99999"]);
+    std::fs::remove_dir_all(&home).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value 'Bogus=127.0.0.1:99999'"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_run_without_a_single_answer_exits_with_2() {
+    let (address, _task) = silent_server().await;
+    let output = run_against("exit-silent", address, &[]);
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_fastest_median_above_the_fail_threshold_exits_with_3() {
+    let server = MockServer::start(Behavior { latency: Duration::from_millis(50), ..Default::default() }).await;
+    let output = run_against("exit-slow", server.address, &["--fail-threshold", "10"]);
+    assert_eq!(output.status.code(), Some(3), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run_against("exit-fast-enough", server.address, &["--fail-threshold", "250"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_run_with_stdin_from_dev_null_does_not_wait_for_enter() {
+    let server = MockServer::start(Behavior::default()).await;
+    let address = format!("Mock={}", server.address);
+    // --pause asks for the prompt, which a closed stdin answers at once.
+    for extra in [&[][..], &["--pause"]] {
+        let home = home("stdin-closed", None);
+        let mut child = command(&home, &[], &[&quick_run(&address)[..], extra].concat()).stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap();
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            if start.elapsed() > Duration::from_secs(30) {
+                child.kill().unwrap();
+                panic!("still running after 30 s with {:?}", extra);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        std::fs::remove_dir_all(&home).unwrap();
+        assert_eq!(status.code(), Some(0), "with {:?}", extra);
+    }
+}
+
+/// What a --domain of `raw` is queried as and shown as, or why the binary rejected it.
+fn domain(raw: &str) -> Result<(String, String), String> {
+    let home = home("domain", None);
+    let output = run(&home, &[], &["--dry-run", "--format", "json", "--no-system", &format!("--domain={}", raw)]);
+    std::fs::remove_dir_all(&home).unwrap();
+    if !output.status.success() {
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().unwrap_or_default().rsplit(": ").next().unwrap_or_default().to_string());
+    }
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let queried = plan["config"]["domains"][0].as_str().unwrap().to_string();
+    // Only names that look different in Unicode have a display of their own.
+    let shown = plan["config"]["domains_display"][0].as_str().map_or_else(|| queried.clone(), str::to_string);
+    Ok((queried, shown))
+}
+
+fn queried(raw: &str) -> String {
+    domain(raw).unwrap_or_else(|error| panic!("{:?} rejected: {}", raw, error)).0
+}
+
+#[test]
+fn domains_are_lowercased() {
+    assert_eq!(queried("EXAMPLE.Com"), "example.com");
+}
+
+#[test]
+fn trailing_dots_are_dropped() {
+    assert_eq!(queried("example.com."), "example.com");
+    assert_eq!(queried("example.com.."), "example.com");
+}
+
+#[test]
+fn urls_are_cut_down_to_the_host() {
+    assert_eq!(queried("https://Example.com:8443/path?q=1"), "example.com");
+}
+
+#[test]
+fn internationalized_domains_are_queried_in_punycode_and_shown_in_unicode() {
+    assert_eq!(domain("bücher.de"), Ok(("xn--bcher-kva.de".to_string(), "bücher.de".to_string())));
+    assert_eq!(domain("xn--bcher-kva.de"), Ok(("xn--bcher-kva.de".to_string(), "bücher.de".to_string())));
+}
+
+#[test]
+fn garbage_is_rejected_with_the_reason() {
+    assert_eq!(domain(""), Err("empty".to_string()));
+    assert_eq!(domain("..."), Err("empty".to_string()));
+    assert_eq!(domain("com"), Err("a bare label, not a domain".to_string()));
+    assert_eq!(domain("exa mple.com"), Err("not a valid domain name".to_string()));
+    assert_eq!(domain("a..b.com"), Err("not a valid domain name".to_string()));
+    assert_eq!(domain("ü..de"), Err("not a valid internationalized domain name".to_string()));
+}
+
+#[test]
+fn uppercase_unicode_is_mapped_before_encoding() {
+    assert_eq!(domain("MÜNCHEN.DE"), Ok(("xn--mnchen-3ya.de".to_string(), "münchen.de".to_string())));
+}
+
+#[test]
+fn emoji_domains_are_mapped_by_uts_46() {
+    assert_eq!(domain("☕.example"), Ok(("xn--53h.example".to_string(), "☕.example".to_string())));
+}
+
+#[test]
+fn unicode_that_uts_46_disallows_is_rejected() {
+    // A right-to-left override, and the replacement character that stands in for broken input.
+    assert_eq!(domain("a\u{202e}b.com"), Err("not a valid internationalized domain name".to_string()));
+    assert_eq!(domain("\u{fffd}.com"), Err("not a valid internationalized domain name".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn providers_the_total_budget_left_no_time_for_are_listed_as_not_tested() {
+    let (first, _first) = silent_server().await;
+    let (second, _second) = silent_server().await;
+    let home = home("total-budget", None);
+    let (first, second) = (format!("One={}", first), format!("Two={}", second));
+    let args = [&quick_run(&first)[..], &["--server", &second, "--concurrency", "1", "--total-budget", "100ms"]].concat();
+    let output = run(&home, &[], &args);
+    std::fs::remove_dir_all(&home).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("not tested (time budget)"), "{}", stdout);
+    assert!(stdout.contains("(not tested, time budget)"), "{}", stdout);
+}
+
+/// A run against a server that leaves every other query unanswered, so half of the two domains'
+/// queries succeed, with --min-success at `min_success`.
+async fn half_answered(test: &str, min_success: &str, format: &str) -> (Output, MockServer) {
+    let server = MockServer::start(Behavior { drop_every: Some(2), ..Default::default() }).await;
+    let home = home(test, None);
+    let mock = format!("Mock={}", server.address);
+    let args = [&quick_run(&mock)[..], &["--domain", "example.org", "--min-success", min_success, "--format", format]].concat();
+    let output = run(&home, &[], &args);
+    std::fs::remove_dir_all(&home).unwrap();
+    (output, server)
+}
+
+/// The providers of the report rows in `rows`.
+fn names(rows: &Value) -> Vec<&str> {
+    rows.as_array().unwrap().iter().map(|row| row["provider"].as_str().unwrap()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_success_rate_exactly_at_min_success_passes() {
+    let (output, server) = half_answered("min-success-boundary", "50", "json").await;
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["results"][0]["success_rate"], 50.0);
+    assert_eq!(names(&report["results"]), [format!("Mock (port {})", server.address.port())]);
+    assert!(report["excluded"].as_array().is_none_or(Vec::is_empty), "{}", report["excluded"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn with_every_provider_below_min_success_the_best_unreliable_one_is_named() {
+    let (output, server) = half_answered("min-success-json", "50.5", "json").await;
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["config"]["min_success"], 50.5);
+    assert!(names(&report["results"]).is_empty());
+    assert_eq!(names(&report["excluded"]), [format!("Mock (port {})", server.address.port())]);
+
+    let (output, server) = half_answered("min-success-table", "50.5", "table").await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let name = format!("Mock (port {})", server.address.port());
+    // Flagged in the table, next to the "bogon answers" of the mock's documentation address.
+    assert!(stdout.contains(&format!("{} (unreliable", name)), "{}", stdout);
+    assert!(stdout.contains(&format!("No DNS provider reached the 50.5% success threshold; the best of the unreliable ones is {}", name)), "{}", stdout);
+}
+
+/// A JSON run of `rounds` rounds of two domains against two servers, one of which leaves every
+/// third query unanswered, with `extra` on top: the report and the home the run lived in.
+async fn two_servers(test: &str, rounds: &str, extra: &[&str]) -> (Value, PathBuf, [MockServer; 2]) {
+    let fine = MockServer::start(Behavior { latency: Duration::from_millis(5), ..Default::default() }).await;
+    let lossy = MockServer::start(Behavior { drop_every: Some(3), ..Default::default() }).await;
+    let home = home(test, None);
+    let (first, second) = (format!("Fine={}", fine.address), format!("Lossy={}", lossy.address));
+    let mut args = [&quick_run(&first)[..], &["--server", &second, "--domain", "example.org", "--format", "json"], extra].concat();
+    let at = args.iter().position(|&arg| arg == "--rounds").unwrap();
+    args[at + 1] = rounds;
+    let output = run(&home, &[], &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    (serde_json::from_slice(&output.stdout).unwrap(), home, [fine, lossy])
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_median_can_be_recomputed_from_the_samples_file() {
+    let (report, home, _servers) = two_servers("samples-median", "4", &["--samples", "samples.jsonl"]).await;
+    let lines = std::fs::read_to_string(home.join("samples.jsonl")).unwrap();
+    std::fs::remove_dir_all(&home).unwrap();
+    let samples: Vec<Value> = lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(samples.len(), 2 * 4 * 2);
+    for result in report["results"].as_array().unwrap().iter().chain(report["excluded"].as_array().into_iter().flatten()) {
+        let provider = result["provider"].as_str().unwrap();
+        let mut answered: Vec<f64> = samples.iter()
+            .filter(|sample| sample["provider"] == provider && sample["outcome"] == "ok")
+            .map(|sample| sample["duration_ms"].as_f64().unwrap())
+            .collect();
+        answered.sort_by(f64::total_cmp);
+        let middle = answered.len() / 2;
+        let median = if answered.len().is_multiple_of(2) { (answered[middle - 1] + answered[middle]) / 2.0 } else { answered[middle] };
+        let reported = result["median_ms"].as_f64().unwrap();
+        assert!((median - reported).abs() < 0.001, "{}: {} recomputed, {} reported", provider, median, reported);
+        let failed = samples.iter().filter(|sample| sample["provider"] == provider && sample["outcome"] == "timeout").count();
+        assert_eq!(answered.len() + failed, 8, "{}", provider);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_sample_export_has_a_row_per_provider_round_and_domain() {
+    let (report, home, [fine, lossy]) = two_servers("export-rows", "3", &["--export-samples", "samples.csv"]).await;
+    let csv = std::fs::read_to_string(home.join("samples.csv")).unwrap();
+    std::fs::remove_dir_all(&home).unwrap();
+    let rows: Vec<Vec<&str>> = csv.lines().skip(1).map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 2 * 3 * 2);
+    // By provider, round and domain, failed queries with their kind and no duration.
+    let names = [format!("Fine (port {})", fine.address.port()), format!("Lossy (port {})", lossy.address.port())];
+    let order: Vec<(&str, &str, &str)> = rows.iter().map(|row| (row[0], row[4], row[2])).collect();
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(order, sorted);
+    assert!(names.iter().all(|name| rows.iter().filter(|row| row[0] == name).count() == 6), "{:?}", names);
+    let failed: Vec<_> = rows.iter().filter(|row| row[5] == "failed").collect();
+    assert!(!failed.is_empty() && failed.iter().all(|row| row[6] == "timeout" && row[7].is_empty()), "{:?}", failed);
+    let completed: u64 = report["results"].as_array().unwrap().iter().chain(report["excluded"].as_array().into_iter().flatten())
+        .map(|result| result["completed_queries"].as_u64().unwrap())
+        .sum();
+    assert_eq!(completed, rows.len() as u64);
+}
+
+/// `text` without its ANSI color codes, as it shows on screen.
+fn visible(text: &str) -> String {
+    let mut shown = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            shown.push(c);
+        }
+    }
+    shown
+}
+
+/// The header and provider rows of the detailed table of a run against a fast server and a
+/// slow one, with --color `color`.
+async fn table_rows(color: &str) -> Vec<String> {
+    let fast = MockServer::start(Behavior::default()).await;
+    let slow = MockServer::start(Behavior { latency: Duration::from_millis(60), ..Default::default() }).await;
+    let home = home(&format!("color-{}", color), None);
+    let (first, second) = (format!("Fast={}", fast.address), format!("Slow={}", slow.address));
+    let output = run(&home, &[], &[&quick_run(&first)[..], &["--server", &second, "--format", "table", "--color", color]].concat());
+    std::fs::remove_dir_all(&home).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // Between the line under the title and the blank line after the last row, without the
+    // notes indented under a row.
+    stdout.lines()
+        .skip_while(|line| !line.starts_with("Detailed Results"))
+        .skip(2)
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.starts_with("---") && !line.starts_with("    "))
+        .map(String::from)
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn colored_cells_take_as_much_room_as_plain_ones() {
+    let colored = table_rows("always").await;
+    let plain = table_rows("never").await;
+    assert_eq!(colored.len(), 3, "{:?}", colored);
+    assert!(colored[1..].iter().all(|row| row.contains("\x1b[")), "{:?}", colored);
+    assert!(plain.iter().all(|row| !row.contains('\x1b')), "{:?}", plain);
+    let width = |row: &String| visible(row).chars().count();
+    let header = width(&plain[0]);
+    assert!(colored.iter().chain(&plain).all(|row| width(row) == header), "{:?} {:?}", colored, plain);
+}
+
+#[test]
+fn completions_cover_the_flags_for_every_shell() {
+    let home = home("completions", None);
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = run(&home, &[], &["completions", shell]);
+        assert!(output.status.success(), "{}: {}", shell, String::from_utf8_lossy(&output.stderr));
+        let script = String::from_utf8_lossy(&output.stdout);
+        for flag in ["rounds", "server", "format", "dry-run"] {
+            assert!(script.contains(flag), "{} completion without --{}", shell, flag);
+        }
+        assert!(!script.contains("DNS Speed Test"), "{} completion ran a test", shell);
+    }
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn the_man_page_lists_the_flags_with_their_defaults() {
+    let home = home("man", None);
+    let output = run(&home, &[], &["--man"]);
+    std::fs::remove_dir_all(&home).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let page = String::from_utf8_lossy(&output.stdout);
+    assert!(page.starts_with(".ie"), "{}", &page[..page.len().min(200)]);
+    for flag in ["rounds", "server", "format", "dry\\-run"] {
+        assert!(page.contains(&format!("\\-\\-{}", flag)), "man page without --{}", flag);
+    }
+    assert!(page.contains("[default: 5]"), "no default for --rounds");
+}
+
+/// --check against an answering server and a silent one, with `extra` on top.
+async fn check(test: &str, extra: &[&str]) -> (Output, MockServer) {
+    let server = MockServer::start(Behavior::default()).await;
+    let (silent, _task) = silent_server().await;
+    let home = home(test, None);
+    let (up, down) = (format!("Up={}", server.address), format!("Down={}", silent));
+    let args = [&["--no-system", "--only-custom", "--server", up.as_str(), "--server", down.as_str(), "--check", "--timeout", "300ms"][..], extra].concat();
+    let output = run(&home, &[], &args);
+    std::fs::remove_dir_all(&home).unwrap();
+    (output, server)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_lists_each_provider_as_reachable_or_not() {
+    let (output, server) = check("check-table", &[]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    let row = |name: &str| stdout.lines().find(|line| line.starts_with(name)).unwrap_or_else(|| panic!("no {} row in {}", name, stdout)).to_string();
+    let up = row(&format!("Up (port {})", server.address.port()));
+    assert!(up.contains(" reachable") && !up.contains("n/a"), "{}", up);
+    assert!(row("Down").contains("unreachable"), "{}", stdout);
+    assert!(stdout.contains("1 of 2 providers reachable"), "{}", stdout);
+    assert!(stdout.contains("Port 53 interception:"), "{}", stdout);
+    // No measurement: the check's query and the interception check's, and no report.
+    assert_eq!(server.udp_queries(), 2);
+    assert!(!stdout.contains("Detailed Results"), "{}", stdout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_fails_with_fewer_reachable_providers_than_asked_for() {
+    let (output, _server) = check("check-min", &["--min-reachable", "2"]).await;
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Fewer providers reachable than --min-reachable 2"));
+    let (output, _server) = check("check-min-met", &["--min-reachable", "1"]).await;
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn check_does_not_go_with_a_dry_run() {
+    let error = rejected("check-dry-run", None, &[], &["--check"]);
+    assert!(error.contains("cannot be used with"), "{}", error);
+}
+
+/// A run of two domains against a server that answers everything, one that leaves every other
+/// query unanswered and one that answers nothing, in `format`, with nobody excluded for their
+/// success rate.
+async fn fine_lossy_silent(test: &str, format: &str) -> (String, [String; 3]) {
+    let fine = MockServer::start(Behavior { latency: Duration::from_millis(5), ..Default::default() }).await;
+    let lossy = MockServer::start(Behavior { drop_every: Some(2), ..Default::default() }).await;
+    let (silent, _task) = silent_server().await;
+    let home = home(test, None);
+    let servers = [format!("Fine={}", fine.address), format!("Lossy={}", lossy.address), format!("Silent={}", silent)];
+    let mut args = [&quick_run(&servers[0])[..], &["--server", &servers[1], "--server", &servers[2], "--domain", "example.org"]].concat();
+    args.extend(["--min-success", "0", "--format", format]);
+    let output = run(&home, &[], &args);
+    std::fs::remove_dir_all(&home).unwrap();
+    let names = [("Fine", fine.address.port()), ("Lossy", lossy.address.port()), ("Silent", silent.port())].map(|(name, port)| format!("{} (port {})", name, port));
+    (String::from_utf8(output.stdout).unwrap(), names)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_provider_without_answers_has_nulls_where_the_others_have_figures() {
+    let (stdout, [fine, lossy, silent]) = fine_lossy_silent("unreachable-json", "json").await;
+    let report: Value = serde_json::from_str(&stdout).unwrap();
+    let results = report["results"].as_array().unwrap();
+    let row = |name: &str| results.iter().find(|row| row["provider"] == name).unwrap_or_else(|| panic!("no {} in {}", name, stdout));
+    // Fully answered, half answered and unanswered, in that order whatever the latencies.
+    assert_eq!(names(&report["results"]), [fine.as_str(), lossy.as_str(), silent.as_str()]);
+    assert_eq!(row(&fine)["success_rate"], 100.0);
+    assert_eq!(row(&lossy)["success_rate"], 50.0);
+    assert_eq!(row(&silent)["success_rate"], 0.0);
+    for field in ["median_ms", "avg_ms", "min_ms", "max_ms"] {
+        assert!(row(&fine)[field].is_f64() && row(&lossy)[field].is_f64(), "{}", field);
+        assert!(row(&silent)[field].is_null(), "{}: {}", field, row(&silent)[field]);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_provider_without_answers_is_shown_as_unreachable_last() {
+    let (stdout, [fine, lossy, silent]) = fine_lossy_silent("unreachable-table", "table").await;
+    let rows: Vec<&str> = stdout.lines()
+        .skip_while(|line| !line.starts_with("Detailed Results"))
+        .filter(|line| [&fine, &lossy, &silent].iter().any(|name| line.starts_with(name.as_str())))
+        .collect();
+    assert_eq!(rows.len(), 3, "{}", stdout);
+    assert!(rows[0].starts_with(&fine) && rows[1].starts_with(&lossy) && rows[2].starts_with(&silent), "{:?}", rows);
+    assert!(rows[2].contains("unreachable, 0/2 queries answered") && !rows[2].contains(" ms"), "{}", rows[2]);
+    assert!(!rows[0].contains("unreachable") && !rows[1].contains("unreachable"), "{:?}", rows);
+    // Neither the fastest nor the recommended one.
+    assert!(stdout.contains(&format!("Best provider by score: {}", fine)), "{}", stdout);
+    assert!(!stdout.lines().any(|line| line.contains("baseline") && line.starts_with(&silent)), "{}", stdout);
+}
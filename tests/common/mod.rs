@@ -0,0 +1,248 @@
+//! A DNS server on 127.0.0.1 (or another loopback address) that answers as a test tells it to, over UDP and TCP on the same
+//! port, so the measurement runs without the public internet. It is stopped when dropped.
+
+// Each test file is its own crate and uses only part of the harness.
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dns_speed_test::{DnsProvider, SpeedTestConfig};
+use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+use hickory_resolver::proto::rr::rdata::{A, HINFO};
+use hickory_resolver::proto::rr::{RData, Record, RecordType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// The address every A query is answered with unless a test sets another.
+pub const ANSWER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+/// How the server answers. The default answers every query at once with `ANSWER`.
+#[derive(Clone, Debug)]
+pub struct Behavior {
+    /// The address of every A answer
+    pub answer: Ipv4Addr,
+    /// Waited before each response
+    pub latency: Duration,
+    /// Leaves every Nth UDP query unanswered, counting from the first the server receives
+    pub drop_every: Option<usize>,
+    /// Names answered with SERVFAIL, without the trailing dot
+    pub servfail: Vec<String>,
+    /// Names answered with no records and NOERROR, without the trailing dot
+    pub nodata: Vec<String>,
+    /// UDP responses have the TC bit set and no answers, so clients retry over TCP
+    pub truncate_udp: bool,
+    /// Whether to listen on TCP as well
+    pub tcp: bool,
+    /// How ANY queries are answered; `None` answers them with no records
+    pub any: Option<AnyReply>,
+}
+
+/// A response to ANY, as different servers give it.
+#[derive(Clone, Copy, Debug)]
+pub enum AnyReply {
+    /// The A record of the name
+    Records,
+    /// A single synthesized HINFO record, as RFC 8482 suggests
+    Minimal,
+    /// No records and this response code, such as NOTIMP or REFUSED
+    Code(ResponseCode),
+}
+
+impl Default for Behavior {
+    fn default() -> Behavior {
+        Behavior {
+            answer: ANSWER,
+            latency: Duration::ZERO,
+            drop_every: None,
+            servfail: Vec::new(),
+            nodata: Vec::new(),
+            truncate_udp: false,
+            tcp: true,
+            any: None,
+        }
+    }
+}
+
+/// A running server, with a count of the queries it got.
+pub struct MockServer {
+    pub address: SocketAddr,
+    udp_queries: Arc<AtomicUsize>,
+    tcp_queries: Arc<AtomicUsize>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts a server answering as `behavior` says on a free port.
+    pub async fn start(behavior: Behavior) -> MockServer {
+        MockServer::start_on(Ipv4Addr::LOCALHOST, behavior).await
+    }
+
+    /// The same at another loopback address, for a provider that has to differ from another
+    /// by address rather than port alone.
+    pub async fn start_on(ip: Ipv4Addr, behavior: Behavior) -> MockServer {
+        let behavior = Arc::new(behavior);
+        // UDP and TCP need the same port, which another test may hold for one of them.
+        let (udp, tcp) = loop {
+            let udp = UdpSocket::bind((ip, 0)).await.expect("bind UDP");
+            if !behavior.tcp {
+                break (udp, None);
+            }
+            let port = udp.local_addr().unwrap().port();
+            if let Ok(tcp) = TcpListener::bind((ip, port)).await {
+                break (udp, Some(tcp));
+            }
+        };
+        let address = udp.local_addr().unwrap();
+        let udp_queries = Arc::new(AtomicUsize::new(0));
+        let tcp_queries = Arc::new(AtomicUsize::new(0));
+        let mut tasks = vec![tokio::spawn(serve_udp(Arc::new(udp), behavior.clone(), udp_queries.clone()))];
+        if let Some(tcp) = tcp {
+            tasks.push(tokio::spawn(serve_tcp(tcp, behavior, tcp_queries.clone())));
+        }
+        MockServer { address, udp_queries, tcp_queries, tasks }
+    }
+
+    /// A provider for this server, alone in the run.
+    pub fn provider(&self, name: &str) -> DnsProvider {
+        DnsProvider { port: Some(self.address.port()), ..DnsProvider::new(name, self.address.ip()) }
+    }
+
+    pub fn udp_queries(&self) -> usize {
+        self.udp_queries.load(Ordering::SeqCst)
+    }
+
+    pub fn tcp_queries(&self) -> usize {
+        self.tcp_queries.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// A UDP socket on 127.0.0.1 that reads every query and answers none, and no TCP listener:
+/// a provider that never answers.
+pub async fn silent_server() -> (SocketAddr, JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind UDP");
+    let address = socket.local_addr().unwrap();
+    let task = tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        while socket.recv_from(&mut buf).await.is_ok() {}
+    });
+    (address, task)
+}
+
+/// A run against `providers` with the checks that need the internet or extra queries off, no
+/// warm-up, cooldown or final retry, so every query the server gets is one measured one.
+pub fn config(providers: Vec<DnsProvider>, domains: &[&str], rounds: u32) -> SpeedTestConfig {
+    SpeedTestConfig {
+        providers,
+        domains: domains.iter().map(|domain| domain.to_string()).collect(),
+        rounds,
+        warmup: 0,
+        timeout: Duration::from_millis(300),
+        tcp_check: false,
+        cooldown: Duration::ZERO,
+        final_retry: false,
+        preflight: false,
+        dnssec_check: false,
+        nxdomain_check: false,
+        nxdomain_probes: 0,
+        pop_check: false,
+        identity_check: false,
+        size_check: false,
+        concurrency: 1,
+        ..Default::default()
+    }
+}
+
+async fn serve_udp(socket: Arc<UdpSocket>, behavior: Arc<Behavior>, queries: Arc<AtomicUsize>) {
+    let mut buf = [0u8; 4096];
+    while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+        let received = Instant::now();
+        let count = queries.fetch_add(1, Ordering::SeqCst) + 1;
+        if behavior.drop_every.is_some_and(|n| count.is_multiple_of(n)) {
+            continue;
+        }
+        let Some(response) = respond(&buf[..len], &behavior, true) else { continue };
+        let (socket, latency) = (socket.clone(), behavior.latency);
+        // Each response waits on its own, so a delay never holds up the next query.
+        tokio::spawn(async move {
+            tokio::time::sleep_until((received + latency).into()).await;
+            let _ = socket.send_to(&response, client).await;
+        });
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, behavior: Arc<Behavior>, queries: Arc<AtomicUsize>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve_connection(stream, behavior.clone(), queries.clone()));
+    }
+}
+
+/// The queries of one TCP connection in turn, each preceded by its length.
+async fn serve_connection(mut stream: TcpStream, behavior: Arc<Behavior>, queries: Arc<AtomicUsize>) {
+    loop {
+        let mut len = [0u8; 2];
+        if stream.read_exact(&mut len).await.is_err() {
+            return;
+        }
+        let mut query = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        if stream.read_exact(&mut query).await.is_err() {
+            return;
+        }
+        queries.fetch_add(1, Ordering::SeqCst);
+        let Some(response) = respond(&query, &behavior, false) else { continue };
+        tokio::time::sleep(behavior.latency).await;
+        let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&response);
+        if stream.write_all(&framed).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The response to `query` as `behavior` says, `None` if it cannot be parsed.
+fn respond(query: &[u8], behavior: &Behavior, udp: bool) -> Option<Vec<u8>> {
+    let query = Message::from_vec(query).ok()?;
+    let mut response = Message::new();
+    response.set_id(query.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(query.op_code())
+        .set_recursion_desired(query.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(query.queries().to_vec());
+    let question = query.queries().first()?;
+    let name = question.name().to_ascii();
+    let listed = |names: &[String]| names.iter().any(|listed| name.trim_end_matches('.').eq_ignore_ascii_case(listed));
+    if listed(&behavior.servfail) {
+        response.set_response_code(ResponseCode::ServFail);
+    } else if listed(&behavior.nodata) {
+        // The question alone.
+    } else if udp && behavior.truncate_udp {
+        response.set_truncated(true);
+    } else {
+        let address = Record::from_rdata(question.name().clone(), 60, RData::A(A(behavior.answer)));
+        match (question.query_type(), behavior.any) {
+            (RecordType::A, _) | (RecordType::ANY, Some(AnyReply::Records)) => {
+                response.add_answer(address);
+            },
+            (RecordType::ANY, Some(AnyReply::Minimal)) => {
+                let hinfo = HINFO::new("RFC8482".to_string(), String::new());
+                response.add_answer(Record::from_rdata(question.name().clone(), 3600, RData::HINFO(hinfo)));
+            },
+            (RecordType::ANY, Some(AnyReply::Code(code))) => {
+                response.set_response_code(code);
+            },
+            _ => {},
+        }
+    }
+    response.to_vec().ok()
+}
@@ -0,0 +1,92 @@
+//! DDR designations (RFC 9462) read from responses in wire format: the SVCB answers of
+//! Cloudflare, Google and Quad9 to `_dns.resolver.arpa`, and records that break the rules.
+
+use std::net::IpAddr;
+use dns_speed_test::{parse_ddr, DdrEndpoint, DdrProtocol};
+
+// The records the three resolvers serve, with the owner name compressed as they send it.
+// Cloudflare: `1 one.one.one.one. alpn=h2,h3 port=443 ipv4hint ipv6hint dohpath=/dns-query{?dns}`
+// and `2 one.one.one.one. alpn=dot port=853 ipv4hint ipv6hint`; Google and Quad9 the other way
+// round, with Quad9 offering h2 alone.
+
+const CLOUDFLARE: &str = "\
+    1c2a81800001000200000000045f646e73087265736f6c76657204617270610000400001c00c004000010000012c0067\
+    0001036f6e65036f6e65036f6e65036f6e6500000100060268320268330003000201bb00040008010101010100000100\
+    0600202606470047000000000000000000111126064700470000000000000000001001000700102f646e732d71756572\
+    797b3f646e737dc00c004000010000012c00510002036f6e65036f6e65036f6e65036f6e65000001000403646f740003\
+    000203550004000801010101010000010006002026064700470000000000000000001111260647004700000000000000\
+    00001001";
+
+const GOOGLE: &str = "\
+    5b0181800001000200000000045f646e73087265736f6c76657204617270610000400001c00c004000010000012c004c\
+    000103646e7306676f6f676c65000001000403646f740003000203550004000808080808080804040006002020014860\
+    48600000000000000000888820014860486000000000000000008844c00c004000010000012c0062000203646e730667\
+    6f6f676c6500000100060268320268330003000201bb0004000808080808080804040006002020014860486000000000\
+    00000000888820014860486000000000000000008844000700102f646e732d71756572797b3f646e737d";
+
+const QUAD9: &str = "\
+    9e3d81800001000200000000045f646e73087265736f6c76657204617270610000400001c00c004000010000012c004f\
+    000103646e73057175616439036e6574000001000403646f740003000203550004000809090909957070700006002026\
+    2000fe0000000000000000000000fe262000fe000000000000000000000009c00c004000010000012c0062000203646e\
+    73057175616439036e657400000100030268320003000201bb00040008090909099570707000060020262000fe000000\
+    0000000000000000fe262000fe000000000000000000000009000700102f646e732d71756572797b3f646e737d";
+
+const BAD_DOHPATH: &str = "\
+    0e1181800001000200000000045f646e73087265736f6c76657204617270610000400001c00c004000010000012c0014\
+    0000087265736f6c766572076578616d706c6500c00c004000010000012c00350001087265736f6c766572076578616d\
+    706c65000001000702683203646f7400040004c00002350007000a2f646e732d7175657279";
+
+fn bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+fn endpoint(protocol: DdrProtocol, priority: u16, host: &str, port: u16, addresses: &[&str]) -> DdrEndpoint {
+    let doh_path = matches!(protocol, DdrProtocol::Doh | DdrProtocol::Doh3).then(|| "/dns-query".to_string());
+    let addresses = addresses.iter().map(|address| address.parse::<IpAddr>().unwrap()).collect();
+    DdrEndpoint { protocol, priority, host: host.to_string(), port, doh_path, addresses }
+}
+
+#[test]
+fn cloudflare_prefers_doh_over_dot() {
+    let hints = ["1.1.1.1", "1.0.0.1", "2606:4700:4700::1111", "2606:4700:4700::1001"];
+    assert_eq!(parse_ddr(&bytes(CLOUDFLARE)), Some(vec![
+        endpoint(DdrProtocol::Doh, 1, "one.one.one.one", 443, &hints),
+        endpoint(DdrProtocol::Doh3, 1, "one.one.one.one", 443, &hints),
+        endpoint(DdrProtocol::Dot, 2, "one.one.one.one", 853, &hints),
+    ]));
+}
+
+#[test]
+fn google_prefers_dot_over_doh() {
+    let hints = ["8.8.8.8", "8.8.4.4", "2001:4860:4860::8888", "2001:4860:4860::8844"];
+    assert_eq!(parse_ddr(&bytes(GOOGLE)), Some(vec![
+        endpoint(DdrProtocol::Dot, 1, "dns.google", 853, &hints),
+        endpoint(DdrProtocol::Doh, 2, "dns.google", 443, &hints),
+        endpoint(DdrProtocol::Doh3, 2, "dns.google", 443, &hints),
+    ]));
+}
+
+#[test]
+fn quad9_offers_doh_over_http2_alone() {
+    let hints = ["9.9.9.9", "149.112.112.112", "2620:fe::fe", "2620:fe::9"];
+    let endpoints = parse_ddr(&bytes(QUAD9)).unwrap();
+    assert_eq!(endpoints, vec![
+        endpoint(DdrProtocol::Dot, 1, "dns.quad9.net", 853, &hints),
+        endpoint(DdrProtocol::Doh, 2, "dns.quad9.net", 443, &hints),
+    ]);
+    assert_eq!(endpoints[1].describe(), "DoH dns.quad9.net:443/dns-query");
+}
+
+#[test]
+fn alias_mode_and_a_dohpath_without_the_dns_variable_are_left_out() {
+    // `0 resolver.example.` and `1 resolver.example. alpn=h2,dot ipv4hint=192.0.2.53
+    // dohpath=/dns-query`: DoT at its default port is all that is left.
+    assert_eq!(parse_ddr(&bytes(BAD_DOHPATH)), Some(vec![endpoint(DdrProtocol::Dot, 1, "resolver.example", 853, &["192.0.2.53"])]));
+}
+
+#[test]
+fn a_record_cut_short_does_not_parse() {
+    let mut response = bytes(CLOUDFLARE);
+    response.truncate(response.len() - 10);
+    assert_eq!(parse_ddr(&response), None);
+}
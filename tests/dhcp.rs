@@ -0,0 +1,162 @@
+//! Finding the DNS servers of the DHCP leases in what each platform keeps of them, from
+//! captured files and output.
+
+use std::net::IpAddr;
+use dns_speed_test::{dhcp_servers_with, parse_dhclient_leases, parse_getoption, parse_ipconfig_all, parse_networkd_lease};
+
+fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+    addresses.iter().map(|address| address.parse().unwrap()).collect()
+}
+
+/// /run/systemd/netif/leases/2
+const NETWORKD_LEASE: &str = "\
+# This is private data. Do not parse.
+ADDRESS=192.168.1.23
+NETMASK=255.255.255.0
+ROUTER=192.168.1.1
+SERVER_ADDRESS=192.168.1.1
+NEXT_SERVER=0.0.0.0
+BROADCAST=192.168.1.255
+T1=43200
+T2=75600
+LIFETIME=86400
+DNS=192.168.1.1 1.1.1.1
+DOMAINNAME=lan
+HOSTNAME=laptop
+CLIENTID=ff3c22fb1200020000ab11d8c5b1f3a7c2e5d4
+";
+
+/// /var/lib/dhcp/dhclient.eth0.leases after a renewal that changed the servers.
+const DHCLIENT_LEASES: &str = "\
+lease {
+  interface \"eth0\";
+  fixed-address 10.0.0.57;
+  option subnet-mask 255.255.255.0;
+  option routers 10.0.0.1;
+  option dhcp-lease-time 86400;
+  option dhcp-message-type 5;
+  option domain-name-servers 10.0.0.1;
+  option dhcp-server-identifier 10.0.0.1;
+  option domain-name \"home.example\";
+  renew 2 2026/10/13 08:12:01;
+  rebind 2 2026/10/13 18:47:33;
+  expire 2 2026/10/13 21:47:33;
+}
+lease {
+  interface \"eth0\";
+  fixed-address 10.0.0.57;
+  option subnet-mask 255.255.255.0;
+  option routers 10.0.0.1;
+  option dhcp-lease-time 86400;
+  option dhcp-message-type 5;
+  option domain-name-servers 10.0.0.2, 10.0.0.3;
+  option dhcp-server-identifier 10.0.0.1;
+  renew 3 2026/10/14 08:12:01;
+  rebind 3 2026/10/14 18:47:33;
+  expire 3 2026/10/14 21:47:33;
+}
+";
+
+/// An English Windows with Wi-Fi on DHCP and an Ethernet adapter set up by hand.
+const IPCONFIG_ALL: &str = "\
+Windows IP Configuration
+
+   Host Name . . . . . . . . . . . . : LAPTOP
+   Primary Dns Suffix  . . . . . . . :
+   Node Type . . . . . . . . . . . . : Hybrid
+   IP Routing Enabled. . . . . . . . : No
+   WINS Proxy Enabled. . . . . . . . : No
+   DNS Suffix Search List. . . . . . : fritz.box
+
+Ethernet adapter Ethernet:
+
+   Connection-specific DNS Suffix  . :
+   Description . . . . . . . . . . . : Intel(R) Ethernet Connection (13) I219-V
+   Physical Address. . . . . . . . . : 3C-22-FB-12-34-57
+   DHCP Enabled. . . . . . . . . . . : No
+   Autoconfiguration Enabled . . . . : Yes
+   IPv4 Address. . . . . . . . . . . : 192.168.50.10(Preferred)
+   Subnet Mask . . . . . . . . . . . : 255.255.255.0
+   Default Gateway . . . . . . . . . : 192.168.50.1
+   DNS Servers . . . . . . . . . . . : 8.8.8.8
+                                       8.8.4.4
+   NetBIOS over Tcpip. . . . . . . . : Enabled
+
+Wireless LAN adapter Wi-Fi:
+
+   Connection-specific DNS Suffix  . : fritz.box
+   Description . . . . . . . . . . . : Intel(R) Wi-Fi 6 AX201 160MHz
+   Physical Address. . . . . . . . . : 3C-22-FB-12-34-56
+   DHCP Enabled. . . . . . . . . . . : Yes
+   Autoconfiguration Enabled . . . . : Yes
+   IPv6 Address. . . . . . . . . . . : 2a02:8109:1234:5600::1f3(Preferred)
+   IPv4 Address. . . . . . . . . . . : 192.168.178.23(Preferred)
+   Subnet Mask . . . . . . . . . . . : 255.255.255.0
+   Lease Obtained. . . . . . . . . . : Tuesday, 14 October 2026 08:12:01
+   Lease Expires . . . . . . . . . . : Wednesday, 15 October 2026 08:12:01
+   Default Gateway . . . . . . . . . : 192.168.178.1
+   DHCP Server . . . . . . . . . . . : 192.168.178.1
+   DHCPv6 IAID . . . . . . . . . . . : 104604411
+   DNS Servers . . . . . . . . . . . : 192.168.178.1
+                                       2a02:8109:1234:5600::1
+   NetBIOS over Tcpip. . . . . . . . : Enabled
+";
+
+#[test]
+fn a_networkd_lease_lists_its_dns_line() {
+    assert_eq!(parse_networkd_lease(NETWORKD_LEASE), ips(&["192.168.1.1", "1.1.1.1"]));
+    assert!(parse_networkd_lease("ADDRESS=192.168.1.23\n").is_empty());
+}
+
+#[test]
+fn dhclient_takes_the_last_lease() {
+    assert_eq!(parse_dhclient_leases(DHCLIENT_LEASES), ips(&["10.0.0.2", "10.0.0.3"]));
+    assert!(parse_dhclient_leases("").is_empty());
+}
+
+#[test]
+fn getoption_prints_one_server_or_nothing() {
+    assert_eq!(parse_getoption("192.168.178.1\n"), ips(&["192.168.178.1"]));
+    assert!(parse_getoption("").is_empty());
+}
+
+#[test]
+fn ipconfig_lists_only_the_servers_of_dhcp_adapters() {
+    assert_eq!(parse_ipconfig_all(IPCONFIG_ALL), ips(&["192.168.178.1", "2a02:8109:1234:5600::1"]));
+}
+
+/// The Linux sources: a networkd directory and a dhclient one, each listed by `read` or not.
+fn linux(networkd: Option<Vec<String>>, dhclient: Option<Vec<String>>) -> Result<Vec<IpAddr>, String> {
+    dhcp_servers_with(|_| None, |dir| match dir {
+        "/run/systemd/netif/leases" => networkd.clone(),
+        "/var/lib/dhcp" => dhclient.clone(),
+        _ => None,
+    })
+}
+
+#[test]
+fn linux_prefers_networkd_and_falls_back_to_dhclient() {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    let networkd = || Some(vec![NETWORKD_LEASE.to_string(), "DNS=1.1.1.1 9.9.9.9\n".to_string()]);
+    let dhclient = || Some(vec![DHCLIENT_LEASES.to_string()]);
+    assert_eq!(linux(networkd(), dhclient()), Ok(ips(&["192.168.1.1", "1.1.1.1", "9.9.9.9"])));
+    assert_eq!(linux(Some(Vec::new()), dhclient()), Ok(ips(&["10.0.0.2", "10.0.0.3"])));
+    assert!(linux(None, None).is_err());
+}
+
+#[test]
+fn macos_and_windows_ask_ipconfig() {
+    let run = |argv: &[&str]| match argv {
+        ["ipconfig", "getoption", "en0", "domain_name_server"] => Some("192.168.178.1\n".to_string()),
+        ["ipconfig", "/all"] => Some(IPCONFIG_ALL.to_string()),
+        _ => None,
+    };
+    if cfg!(target_os = "macos") {
+        assert_eq!(dhcp_servers_with(run, |_| None), Ok(ips(&["192.168.178.1"])));
+    } else if cfg!(windows) {
+        assert_eq!(dhcp_servers_with(run, |_| None), Ok(ips(&["192.168.178.1", "2a02:8109:1234:5600::1"])));
+        assert!(dhcp_servers_with(|_| None, |_| None).is_err());
+    }
+}
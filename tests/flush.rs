@@ -0,0 +1,52 @@
+//! Flushing the system's DNS cache, with a stand-in for the commands that would do it.
+
+use std::cell::{Cell, RefCell};
+use dns_speed_test::{flush_dns_cache, run_command};
+
+#[test]
+fn every_command_runs_and_is_reported() {
+    let ran = RefCell::new(Vec::new());
+    let record = |argv: &[&str]| {
+        ran.borrow_mut().push(argv.join(" "));
+        Ok(())
+    };
+    let flushed = flush_dns_cache(&record);
+    let ran = ran.into_inner();
+    if ran.is_empty() {
+        // A platform without a known way runs nothing and says so.
+        assert!(flushed.is_err());
+        return;
+    }
+    assert_eq!(flushed, Ok(ran.join(", ")));
+    if cfg!(target_os = "linux") {
+        assert_eq!(ran, ["resolvectl flush-caches"]);
+    } else if cfg!(target_os = "macos") {
+        assert_eq!(ran, ["dscacheutil -flushcache", "killall -HUP mDNSResponder"]);
+    } else if cfg!(windows) {
+        assert_eq!(ran, ["ipconfig /flushdns"]);
+    }
+}
+
+#[test]
+fn the_first_failure_stops_the_flush() {
+    let ran = Cell::new(0);
+    let fail = |argv: &[&str]| {
+        ran.set(ran.get() + 1);
+        Err(format!("`{}` failed: Permission denied", argv.join(" ")))
+    };
+    let flushed = flush_dns_cache(&fail);
+    if ran.get() == 0 {
+        return;
+    }
+    assert_eq!(ran.get(), 1);
+    assert!(flushed.unwrap_err().ends_with("failed: Permission denied"));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_failing_command_is_reported_with_its_stderr() {
+    let error = run_command(&["sh", "-c", "echo '' >&2; echo 'not allowed' >&2; exit 3"]).unwrap_err();
+    assert_eq!(error, "`sh -c echo '' >&2; echo 'not allowed' >&2; exit 3` failed: not allowed");
+    assert_eq!(run_command(&["true"]), Ok(()));
+    assert!(run_command(&["dnsspeedtest-no-such-program"]).unwrap_err().starts_with("cannot run dnsspeedtest-no-such-program: "));
+}
@@ -0,0 +1,126 @@
+//! Finding the default gateway in what each platform reports about its routes, from captured
+//! output.
+
+use std::net::IpAddr;
+use dns_speed_test::{default_gateway_with, parse_proc_route, parse_route_get, parse_route_print};
+
+fn ip(address: &str) -> Option<IpAddr> {
+    Some(address.parse().unwrap())
+}
+
+/// Linux with Ethernet and Wi-Fi both up, Docker's bridge and their local routes; Ethernet has
+/// the lower metric.
+const PROC_ROUTE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlp2s0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+enp0s31f6\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+enp0s31f6\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\t0\t0\t0
+wlp2s0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+";
+
+/// Only local routes, as on a machine without a way out.
+const PROC_ROUTE_LOCAL: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\t0\t0\t0
+";
+
+const MACOS_ROUTE_GET: &str = "\
+   route to: default
+destination: default
+       mask: default
+    gateway: 192.168.178.1
+  interface: en0
+      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING,GLOBAL>
+ recvpipe  sendpipe  ssthresh  rtt,msec    rttvar  hopcount      mtu     expire
+       0         0         0         0         0         0      1500         0
+";
+
+/// macOS with a VPN that routes everything through its tunnel: there is no gateway address.
+const MACOS_ROUTE_GET_TUNNEL: &str = "\
+   route to: default
+destination: default
+       mask: default
+  interface: utun4
+      flags: <UP,DONE,STATIC,PRCLONING,GLOBAL>
+";
+
+const WINDOWS_ROUTE_PRINT: &str = "\
+===========================================================================
+Interface List
+ 12...3c 22 fb 12 34 56 ......Intel(R) Wi-Fi 6 AX201 160MHz
+  1...........................Software Loopback Interface 1
+===========================================================================
+
+IPv4 Route Table
+===========================================================================
+Active Routes:
+Network Destination        Netmask          Gateway       Interface  Metric
+          0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.10     35
+===========================================================================
+Persistent Routes:
+  None
+";
+
+/// A German Windows: the labels change, the columns do not.
+const WINDOWS_ROUTE_PRINT_GERMAN: &str = "\
+IPv4-Routentabelle
+===========================================================================
+Aktive Routen:
+     Netzwerkziel    Netzwerkmaske          Gateway    Schnittstelle Metrik
+          0.0.0.0          0.0.0.0       10.0.0.138       10.0.0.24     25
+===========================================================================
+Ständige Routen:
+  Keine
+";
+
+#[test]
+fn linux_takes_the_default_route_with_the_lowest_metric() {
+    assert_eq!(parse_proc_route(PROC_ROUTE), ip("10.0.0.1"));
+}
+
+#[test]
+fn linux_without_a_default_route_has_no_gateway() {
+    assert_eq!(parse_proc_route(PROC_ROUTE_LOCAL), None);
+    assert_eq!(parse_proc_route(""), None);
+}
+
+#[test]
+fn a_default_route_that_is_down_is_ignored() {
+    let down = PROC_ROUTE.replace("0100000A\t0003", "0100000A\t0002");
+    assert_eq!(parse_proc_route(&down), ip("192.168.1.1"));
+}
+
+#[test]
+fn macos_reads_the_gateway_line() {
+    assert_eq!(parse_route_get(MACOS_ROUTE_GET), ip("192.168.178.1"));
+    assert_eq!(parse_route_get(MACOS_ROUTE_GET_TUNNEL), None);
+}
+
+#[test]
+fn windows_reads_the_default_row_in_any_language() {
+    assert_eq!(parse_route_print(WINDOWS_ROUTE_PRINT), ip("192.168.1.1"));
+    assert_eq!(parse_route_print(WINDOWS_ROUTE_PRINT_GERMAN), ip("10.0.0.138"));
+    assert_eq!(parse_route_print("Active Routes:\n  None\n"), None);
+}
+
+#[test]
+fn discovery_reads_the_platform_source() {
+    let run = |argv: &[&str]| match argv {
+        ["route", "-n", "get", "default"] => Some(MACOS_ROUTE_GET.to_string()),
+        ["route", "print", "-4", "0.0.0.0"] => Some(WINDOWS_ROUTE_PRINT.to_string()),
+        _ => None,
+    };
+    let read = |path: &str| (path == "/proc/net/route").then(|| PROC_ROUTE.to_string());
+    let expected = if cfg!(target_os = "linux") {
+        ip("10.0.0.1")
+    } else if cfg!(target_os = "macos") {
+        ip("192.168.178.1")
+    } else if cfg!(windows) {
+        ip("192.168.1.1")
+    } else {
+        None
+    };
+    assert_eq!(default_gateway_with(run, read), expected);
+    assert_eq!(default_gateway_with(|_| None, |_| None), None);
+}
@@ -0,0 +1,111 @@
+//! The history database: runs recorded at known ages and the 7- and 30-day figures read back.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use dns_speed_test::history::{self, Reliability};
+use dns_speed_test::{SpeedTestConfig, TestResult};
+use rusqlite::{params, Connection};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A fixed "now", so the ages below do not depend on when the test runs.
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_800_000_000)
+}
+
+/// A fresh database for one test, deleted again when dropped.
+struct Db {
+    path: PathBuf,
+    connection: Connection,
+}
+
+impl Db {
+    fn new(test: &str) -> Db {
+        let path = std::env::temp_dir().join(format!("dnsspeedtest-history-{}-{}.db", test, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = history::open(&path).unwrap();
+        Db { path, connection }
+    }
+
+    /// One run `age` before [`now`] in which each provider had the given success rate.
+    fn run(&mut self, age: Duration, rates: &[(&str, f64)]) {
+        let results: Vec<TestResult> = rates.iter()
+            .map(|&(provider, success_rate)| TestResult {
+                provider: provider.to_string(),
+                success_rate,
+                median_duration: (success_rate > 0.0).then_some(Duration::from_millis(20)),
+                ..Default::default()
+            })
+            .collect();
+        history::record(&mut self.connection, &SpeedTestConfig::default(), &results.iter().collect::<Vec<_>>()).unwrap();
+        // `record` stamps the run with the real time; move it to its age.
+        let timestamp = (now() - age).duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        self.connection.execute("UPDATE runs SET timestamp = ?1 WHERE id = (SELECT MAX(id) FROM runs)", params![timestamp]).unwrap();
+    }
+
+    fn reliability(&self, provider: &str) -> Option<Reliability> {
+        history::reliability(&self.connection, now()).unwrap().get(provider).copied()
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn the_week_and_the_month_average_their_own_runs() {
+    let mut db = Db::new("windows");
+    db.run(DAY, &[("Steady", 100.0)]);
+    db.run(3 * DAY, &[("Steady", 90.0)]);
+    db.run(6 * DAY, &[("Steady", 80.0)]);
+    db.run(20 * DAY, &[("Steady", 50.0)]);
+    // (100 + 90 + 80) / 3 for the week, with the 20-day-old run also in the month.
+    assert_eq!(db.reliability("Steady"), Some(Reliability { week: Some(90.0), month: Some(80.0) }));
+}
+
+#[test]
+fn fewer_than_three_runs_in_a_window_give_no_figure() {
+    let mut db = Db::new("few");
+    db.run(DAY, &[("Sparse", 100.0)]);
+    db.run(2 * DAY, &[("Sparse", 100.0)]);
+    db.run(10 * DAY, &[("Sparse", 70.0)]);
+    assert_eq!(db.reliability("Sparse"), Some(Reliability { week: None, month: Some(90.0) }));
+}
+
+#[test]
+fn runs_on_the_window_edge_count_and_older_ones_do_not() {
+    let mut db = Db::new("edges");
+    for _ in 0..3 {
+        db.run(7 * DAY, &[("Edge", 60.0)]);
+        db.run(30 * DAY + Duration::from_secs(1), &[("Edge", 0.0), ("Gone", 100.0)]);
+    }
+    assert_eq!(db.reliability("Edge"), Some(Reliability { week: Some(60.0), month: Some(60.0) }));
+    assert_eq!(db.reliability("Gone"), None);
+}
+
+#[test]
+fn providers_are_averaged_apart_within_a_run() {
+    let mut db = Db::new("providers");
+    for rate in [100.0, 95.0, 90.0] {
+        db.run(2 * DAY, &[("First", rate), ("Second", rate - 50.0)]);
+    }
+    assert_eq!(db.reliability("First"), Some(Reliability { week: Some(95.0), month: Some(95.0) }));
+    assert_eq!(db.reliability("Second"), Some(Reliability { week: Some(45.0), month: Some(45.0) }));
+}
+
+#[test]
+fn the_history_of_a_provider_is_its_last_runs_oldest_first() {
+    let mut db = Db::new("last");
+    for (age, rate) in [(4, 60.0), (3, 0.0), (2, 80.0), (1, 90.0)] {
+        db.run(age * DAY, &[("Listed", rate), ("Other", 100.0)]);
+    }
+    let entries = history::provider_history(&db.connection, "listed", 3).unwrap();
+    let rates: Vec<f64> = entries.iter().map(|entry| entry.success_rate).collect();
+    assert_eq!(rates, [0.0, 80.0, 90.0]);
+    assert_eq!(entries[0].time, now() - 3 * DAY);
+    // A run without an answer has no median.
+    assert_eq!(entries[0].median, None);
+    assert_eq!(entries[1].median, Some(Duration::from_millis(20)));
+}
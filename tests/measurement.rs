@@ -0,0 +1,534 @@
+//! The measurement against the mock server: what it was told to do has to show in the results.
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::Duration;
+use common::{config, silent_server, AnyReply, Behavior, MockServer, ANSWER};
+use dns_speed_test::{compare_by, provider_order, run_all, run_all_with_progress, test_failover, test_provider, AnyHandling, BlockOutcome, DnsProvider, FailureKind, QueryType, Progress, SampleStatus, SortKey, SpeedTestConfig, Transport};
+use hickory_resolver::proto::op::ResponseCode;
+
+const DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+/// How far above the injected delay a loaded test machine may push a latency.
+const TOLERANCE: Duration = Duration::from_millis(60);
+
+#[tokio::test]
+async fn latency_reflects_the_injected_delay() {
+    let delay = Duration::from_millis(40);
+    let server = MockServer::start(Behavior { latency: delay, ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Slow")], DOMAINS, 3);
+
+    let results = test_provider(&cfg.providers[0], &cfg).await;
+    let result = &results[0];
+    assert_eq!(result.success_rate, 100.0);
+    assert_eq!(server.udp_queries(), 9);
+    let (min, median, max) = (result.min_latency.unwrap(), result.median_duration.unwrap(), result.max_latency.unwrap());
+    assert!(min >= delay, "min {:?} below the delay", min);
+    assert!(median >= delay && median < delay + TOLERANCE, "median {:?}", median);
+    assert!(max < delay + TOLERANCE, "max {:?}", max);
+    assert!(result.avg_duration.is_some_and(|avg| avg >= delay));
+    assert!(result.samples.iter().all(|sample| sample.status == SampleStatus::Ok && sample.duration.is_some_and(|d| d >= delay)));
+    assert!(result.answers.values().all(|addresses| addresses.iter().eq([&IpAddr::V4(ANSWER)])));
+}
+
+#[tokio::test]
+async fn a_faster_server_has_the_lower_median() {
+    let fast = MockServer::start(Behavior { latency: Duration::from_millis(5), ..Default::default() }).await;
+    let slow = MockServer::start(Behavior { latency: Duration::from_millis(50), ..Default::default() }).await;
+    let cfg = config(vec![fast.provider("Fast"), slow.provider("Slow")], DOMAINS, 2);
+
+    let fast = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    let slow = test_provider(&cfg.providers[1], &cfg).await.remove(0);
+    let (fast, slow) = (fast.median_duration.unwrap(), slow.median_duration.unwrap());
+    assert!(fast + Duration::from_millis(30) < slow, "{:?} vs {:?}", fast, slow);
+}
+
+#[tokio::test]
+async fn dropped_packets_count_as_timeouts() {
+    let server = MockServer::start(Behavior { drop_every: Some(3), ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Lossy")], DOMAINS, 3);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    // 9 queries, of which the 3rd, 6th and 9th go unanswered.
+    assert_eq!(server.udp_queries(), 9);
+    assert!((result.success_rate - 6.0 / 9.0 * 100.0).abs() < 0.01, "success rate {}", result.success_rate);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Timeout), Some(&3));
+    assert_eq!(result.failures.len(), 3);
+    assert_eq!(result.samples.iter().filter(|sample| sample.status == SampleStatus::Failed).count(), 3);
+    let loss = result.packet_loss.expect("UDP counts its packets");
+    assert_eq!((loss.sent, loss.lost), (9, 3));
+    // The answers that came back still give the latencies, from them alone.
+    assert!(result.median_duration.is_some_and(|median| median < cfg.timeout), "median {:?}", result.median_duration);
+    assert!(result.max_latency.is_some_and(|max| max < cfg.timeout), "max {:?}", result.max_latency);
+}
+
+#[tokio::test]
+async fn servfail_is_a_failure_of_its_own_kind() {
+    let server = MockServer::start(Behavior { servfail: vec!["example.org".to_string()], ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Broken")], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert!((result.success_rate - 4.0 / 6.0 * 100.0).abs() < 0.01, "success rate {}", result.success_rate);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Servfail), Some(&2));
+    assert!(result.failures.iter().all(|failure| failure.domain == "example.org"));
+}
+
+/// The success rate and the share of usable answers against a server answering every A query
+/// with `answer`.
+async fn resolved_and_usable(answer: Ipv4Addr) -> (f64, Option<f64>) {
+    let server = MockServer::start(Behavior { answer, ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Answering")], DOMAINS, 2);
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    (result.success_rate, result.usable_answers.map(|usable| usable.percent))
+}
+
+#[tokio::test]
+async fn a_sentinel_answer_resolves_but_is_of_no_use() {
+    assert_eq!(resolved_and_usable(Ipv4Addr::UNSPECIFIED).await, (100.0, Some(0.0)));
+}
+
+#[tokio::test]
+async fn a_public_answer_resolves_and_is_usable() {
+    assert_eq!(resolved_and_usable(Ipv4Addr::new(93, 184, 215, 14)).await, (100.0, Some(100.0)));
+}
+
+/// A server at `ip` that fails `example.org`, and with `validating` the broken DNSSEC probe as
+/// well, so that the DNSSEC check finds it validating. Only a provider at another address
+/// counts as a witness, so each gets its own.
+async fn failing_example_org(ip: [u8; 4], validating: bool) -> MockServer {
+    let mut servfail = vec!["example.org".to_string()];
+    if validating {
+        servfail.push("dnssec-failed.org".to_string());
+    }
+    MockServer::start_on(ip.into(), Behavior { servfail, ..Default::default() }).await
+}
+
+/// The failure kinds and success rate of each provider of a run with the DNSSEC check on.
+async fn dnssec_run(servers: &[(&str, &MockServer)]) -> Vec<(String, Vec<FailureKind>, f64)> {
+    let providers = servers.iter().map(|(name, server)| server.provider(name)).collect();
+    let cfg = SpeedTestConfig { dnssec_check: true, ..config(providers, DOMAINS, 2) };
+    let mut results: Vec<_> = run_all(&cfg).await.into_iter()
+        .map(|result| (result.provider, result.failures.iter().map(|failure| failure.kind).collect(), result.success_rate))
+        .collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[tokio::test]
+async fn a_servfail_two_validating_providers_share_is_the_domains_fault() {
+    let (one, two) = (failing_example_org([127, 0, 0, 1], true).await, failing_example_org([127, 0, 0, 2], true).await);
+    let results = dnssec_run(&[("One", &one), ("Two", &two)]).await;
+    // Each is the other's validating witness: both keep the failures listed, out of the rate.
+    for (provider, kinds, success_rate) in results {
+        assert_eq!(kinds, [FailureKind::DomainDnssec; 2], "{}", provider);
+        assert_eq!(success_rate, 100.0, "{}", provider);
+    }
+}
+
+#[tokio::test]
+async fn a_servfail_only_non_validating_providers_share_stays_the_providers() {
+    let validating = failing_example_org([127, 0, 0, 1], true).await;
+    let other = failing_example_org([127, 0, 0, 2], false).await;
+    let answering = MockServer::start_on([127, 0, 0, 3].into(), Behavior::default()).await;
+    let results = dnssec_run(&[("Answering", &answering), ("Other", &other), ("Validating", &validating)]).await;
+    assert_eq!(results[0].1, []);
+    // The validating provider vouches for the other's SERVFAIL, but has no validating provider
+    // of its own to agree with: the non-validating one failing too is no witness.
+    assert_eq!((&results[1].1[..], results[1].2), (&[FailureKind::DomainDnssec; 2][..], 100.0));
+    assert_eq!(results[2].1, [FailureKind::Servfail; 2]);
+    assert!((results[2].2 - 4.0 / 6.0 * 100.0).abs() < 0.01, "success rate {}", results[2].2);
+}
+
+#[tokio::test]
+async fn the_https_probe_counts_nodata_as_correct_and_servfail_as_not() {
+    // The mock has no HTTPS records: NODATA for two of the domains, SERVFAIL for the third.
+    let server = MockServer::start(Behavior { servfail: vec!["example.org".to_string()], ..Default::default() }).await;
+    let cfg = SpeedTestConfig { https_probe: true, ..config(vec![server.provider("HTTPS")], DOMAINS, 1) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert!(result.https_success_rate.is_some_and(|rate| (rate - 2.0 / 3.0 * 100.0).abs() < 0.01), "{:?}", result.https_success_rate);
+    assert_eq!(result.https_failures.into_iter().collect::<Vec<_>>(), [(FailureKind::Servfail, 1)]);
+    assert!(result.https_median.is_some());
+    // The probe stays out of the rounds: three A queries, one of them failed.
+    assert_eq!(result.completed_queries, 3);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Servfail), Some(&1));
+}
+
+#[tokio::test]
+async fn the_https_probe_of_a_clean_server_is_all_correct() {
+    let server = MockServer::start(Behavior::default()).await;
+    let cfg = SpeedTestConfig { https_probe: true, ..config(vec![server.provider("HTTPS")], DOMAINS, 1) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.https_success_rate, Some(100.0));
+    assert!(result.https_failures.is_empty());
+    assert_eq!(server.udp_queries(), 2 * DOMAINS.len());
+}
+
+/// The blocking test's outcome for each of `domains` from a server answering `answer`, with no
+/// records for `nodata` and SERVFAIL for `broken.example`, and the share blocked.
+async fn blocking(answer: Ipv4Addr, domains: &[&str], nodata: &[&str]) -> (Vec<BlockOutcome>, Option<f64>) {
+    let server = MockServer::start(Behavior {
+        answer,
+        nodata: nodata.iter().map(|domain| domain.to_string()).collect(),
+        servfail: vec!["broken.example".to_string()],
+        ..Default::default()
+    }).await;
+    let cfg = SpeedTestConfig {
+        blocking_domains: Some(domains.iter().map(|domain| domain.to_string()).collect()),
+        ..config(vec![server.provider("Mock")], DOMAINS, 1)
+    };
+    let blocking = run_all(&cfg).await.remove(0).blocking.unwrap();
+    (blocking.outcomes.iter().map(|sample| sample.outcome).collect(), blocking.blocked_percent)
+}
+
+#[tokio::test]
+async fn only_a_sentinel_answer_counts_as_blocked() {
+    let (outcomes, blocked) = blocking(Ipv4Addr::UNSPECIFIED, &["ads.example", "empty.example", "broken.example"], &["empty.example"]).await;
+    assert_eq!(outcomes, [BlockOutcome::Blocked, BlockOutcome::Unknown, BlockOutcome::Unknown]);
+    assert_eq!(blocked, Some(100.0));
+}
+
+#[tokio::test]
+async fn an_empty_answer_is_neither_blocked_nor_resolved() {
+    let (outcomes, blocked) = blocking(Ipv4Addr::new(93, 184, 215, 14), &["ads.example", "empty.example"], &["empty.example"]).await;
+    assert_eq!(outcomes, [BlockOutcome::Resolved, BlockOutcome::Unknown]);
+    assert_eq!(blocked, Some(0.0));
+    let (outcomes, blocked) = blocking(Ipv4Addr::UNSPECIFIED, &["empty.example"], &["empty.example"]).await;
+    assert_eq!(outcomes, [BlockOutcome::Unknown]);
+    assert_eq!(blocked, None);
+}
+
+#[tokio::test]
+async fn truncated_answers_are_retried_over_tcp() {
+    let server = MockServer::start(Behavior { truncate_udp: true, ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Truncating")], DOMAINS, 1);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.success_rate, 100.0);
+    assert_eq!(result.truncated_retries, 3);
+    assert_eq!((server.udp_queries(), server.tcp_queries()), (3, 3));
+}
+
+#[tokio::test]
+async fn a_silent_provider_has_no_latencies() {
+    let (address, _task) = silent_server().await;
+    let provider = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) };
+    let cfg = config(vec![provider], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.success_rate, 0.0);
+    assert_eq!(result.completed_queries, 6);
+    assert_eq!((result.median_duration, result.avg_duration), (None, None));
+    assert_eq!((result.min_latency, result.max_latency), (None, None));
+    assert_eq!(result.p95, None);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Timeout), Some(&6));
+    assert_eq!(result.round_medians, vec![None, None]);
+    assert!(result.score.is_none());
+    // JSON has null for them rather than a number.
+    let json = serde_json::to_value(&result).unwrap();
+    assert!(["median_ms", "avg_ms", "min_ms", "max_ms"].iter().all(|field| json[field].is_null()), "{}", json);
+}
+
+#[tokio::test]
+async fn a_provider_that_misses_some_queries_has_latencies_of_the_rest() {
+    let server = MockServer::start(Behavior { drop_every: Some(2), latency: Duration::from_millis(10), ..Default::default() }).await;
+    let cfg = config(vec![server.provider("Lossy")], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.completed_queries, 6);
+    assert!(result.success_rate > 0.0 && result.success_rate < 100.0, "success rate {}", result.success_rate);
+    let answered = result.samples.iter().filter(|sample| sample.status == SampleStatus::Ok).count();
+    assert_eq!(result.success_rate, answered as f64 / 6.0 * 100.0);
+    assert!(result.median_duration.is_some_and(|median| median >= Duration::from_millis(10)), "{:?}", result.median_duration);
+    assert!(result.min_latency <= result.median_duration && result.median_duration <= result.max_latency);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Timeout), Some(&(6 - answered as u32)));
+    assert!(result.score.is_some());
+}
+
+#[tokio::test]
+async fn a_provider_that_answers_everything_has_no_failures() {
+    let server = MockServer::start(Behavior::default()).await;
+    let cfg = config(vec![server.provider("Fine")], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.success_rate, 100.0);
+    assert_eq!(result.completed_queries, 6);
+    assert!(result.failure_kinds.is_empty(), "{:?}", result.failure_kinds);
+    assert!(result.samples.iter().all(|sample| sample.status == SampleStatus::Ok));
+    assert!(result.round_medians.iter().all(Option::is_some));
+    assert!([result.median_duration, result.avg_duration, result.min_latency, result.max_latency].iter().all(Option::is_some));
+}
+
+#[tokio::test]
+async fn a_silent_provider_fails_the_preflight_check() {
+    let (address, _task) = silent_server().await;
+    let provider = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) };
+    let cfg = SpeedTestConfig { preflight: true, ..config(vec![provider], DOMAINS, 2) };
+
+    let result = run_all(&cfg).await.remove(0);
+    assert!(result.unreachable);
+    assert_eq!(result.success_rate, 0.0);
+    assert_eq!(result.median_duration, None);
+    assert!(result.samples.is_empty());
+}
+
+#[tokio::test]
+async fn a_silent_provider_ranks_last_whatever_the_sort_key() {
+    let (address, _task) = silent_server().await;
+    let silent = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Aaa silent", address.ip()) };
+    let lossy = MockServer::start(Behavior { drop_every: Some(2), ..Default::default() }).await;
+    let cfg = config(vec![silent, lossy.provider("Zzz lossy")], DOMAINS, 2);
+
+    let silent = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    let lossy = test_provider(&cfg.providers[1], &cfg).await.remove(0);
+    assert!(lossy.success_rate > 0.0 && lossy.success_rate < 100.0, "success rate {}", lossy.success_rate);
+    // By name the silent one would come first, and by median or minimum None sorts before Some.
+    for key in [SortKey::Name, SortKey::Median, SortKey::Min, SortKey::Success, SortKey::Score, SortKey::P95] {
+        assert!(compare_by(&lossy, &silent, key, None).is_lt(), "{:?}", key);
+    }
+}
+
+// On tokio's paused clock, which jumps to the next timer whenever the test is idle: a waited
+// timeout then takes exactly its length, however loaded the machine is.
+
+#[tokio::test(start_paused = true)]
+async fn a_dropped_query_is_waited_on_for_the_timeout_alone() {
+    let (address, _task) = silent_server().await;
+    let provider = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) };
+    let cfg = config(vec![provider], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.failed_durations.len(), 6);
+    assert!(result.failed_durations.iter().all(|&waited| waited >= cfg.timeout && waited <= cfg.timeout + Duration::from_millis(2)), "{:?}", result.failed_durations);
+    assert!(result.failure_wait.is_some_and(|wait| wait >= cfg.timeout && wait <= cfg.timeout + Duration::from_millis(2)), "{:?}", result.failure_wait);
+    let json = serde_json::to_value(&result).unwrap();
+    assert!(json["failure_wait_ms"].as_f64().is_some_and(|ms| ms >= 300.0), "{}", json);
+}
+
+#[tokio::test(start_paused = true)]
+async fn every_attempt_adds_one_timeout_to_the_deadline() {
+    let (address, _task) = silent_server().await;
+    let provider = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) };
+    let cfg = SpeedTestConfig { attempts: 3, ..config(vec![provider], DOMAINS, 1) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.packet_loss.map(|loss| (loss.sent, loss.lost)), Some((9, 9)));
+    let budget = cfg.timeout * 3;
+    assert!(result.failed_durations.iter().all(|&waited| waited >= budget && waited <= budget + Duration::from_millis(5)), "{:?}", result.failed_durations);
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_and_escalation_each_add_one_budget() {
+    let (address, _task) = silent_server().await;
+    let provider = DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) };
+    let escalation = Duration::from_secs(1);
+    let cfg = SpeedTestConfig { attempts: 2, retries: 2, escalation_timeout: Some(escalation), ..config(vec![provider], &DOMAINS[..1], 1) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    // Three tries of two attempts, 100 and 200 ms of backoff, and the escalation's two attempts.
+    let worst = cfg.timeout * 2 * 3 + Duration::from_millis(300) + escalation * 2;
+    assert_eq!(result.failed_durations.len(), 1);
+    assert!(result.failed_durations.iter().all(|&waited| waited >= worst && waited <= worst + Duration::from_millis(5)), "{:?}", result.failed_durations);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_stalled_tcp_query_is_cut_off_at_the_timeout() {
+    // Accepts the connection and reads the query, but answers too late.
+    let server = MockServer::start(Behavior { latency: Duration::from_secs(60), ..Default::default() }).await;
+    let cfg = SpeedTestConfig { transport: Transport::Tcp, ..config(vec![server.provider("Stalled")], DOMAINS, 1) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.success_rate, 0.0);
+    assert_eq!(result.failure_kinds.get(&FailureKind::Timeout), Some(&3));
+    assert_eq!(result.failed_durations.len(), 3);
+    // The resolver resends on top of its first try over TCP; the deadline leaves it one.
+    assert!(result.failed_durations.iter().all(|&waited| waited <= cfg.timeout + Duration::from_millis(2)), "{:?}", result.failed_durations);
+}
+
+#[tokio::test]
+async fn answered_queries_wait_on_no_failures() {
+    let server = MockServer::start(Behavior::default()).await;
+    let cfg = config(vec![server.provider("Fine")], DOMAINS, 2);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert!(result.failed_durations.is_empty());
+    assert_eq!(result.failure_wait, None);
+    assert!(serde_json::to_value(&result).unwrap().get("failure_wait_ms").is_none());
+}
+
+/// `cfg` with --failover against `server` as the primary and a closed address beside it as the
+/// secondary, so only the healthy pass reaches the server: the degraded one queries an address
+/// that never answers, then the secondary.
+async fn failover_queries(server: &MockServer, cfg: &SpeedTestConfig) -> usize {
+    let secondary: IpAddr = "127.0.0.2".parse().unwrap();
+    let failover = test_failover(server.address.ip(), secondary, server.address.port(), cfg).await;
+    assert!(failover.healthy_median.is_some());
+    server.udp_queries()
+}
+
+#[tokio::test]
+async fn failover_without_warmup_sends_only_the_test_queries() {
+    let server = MockServer::start(Behavior::default()).await;
+    let cfg = config(vec![server.provider("Primary")], DOMAINS, 1);
+    assert_eq!(failover_queries(&server, &cfg).await, cfg.rounds as usize * DOMAINS.len());
+}
+
+#[tokio::test]
+async fn failover_warms_up_as_often_as_configured() {
+    let server = MockServer::start(Behavior::default()).await;
+    let cfg = SpeedTestConfig { warmup: 2, ..config(vec![server.provider("Primary")], DOMAINS, 1) };
+    assert_eq!(failover_queries(&server, &cfg).await, 2 + DOMAINS.len());
+}
+
+#[tokio::test]
+async fn both_failover_passes_warm_up_alike() {
+    // The server is the secondary, which both passes end up at.
+    let server = MockServer::start(Behavior::default()).await;
+    let primary: IpAddr = "127.0.0.2".parse().unwrap();
+    let cfg = SpeedTestConfig { warmup: 1, ..config(vec![server.provider("Secondary")], DOMAINS, 1) };
+
+    let failover = test_failover(primary, server.address.ip(), server.address.port(), &cfg).await;
+    assert!(failover.healthy_median.is_some() && failover.failover_median.is_some(), "{:?}", failover);
+    assert_eq!(server.udp_queries(), 2 * (1 + DOMAINS.len()));
+}
+
+// The time budgets are checked between queries, so on paused time with every query timing out
+// after 300 ms, a budget of 700 ms lets exactly three start: at 0, 300 and 600 ms.
+
+fn silent_provider(name: &str, address: std::net::SocketAddr) -> DnsProvider {
+    DnsProvider { port: Some(address.port()), ..DnsProvider::new(name, address.ip()) }
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_provider_over_its_budget_is_cut_short_with_what_it_got_through() {
+    let (address, _task) = silent_server().await;
+    let cfg = SpeedTestConfig { provider_budget: Some(Duration::from_millis(700)), ..config(vec![silent_provider("Silent", address)], DOMAINS, 2) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert!(result.budget_exceeded);
+    assert_eq!(result.completed_queries, 3);
+    assert_eq!(result.samples.len(), 3);
+    let json = serde_json::to_value(&result).unwrap();
+    assert_eq!((json["budget_exceeded"].as_bool(), json["completed_queries"].as_u64()), (Some(true), Some(3)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_provider_within_its_budget_runs_every_query() {
+    let (address, _task) = silent_server().await;
+    let cfg = SpeedTestConfig { provider_budget: Some(Duration::from_secs(2)), ..config(vec![silent_provider("Silent", address)], DOMAINS, 2) };
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert!(!result.budget_exceeded);
+    assert_eq!(result.completed_queries, 6);
+}
+
+#[tokio::test(start_paused = true)]
+async fn the_total_budget_leaves_later_providers_untested() {
+    let (first, _first) = silent_server().await;
+    let (second, _second) = silent_server().await;
+    let (third, _third) = silent_server().await;
+    let providers = vec![silent_provider("One", first), silent_provider("Two", second), silent_provider("Three", third)];
+    let cfg = SpeedTestConfig { total_budget: Some(Duration::from_millis(700)), ..config(providers, DOMAINS, 2) };
+
+    let results = run_all(&cfg).await;
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|result| result.budget_exceeded));
+    // Whichever provider went first got three queries in; the others none.
+    let mut completed: Vec<u32> = results.iter().map(|result| result.completed_queries).collect();
+    completed.sort();
+    assert_eq!(completed, [0, 0, 3]);
+    assert!(results.iter().filter(|result| result.completed_queries == 0).all(|result| result.samples.is_empty()));
+}
+
+/// The providers in the order a run with `seed` started them, one at a time, and the order
+/// `provider_order` gave for it.
+async fn started(seed: Option<u64>) -> (Vec<String>, Vec<String>) {
+    let server = MockServer::start(Behavior::default()).await;
+    let providers = ["A", "B", "C", "D", "E", "F"].iter().map(|name| server.provider(name)).collect();
+    let cfg = SpeedTestConfig { provider_order_seed: seed, ..config(providers, &DOMAINS[..1], 1) };
+    let order = provider_order(&cfg).into_iter().map(|index| cfg.providers[index].name.clone()).collect();
+    let started = Mutex::new(Vec::new());
+    run_all_with_progress(&cfg, &|progress| if let Progress::Started(provider) = progress {
+        started.lock().unwrap().push(provider.name.clone());
+    }).await;
+    (started.into_inner().unwrap(), order)
+}
+
+#[tokio::test]
+async fn providers_are_tested_in_the_seeded_order() {
+    let (seeded, order) = started(Some(3)).await;
+    assert_eq!(seeded, order);
+    assert_ne!(seeded, ["A", "B", "C", "D", "E", "F"]);
+    assert_eq!(started(Some(3)).await.0, seeded);
+}
+
+#[tokio::test]
+async fn without_a_seed_providers_are_tested_as_configured() {
+    let (started, order) = started(None).await;
+    assert_eq!(started, ["A", "B", "C", "D", "E", "F"]);
+    assert_eq!(order, started);
+}
+
+const SHUFFLED: &[&str] = &["a.example", "b.example", "c.example", "d.example", "e.example", "f.example"];
+
+/// The samples of three rounds of `SHUFFLED` against a server that fails two of them, in the
+/// order they were sent, with the queries the server got and the failures as reported.
+async fn shuffled(seed: Option<u64>) -> (Vec<(u32, String)>, usize, Vec<(String, FailureKind)>) {
+    let server = MockServer::start(Behavior { servfail: vec!["b.example".to_string(), "e.example".to_string()], ..Default::default() }).await;
+    let cfg = SpeedTestConfig { shuffle_seed: seed, ..config(vec![server.provider("Shuffled")], SHUFFLED, 3) };
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.completed_queries, 18);
+    let sent = result.samples.iter().map(|sample| (sample.round, sample.domain.clone())).collect();
+    let failures = result.failures.iter().map(|failure| (failure.domain.clone(), failure.kind)).collect();
+    (sent, server.udp_queries(), failures)
+}
+
+#[tokio::test]
+async fn shuffling_changes_the_order_of_the_queries_alone() {
+    let (fixed, fixed_queries, fixed_failures) = shuffled(None).await;
+    let (seeded, seeded_queries, seeded_failures) = shuffled(Some(7)).await;
+    assert_ne!(seeded, fixed);
+    let sorted = |mut sent: Vec<(u32, String)>| {
+        sent.sort();
+        sent
+    };
+    assert_eq!(sorted(seeded.clone()), sorted(fixed.clone()));
+    assert_eq!((seeded_queries, fixed_queries), (18, 18));
+    // Failures are listed in the configured order either way.
+    assert_eq!(seeded_failures, fixed_failures);
+    assert_eq!(fixed_failures.len(), 6);
+
+    let (again, _, _) = shuffled(Some(7)).await;
+    assert_eq!(again, seeded);
+    let (other, _, _) = shuffled(Some(8)).await;
+    assert_ne!(other, seeded);
+}
+
+/// How the mock's `reply` to ANY for one domain was classified, and the success rate.
+async fn any_handling(reply: Option<AnyReply>) -> (Vec<(AnyHandling, u32)>, f64) {
+    let server = MockServer::start(Behavior { any: reply, ..Default::default() }).await;
+    let cfg = SpeedTestConfig { record_types: vec![QueryType::Any], ..config(vec![server.provider("ANY")], &DOMAINS[..1], 2) };
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    (result.any_handling.into_iter().collect(), result.success_rate)
+}
+
+#[tokio::test]
+async fn any_responses_are_classified_by_their_content() {
+    assert_eq!(any_handling(Some(AnyReply::Records)).await, (vec![(AnyHandling::Full, 2)], 100.0));
+    assert_eq!(any_handling(Some(AnyReply::Minimal)).await, (vec![(AnyHandling::Minimal, 2)], 100.0));
+}
+
+#[tokio::test]
+async fn any_declined_with_notimp_or_refused_counts_as_handled() {
+    assert_eq!(any_handling(Some(AnyReply::Code(ResponseCode::NotImp))).await, (vec![(AnyHandling::NotImplemented, 2)], 100.0));
+    assert_eq!(any_handling(Some(AnyReply::Code(ResponseCode::Refused))).await, (vec![(AnyHandling::Refused, 2)], 100.0));
+}
+
+#[tokio::test]
+async fn any_without_records_or_with_servfail_is_not_classified() {
+    // An empty NOERROR is a plain NODATA answer; SERVFAIL stays a failure.
+    assert_eq!(any_handling(None).await, (vec![], 100.0));
+    assert_eq!(any_handling(Some(AnyReply::Code(ResponseCode::ServFail))).await, (vec![], 0.0));
+}
@@ -0,0 +1,59 @@
+//! A timed run against the mock server, which answers at once: what a query costs outside its
+//! timed round trip, such as resolver setup, name parsing and bookkeeping, and what the resolver
+//! adds to a bare UDP exchange. `cargo test --release --test overhead -- --nocapture` prints the
+//! figures.
+
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use common::{config, Behavior, MockServer};
+use dns_speed_test::test_provider;
+use hickory_resolver::proto::op::{Message, Query};
+use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::Name;
+
+const ROUNDS: u32 = 20;
+const DOMAINS: usize = 25;
+/// Far above what a debug build on a loaded machine needs, and far below a resolver rebuilt or
+/// a name reparsed per query.
+const MAX_OVERHEAD: Duration = Duration::from_millis(2);
+
+/// The mean time of `count` bare UDP exchanges with `server`, one after another: the floor under
+/// any resolver's round trip.
+fn bare_round_trip(server: SocketAddr, count: u32) -> Duration {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(server).unwrap();
+    let mut query = Message::new();
+    query.set_id(1).set_recursion_desired(true).add_query(Query::query(Name::from_ascii("example.com.").unwrap(), RecordType::A));
+    let query = query.to_vec().unwrap();
+    let mut buf = [0u8; 512];
+    let start = Instant::now();
+    for _ in 0..count {
+        socket.send(&query).unwrap();
+        socket.recv(&mut buf).unwrap();
+    }
+    start.elapsed() / count
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_time_around_each_query_stays_small() {
+    let server = MockServer::start(Behavior::default()).await;
+    let domains: Vec<String> = (0..DOMAINS).map(|n| format!("overhead{}.example.com", n)).collect();
+    let domains: Vec<&str> = domains.iter().map(String::as_str).collect();
+    let cfg = config(vec![server.provider("Mock")], &domains, ROUNDS);
+
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    let queries = ROUNDS * DOMAINS as u32;
+    assert_eq!(result.completed_queries, queries);
+    assert_eq!(result.success_rate, 100.0);
+    let round_trips: Duration = result.samples.iter().filter_map(|sample| sample.duration).sum();
+    let overhead = result.timing.queries.saturating_sub(round_trips) / queries;
+    let address = server.address;
+    let bare = tokio::task::spawn_blocking(move || bare_round_trip(address, queries)).await.unwrap();
+    println!(
+        "{} queries in {:?}: {:?} per query, {:?} of it outside the timed round trip; a bare UDP exchange takes {:?}",
+        queries, result.timing.queries, result.timing.queries / queries, overhead, bare,
+    );
+    assert!(overhead < MAX_OVERHEAD, "{:?} per query outside the round trip", overhead);
+}
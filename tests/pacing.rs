@@ -0,0 +1,92 @@
+//! Pacing on paused time: the --qps pacer's spacing has to hold for every task sharing it, and
+//! --concurrency 1 has to test the providers strictly one after another.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use common::{config, silent_server};
+use dns_speed_test::{run_all_with_progress, DnsProvider, Pacer, Progress, SpeedTestConfig};
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_callers_share_the_spacing() {
+    let pacer = Arc::new(Pacer::per_second(10.0));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let (pacer, sent) = (pacer.clone(), sent.clone());
+            tokio::spawn(async move {
+                for _ in 0..3 {
+                    pacer.wait().await;
+                    sent.lock().unwrap().push(start.elapsed());
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut sent = sent.lock().unwrap().clone();
+    sent.sort();
+    let expected: Vec<Duration> = (0..12).map(|slot| Duration::from_millis(100) * slot).collect();
+    assert_eq!(sent, expected);
+}
+
+#[tokio::test(start_paused = true)]
+async fn an_idle_pacer_saves_up_no_burst() {
+    let pacer = Pacer::per_second(10.0);
+    pacer.wait().await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let start = Instant::now();
+    pacer.wait().await;
+    pacer.wait().await;
+    assert_eq!(start.elapsed(), Duration::from_millis(100));
+}
+
+/// When each of three providers that never answer started and finished, in the order of the
+/// events, with `concurrency` of them at a time: two queries each, so 600 ms per provider.
+async fn provider_windows(concurrency: usize) -> (Vec<(String, Duration, Duration)>, Duration) {
+    let mut servers = Vec::new();
+    for _ in 0..3 {
+        servers.push(silent_server().await);
+    }
+    let providers = servers.iter().enumerate()
+        .map(|(i, (address, _))| DnsProvider { port: Some(address.port()), ..DnsProvider::new(format!("Silent {}", i), address.ip()) })
+        .collect();
+    let cfg = SpeedTestConfig { concurrency, ..config(providers, &["example.com", "example.org"], 1) };
+    let start = Instant::now();
+    let events = Mutex::new(Vec::new());
+    run_all_with_progress(&cfg, &|progress| match progress {
+        Progress::Started(provider) => events.lock().unwrap().push((provider.name.clone(), start.elapsed(), None)),
+        Progress::Finished(provider, _) => {
+            let mut events = events.lock().unwrap();
+            let window = events.iter_mut().find(|(name, _, end)| *name == provider.name && end.is_none()).unwrap();
+            window.2 = Some(start.elapsed());
+        },
+        _ => {},
+    }).await;
+    let windows = events.into_inner().unwrap().into_iter().map(|(name, from, to)| (name, from, to.unwrap())).collect();
+    (windows, start.elapsed())
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrency_1_runs_the_providers_one_after_another() {
+    let (windows, elapsed) = provider_windows(1).await;
+    assert_eq!(windows.len(), 3);
+    for pair in windows.windows(2) {
+        assert!(pair[0].2 <= pair[1].1, "{} still running when {} started: {:?}", pair[0].0, pair[1].0, windows);
+    }
+    // As long as the providers take one by one, without a moment of overlap.
+    let busy: Duration = windows.iter().map(|(_, from, to)| *to - *from).sum();
+    assert!(busy >= Duration::from_millis(1800) && elapsed - busy < Duration::from_millis(10), "{:?} of {:?}", busy, elapsed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_higher_concurrency_runs_them_side_by_side() {
+    let (windows, elapsed) = provider_windows(3).await;
+    assert!(windows.iter().all(|(_, from, _)| *from < windows[0].2), "{:?}", windows);
+    assert!(elapsed < Duration::from_millis(1200), "{:?}", elapsed);
+}
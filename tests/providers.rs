@@ -0,0 +1,112 @@
+//! The providers a --providers-file lists, and the built-in ones the presets pick.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use dns_speed_test::{builtin_catalog, classify_address, load_providers_file, preset_providers, DnsProvider, IpVersion, ProviderSet, ProvidersMode, PRESETS};
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize)]
+struct File<'a> {
+    mode: &'a str,
+    providers: &'a [DnsProvider],
+}
+
+/// One provider with nothing but the required fields and one with every field set.
+fn providers() -> Vec<DnsProvider> {
+    let plain = DnsProvider::new("Plain", "203.0.113.53".parse().unwrap());
+    let full = DnsProvider {
+        organization: Some("Example".to_string()),
+        addresses: vec!["198.51.100.53".parse().unwrap(), "2001:db8::53".parse().unwrap()],
+        doh_host: Some("doh.example".to_string()),
+        doh_path: Some("/profile".to_string()),
+        dot_host: Some("dot.example".to_string()),
+        doq_host: Some("doq.example".to_string()),
+        doq_port: Some(8853),
+        system: true,
+        port: Some(5353),
+        timeout: Some(Duration::from_millis(500)),
+        attempts: Some(2),
+        cooldown: Some(Duration::from_millis(20)),
+        ..DnsProvider::new("Full (tagged)", "198.51.100.53".parse().unwrap())
+    };
+    vec![plain, full]
+}
+
+fn write(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dnsspeedtest-{}-{}", std::process::id(), name));
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+/// `providers` written to a file by `serialize` and loaded again, compared by their serialized
+/// form, as `DnsProvider` has no `PartialEq`.
+fn round_trip(name: &str, serialize: impl Fn(&File) -> String) {
+    let providers = providers();
+    let path = write(name, &serialize(&File { mode: "replace", providers: &providers }));
+    let file = load_providers_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(file.mode, Some(ProvidersMode::Replace));
+    let values = |providers: &[DnsProvider]| providers.iter().map(|provider| serde_json::to_value(provider).unwrap()).collect::<Vec<Value>>();
+    assert_eq!(values(&file.providers), values(&providers));
+}
+
+#[test]
+fn providers_survive_a_toml_file() {
+    round_trip("round-trip.toml", |file| toml::to_string(file).unwrap());
+}
+
+#[test]
+fn providers_survive_a_json_file() {
+    round_trip("round-trip.json", |file| serde_json::to_string_pretty(file).unwrap());
+}
+
+#[test]
+fn a_file_without_a_mode_leaves_it_open() {
+    let path = write("no-mode.toml", "[[providers]]\nname = \"Only\"\naddresses = [\"203.0.113.53\"]\n");
+    let file = load_providers_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(file.mode, None);
+    assert_eq!(file.providers.len(), 1);
+}
+
+#[test]
+fn every_builtin_address_parses_and_is_public() {
+    // The catalog parses each address of both families of every entry, and panics on a typo.
+    for (_, provider) in builtin_catalog() {
+        assert!(!provider.addresses.is_empty(), "{}", provider.name);
+        for ip in &provider.addresses {
+            assert_eq!(classify_address(*ip), None, "{}: {}", provider.name, ip);
+        }
+        let mut unique = provider.addresses.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), provider.addresses.len(), "{}: {:?}", provider.name, provider.addresses);
+    }
+}
+
+#[test]
+fn every_preset_names_builtin_providers() {
+    let catalog = builtin_catalog();
+    for preset in PRESETS {
+        assert!(!preset.providers.is_empty(), "{}", preset.name);
+        for name in preset.providers {
+            let organization = Some(name.to_string());
+            assert!(catalog.iter().any(|(_, provider)| provider.organization == organization), "{}: {}", preset.name, name);
+        }
+    }
+}
+
+#[test]
+fn preset_providers_keep_to_the_family_asked_for() {
+    for preset in PRESETS {
+        for (ip_version, v4) in [(IpVersion::V4, true), (IpVersion::V6, false)] {
+            let providers = preset_providers(preset, ProviderSet::All, ip_version);
+            assert!(!providers.is_empty(), "{} {:?}", preset.name, ip_version);
+            assert!(providers.iter().flat_map(|provider| &provider.addresses).all(|ip| ip.is_ipv4() == v4), "{} {:?}", preset.name, ip_version);
+        }
+        let both = preset_providers(preset, ProviderSet::All, IpVersion::Both).len();
+        let single = preset_providers(preset, ProviderSet::All, IpVersion::V4).len() + preset_providers(preset, ProviderSet::All, IpVersion::V6).len();
+        assert_eq!(both, single, "{}", preset.name);
+    }
+}
@@ -0,0 +1,93 @@
+//! DoQ against servers that do not speak QUIC: the handshake has to fail within its timeout,
+//! and a run over DoQ has to report the provider as unreachable under the DoQ label rather than
+//! hang or fall back to plain DNS.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use common::{config, silent_server, Behavior, MockServer};
+use dns_speed_test::{quic_handshake, run_all, test_provider, DnsProvider, SpeedTestConfig, Transport};
+
+const DOMAINS: &[&str] = &["example.com", "example.org"];
+const HOST: &str = "dns.example";
+const TIMEOUT: Duration = Duration::from_millis(300);
+/// How far past its timeout a loaded test machine may let a handshake give up.
+const TOLERANCE: Duration = Duration::from_millis(200);
+
+/// `provider` with a DoQ endpoint on `port` of its address.
+fn with_doq(provider: DnsProvider, port: u16) -> DnsProvider {
+    DnsProvider { doq_host: Some(HOST.to_string()), doq_port: Some(port), ..provider }
+}
+
+async fn failed_handshake(server: SocketAddr) -> String {
+    let start = Instant::now();
+    let error = quic_handshake(server, HOST, None, TIMEOUT).await.expect_err("no QUIC server there");
+    assert!(start.elapsed() < TIMEOUT + TOLERANCE, "gave up after {:?}", start.elapsed());
+    error
+}
+
+#[tokio::test]
+async fn a_handshake_with_a_plain_dns_server_fails() {
+    let server = MockServer::start(Behavior::default()).await;
+    failed_handshake(server.address).await;
+    // The QUIC packets reached it, but none was a DNS query it could answer.
+    assert!(server.udp_queries() > 0);
+}
+
+#[tokio::test]
+async fn a_handshake_nobody_answers_times_out() {
+    let (address, _task) = silent_server().await;
+    let error = failed_handshake(address).await;
+    assert!(error.contains("timed out"), "{}", error);
+}
+
+#[tokio::test]
+async fn a_handshake_with_a_closed_port_fails() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    drop(socket);
+    failed_handshake(address).await;
+}
+
+#[tokio::test]
+async fn queries_over_doq_to_a_plain_dns_server_fail_under_the_doq_label() {
+    let server = MockServer::start(Behavior::default()).await;
+    let provider = with_doq(server.provider("Plain only"), server.address.port());
+    let cfg = SpeedTestConfig { transport: Transport::Quic, timeout: TIMEOUT, tcp_check: true, ..config(vec![provider], DOMAINS, 1) };
+
+    let start = Instant::now();
+    let result = test_provider(&cfg.providers[0], &cfg).await.remove(0);
+    assert_eq!(result.transport, "DoQ");
+    assert_eq!(result.success_rate, 0.0);
+    assert_eq!(result.median_duration, None);
+    // The handshakes stand in for the TCP connects, and none succeeded.
+    assert_eq!((result.tcp_connect, result.quic_handshake), (None, None));
+    assert_eq!(server.tcp_queries(), 0);
+    // Three handshakes and two queries, each cut off at the timeout.
+    assert!(start.elapsed() < TIMEOUT * 5 + TOLERANCE * 2, "took {:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn a_silent_provider_fails_the_preflight_check_over_doq() {
+    let (address, _task) = silent_server().await;
+    let silent = with_doq(DnsProvider { port: Some(address.port()), ..DnsProvider::new("Silent", address.ip()) }, address.port());
+    let cfg = SpeedTestConfig { transport: Transport::Quic, preflight: true, ..config(vec![silent], DOMAINS, 1) };
+    let result = run_all(&cfg).await.remove(0);
+    assert!(result.unreachable);
+    assert!(result.samples.is_empty());
+    assert_eq!(result.transport, "DoQ");
+}
+
+#[test]
+fn only_providers_with_a_doq_host_support_doq() {
+    let plain = DnsProvider::new("Plain", "192.0.2.53".parse().unwrap());
+    assert!(!plain.supports(Transport::Quic));
+    let provider: DnsProvider = serde_json::from_str(r#"{"name": "DoQ", "addresses": ["192.0.2.53"], "doq_host": "dns.example", "doq_port": 8853}"#).unwrap();
+    assert!(provider.supports(Transport::Quic));
+    assert_eq!((provider.tls_host(Transport::Quic), provider.port_for(Transport::Quic)), (Some(HOST), 8853));
+    // Port 853 is the default, stored as none; DoT keeps it whatever the DoQ port.
+    let standard: DnsProvider = serde_json::from_str(r#"{"name": "DoQ", "addresses": ["192.0.2.53"], "doq_host": "dns.example", "doq_port": 853}"#).unwrap();
+    assert_eq!((standard.doq_port, standard.port_for(Transport::Quic)), (None, 853));
+    assert_eq!(provider.port_for(Transport::Tls), 853);
+}
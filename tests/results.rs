@@ -0,0 +1,128 @@
+//! The classifications behind the result fields, on hand-picked inputs.
+
+use std::io;
+use std::net::IpAddr;
+use dns_speed_test::{classify_address, usable_answer, AddressRange, FailureKind};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::error::{ProtoError, ProtoErrorKind};
+use hickory_resolver::proto::op::{Query, ResponseCode};
+use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::Name;
+
+fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+    addresses.iter().map(|address| address.parse().unwrap()).collect()
+}
+
+#[test]
+fn an_empty_answer_is_not_usable() {
+    assert!(!usable_answer(&[]));
+}
+
+#[test]
+fn blocking_sentinels_alone_are_not_usable() {
+    assert!(!usable_answer(&ips(&["0.0.0.0"])));
+    assert!(!usable_answer(&ips(&["::"])));
+    assert!(!usable_answer(&ips(&["0.0.0.0", "::", "127.0.0.1", "::1"])));
+}
+
+#[test]
+fn one_public_address_among_sentinels_is_usable() {
+    assert!(usable_answer(&ips(&["0.0.0.0", "93.184.215.14"])));
+    assert!(usable_answer(&ips(&["::", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"])));
+}
+
+#[test]
+fn a_normal_answer_is_usable() {
+    assert!(usable_answer(&ips(&["93.184.215.14"])));
+    assert!(usable_answer(&ips(&["142.250.185.78", "142.250.185.110"])));
+}
+
+fn no_records(response_code: ResponseCode) -> ResolveError {
+    let query = Query::query(Name::from_ascii("example.com.").unwrap(), RecordType::A);
+    ResolveErrorKind::NoRecordsFound { query: Box::new(query), soa: None, negative_ttl: None, response_code, trusted: true }.into()
+}
+
+fn io_error(kind: io::ErrorKind) -> io::Error {
+    io::Error::new(kind, "from the test")
+}
+
+#[test]
+fn each_resolver_error_maps_to_its_failure_kind() {
+    let cases: Vec<(ResolveError, FailureKind)> = vec![
+        (ResolveErrorKind::Timeout.into(), FailureKind::Timeout),
+        (no_records(ResponseCode::NXDomain), FailureKind::Nxdomain),
+        (no_records(ResponseCode::ServFail), FailureKind::Servfail),
+        (no_records(ResponseCode::Refused), FailureKind::Refused),
+        (no_records(ResponseCode::NotImp), FailureKind::OtherResponse),
+        (no_records(ResponseCode::FormErr), FailureKind::OtherResponse),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::TimedOut)).into(), FailureKind::Timeout),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::ConnectionRefused)).into(), FailureKind::ConnectionRefused),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::ConnectionReset)).into(), FailureKind::ConnectionRefused),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::NetworkUnreachable)).into(), FailureKind::NoRoute),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::HostUnreachable)).into(), FailureKind::NoRoute),
+        (ResolveErrorKind::Io(io_error(io::ErrorKind::BrokenPipe)).into(), FailureKind::Network),
+        (ResolveErrorKind::Proto(ProtoError::from(ProtoErrorKind::Timeout)).into(), FailureKind::Timeout),
+        (ResolveErrorKind::Proto(ProtoError::from(ProtoErrorKind::Io(io_error(io::ErrorKind::ConnectionRefused)))).into(), FailureKind::ConnectionRefused),
+        (ResolveErrorKind::Proto(ProtoError::from(ProtoErrorKind::Message("bad certificate"))).into(), FailureKind::Network),
+        (ResolveErrorKind::NoConnections.into(), FailureKind::Network),
+        (ResolveErrorKind::Message("anything else").into(), FailureKind::Other),
+    ];
+    for (error, kind) in cases {
+        assert_eq!(FailureKind::of(&error), kind, "{:?}", error);
+    }
+}
+
+#[test]
+fn each_address_falls_in_its_range() {
+    let cases: &[(&str, Option<AddressRange>)] = &[
+        ("0.0.0.0", Some(AddressRange::Unspecified)),
+        ("::", Some(AddressRange::Unspecified)),
+        ("0.0.0.1", Some(AddressRange::ThisNetwork)),
+        ("0.255.255.255", Some(AddressRange::ThisNetwork)),
+        ("10.0.0.1", Some(AddressRange::Private)),
+        ("172.16.0.1", Some(AddressRange::Private)),
+        ("172.31.255.255", Some(AddressRange::Private)),
+        ("172.32.0.1", None),
+        ("192.168.1.1", Some(AddressRange::Private)),
+        ("100.63.255.255", None),
+        ("100.64.0.1", Some(AddressRange::SharedAddressSpace)),
+        ("100.127.255.255", Some(AddressRange::SharedAddressSpace)),
+        ("100.128.0.1", None),
+        ("127.0.0.1", Some(AddressRange::Loopback)),
+        ("127.255.255.254", Some(AddressRange::Loopback)),
+        ("::1", Some(AddressRange::Loopback)),
+        ("169.254.1.1", Some(AddressRange::LinkLocal)),
+        ("fe80::1", Some(AddressRange::LinkLocal)),
+        ("febf::1", Some(AddressRange::LinkLocal)),
+        ("fec0::1", None),
+        ("192.0.2.1", Some(AddressRange::Documentation)),
+        ("198.51.100.1", Some(AddressRange::Documentation)),
+        ("203.0.113.1", Some(AddressRange::Documentation)),
+        ("2001:db8::1", Some(AddressRange::Documentation)),
+        ("3fff::1", Some(AddressRange::Documentation)),
+        ("3fff:fff::1", Some(AddressRange::Documentation)),
+        ("198.17.255.255", None),
+        ("198.18.0.1", Some(AddressRange::Benchmarking)),
+        ("198.19.255.255", Some(AddressRange::Benchmarking)),
+        ("198.20.0.1", None),
+        ("fc00::1", Some(AddressRange::UniqueLocal)),
+        ("fd12:3456::1", Some(AddressRange::UniqueLocal)),
+        ("192.0.0.1", Some(AddressRange::Reserved)),
+        ("240.0.0.1", Some(AddressRange::Reserved)),
+        ("255.255.255.255", Some(AddressRange::Reserved)),
+        ("1.1.1.1", None),
+        ("93.184.215.14", None),
+        ("2606:4700:4700::1111", None),
+        ("2001:4860:4860::8888", None),
+    ];
+    for &(address, range) in cases {
+        assert_eq!(classify_address(address.parse().unwrap()), range, "{}", address);
+    }
+}
+
+#[test]
+fn a_mapped_address_is_classified_as_the_ipv4_address() {
+    for (mapped, v4) in [("::ffff:10.0.0.1", "10.0.0.1"), ("::ffff:0.0.0.0", "0.0.0.0"), ("::ffff:127.0.0.1", "127.0.0.1"), ("::ffff:1.1.1.1", "1.1.1.1")] {
+        assert_eq!(classify_address(mapped.parse().unwrap()), classify_address(v4.parse().unwrap()), "{}", mapped);
+    }
+}
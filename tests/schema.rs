@@ -0,0 +1,100 @@
+//! The JSON report is a contract: the fields of `--schema`'s fully populated example, with their
+//! types, have to match the snapshot of the schema version it declares, and a real run may only
+//! use fields the snapshot has.
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use common::{Behavior, MockServer};
+use serde_json::Value;
+
+/// Every field of `value` as "path: type", array elements merged under "[]".
+fn fields(path: &str, value: &Value, out: &mut BTreeSet<String>) {
+    let kind = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    if !path.is_empty() {
+        out.insert(format!("{}: {}", path, kind));
+    }
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| fields(&format!("{}[]", path), item, out)),
+        Value::Object(map) => map.iter().for_each(|(key, item)| fields(&if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) }, item, out)),
+        _ => {},
+    }
+}
+
+/// The "path: type" lines of the snapshot of schema `version`.
+fn snapshot_fields(version: u64) -> BTreeSet<String> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/snapshots/schema-v{}.txt", version));
+    std::fs::read_to_string(path).unwrap().lines().map(String::from).collect()
+}
+
+#[test]
+fn the_report_matches_the_snapshot_of_its_schema_version() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dns_speed_test")).arg("--schema").output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let example: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let version = example["schema_version"].as_u64().expect("schema_version");
+
+    let mut actual = BTreeSet::new();
+    fields("", &example, &mut actual);
+    let snapshot = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/snapshots/schema-v{}.txt", version));
+    let current: String = actual.iter().map(|line| format!("{}\n", line)).collect();
+    let Ok(expected) = std::fs::read_to_string(&snapshot) else {
+        panic!("no snapshot for schema version {}; save this as {}:\n{}", version, snapshot.display(), current);
+    };
+    let expected: BTreeSet<String> = expected.lines().map(String::from).collect();
+
+    let gone: Vec<_> = expected.difference(&actual).collect();
+    let added: Vec<_> = actual.difference(&expected).collect();
+    assert!(gone.is_empty(), "fields removed, renamed or retyped without bumping SCHEMA_VERSION: {:?}; added: {:?}", gone, added);
+    assert!(added.is_empty(), "fields added to schema version {}: {:?}; update {} to:\n{}", version, added, snapshot.display(), current);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_real_report_uses_only_the_snapshot_fields() {
+    let server = MockServer::start(Behavior::default()).await;
+    let mock = format!("Mock={}", server.address);
+    let home = std::env::temp_dir().join(format!("dnsspeedtest-schema-{}", std::process::id()));
+    std::fs::create_dir_all(&home).unwrap();
+    // The snapshot's maps keyed by domain have the example's one domain, so the run has it too.
+    let output = Command::new(env!("CARGO_BIN_EXE_dns_speed_test"))
+        .args(["--no-system", "--only-custom", "--server", &mock, "--domain", "example.com", "--rounds", "2"])
+        .args(["--warmup", "0", "--cooldown", "0", "--timeout", "300ms", "--no-interception-check", "--no-pop-check", "--no-identity-check", "--format", "json"])
+        .envs(["HOME", "XDG_CONFIG_HOME", "XDG_CACHE_HOME", "XDG_DATA_HOME"].map(|dir| (dir, &home)))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&home).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Nothing but the report on stdout; the progress lines went to stderr.
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Mock"));
+
+    let known: BTreeSet<String> = snapshot_fields(report["schema_version"].as_u64().unwrap()).iter()
+        .map(|line| line.split_once(": ").unwrap().0.to_string())
+        .collect();
+    let mut used = BTreeSet::new();
+    fields("", &report, &mut used);
+    let unknown: Vec<&str> = used.iter().map(|line| line.split_once(": ").unwrap().0).filter(|path| !known.contains(*path)).collect();
+    assert!(unknown.is_empty(), "fields missing from the snapshot: {:?}", unknown);
+    // The ones scripts graph, by the names they have always had.
+    for field in ["schema_version", "version", "timestamp", "config", "results"] {
+        assert!(report.get(field).is_some(), "no {}", field);
+    }
+    assert_eq!(report["config"]["rounds"], 2);
+    assert_eq!(report["config"]["domains"], serde_json::json!(["example.com"]));
+    let result = &report["results"][0];
+    for field in ["median_ms", "avg_ms", "min_ms", "max_ms", "success_rate"] {
+        assert!(result[field].is_f64(), "{}: {}", field, result[field]);
+    }
+    assert_eq!(result["provider"], format!("Mock (port {})", server.address.port()));
+    assert_eq!(result["failed_domains"], serde_json::json!([]));
+}
@@ -0,0 +1,185 @@
+//! The statistics every figure in the results comes from, on hand-picked samples.
+
+use std::time::Duration;
+use dns_speed_test::stats::{histogram, jitter, median_interval, median_settled, near_timeout, percentile, score, std_dev, trim, Bucket, Precision, ScoreWeights, ADAPTIVE_MIN_SAMPLES};
+
+fn ms(values: &[u64]) -> Vec<Duration> {
+    values.iter().map(|&value| Duration::from_millis(value)).collect()
+}
+
+#[test]
+fn the_median_of_nothing_is_none() {
+    assert_eq!(percentile(&[], 50.0), None);
+}
+
+#[test]
+fn the_median_of_an_odd_count_is_the_middle_sample() {
+    assert_eq!(percentile(&ms(&[10, 20, 90]), 50.0), Some(Duration::from_millis(20)));
+}
+
+#[test]
+fn the_median_of_an_even_count_averages_the_middle_samples() {
+    assert_eq!(percentile(&ms(&[10, 20, 30, 90]), 50.0), Some(Duration::from_millis(25)));
+    assert_eq!(percentile(&ms(&[10, 20]), 50.0), Some(Duration::from_millis(15)));
+}
+
+#[test]
+fn a_single_sample_is_every_percentile() {
+    let one = ms(&[42]);
+    for p in [0.0, 50.0, 95.0, 99.0, 100.0] {
+        assert_eq!(percentile(&one, p), Some(Duration::from_millis(42)));
+    }
+}
+
+#[test]
+fn two_samples_interpolate_between_the_ends() {
+    let two = ms(&[10, 20]);
+    assert_eq!(percentile(&two, 0.0), Some(Duration::from_millis(10)));
+    assert_eq!(percentile(&two, 100.0), Some(Duration::from_millis(20)));
+    assert_eq!(percentile(&two, 95.0), Some(Duration::from_micros(19_500)));
+}
+
+#[test]
+fn the_tail_percentiles_of_fifty_samples_interpolate_between_ranks() {
+    // 1 ms to 50 ms: p95 sits at rank 46.55, p99 at 48.51, counting from 0.
+    let fifty: Vec<Duration> = (1..=50).map(Duration::from_millis).collect();
+    assert_eq!(percentile(&fifty, 0.0), Some(Duration::from_millis(1)));
+    assert_eq!(percentile(&fifty, 50.0), Some(Duration::from_micros(25_500)));
+    assert_eq!(percentile(&fifty, 95.0), Some(Duration::from_micros(47_550)));
+    assert_eq!(percentile(&fifty, 99.0), Some(Duration::from_micros(49_510)));
+    assert_eq!(percentile(&fifty, 100.0), Some(Duration::from_millis(50)));
+}
+
+#[test]
+fn spread_needs_two_samples() {
+    for samples in [ms(&[]), ms(&[10])] {
+        assert_eq!(std_dev(&samples), None);
+        assert_eq!(jitter(&samples), None);
+    }
+    assert_eq!(std_dev(&ms(&[10, 20])).map(|d| d.as_micros()), Some(7071));
+    // Jitter follows the order of the queries: 10 → 20 → 10 moves 10 ms each time.
+    assert_eq!(jitter(&ms(&[10, 20, 10])), Some(Duration::from_millis(10)));
+}
+
+#[test]
+fn trimming_keeps_at_least_three_samples() {
+    let ten: Vec<u32> = (1..=10).collect();
+    assert_eq!(trim(&ten, 10.0), &[2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(trim(&ten, 50.0), &[4, 5, 6, 7]);
+    assert_eq!(trim(&[1, 2, 3, 4, 5], 40.0), &[2, 3, 4]);
+    assert_eq!(trim(&[1, 2, 3], 50.0), &[1, 2, 3]);
+    assert_eq!(trim(&[1, 2], 50.0), &[1, 2]);
+}
+
+#[test]
+fn no_samples_have_no_median_interval() {
+    assert_eq!(median_interval(&[]), None);
+    assert!(!median_settled(&[], Precision::Percent(100.0)));
+}
+
+#[test]
+fn the_median_interval_stays_within_the_samples() {
+    assert_eq!(median_interval(&ms(&[7])), Some((Duration::from_millis(7), Duration::from_millis(7))));
+    // n = 15: ranks 15/2 ∓ 0.98·√15, i.e. the 3rd and the 13th sample.
+    let fifteen: Vec<Duration> = (1..=15).map(Duration::from_millis).collect();
+    assert_eq!(median_interval(&fifteen), Some((Duration::from_millis(3), Duration::from_millis(13))));
+}
+
+#[test]
+fn too_few_samples_never_settle() {
+    let identical = vec![Duration::from_millis(10); ADAPTIVE_MIN_SAMPLES - 1];
+    assert!(!median_settled(&identical, Precision::Within(Duration::from_millis(1))));
+    assert!(median_settled(&vec![Duration::from_millis(10); ADAPTIVE_MIN_SAMPLES], Precision::Within(Duration::from_millis(1))));
+}
+
+#[test]
+fn a_tight_cluster_settles_and_a_wide_spread_does_not() {
+    let tight: Vec<Duration> = (0..20).map(|i| Duration::from_micros(10_000 + i * 50)).collect();
+    let wide: Vec<Duration> = (1..=20).map(|i| Duration::from_millis(i * 10)).collect();
+    for precision in [Precision::Within(Duration::from_millis(1)), Precision::Percent(10.0)] {
+        assert!(median_settled(&tight, precision), "{}", precision);
+        assert!(!median_settled(&wide, precision), "{}", precision);
+    }
+}
+
+fn assert_score(score: Option<f64>, expected: f64) {
+    let score = score.expect("a score");
+    assert!((score - expected).abs() < 1e-9, "{} != {}", score, expected);
+}
+
+#[test]
+fn failures_lengthen_the_median_by_their_weighted_share() {
+    // 20 ms × (1 + 10 × 0.05) = 30
+    assert_score(score(Duration::from_millis(20), 95.0, None, ScoreWeights::default()), 30.0);
+    // 40 ms × (1 + 2 × 0.25) = 60
+    assert_score(score(Duration::from_millis(40), 75.0, None, ScoreWeights { success: 2.0, jitter: 0.5 }), 60.0);
+    assert_score(score(Duration::from_millis(20), 100.0, None, ScoreWeights::default()), 20.0);
+}
+
+#[test]
+fn jitter_adds_its_weight_in_milliseconds() {
+    // 20 ms + 0.5 × 4 ms = 22
+    assert_score(score(Duration::from_millis(20), 100.0, Some(Duration::from_millis(4)), ScoreWeights::default()), 22.0);
+    // 20 ms × (1 + 10 × 0.1) + 0.5 × 6 ms = 43
+    assert_score(score(Duration::from_millis(20), 90.0, Some(Duration::from_millis(6)), ScoreWeights::default()), 43.0);
+}
+
+#[test]
+fn zero_weights_score_the_median_alone() {
+    let median_only = ScoreWeights { success: 0.0, jitter: 0.0 };
+    assert_score(score(Duration::from_millis(20), 50.0, Some(Duration::from_millis(8)), median_only), 20.0);
+}
+
+#[test]
+fn no_answers_score_worst() {
+    assert_eq!(score(Duration::from_millis(20), 0.0, None, ScoreWeights::default()), None);
+}
+
+fn bucket(start: u64, end: u64, count: usize) -> Bucket {
+    Bucket { start: Duration::from_millis(start), end: Duration::from_millis(end), count }
+}
+
+#[test]
+fn no_samples_have_no_histogram() {
+    assert_eq!(histogram(&[], 10), Vec::new());
+}
+
+#[test]
+fn a_single_sample_gets_a_bucket_of_its_own_scale() {
+    // 7 ms over 10 buckets is 0.7 ms each, rounded up to 1 ms.
+    assert_eq!(histogram(&ms(&[7]), 10), vec![bucket(7, 8, 1)]);
+}
+
+#[test]
+fn identical_samples_share_one_bucket() {
+    assert_eq!(histogram(&ms(&[7, 7, 7]), 10), vec![bucket(7, 8, 3)]);
+}
+
+#[test]
+fn a_sample_on_a_boundary_starts_the_next_bucket() {
+    assert_eq!(histogram(&ms(&[10, 20, 30]), 2), vec![bucket(10, 20, 1), bucket(20, 30, 1), bucket(30, 40, 1)]);
+}
+
+#[test]
+fn the_gaps_between_clusters_are_kept() {
+    assert_eq!(histogram(&ms(&[1, 2, 48]), 5), vec![bucket(0, 10, 2), bucket(10, 20, 0), bucket(20, 30, 0), bucket(30, 40, 0), bucket(40, 50, 1)]);
+}
+
+#[test]
+fn a_duration_exactly_at_a_near_timeout_threshold_is_not_over_it() {
+    let timeout = Duration::from_millis(100);
+    let just = Duration::from_micros(1);
+    let half = Duration::from_millis(50);
+    let most = Duration::from_millis(90);
+    assert_eq!(near_timeout(&[half - just], timeout), (0, 0));
+    assert_eq!(near_timeout(&[half], timeout), (0, 0));
+    assert_eq!(near_timeout(&[half + just], timeout), (1, 0));
+    assert_eq!(near_timeout(&[most - just], timeout), (1, 0));
+    assert_eq!(near_timeout(&[most], timeout), (1, 0));
+    assert_eq!(near_timeout(&[most + just], timeout), (1, 1));
+}
+
+#[test]
+fn with_a_zero_timeout_every_wait_is_near_it() {
+    assert_eq!(near_timeout(&ms(&[0, 1, 30]), Duration::ZERO), (2, 2));
+}
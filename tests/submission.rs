@@ -0,0 +1,110 @@
+//! What --submit may send: only built-in providers, under their catalog names, and nothing
+//! about the machine that --submit-include does not allow.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use dns_speed_test::{builtin_catalog, redact, submission, Client, ClientField, ClientNetwork, DnsProvider, TestResult};
+
+/// The first built-in provider with both address families.
+fn builtin() -> DnsProvider {
+    builtin_catalog().into_iter()
+        .map(|(_, provider)| provider)
+        .find(|provider| provider.addresses.iter().any(IpAddr::is_ipv4) && provider.addresses.iter().any(IpAddr::is_ipv6))
+        .expect("a built-in provider with IPv4 and IPv6 addresses")
+}
+
+fn result(name: &str, addresses: &[IpAddr]) -> TestResult {
+    TestResult {
+        provider: name.to_string(),
+        organization: name.to_string(),
+        addresses: addresses.iter().map(IpAddr::to_string).collect(),
+        transport: "UDP".to_string(),
+        median_duration: Some(Duration::from_millis(12)),
+        success_rate: 100.0,
+        completed_queries: 10,
+        ..Default::default()
+    }
+}
+
+fn v4(provider: &DnsProvider) -> IpAddr {
+    *provider.addresses.iter().find(|address| address.is_ipv4()).unwrap()
+}
+
+#[test]
+fn a_builtin_provider_goes_out_under_its_catalog_name() {
+    let provider = builtin();
+    let submitted = redact(&result("My favourite resolver", &[v4(&provider)])).expect("a built-in address is submitted");
+    assert_eq!(submitted.provider, provider.name);
+    assert_eq!((submitted.ip_version, submitted.transport.as_str()), ("v4", "UDP"));
+    assert_eq!(submitted.median_ms, Some(12.0));
+    assert_eq!((submitted.success_rate, submitted.queries), (100.0, 10));
+}
+
+#[test]
+fn custom_and_local_servers_are_left_out() {
+    for address in ["192.168.1.1", "10.0.0.53", "127.0.0.53", "203.0.113.7", "fd00::1", "2001:db8::53"] {
+        assert_eq!(redact(&result("Router", &[address.parse().unwrap()])), None, "{}", address);
+    }
+}
+
+#[test]
+fn a_builtin_name_on_another_address_is_left_out() {
+    let provider = builtin();
+    assert_eq!(redact(&result(&provider.name, &["198.51.100.1".parse().unwrap()])), None);
+}
+
+#[test]
+fn a_builtin_address_next_to_a_custom_one_is_left_out() {
+    let provider = builtin();
+    assert_eq!(redact(&result(&provider.name, &[v4(&provider), "192.168.1.1".parse().unwrap()])), None);
+}
+
+#[test]
+fn addresses_of_two_providers_or_families_are_left_out() {
+    let catalog = builtin_catalog();
+    let first = &catalog[0].1;
+    let other = catalog.iter().map(|(_, provider)| provider)
+        .find(|provider| provider.name != first.name && provider.addresses.iter().any(IpAddr::is_ipv4))
+        .unwrap();
+    assert_eq!(redact(&result("Mixed", &[v4(first), v4(other)])), None);
+
+    let provider = builtin();
+    let v6 = *provider.addresses.iter().find(|address| address.is_ipv6()).unwrap();
+    assert_eq!(redact(&result(&provider.name, &[v4(&provider), v6])), None);
+    assert_eq!(redact(&result(&provider.name, &[v6])).map(|submitted| submitted.ip_version), Some("v6"));
+}
+
+#[test]
+fn a_result_without_addresses_is_left_out() {
+    assert_eq!(redact(&result("Nothing", &[])), None);
+}
+
+#[test]
+fn the_payload_has_no_address_or_name_of_the_run() {
+    let provider = builtin();
+    let mut system = result("System resolver (192.168.1.1)", &[v4(&provider)]);
+    system.system_resolver = true;
+    let results = [system, result("Home router", &["192.168.1.1".parse().unwrap()]), result("office-dns.example", &["203.0.113.7".parse().unwrap()])];
+    let network = ClientNetwork { asn: 64500, country: "NL".to_string() };
+
+    let payload = submission(&results, 3, &[], Some(&network), SystemTime::UNIX_EPOCH);
+    assert_eq!(payload.results.len(), 1);
+    assert_eq!(payload.date, "1970-01-01");
+    let json = serde_json::to_string(&payload).unwrap();
+    for private in ["192.168", "203.0.113", &v4(&provider).to_string(), "router", "office", "System", "64500", "NL", "system_resolver"] {
+        assert!(!json.contains(private), "{} in {}", private, json);
+    }
+    assert_eq!(payload.client, Client::default());
+}
+
+#[test]
+fn client_fields_only_as_allowed() {
+    let network = ClientNetwork { asn: 64500, country: "NL".to_string() };
+    let client = |include: &[ClientField], network: Option<&ClientNetwork>| submission(&[], 1, include, network, SystemTime::now()).client;
+
+    assert_eq!(client(&[ClientField::Asn], Some(&network)), Client { asn: Some(64500), ..Default::default() });
+    assert_eq!(client(&[ClientField::Country], Some(&network)), Client { country: Some("NL".to_string()), ..Default::default() });
+    assert_eq!(client(&[ClientField::Os], Some(&network)), Client { os: Some(std::env::consts::OS), ..Default::default() });
+    // Allowed but not found: left out rather than guessed.
+    assert_eq!(client(&[ClientField::Asn, ClientField::Country], None), Client::default());
+}
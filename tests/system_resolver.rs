@@ -0,0 +1,65 @@
+//! The system resolver is for the setup phase alone: with a stub in its place that panics on
+//! every lookup, the measurement over every transport must never reach it.
+
+mod common;
+
+use std::net::IpAddr;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use common::{config, Behavior, MockServer};
+use dns_speed_test::{lookup_host, run_all, set_system_resolver, SpeedTestConfig, Transport};
+
+const DOMAINS: &[&str] = &["example.com", "example.org"];
+const HOST: &str = "dns.example";
+
+/// Every hostname the stub was asked for, by whichever task asked.
+static LOOKUPS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn panicking_stub(host: &str) -> Result<Vec<IpAddr>, String> {
+    LOOKUPS.lock().unwrap().push(host.to_string());
+    panic!("system lookup of {}", host);
+}
+
+fn install_stub() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| set_system_resolver(panicking_stub));
+}
+
+fn looked_up(host: &str) -> bool {
+    LOOKUPS.lock().unwrap().iter().any(|lookup| lookup == host)
+}
+
+#[tokio::test]
+async fn setup_lookups_go_to_the_stub() {
+    install_stub();
+    // The panic stays in the task; the record shows the stub was asked.
+    let lookup = tokio::spawn(async { lookup_host("setup.example", None).await }).await;
+    assert!(lookup.is_err_and(|error| error.is_panic()));
+    assert!(looked_up("setup.example"));
+}
+
+#[tokio::test]
+async fn the_measurement_never_uses_the_system_resolver() {
+    install_stub();
+    let server = MockServer::start(Behavior::default()).await;
+    // A hostname on every encrypted endpoint, which only the TLS and QUIC handshakes use.
+    let mut provider = server.provider("Hostnames");
+    provider.dot_host = Some(HOST.to_string());
+    provider.doh_host = Some(HOST.to_string());
+    provider.doq_host = Some(HOST.to_string());
+    for transport in [Transport::Udp, Transport::Tcp, Transport::Tls, Transport::Https, Transport::Quic] {
+        let cfg = SpeedTestConfig {
+            transport,
+            preflight: true,
+            tcp_check: true,
+            warmup: 1,
+            timeout: Duration::from_millis(300),
+            ..config(vec![provider.clone()], DOMAINS, 1)
+        };
+        let results = run_all(&cfg).await;
+        assert_eq!(results.len(), 1, "{:?}", transport);
+    }
+    // The other test may have run first; its lookup is the only one allowed.
+    let lookups = LOOKUPS.lock().unwrap();
+    assert!(lookups.iter().all(|host| host == "setup.example"), "{:?}", lookups);
+}
@@ -0,0 +1,75 @@
+//! Finding systemd-resolved's upstream servers in `resolvectl dns` and its resolv.conf, from
+//! captured output.
+
+use std::net::SocketAddr;
+use dns_speed_test::{parse_resolv_conf, parse_resolvectl_dns, resolved_upstreams_with};
+
+fn servers(addresses: &[&str]) -> Vec<SocketAddr> {
+    addresses.iter().map(|address| address.parse().unwrap()).collect()
+}
+
+/// DNS over TLS to Cloudflare and Quad9 globally, the router on Ethernet, Tailscale's resolver
+/// on its tunnel and links without servers.
+const RESOLVECTL_DNS: &str = "\
+Global: 1.1.1.1#cloudflare-dns.com 9.9.9.9#dns.quad9.net
+Link 2 (enp0s31f6): 192.168.1.1 fe80::1%enp0s31f6 1.1.1.1
+Link 3 (wlp2s0):
+Link 4 (docker0):
+Link 7 (tailscale0): 100.100.100.100 [fd7a:115c:a1e0::53]:5353
+";
+
+const RESOLV_CONF: &str = "\
+# This is /run/systemd/resolve/resolv.conf managed by man:systemd-resolved(8).
+# Do not edit.
+#
+# This file might be symlinked as /etc/resolv.conf. If you're looking at
+# /etc/resolv.conf and seeing this text, you have followed the symlink.
+
+nameserver 192.168.1.1
+nameserver 2606:4700:4700::1111
+nameserver 192.168.1.1
+nameserver not-an-address
+search lan
+";
+
+#[test]
+fn resolvectl_lists_global_servers_first_without_names_or_scoped_addresses() {
+    assert_eq!(
+        parse_resolvectl_dns(RESOLVECTL_DNS),
+        servers(&["1.1.1.1:53", "9.9.9.9:53", "192.168.1.1:53", "100.100.100.100:53", "[fd7a:115c:a1e0::53]:5353"]),
+    );
+}
+
+#[test]
+fn resolvectl_without_servers_lists_none() {
+    assert_eq!(parse_resolvectl_dns("Global:\nLink 2 (enp0s31f6):\n"), []);
+}
+
+#[test]
+fn resolv_conf_gives_its_nameservers_once() {
+    assert_eq!(parse_resolv_conf(RESOLV_CONF), servers(&["192.168.1.1:53", "[2606:4700:4700::1111]:53"]));
+}
+
+#[test]
+fn resolvectl_comes_before_the_file() {
+    let found = resolved_upstreams_with(
+        |argv| (argv == ["resolvectl", "dns"]).then(|| RESOLVECTL_DNS.to_string()),
+        |_| Some(RESOLV_CONF.to_string()),
+    );
+    assert_eq!(found.unwrap().len(), 5);
+}
+
+#[test]
+fn the_file_stands_in_for_a_missing_resolvectl() {
+    let found = resolved_upstreams_with(
+        |_| None,
+        |path| (path == "/run/systemd/resolve/resolv.conf").then(|| RESOLV_CONF.to_string()),
+    );
+    assert_eq!(found, Ok(servers(&["192.168.1.1:53", "[2606:4700:4700::1111]:53"])));
+}
+
+#[test]
+fn neither_source_is_an_error() {
+    let error = resolved_upstreams_with(|_| None, |_| None).unwrap_err();
+    assert!(error.contains("/run/systemd/resolve/resolv.conf"), "{}", error);
+}
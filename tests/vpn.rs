@@ -0,0 +1,146 @@
+//! Finding VPN tunnels in what each platform reports about its interfaces, from captured output.
+
+use std::net::IpAddr;
+use dns_speed_test::{check_vpn, linux_tunnel, parse_ifconfig, parse_ipconfig, Tunnel};
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+/// macOS with Tailscale up: its own `utun` interfaces with link-local addresses only, Wi-Fi,
+/// and the Tailscale tunnel.
+const MACOS_IFCONFIG: &str = "\
+lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+	options=1203<RXCSUM,TXCSUM,TXSTATUS,SW_TIMESTAMP>
+	inet 127.0.0.1 netmask 0xff000000
+	inet6 ::1 prefixlen 128
+	inet6 fe80::1%lo0 prefixlen 64 scopeid 0x1
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+	options=6460<TSO4,TSO6,CHANNEL_IO,PARTIAL_CSUM,ZEROINVERT_CSUM>
+	ether 3c:22:fb:12:34:56
+	inet6 fe80::14a2:7d3c:9e1f:2b4a%en0 prefixlen 64 secured scopeid 0xb
+	inet 192.168.178.23 netmask 0xffffff00 broadcast 192.168.178.255
+	status: active
+utun0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+	inet6 fe80::d1a4:6b2e:41c8:9f3a%utun0 prefixlen 64 scopeid 0xf
+	nd6 options=201<PERFORMNUD,DAD>
+utun1: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 2000
+	inet6 fe80::3e1b:c3d4:92e1:7a05%utun1 prefixlen 64 scopeid 0x10
+	nd6 options=201<PERFORMNUD,DAD>
+utun4: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1280
+	inet 100.101.102.103 --> 100.101.102.103 netmask 0xffffffff
+	inet6 fe80::3e22:fbff:fe12:3456%utun4 prefixlen 64 scopeid 0x14
+	inet6 fd7a:115c:a1e0::1234 prefixlen 48
+	nd6 options=201<PERFORMNUD,DAD>
+utun5: flags=8010<POINTOPOINT,MULTICAST> mtu 1500
+	inet 10.8.0.6 --> 10.8.0.5 netmask 0xffffffff
+";
+
+/// Windows with WireGuard up, a disconnected OpenVPN adapter and a corporate PPP connection.
+const WINDOWS_IPCONFIG: &str = "\r
+Windows IP Configuration\r
+\r
+   Host Name . . . . . . . . . . . . : DESKTOP-4F2K9QX\r
+   Primary Dns Suffix  . . . . . . . :\r
+   Node Type . . . . . . . . . . . . : Hybrid\r
+\r
+Ethernet adapter Ethernet:\r
+\r
+   Connection-specific DNS Suffix  . : fritz.box\r
+   Description . . . . . . . . . . . : Intel(R) Ethernet Connection (7) I219-V\r
+   Physical Address. . . . . . . . . : 3C-22-FB-12-34-56\r
+   DHCP Enabled. . . . . . . . . . . : Yes\r
+   Link-local IPv6 Address . . . . . : fe80::14a2:7d3c:9e1f:2b4a%11(Preferred)\r
+   IPv4 Address. . . . . . . . . . . : 192.168.178.23(Preferred)\r
+   Subnet Mask . . . . . . . . . . . : 255.255.255.0\r
+   Default Gateway . . . . . . . . . : 192.168.178.1\r
+   DNS Servers . . . . . . . . . . . : 192.168.178.1\r
+\r
+Unknown adapter home:\r
+\r
+   Connection-specific DNS Suffix  . :\r
+   Description . . . . . . . . . . . : WireGuard Tunnel\r
+   Physical Address. . . . . . . . . :\r
+   DHCP Enabled. . . . . . . . . . . : No\r
+   IPv4 Address. . . . . . . . . . . : 10.64.0.2(Preferred)\r
+   Subnet Mask . . . . . . . . . . . : 255.255.255.255\r
+   DNS Servers . . . . . . . . . . . : 10.64.0.1\r
+\r
+Unknown adapter OpenVPN TAP-Windows6:\r
+\r
+   Media State . . . . . . . . . . . : Media disconnected\r
+   Description . . . . . . . . . . . : TAP-Windows Adapter V9\r
+\r
+PPP adapter Office:\r
+\r
+   Description . . . . . . . . . . . : Office\r
+   IPv4 Address. . . . . . . . . . . : 172.20.1.15(Preferred)\r
+   Subnet Mask . . . . . . . . . . . : 255.255.255.255\r
+";
+
+#[test]
+fn macos_tunnels_need_an_address_and_to_be_up() {
+    let tunnels = parse_ifconfig(MACOS_IFCONFIG);
+    assert_eq!(tunnels, vec![Tunnel {
+        interface: "utun4".to_string(),
+        kind: "Tailscale",
+        addresses: vec![ip("100.101.102.103"), ip("fd7a:115c:a1e0::1234")],
+    }]);
+}
+
+#[test]
+fn a_point_to_point_interface_is_a_tunnel_whatever_its_name() {
+    let output = "\
+gif0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1280
+	inet 10.1.2.3 --> 10.1.2.4 netmask 0xffffffff
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+	inet 192.168.1.10 netmask 0xffffff00 broadcast 192.168.1.255
+";
+    let tunnels = parse_ifconfig(output);
+    assert_eq!(tunnels.len(), 1);
+    assert_eq!((tunnels[0].interface.as_str(), tunnels[0].kind), ("gif0", "VPN"));
+}
+
+#[test]
+fn windows_tunnels_by_adapter_description() {
+    let tunnels = parse_ipconfig(WINDOWS_IPCONFIG);
+    assert_eq!(tunnels, vec![
+        Tunnel { interface: "home".to_string(), kind: "WireGuard", addresses: vec![ip("10.64.0.2")] },
+        Tunnel { interface: "Office".to_string(), kind: "PPP", addresses: vec![ip("172.20.1.15")] },
+    ]);
+}
+
+#[test]
+fn linux_tunnels_by_interface_type() {
+    // The types in /sys/class/net/<name>/type.
+    assert_eq!(linux_tunnel("wg0", Some("65534\n")), Some("WireGuard"));
+    assert_eq!(linux_tunnel("tailscale0", Some("65534\n")), Some("Tailscale"));
+    assert_eq!(linux_tunnel("tun0", Some("65534\n")), Some("VPN"));
+    assert_eq!(linux_tunnel("proton0", Some("65534\n")), Some("Proton VPN"));
+    assert_eq!(linux_tunnel("office", Some("65534\n")), Some("VPN"));
+    assert_eq!(linux_tunnel("ppp0", Some("512\n")), Some("PPP"));
+    assert_eq!(linux_tunnel("he-ipv6", Some("776\n")), Some("IP tunnel"));
+    // Ethernet, Wi-Fi and bridges are not, however they are named.
+    assert_eq!(linux_tunnel("eth0", Some("1\n")), None);
+    assert_eq!(linux_tunnel("wlp2s0", Some("1\n")), None);
+    assert_eq!(linux_tunnel("docker0", Some("1\n")), None);
+    // Without the type, by the name alone.
+    assert_eq!(linux_tunnel("wg1", None), Some("WireGuard"));
+    assert_eq!(linux_tunnel("wlan0", None), None);
+}
+
+#[test]
+fn loopback_goes_out_directly() {
+    let check = check_vpn([ip("127.0.0.1"), ip("127.0.0.1")], None);
+    assert_eq!(check.egress.len(), 1);
+    let egress = &check.egress[&ip("127.0.0.1")];
+    assert_eq!(egress.source, ip("127.0.0.1"));
+    assert_eq!(egress.tunnel, None);
+    assert!(!check.detected());
+}
+
+#[test]
+fn an_address_of_the_other_family_than_bind_has_no_way_out() {
+    let check = check_vpn([ip("::1")], Some(ip("127.0.0.1")));
+    assert!(check.egress.is_empty());
+}